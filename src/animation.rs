@@ -0,0 +1,202 @@
+use crate::vector3d::Quaternion;
+use std::ops::{Add, Mul, Sub};
+
+/// Values keyframes can hold: anything that blends linearly, like `f64`,
+/// `Vector3D` and `Color`
+pub trait Animatable: Copy + Add<Output = Self> + Sub<Output = Self> + Mul<f64, Output = Self> {}
+
+impl<T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f64, Output = T>> Animatable for T {}
+
+/// How a track moves from one key to the next
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interpolation {
+    /// Holds each key's value until the next key
+    Step,
+    Linear,
+    /// Eases between each pair of keys along the timing curve from (0, 0)
+    /// to (1, 1) with control points (x1, y1) and (x2, y2), like CSS
+    /// `cubic-bezier`. The x coordinates are clamped to [0, 1] so time
+    /// never runs backwards.
+    Bezier { x1: f64, y1: f64, x2: f64, y2: f64 },
+    /// Smooth curve through every key, tangents taken from the neighbouring
+    /// keys and scaled by their spacing in time
+    CatmullRom,
+}
+
+impl Interpolation {
+    pub const EASE_IN_OUT: Interpolation = Interpolation::Bezier {
+        x1: 0.42,
+        y1: 0.0,
+        x2: 0.58,
+        y2: 1.0,
+    };
+
+    /// Looks up a mode by name; `bezier` is `EASE_IN_OUT`
+    pub fn from_name(name: &str) -> Option<Interpolation> {
+        match name {
+            "step" => Some(Interpolation::Step),
+            "linear" => Some(Interpolation::Linear),
+            "bezier" => Some(Interpolation::EASE_IN_OUT),
+            "catmull-rom" => Some(Interpolation::CatmullRom),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Keyframe<T> {
+    pub time: f64,
+    pub value: T,
+}
+
+/// Keyframed value over time. Before the first key and after the last the
+/// track holds the end values.
+#[derive(Debug, Clone)]
+pub struct Track<T> {
+    keys: Vec<Keyframe<T>>,
+    pub interpolation: Interpolation,
+}
+
+impl<T: Copy> Track<T> {
+    /// Track through `keys`, which are sorted by time. Keys sharing a time
+    /// keep their order, so two keys can make a jump.
+    pub fn new(mut keys: Vec<Keyframe<T>>, interpolation: Interpolation) -> Track<T> {
+        keys.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Track { keys, interpolation }
+    }
+
+    pub fn keys(&self) -> &[Keyframe<T>] {
+        &self.keys
+    }
+
+    /// Index of the last key at or before `time` and how far `time` is
+    /// towards the next key, or `Err` with the end value held outside the
+    /// keys. `None` for a track without keys.
+    fn locate(&self, time: f64) -> Option<Result<(usize, f64), T>> {
+        let keys = &self.keys;
+        let first = keys.first()?;
+        let last = keys[keys.len() - 1];
+        if time < first.time {
+            return Some(Err(first.value));
+        }
+        if time >= last.time {
+            return Some(Err(last.value));
+        }
+
+        // The key after the last one at or before `time` is later
+        let i = keys.partition_point(|key| key.time <= time) - 1;
+        Some(Ok((i, (time - keys[i].time) / (keys[i + 1].time - keys[i].time))))
+    }
+}
+
+impl<T: Animatable> Track<T> {
+    /// Value at `time`, or `None` for a track without keys
+    pub fn sample(&self, time: f64) -> Option<T> {
+        let (i, s) = match self.locate(time)? {
+            Ok(segment) => segment,
+            Err(held) => return Some(held),
+        };
+        let keys = &self.keys;
+        let (a, b) = (keys[i], keys[i + 1]);
+        let span = b.time - a.time;
+
+        Some(match self.interpolation {
+            Interpolation::Step => a.value,
+            Interpolation::Linear => lerp(a.value, b.value, s),
+            Interpolation::Bezier { x1, y1, x2, y2 } => lerp(a.value, b.value, ease(x1, y1, x2, y2, s)),
+            Interpolation::CatmullRom => {
+                let tangent = |index: usize| {
+                    let before = keys[index.saturating_sub(1)];
+                    let after = keys[(index + 1).min(keys.len() - 1)];
+                    let spacing = after.time - before.time;
+                    if spacing > 0.0 {
+                        (after.value - before.value) * (1.0 / spacing)
+                    } else {
+                        (b.value - a.value) * (1.0 / span)
+                    }
+                };
+                hermite(a.value, tangent(i) * span, b.value, tangent(i + 1) * span, s)
+            }
+        })
+    }
+}
+
+impl Track<Quaternion> {
+    /// Rotation track through `keys`. Each key is flipped onto the same side
+    /// as the one before it, so every step between keys turns the short way.
+    pub fn rotation(keys: Vec<Keyframe<Quaternion>>, interpolation: Interpolation) -> Track<Quaternion> {
+        let mut track = Track::new(keys, interpolation);
+        for i in 1..track.keys.len() {
+            if track.keys[i].value.dot(track.keys[i - 1].value) < 0.0 {
+                track.keys[i].value = -track.keys[i].value;
+            }
+        }
+        track
+    }
+
+    /// Rotation at `time`. Linear and Bézier tracks slerp between keys, so
+    /// they turn at a steady rate along the shortest arc; Catmull-Rom curves
+    /// run through the quaternions' components and are renormalized.
+    pub fn sample_rotation(&self, time: f64) -> Option<Quaternion> {
+        let (i, s) = match self.locate(time)? {
+            Ok(segment) => segment,
+            Err(held) => return Some(held),
+        };
+        let (a, b) = (self.keys[i].value, self.keys[i + 1].value);
+
+        Some(match self.interpolation {
+            Interpolation::Step => a,
+            Interpolation::Linear => a.slerp(b, s),
+            Interpolation::Bezier { x1, y1, x2, y2 } => a.slerp(b, ease(x1, y1, x2, y2, s)),
+            Interpolation::CatmullRom => self.sample(time)?.normalize(),
+        })
+    }
+}
+
+#[inline]
+fn lerp<T: Animatable>(a: T, b: T, s: f64) -> T {
+    a + (b - a) * s
+}
+
+/// Cubic Hermite curve from `p0` to `p1` with end tangents `m0` and `m1`,
+/// both per unit `s`
+#[inline]
+fn hermite<T: Animatable>(p0: T, m0: T, p1: T, m1: T, s: f64) -> T {
+    let s2 = s * s;
+    let s3 = s2 * s;
+    p0 * (2.0 * s3 - 3.0 * s2 + 1.0) + m0 * (s3 - 2.0 * s2 + s) + p1 * (-2.0 * s3 + 3.0 * s2) + m1 * (s3 - s2)
+}
+
+/// Progress at time fraction `x` along a CSS-style cubic Bézier timing curve
+fn ease(x1: f64, y1: f64, x2: f64, y2: f64, x: f64) -> f64 {
+    let (x1, x2) = (x1.clamp(0.0, 1.0), x2.clamp(0.0, 1.0));
+    let bezier = |p1: f64, p2: f64, t: f64| {
+        let u = 1.0 - t;
+        3.0 * u * u * t * p1 + 3.0 * u * t * t * p2 + t * t * t
+    };
+
+    // x(t) is monotonic, so bisection always finds the parameter; Newton
+    // steps speed it up where the slope allows
+    let (mut low, mut high) = (0.0, 1.0);
+    let mut t = x;
+    for _ in 0..32 {
+        let error = bezier(x1, x2, t) - x;
+        if error.abs() < 1e-9 {
+            break;
+        }
+        if error > 0.0 {
+            high = t;
+        } else {
+            low = t;
+        }
+        let u = 1.0 - t;
+        let slope = 3.0 * u * u * x1 + 6.0 * u * t * (x2 - x1) + 3.0 * t * t * (1.0 - x2);
+        let newton = t - error / slope;
+        t = if slope > 1e-6 && newton > low && newton < high {
+            newton
+        } else {
+            0.5 * (low + high)
+        };
+    }
+    bezier(y1, y2, t)
+}
@@ -0,0 +1,158 @@
+use crate::framebuffer::Framebuffer;
+use crate::sampler::hash;
+use crate::sphere::Color;
+use crate::vector3d::Vector3D;
+
+/// Arbitrary output variables rendered alongside the beauty image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aov {
+    /// Camera-space depth of the first hit along the view axis, 0 on a miss
+    Depth,
+    /// World-space normal of the first hit
+    Normal,
+    /// Material colour of the first hit
+    Albedo,
+    /// Lighting computed at the first hit, including its own emission (the
+    /// background on a miss)
+    Direct,
+    /// Light arriving through reflections; `Direct + Reflected` is the beauty
+    Reflected,
+    /// Fraction of light intensity at the first hit blocked by other objects
+    Shadow,
+    /// 1-based index of the first object hit, 0 for the background
+    ObjectId,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 7] = [
+        Aov::Depth,
+        Aov::Normal,
+        Aov::Albedo,
+        Aov::Direct,
+        Aov::Reflected,
+        Aov::Shadow,
+        Aov::ObjectId,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Aov> {
+        match name {
+            "depth" => Some(Aov::Depth),
+            "normal" => Some(Aov::Normal),
+            "albedo" => Some(Aov::Albedo),
+            "direct" => Some(Aov::Direct),
+            "reflected" => Some(Aov::Reflected),
+            "shadow" => Some(Aov::Shadow),
+            "id" | "object-id" => Some(Aov::ObjectId),
+            _ => None,
+        }
+    }
+
+    /// Parses a comma separated list such as `depth,normal` or `all`
+    pub fn parse_list(list: &str) -> Option<Vec<Aov>> {
+        if list == "all" {
+            return Some(Aov::ALL.to_vec());
+        }
+        list.split(',').map(|name| Aov::from_name(name.trim())).collect()
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::Direct => "direct",
+            Aov::Reflected => "reflected",
+            Aov::Shadow => "shadow",
+            Aov::ObjectId => "id",
+        }
+    }
+
+    /// Channel names as written to EXR layers
+    pub fn channel_names(self) -> &'static [&'static str] {
+        match self {
+            Aov::Depth => &["Z"],
+            Aov::Normal => &["X", "Y", "Z"],
+            Aov::Albedo | Aov::Direct | Aov::Reflected => &["R", "G", "B"],
+            Aov::Shadow => &["Y"],
+            Aov::ObjectId => &["id"],
+        }
+    }
+
+    pub fn channels(self) -> usize {
+        self.channel_names().len()
+    }
+
+    /// Whether samples are averaged; object IDs keep the first sample instead
+    pub fn is_averaged(self) -> bool {
+        self != Aov::ObjectId
+    }
+
+    /// Maps the raw values to a displayable RGB image
+    pub fn preview(self, image: &Framebuffer) -> Framebuffer {
+        let mut preview = Framebuffer::new(image.width, image.height, 3);
+        let max_depth = image
+            .as_slice()
+            .iter()
+            .fold(0.0f32, |max, &value| if self == Aov::Depth { max.max(value) } else { max });
+
+        for y in 0..image.height {
+            for x in 0..image.width {
+                let pixel = image.pixel(x, y);
+                let rgb = match self {
+                    Aov::Depth => {
+                        let value = if max_depth > 0.0 { 1.0 - pixel[0] / max_depth } else { 0.0 };
+                        let value = if pixel[0] > 0.0 { value } else { 0.0 };
+                        [value, value, value]
+                    }
+                    Aov::Normal => [pixel[0] * 0.5 + 0.5, pixel[1] * 0.5 + 0.5, pixel[2] * 0.5 + 0.5],
+                    Aov::Shadow => [pixel[0], pixel[0], pixel[0]],
+                    Aov::ObjectId => {
+                        let id = pixel[0] as u32;
+                        if id == 0 {
+                            [0.0, 0.0, 0.0]
+                        } else {
+                            let h = hash(id);
+                            [
+                                (h & 0xff) as f32 / 255.0,
+                                ((h >> 8) & 0xff) as f32 / 255.0,
+                                ((h >> 16) & 0xff) as f32 / 255.0,
+                            ]
+                        }
+                    }
+                    Aov::Albedo | Aov::Direct | Aov::Reflected => [pixel[0], pixel[1], pixel[2]],
+                };
+                preview.pixel_mut(x, y).copy_from_slice(&rgb);
+            }
+        }
+
+        preview
+    }
+}
+
+/// AOV values of a single camera sample, taken at its first hit
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AovSample {
+    pub depth: f64,
+    pub normal: Vector3D,
+    pub albedo: Color,
+    pub direct: Color,
+    pub reflected: Color,
+    pub shadow: f64,
+    pub object_id: u32,
+}
+
+impl AovSample {
+    /// Writes the channels of `aov` into `out`
+    #[inline]
+    pub fn write(&self, aov: Aov, out: &mut [f64]) {
+        match aov {
+            Aov::Depth => out[0] = self.depth,
+            Aov::Normal => out.copy_from_slice(&[self.normal.x, self.normal.y, self.normal.z]),
+            Aov::Albedo => out.copy_from_slice(&[self.albedo.r, self.albedo.g, self.albedo.b]),
+            Aov::Direct => out.copy_from_slice(&[self.direct.r, self.direct.g, self.direct.b]),
+            Aov::Reflected => out.copy_from_slice(&[self.reflected.r, self.reflected.g, self.reflected.b]),
+            Aov::Shadow => out[0] = self.shadow,
+            Aov::ObjectId => out[0] = self.object_id as f64,
+        }
+    }
+}
@@ -0,0 +1,182 @@
+use crate::animation::Track;
+use crate::scene::Camera;
+use crate::vector3d::Vector3D;
+
+/// Which way a camera on a `CameraRig::Path` looks
+#[derive(Debug, Clone, Copy)]
+pub enum PathOrientation {
+    /// Always at this point
+    LookAt(Vector3D),
+    /// Along the direction of travel
+    Tangent,
+}
+
+/// Drives the camera over an animation, so common camera moves don't need
+/// a track per property. Times are in frames, like `Track` keys.
+#[derive(Debug, Clone)]
+pub enum CameraRig {
+    /// Turntable: circles `target` at `radius`, `height` above it, making
+    /// `turns` full turns over the animation so the loop closes. The camera
+    /// starts `start_angle` degrees round from the target's -z side and
+    /// moves towards +x.
+    Orbit {
+        target: Vector3D,
+        radius: f64,
+        height: f64,
+        start_angle: f64,
+        turns: f64,
+    },
+    /// Moves along the line from `target` through the camera's resting
+    /// position, at the distance from `target` given by the track. With
+    /// `zoom` the field of view widens as the camera closes in so the
+    /// target keeps its size on screen (the dolly zoom effect).
+    Dolly {
+        target: Vector3D,
+        distance: Track<f64>,
+        zoom: bool,
+    },
+    /// Flies through the points of a track, usually Catmull-Rom
+    Path {
+        points: Track<Vector3D>,
+        orientation: PathOrientation,
+    },
+}
+
+impl CameraRig {
+    /// `rest` posed at `frame` of an animation `frames` long. `rest` is the
+    /// camera without the rig; its field of view is kept except by a
+    /// zooming dolly.
+    pub fn pose(&self, rest: &Camera, frame: f64, frames: usize) -> Camera {
+        let mut camera = *rest;
+        match self {
+            CameraRig::Orbit {
+                target,
+                radius,
+                height,
+                start_angle,
+                turns,
+            } => {
+                let angle = (start_angle + 360.0 * turns * frame / frames as f64).to_radians();
+                camera.position = *target + Vector3D::new(radius * angle.sin(), *height, -radius * angle.cos());
+                camera.target = *target;
+            }
+            CameraRig::Dolly { target, distance, zoom } => {
+                let offset = rest.position - *target;
+                let direction = if offset.magnitude_squared() > 0.0 {
+                    offset.normalize()
+                } else {
+                    Vector3D::new(0.0, 0.0, -1.0)
+                };
+                let current = distance.sample(frame).unwrap_or(offset.magnitude());
+                camera.position = *target + direction * current;
+                camera.target = *target;
+
+                // Keep tan(fov / 2) * distance, the height framed at the
+                // target, what it was at the first key
+                let start = distance.keys().first().map_or(current, |key| key.value);
+                if *zoom && current > 0.0 {
+                    let half_height = (rest.fov.to_radians() * 0.5).tan() * start;
+                    camera.fov = (2.0 * (half_height / current).atan()).to_degrees();
+                }
+            }
+            CameraRig::Path { points, orientation } => {
+                let Some(position) = points.sample(frame) else {
+                    return camera;
+                };
+                camera.position = position;
+                camera.target = match orientation {
+                    PathOrientation::LookAt(target) => *target,
+                    PathOrientation::Tangent => {
+                        // Central difference, one sided at the ends where
+                        // the track clamps. Beyond them the camera keeps
+                        // the heading it had at the end.
+                        const STEP: f64 = 0.01;
+                        let keys = points.keys();
+                        let frame = frame.clamp(keys[0].time, keys[keys.len() - 1].time);
+                        let ahead = points.sample(frame + STEP).unwrap_or(position);
+                        let behind = points.sample(frame - STEP).unwrap_or(position);
+                        let velocity = ahead - behind;
+                        if velocity.magnitude_squared() > 1e-18 {
+                            position + velocity.normalize()
+                        } else {
+                            position + (rest.target - rest.position)
+                        }
+                    }
+                };
+            }
+        }
+        camera
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::animation::{Interpolation, Keyframe};
+
+    fn rest() -> Camera {
+        Camera::new(Vector3D::new(0.0, 1.0, -10.0), Vector3D::ZERO, 40.0, 1.0)
+    }
+
+    fn assert_close(a: Vector3D, b: Vector3D) {
+        assert!((a - b).magnitude() < 1e-6, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn orbits_close_the_loop() {
+        let rig = CameraRig::Orbit {
+            target: Vector3D::new(0.0, 1.0, 0.0),
+            radius: 5.0,
+            height: 2.0,
+            start_angle: 0.0,
+            turns: 1.0,
+        };
+        let start = rig.pose(&rest(), 0.0, 40);
+        assert_close(start.position, Vector3D::new(0.0, 3.0, -5.0));
+        assert_close(start.target, Vector3D::new(0.0, 1.0, 0.0));
+        assert_close(rig.pose(&rest(), 10.0, 40).position, Vector3D::new(5.0, 3.0, 0.0));
+        assert_close(rig.pose(&rest(), 40.0, 40).position, start.position);
+        assert_eq!(start.fov, 40.0);
+    }
+
+    #[test]
+    fn dolly_zoom_keeps_the_target_framed() {
+        let keys = vec![Keyframe { time: 0.0, value: 10.0 }, Keyframe { time: 10.0, value: 5.0 }];
+        let rig = CameraRig::Dolly {
+            target: Vector3D::ZERO,
+            distance: Track::new(keys, Interpolation::Linear),
+            zoom: true,
+        };
+        let framed = |camera: Camera| (camera.fov.to_radians() * 0.5).tan() * camera.position.magnitude();
+        let start = rig.pose(&rest(), 0.0, 10);
+        let end = rig.pose(&rest(), 10.0, 10);
+        assert!((start.position.magnitude() - 10.0).abs() < 1e-9);
+        assert!((end.position.magnitude() - 5.0).abs() < 1e-9);
+        assert_close(end.position.normalize(), rest().position.normalize());
+        assert!((start.fov - 40.0).abs() < 1e-9);
+        assert!(end.fov > 70.0 && (framed(end) - framed(start)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn tangent_paths_look_where_they_go() {
+        let keys = vec![
+            Keyframe { time: 0.0, value: Vector3D::new(0.0, 0.0, 0.0) },
+            Keyframe { time: 10.0, value: Vector3D::new(10.0, 0.0, 0.0) },
+            Keyframe { time: 20.0, value: Vector3D::new(10.0, 0.0, 10.0) },
+        ];
+        let rig = CameraRig::Path {
+            points: Track::new(keys, Interpolation::Linear),
+            orientation: PathOrientation::Tangent,
+        };
+        let heading = |frame: f64| {
+            let camera = rig.pose(&rest(), frame, 20);
+            (camera.target - camera.position).normalize()
+        };
+        assert_close(heading(0.0), Vector3D::new(1.0, 0.0, 0.0));
+        assert_close(heading(5.0), Vector3D::new(1.0, 0.0, 0.0));
+        assert_close(heading(15.0), Vector3D::new(0.0, 0.0, 1.0));
+        // Past the end the last heading holds
+        assert_close(heading(25.0), Vector3D::new(0.0, 0.0, 1.0));
+        assert_close(rig.pose(&rest(), 25.0, 20).position, Vector3D::new(10.0, 0.0, 10.0));
+    }
+}
@@ -0,0 +1,365 @@
+use crate::aov::Aov;
+use crate::film::{Film, Filter, FilterKind};
+use crate::sampler::SamplerKind;
+use crate::scene::Camera;
+use crate::temporal::TemporalAccumulator;
+use crate::tiles::TileOrder;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8; 4] = b"RTCK";
+const VERSION: u32 = 10;
+const STATE_FILE: &str = "state.bin";
+const HISTORY_FILE: &str = "history.bin";
+
+/// Render settings and progress of an interrupted animation render.
+///
+/// The accumulation film of the frame in progress is stored alongside it;
+/// finished frames are stored as separate files in the same directory.
+#[derive(Debug, Clone)]
+pub struct Checkpoint {
+    pub width: u32,
+    pub height: u32,
+    pub samples: u32,
+    pub num_frames: usize,
+    pub sampler: SamplerKind,
+    pub filter: Filter,
+    /// Tiles are merged into the film one at a time, so resuming with other
+    /// tiles would change the result in the last bits
+    pub tile_size: u32,
+    pub tile_order: TileOrder,
+    pub aovs: Vec<Aov>,
+    /// Whether frames are accumulated temporally, see `save_history`
+    pub temporal: bool,
+    /// `content_hash` of the scene file, so an edited scene starts over
+    pub scene_hash: u64,
+    /// `camera_hash` of the camera the animation starts from
+    pub camera_hash: u64,
+    /// Frame currently being rendered
+    pub frame_index: usize,
+    /// Sample indices of `frame_index` already accumulated into the film
+    pub samples_done: u32,
+}
+
+impl Checkpoint {
+    /// Whether `other` describes the same render, ignoring progress
+    pub fn is_compatible(&self, other: &Checkpoint) -> bool {
+        self.width == other.width
+            && self.height == other.height
+            && self.samples == other.samples
+            && self.num_frames == other.num_frames
+            && self.sampler == other.sampler
+            && self.filter.kind == other.filter.kind
+            && self.filter.radius == other.filter.radius
+            && self.tile_size == other.tile_size
+            && self.tile_order == other.tile_order
+            && self.aovs == other.aovs
+            && self.temporal == other.temporal
+            && self.scene_hash == other.scene_hash
+            && self.camera_hash == other.camera_hash
+    }
+
+    /// Writes the checkpoint atomically, replacing any previous one in `dir`
+    pub fn save(&self, dir: &Path, film: &Film) -> io::Result<()> {
+        fs::create_dir_all(dir)?;
+        let temp_path = dir.join(format!("{}.tmp", STATE_FILE));
+
+        {
+            let mut writer = BufWriter::new(File::create(&temp_path)?);
+            writer.write_all(MAGIC)?;
+            write_u32(&mut writer, VERSION)?;
+            write_u32(&mut writer, self.width)?;
+            write_u32(&mut writer, self.height)?;
+            write_u32(&mut writer, self.samples)?;
+            write_u32(&mut writer, self.num_frames as u32)?;
+            write_name(&mut writer, self.sampler.name())?;
+            write_name(&mut writer, self.filter.kind.name())?;
+            writer.write_all(&self.filter.radius.to_le_bytes())?;
+            write_u32(&mut writer, self.tile_size)?;
+            write_name(&mut writer, self.tile_order.name())?;
+            write_u32(&mut writer, self.aovs.len() as u32)?;
+            for aov in &self.aovs {
+                write_name(&mut writer, aov.name())?;
+            }
+            write_u32(&mut writer, self.temporal as u32)?;
+            write_u64(&mut writer, self.scene_hash)?;
+            write_u64(&mut writer, self.camera_hash)?;
+            write_u32(&mut writer, self.frame_index as u32)?;
+            write_u32(&mut writer, self.samples_done)?;
+
+            if self.samples_done > 0 {
+                film.write_accumulation(&mut writer)?;
+            }
+            writer.flush()?;
+        }
+
+        fs::rename(temp_path, dir.join(STATE_FILE))
+    }
+
+    /// Reads the checkpoint in `dir` together with its accumulation film
+    pub fn load(dir: &Path) -> io::Result<(Checkpoint, Film)> {
+        let mut reader = BufReader::new(File::open(dir.join(STATE_FILE))?);
+
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC || read_u32(&mut reader)? != VERSION {
+            return Err(invalid_data("not a checkpoint file or unsupported version"));
+        }
+
+        let width = read_u32(&mut reader)?;
+        let height = read_u32(&mut reader)?;
+        let samples = read_u32(&mut reader)?;
+        let num_frames = read_u32(&mut reader)? as usize;
+        let sampler = SamplerKind::from_name(&read_name(&mut reader)?)
+            .ok_or_else(|| invalid_data("unknown sampler"))?;
+        let filter_kind = FilterKind::from_name(&read_name(&mut reader)?)
+            .ok_or_else(|| invalid_data("unknown filter"))?;
+        let mut radius = [0u8; 8];
+        reader.read_exact(&mut radius)?;
+        let filter = Filter::new(filter_kind, f64::from_le_bytes(radius));
+        let tile_size = read_u32(&mut reader)?;
+        let tile_order = TileOrder::from_name(&read_name(&mut reader)?)
+            .ok_or_else(|| invalid_data("unknown tile order"))?;
+        let aov_count = read_u32(&mut reader)? as usize;
+        if aov_count > Aov::ALL.len() {
+            return Err(invalid_data("too many AOVs"));
+        }
+        let aovs = (0..aov_count)
+            .map(|_| Aov::from_name(&read_name(&mut reader)?).ok_or_else(|| invalid_data("unknown AOV")))
+            .collect::<io::Result<Vec<Aov>>>()?;
+        let temporal = read_u32(&mut reader)? != 0;
+        let scene_hash = read_u64(&mut reader)?;
+        let camera_hash = read_u64(&mut reader)?;
+        let frame_index = read_u32(&mut reader)? as usize;
+        let samples_done = read_u32(&mut reader)?;
+
+        let mut film = Film::with_aovs(width, height, filter, &aovs);
+        if samples_done > 0 {
+            film.read_accumulation(&mut reader)?;
+        }
+
+        let checkpoint = Checkpoint {
+            width,
+            height,
+            samples,
+            num_frames,
+            sampler,
+            filter,
+            tile_size,
+            tile_order,
+            aovs,
+            temporal,
+            scene_hash,
+            camera_hash,
+            frame_index,
+            samples_done,
+        };
+
+        Ok((checkpoint, film))
+    }
+}
+
+/// 64-bit FNV-1a hash of `bytes`. Unlike the `std` hashers it is the same
+/// on every platform and compiler version, so it can be stored.
+pub fn content_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// `content_hash` of the camera's placement, lens and projection
+pub fn camera_hash(camera: &Camera) -> u64 {
+    let (projection, parameter) = camera.projection.encode();
+    let mut bytes = projection.to_le_bytes().to_vec();
+    for value in [
+        camera.position.x, camera.position.y, camera.position.z,
+        camera.target.x, camera.target.y, camera.target.z,
+        camera.up.x, camera.up.y, camera.up.z,
+        camera.fov, camera.aspect_ratio, parameter,
+        camera.roll, camera.shift_x, camera.shift_y,
+    ] {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+    content_hash(&bytes)
+}
+
+/// Stores the temporal history that frame `frame_index` will be blended with
+pub fn save_history(dir: &Path, frame_index: usize, accumulator: &TemporalAccumulator) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let temp_path = dir.join(format!("{}.tmp", HISTORY_FILE));
+
+    {
+        let mut writer = BufWriter::new(File::create(&temp_path)?);
+        write_u32(&mut writer, frame_index as u32)?;
+        accumulator.write_history(&mut writer)?;
+        writer.flush()?;
+    }
+
+    fs::rename(temp_path, dir.join(HISTORY_FILE))
+}
+
+/// Restores the history saved for `frame_index`, failing if the file
+/// belongs to a different frame
+pub fn load_history(dir: &Path, frame_index: usize, accumulator: &mut TemporalAccumulator) -> io::Result<()> {
+    let mut reader = BufReader::new(File::open(dir.join(HISTORY_FILE))?);
+    if read_u32(&mut reader)? as usize != frame_index {
+        return Err(invalid_data("temporal history belongs to a different frame"));
+    }
+    accumulator.read_history(&mut reader)
+}
+
+fn frame_path(dir: &Path, frame_index: usize) -> PathBuf {
+    dir.join(format!("frame_{:04}.rgb", frame_index))
+}
+
+/// Stores a finished frame's 8-bit RGB pixels, row by row without padding
+pub fn save_frame(dir: &Path, frame_index: usize, rgb: &[u8]) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    fs::write(frame_path(dir, frame_index), rgb)
+}
+
+pub fn load_frame(dir: &Path, frame_index: usize) -> io::Result<Vec<u8>> {
+    fs::read(frame_path(dir, frame_index))
+}
+
+fn write_u32(writer: &mut impl Write, value: u32) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn write_u64(writer: &mut impl Write, value: u64) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn write_name(writer: &mut impl Write, name: &str) -> io::Result<()> {
+    write_u32(writer, name.len() as u32)?;
+    writer.write_all(name.as_bytes())
+}
+
+fn read_name(reader: &mut impl Read) -> io::Result<String> {
+    let length = read_u32(reader)? as usize;
+    if length > 64 {
+        return Err(invalid_data("name too long"));
+    }
+    let mut bytes = vec![0u8; length];
+    reader.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|_| invalid_data("name is not UTF-8"))
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sampler::Sampler;
+    use crate::scene_file::SceneDescription;
+    use crate::tiles::Tiling;
+    use std::ops::Range;
+
+    const SCENE: &str = "\
+background 0.2 0.3 0.5
+camera position 0 1 0 target 0 0.5 5 fov 60
+light key position 3 2 5
+plane ground point 0 -1 0 normal 0 1 0 color 0.5 0.5 0.5 reflectivity 0.1
+sphere blue center 0 0 5 radius 1 color 0.2 0.4 1 reflectivity 0.1
+";
+
+    fn accumulation(film: &Film) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        film.write_accumulation(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn settings(samples: u32, filter: Filter) -> Checkpoint {
+        let description: SceneDescription = SCENE.parse().unwrap();
+        Checkpoint {
+            width: 24,
+            height: 18,
+            samples,
+            num_frames: 1,
+            sampler: SamplerKind::Sobol,
+            filter,
+            tile_size: 8,
+            tile_order: TileOrder::Hilbert,
+            aovs: vec![Aov::Depth],
+            temporal: false,
+            scene_hash: content_hash(SCENE.as_bytes()),
+            camera_hash: camera_hash(&description.camera(24.0 / 18.0)),
+            frame_index: 0,
+            samples_done: 0,
+        }
+    }
+
+    #[test]
+    fn resumed_render_matches_uninterrupted_one() {
+        let description: SceneDescription = SCENE.parse().unwrap();
+        let scene = description.to_scene();
+        let camera = description.camera(24.0 / 18.0);
+        let filter = Filter::new(FilterKind::Gaussian, FilterKind::Gaussian.default_radius());
+        let settings = settings(6, filter);
+        let sampler = Sampler::new(settings.sampler, settings.samples);
+        let tiling = Tiling {
+            tile_size: settings.tile_size,
+            order: settings.tile_order,
+            ..Tiling::default()
+        };
+        let render = |film: &mut Film, samples: Range<u32>| {
+            for sample_index in samples {
+                scene.render_samples(film, &camera, &sampler, sample_index..sample_index + 1, &tiling);
+            }
+        };
+        let new_film = || Film::with_aovs(settings.width, settings.height, filter, &settings.aovs);
+
+        let mut uninterrupted = new_film();
+        render(&mut uninterrupted, 0..settings.samples);
+
+        let mut interrupted = new_film();
+        render(&mut interrupted, 0..2);
+        let checkpoint = Checkpoint { samples_done: 2, ..settings.clone() };
+        let dir = std::env::temp_dir().join(format!("rust-ray-tracing-checkpoint-{}", std::process::id()));
+        checkpoint.save(&dir, &interrupted).unwrap();
+        let loaded = Checkpoint::load(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let (loaded, mut resumed) = loaded.unwrap();
+        assert!(loaded.is_compatible(&settings));
+        assert_eq!(loaded.samples_done, 2);
+        render(&mut resumed, loaded.samples_done..settings.samples);
+        assert!(accumulation(&resumed) == accumulation(&uninterrupted));
+    }
+
+    #[test]
+    fn edited_scene_camera_or_tiling_is_incompatible() {
+        let settings = settings(4, Filter::new(FilterKind::Box, 0.5));
+        let edited_scene = Checkpoint {
+            scene_hash: content_hash(SCENE.replace("radius 1", "radius 2").as_bytes()),
+            ..settings.clone()
+        };
+        let mut camera: SceneDescription = SCENE.parse().unwrap();
+        camera.camera.fov = 50.0;
+        let edited_camera = Checkpoint {
+            camera_hash: camera_hash(&camera.camera(24.0 / 18.0)),
+            ..settings.clone()
+        };
+
+        assert!(settings.is_compatible(&settings.clone()));
+        assert!(!edited_scene.is_compatible(&settings));
+        assert!(!edited_camera.is_compatible(&settings));
+        assert!(!Checkpoint { tile_size: 16, ..settings.clone() }.is_compatible(&settings));
+        assert!(!Checkpoint { tile_order: TileOrder::Spiral, ..settings.clone() }.is_compatible(&settings));
+    }
+}
@@ -0,0 +1,88 @@
+use crate::cylinder::{hit_cap, local_tangents};
+use crate::hittable::{HitRecord, Hittable};
+use crate::ray::Ray;
+use crate::sphere::Material;
+use crate::transform::Frame;
+use crate::vector3d::Vector3D;
+use std::f64::consts::PI;
+
+/// Cone with its base disk at `frame.origin` and its apex `height` along
+/// `frame.w`, closed at the base
+#[derive(Debug, Clone, Copy)]
+pub struct Cone {
+    pub frame: Frame,
+    pub radius: f64,
+    pub height: f64,
+    pub material: Material,
+}
+
+impl Cone {
+    pub fn new(base: Vector3D, apex: Vector3D, radius: f64, material: Material) -> Cone {
+        let axis = apex - base;
+        Cone {
+            frame: Frame::from_axis(base, axis),
+            radius,
+            height: axis.magnitude(),
+            material,
+        }
+    }
+}
+
+impl Hittable for Cone {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let local = self.frame.ray_to_local(ray);
+        let (o, d) = (local.origin, local.direction);
+        let mut closest: Option<(f64, Vector3D, (f64, f64))> = None;
+        let mut closest_t = t_max;
+
+        // Side: x^2 + y^2 = (k (h - z))^2 with k = r / h, for 0 <= z <= h
+        let k = self.radius / self.height;
+        let k2 = k * k;
+        let height_left = self.height - o.z;
+        let a = d.x * d.x + d.y * d.y - k2 * d.z * d.z;
+        let half_b = o.x * d.x + o.y * d.y + k2 * height_left * d.z;
+        let c = o.x * o.x + o.y * o.y - k2 * height_left * height_left;
+
+        let mut roots = [f64::INFINITY; 2];
+        if a.abs() > 1e-12 {
+            let discriminant = half_b * half_b - a * c;
+            if discriminant >= 0.0 {
+                let sqrtd = discriminant.sqrt();
+                let (t0, t1) = ((-half_b - sqrtd) / a, (-half_b + sqrtd) / a);
+                roots = [t0.min(t1), t0.max(t1)];
+            }
+        } else if half_b != 0.0 {
+            // Ray parallel to the slope: it crosses the double cone once
+            roots[0] = -c / (2.0 * half_b);
+        }
+
+        for t in roots {
+            let z = o.z + d.z * t;
+            if t >= t_min && t <= closest_t && (0.0..=self.height).contains(&z) {
+                let point = o + d * t;
+                let rho = (point.x * point.x + point.y * point.y).sqrt();
+                let normal = Vector3D::new(point.x, point.y, k * rho).normalize();
+                let u = point.y.atan2(point.x) / (2.0 * PI) + 0.5;
+                closest = Some((t, normal, (u, z / self.height)));
+                closest_t = t;
+                break;
+            }
+        }
+
+        if let Some((t, uv)) = hit_cap(o, d, 0.0, self.radius, t_min, closest_t) {
+            closest = Some((t, Vector3D::new(0.0, 0.0, -1.0), uv));
+        }
+
+        let (t, normal, uv) = closest?;
+        let (tangent, bitangent) = local_tangents(normal);
+        Some(HitRecord {
+            point: ray.at(t),
+            normal: self.frame.to_world(normal),
+            t,
+            uv,
+            tangent: self.frame.to_world(tangent),
+            bitangent: self.frame.to_world(bitangent),
+            material: self.material,
+        })
+    }
+}
@@ -0,0 +1,250 @@
+use crate::hittable::{HitRecord, Hittable, Interval};
+use crate::ray::Ray;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CsgOperation {
+    Union,
+    Intersection,
+    /// Left minus right
+    Difference,
+}
+
+impl CsgOperation {
+    #[inline]
+    fn inside(self, in_left: bool, in_right: bool) -> bool {
+        match self {
+            CsgOperation::Union => in_left || in_right,
+            CsgOperation::Intersection => in_left && in_right,
+            CsgOperation::Difference => in_left && !in_right,
+        }
+    }
+}
+
+/// Boolean combination of two solids, e.g. a sphere with a box cut out or
+/// a lens from the intersection of two spheres.
+///
+/// Each boundary keeps the material of the child it came from, so carved
+/// cavities show the cutter's material. Nodes nest, since a `Csg` reports
+/// its own intervals. Children must be solids (see `Hittable::intervals`).
+pub struct Csg {
+    pub operation: CsgOperation,
+    pub left: Box<dyn Hittable>,
+    pub right: Box<dyn Hittable>,
+}
+
+/// A surface crossing of one child during the interval sweep
+struct Event {
+    record: HitRecord,
+    from_right: bool,
+    entering: bool,
+}
+
+impl Csg {
+    pub fn new(operation: CsgOperation, left: Box<dyn Hittable>, right: Box<dyn Hittable>) -> Csg {
+        Csg { operation, left, right }
+    }
+
+    pub fn union(left: Box<dyn Hittable>, right: Box<dyn Hittable>) -> Csg {
+        Csg::new(CsgOperation::Union, left, right)
+    }
+
+    pub fn intersection(left: Box<dyn Hittable>, right: Box<dyn Hittable>) -> Csg {
+        Csg::new(CsgOperation::Intersection, left, right)
+    }
+
+    pub fn difference(left: Box<dyn Hittable>, right: Box<dyn Hittable>) -> Csg {
+        Csg::new(CsgOperation::Difference, left, right)
+    }
+}
+
+impl Hittable for Csg {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.intervals(ray)
+            .into_iter()
+            .flat_map(|interval| [interval.enter, interval.exit])
+            .find(|boundary| boundary.t >= t_min && boundary.t <= t_max)
+    }
+
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        let left = self.left.intervals(ray);
+        if left.is_empty() && self.operation != CsgOperation::Union {
+            return left;
+        }
+        let right = self.right.intervals(ray);
+        if right.is_empty() && self.operation != CsgOperation::Intersection {
+            return left;
+        }
+        if left.is_empty() {
+            return right;
+        }
+
+        let mut events: Vec<Event> = Vec::with_capacity(2 * (left.len() + right.len()));
+        for (intervals, from_right) in [(&left, false), (&right, true)] {
+            for interval in intervals {
+                events.push(Event {
+                    record: interval.enter,
+                    from_right,
+                    entering: true,
+                });
+                events.push(Event {
+                    record: interval.exit,
+                    from_right,
+                    entering: false,
+                });
+            }
+        }
+        // Entries first on ties, so touching solids don't split a union
+        events.sort_by(|a, b| a.record.t.total_cmp(&b.record.t).then(b.entering.cmp(&a.entering)));
+
+        let mut combined = Vec::new();
+        let (mut in_left, mut in_right) = (false, false);
+        let mut enter: Option<HitRecord> = None;
+
+        for event in events {
+            let mut record = event.record;
+            // Surfaces of the cutter face into the cavity. The bitangent
+            // turns with the normal so the frame keeps its handedness.
+            if event.from_right && self.operation == CsgOperation::Difference {
+                record.normal = -record.normal;
+                record.bitangent = -record.bitangent;
+            }
+
+            let was_inside = self.operation.inside(in_left, in_right);
+            if event.from_right {
+                in_right = event.entering;
+            } else {
+                in_left = event.entering;
+            }
+            let inside = self.operation.inside(in_left, in_right);
+
+            if inside && !was_inside {
+                enter = Some(record);
+            } else if !inside && was_inside {
+                if let Some(enter) = enter.take() {
+                    if record.t > enter.t {
+                        combined.push(Interval { enter, exit: record });
+                    }
+                }
+            }
+        }
+
+        combined
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plane::Plane;
+    use crate::sphere::{Color, Material, Sphere};
+    use crate::vector3d::Vector3D;
+
+    fn material(r: f64, g: f64, b: f64) -> Material {
+        Material {
+            color: Color { r, g, b },
+            diffuse: 1.0,
+            specular: 0.0,
+            shininess: 1.0,
+            reflectivity: 0.0,
+            pbr: None,
+            emission: Color::default(),
+            texture: None,
+            normal_map: None,
+        }
+    }
+
+    /// Red sphere covering x in [-2, 2]
+    fn left() -> Box<dyn Hittable> {
+        Box::new(Sphere::new(Vector3D::ZERO, 2.0, material(1.0, 0.0, 0.0)))
+    }
+
+    /// Blue sphere covering x in [0, 4]
+    fn right() -> Box<dyn Hittable> {
+        Box::new(Sphere::new(Vector3D::new(2.0, 0.0, 0.0), 2.0, material(0.0, 0.0, 1.0)))
+    }
+
+    /// Half-space x < 0
+    fn half_space() -> Box<dyn Hittable> {
+        Box::new(Plane::new(Vector3D::ZERO, Vector3D::new(1.0, 0.0, 0.0), material(0.0, 1.0, 0.0)))
+    }
+
+    /// Ray along +x from x = -10, so `t` is x + 10
+    fn ray() -> Ray {
+        Ray::new(Vector3D::new(-10.0, 0.0, 0.0), Vector3D::new(1.0, 0.0, 0.0))
+    }
+
+    fn spans(solid: &dyn Hittable, ray: &Ray) -> Vec<(f64, f64)> {
+        solid.intervals(ray).iter().map(|interval| (interval.enter.t, interval.exit.t)).collect()
+    }
+
+    fn assert_spans(solid: &dyn Hittable, expected: &[(f64, f64)]) {
+        let actual = spans(solid, &ray());
+        assert_eq!(actual.len(), expected.len(), "{:?} != {:?}", actual, expected);
+        for (&(enter, exit), &(expected_enter, expected_exit)) in actual.iter().zip(expected) {
+            for (t, expected) in [(enter, expected_enter), (exit, expected_exit)] {
+                assert!(t == expected || (t - expected).abs() < 1e-9, "{:?} != {:?}", actual, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn overlapping_spheres() {
+        assert_spans(&Csg::union(left(), right()), &[(8.0, 14.0)]);
+        assert_spans(&Csg::intersection(left(), right()), &[(10.0, 12.0)]);
+        assert_spans(&Csg::difference(left(), right()), &[(8.0, 10.0)]);
+        assert_spans(&Csg::difference(right(), left()), &[(12.0, 14.0)]);
+    }
+
+    #[test]
+    fn separate_spheres() {
+        let far = || -> Box<dyn Hittable> { Box::new(Sphere::new(Vector3D::new(6.0, 0.0, 0.0), 1.0, material(1.0, 1.0, 1.0))) };
+        assert_spans(&Csg::union(left(), far()), &[(8.0, 12.0), (15.0, 17.0)]);
+        assert_spans(&Csg::intersection(left(), far()), &[]);
+        assert_spans(&Csg::difference(left(), far()), &[(8.0, 12.0)]);
+        // A CSG node is itself a solid, so nodes nest
+        assert_spans(&Csg::difference(Box::new(Csg::union(left(), far())), right()), &[(8.0, 10.0), (15.0, 17.0)]);
+    }
+
+    #[test]
+    fn difference_boundaries_come_from_the_cutter() {
+        let lens = Csg::difference(left(), right());
+        let interval = lens.intervals(&ray())[0];
+        assert_eq!(interval.enter.material.color.r, 1.0);
+        assert_eq!(interval.enter.normal, Vector3D::new(-1.0, 0.0, 0.0));
+        // The cavity wall is the blue sphere's surface, facing into the cavity
+        assert_eq!(interval.exit.material.color.b, 1.0);
+        let normal = interval.exit.normal;
+        assert!((normal.x - 1.0).abs() < 1e-9, "{:?}", normal);
+        // Its tangent frame keeps the handedness it has on the sphere itself
+        let handedness = |record: &HitRecord| record.tangent.cross(record.bitangent).dot(record.normal);
+        let own = right().intervals(&ray())[0].enter;
+        assert!((handedness(&interval.exit) - handedness(&own)).abs() < 1e-9);
+        assert_eq!(interval.exit.tangent, own.tangent);
+
+        let hit = |t_min| lens.hit(&ray(), t_min, f64::INFINITY).map(|hit| hit.t);
+        assert_eq!(hit(0.0), Some(8.0));
+        assert!((hit(9.0).unwrap() - 10.0).abs() < 1e-9);
+        assert_eq!(hit(11.0), None);
+    }
+
+    #[test]
+    fn sphere_and_half_space() {
+        let spans_to_infinity = spans(&*half_space(), &ray());
+        assert_eq!(spans_to_infinity.len(), 1);
+        assert_eq!(spans_to_infinity[0].0, f64::NEG_INFINITY);
+
+        assert_spans(&Csg::difference(left(), half_space()), &[(10.0, 12.0)]);
+        assert_spans(&Csg::intersection(left(), half_space()), &[(8.0, 10.0)]);
+        assert_spans(&Csg::union(left(), half_space()), &[(f64::NEG_INFINITY, 12.0)]);
+        assert_spans(&Csg::difference(half_space(), left()), &[(f64::NEG_INFINITY, 8.0)]);
+
+        // Running along the plane inside it, everything is cut away
+        let inside = Ray::new(Vector3D::new(-1.0, 0.0, -10.0), Vector3D::new(0.0, 0.0, 1.0));
+        assert_eq!(spans(&*half_space(), &inside), vec![(f64::NEG_INFINITY, f64::INFINITY)]);
+        assert!(spans(&Csg::difference(left(), half_space()), &inside).is_empty());
+
+        // The infinite boundary is never returned as a hit
+        let cut = Csg::union(left(), half_space());
+        assert_eq!(cut.hit(&ray(), 0.0, f64::INFINITY).map(|hit| hit.t), Some(12.0));
+    }
+}
@@ -0,0 +1,140 @@
+use crate::hittable::{HitRecord, Hittable};
+use crate::ray::Ray;
+use crate::sphere::Material;
+use crate::transform::Frame;
+use crate::vector3d::{Quaternion, Vector3D};
+
+/// Box spanning `min` to `max` along the world axes
+#[derive(Debug, Clone, Copy)]
+pub struct AxisAlignedBox {
+    pub min: Vector3D,
+    pub max: Vector3D,
+    pub material: Material,
+}
+
+impl AxisAlignedBox {
+    pub fn new(a: Vector3D, b: Vector3D, material: Material) -> AxisAlignedBox {
+        AxisAlignedBox {
+            min: a.min(b),
+            max: a.max(b),
+            material,
+        }
+    }
+
+    pub fn center(&self) -> Vector3D {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn half_extents(&self) -> Vector3D {
+        (self.max - self.min) * 0.5
+    }
+}
+
+impl Hittable for AxisAlignedBox {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let center = self.center();
+        let (t, normal, uv, tangent, bitangent) =
+            hit_box(ray.origin - center, ray.direction, self.half_extents(), t_min, t_max)?;
+
+        Some(HitRecord {
+            point: ray.at(t),
+            normal,
+            t,
+            uv,
+            tangent,
+            bitangent,
+            material: self.material,
+        })
+    }
+}
+
+/// Box with half extents `half_extents` along the axes of `frame`
+#[derive(Debug, Clone, Copy)]
+pub struct OrientedBox {
+    pub frame: Frame,
+    pub half_extents: Vector3D,
+    pub material: Material,
+}
+
+impl OrientedBox {
+    pub fn new(center: Vector3D, half_extents: Vector3D, rotation: Quaternion, material: Material) -> OrientedBox {
+        OrientedBox {
+            frame: Frame::from_rotation(center, rotation),
+            half_extents,
+            material,
+        }
+    }
+}
+
+impl Hittable for OrientedBox {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let local = self.frame.ray_to_local(ray);
+        let (t, normal, uv, tangent, bitangent) = hit_box(local.origin, local.direction, self.half_extents, t_min, t_max)?;
+
+        Some(HitRecord {
+            point: ray.at(t),
+            normal: self.frame.to_world(normal),
+            t,
+            uv,
+            tangent: self.frame.to_world(tangent),
+            bitangent: self.frame.to_world(bitangent),
+            material: self.material,
+        })
+    }
+}
+
+/// Distance, face normal, UVs, tangent and bitangent of a box hit
+type BoxHit = (f64, Vector3D, (f64, f64), Vector3D, Vector3D);
+
+/// Slab test against a box centred on the origin. Returns the distance, the
+/// face normal and the face's UVs, which run from 0 to 1 across each face,
+/// with the axes they run along.
+#[inline]
+fn hit_box(
+    origin: Vector3D,
+    direction: Vector3D,
+    half_extents: Vector3D,
+    t_min: f64,
+    t_max: f64,
+) -> Option<BoxHit> {
+    let mut near = f64::NEG_INFINITY;
+    let mut far = f64::INFINITY;
+
+    for axis in 0..3 {
+        let inv_direction = 1.0 / direction[axis];
+        let t0 = (-half_extents[axis] - origin[axis]) * inv_direction;
+        let t1 = (half_extents[axis] - origin[axis]) * inv_direction;
+        near = near.max(t0.min(t1));
+        far = far.min(t0.max(t1));
+    }
+
+    if near > far {
+        return None;
+    }
+    let t = if near >= t_min && near <= t_max {
+        near
+    } else if far >= t_min && far <= t_max {
+        far
+    } else {
+        return None;
+    };
+
+    // The face is on the axis where the point is relatively furthest out
+    let point = origin + direction * t;
+    let relative = (point / half_extents).abs();
+    let axis = relative.max_dimension();
+    let mut normal = Vector3D::ZERO;
+    normal[axis] = point[axis].signum();
+
+    let (j, k) = ((axis + 1) % 3, (axis + 2) % 3);
+    let uv = (
+        0.5 + 0.5 * point[j] / half_extents[j],
+        0.5 + 0.5 * point[k] / half_extents[k],
+    );
+    let mut tangent = Vector3D::ZERO;
+    tangent[j] = 1.0;
+    let mut bitangent = Vector3D::ZERO;
+    bitangent[k] = 1.0;
+
+    Some((t, normal, uv, tangent, bitangent))
+}
@@ -0,0 +1,106 @@
+use crate::hittable::{tangent_frame, HitRecord, Hittable};
+use crate::ray::Ray;
+use crate::sphere::Material;
+use crate::transform::Frame;
+use crate::vector3d::Vector3D;
+use std::f64::consts::PI;
+
+/// Capped cylinder running from `frame.origin` along `frame.w` for `height`
+#[derive(Debug, Clone, Copy)]
+pub struct Cylinder {
+    pub frame: Frame,
+    pub radius: f64,
+    pub height: f64,
+    pub material: Material,
+}
+
+impl Cylinder {
+    /// Cylinder between the centres of its two caps
+    pub fn new(base: Vector3D, top: Vector3D, radius: f64, material: Material) -> Cylinder {
+        let axis = top - base;
+        Cylinder {
+            frame: Frame::from_axis(base, axis),
+            radius,
+            height: axis.magnitude(),
+            material,
+        }
+    }
+}
+
+impl Hittable for Cylinder {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let local = self.frame.ray_to_local(ray);
+        let (o, d) = (local.origin, local.direction);
+        let mut closest: Option<(f64, Vector3D, (f64, f64))> = None;
+        let mut closest_t = t_max;
+
+        // Side: x^2 + y^2 = r^2 between the caps
+        let a = d.x * d.x + d.y * d.y;
+        if a > 0.0 {
+            let half_b = o.x * d.x + o.y * d.y;
+            let c = o.x * o.x + o.y * o.y - self.radius * self.radius;
+            let discriminant = half_b * half_b - a * c;
+            if discriminant >= 0.0 {
+                let sqrtd = discriminant.sqrt();
+                for t in [(-half_b - sqrtd) / a, (-half_b + sqrtd) / a] {
+                    let z = o.z + d.z * t;
+                    if t >= t_min && t <= closest_t && (0.0..=self.height).contains(&z) {
+                        let point = o + d * t;
+                        let normal = Vector3D::new(point.x, point.y, 0.0) * (1.0 / self.radius);
+                        let u = point.y.atan2(point.x) / (2.0 * PI) + 0.5;
+                        closest = Some((t, normal, (u, z / self.height)));
+                        closest_t = t;
+                        break;
+                    }
+                }
+            }
+        }
+
+        for (z, normal_z) in [(0.0, -1.0), (self.height, 1.0)] {
+            if let Some((t, uv)) = hit_cap(o, d, z, self.radius, t_min, closest_t) {
+                closest = Some((t, Vector3D::new(0.0, 0.0, normal_z), uv));
+                closest_t = t;
+            }
+        }
+
+        let (t, normal, uv) = closest?;
+        let (tangent, bitangent) = local_tangents(normal);
+        Some(HitRecord {
+            point: ray.at(t),
+            normal: self.frame.to_world(normal),
+            t,
+            uv,
+            tangent: self.frame.to_world(tangent),
+            bitangent: self.frame.to_world(bitangent),
+            material: self.material,
+        })
+    }
+}
+
+/// Tangent frame in the local space of a cylinder or cone: around the axis
+/// and up it on the side, along x and y on the caps, matching their UVs
+#[inline]
+pub(crate) fn local_tangents(normal: Vector3D) -> (Vector3D, Vector3D) {
+    if normal.x == 0.0 && normal.y == 0.0 {
+        return (Vector3D::new(1.0, 0.0, 0.0), Vector3D::new(0.0, 1.0, 0.0));
+    }
+    tangent_frame(normal, Vector3D::new(-normal.y, normal.x, 0.0), Vector3D::new(0.0, 0.0, 1.0))
+}
+
+/// Disk of `radius` in the local plane at height `z`, with planar UVs
+#[inline]
+pub(crate) fn hit_cap(o: Vector3D, d: Vector3D, z: f64, radius: f64, t_min: f64, t_max: f64) -> Option<(f64, (f64, f64))> {
+    if d.z == 0.0 {
+        return None;
+    }
+    let t = (z - o.z) / d.z;
+    if t < t_min || t > t_max {
+        return None;
+    }
+    let x = o.x + d.x * t;
+    let y = o.y + d.y * t;
+    if x * x + y * y > radius * radius {
+        return None;
+    }
+    Some((t, (0.5 + 0.5 * x / radius, 0.5 + 0.5 * y / radius)))
+}
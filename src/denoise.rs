@@ -0,0 +1,216 @@
+use crate::aov::Aov;
+use crate::framebuffer::Framebuffer;
+use rayon::prelude::*;
+
+/// B3-spline taps of the à-trous kernel
+const KERNEL: [f32; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+/// AOVs used to keep the filter from blurring across edges
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Guides<'a> {
+    pub normal: Option<&'a Framebuffer>,
+    pub albedo: Option<&'a Framebuffer>,
+    pub depth: Option<&'a Framebuffer>,
+}
+
+impl<'a> Guides<'a> {
+    /// Picks the normal, albedo and depth images out of a render's AOVs
+    pub fn from_aovs(aovs: &'a [(Aov, Framebuffer)]) -> Guides<'a> {
+        let find = |wanted: Aov| aovs.iter().find(|(aov, _)| *aov == wanted).map(|(_, image)| image);
+        Guides {
+            normal: find(Aov::Normal),
+            albedo: find(Aov::Albedo),
+            depth: find(Aov::Depth),
+        }
+    }
+}
+
+/// Edge-avoiding à-trous wavelet filter (Dammertz et al. 2010).
+///
+/// Each iteration applies a 5x5 B3-spline kernel whose taps are spread
+/// `2^i` pixels apart, weighting every tap by how similar its colour and
+/// guide values are to the centre pixel. The colour tolerance halves every
+/// iteration so later, wider passes only smooth what is left of the noise.
+///
+/// The defaults suit this renderer's low-spp output, where the noise is
+/// mostly jittered edge coverage: a single pass (a joint bilateral filter)
+/// helps there, while extra passes start to soften the edges themselves.
+#[derive(Debug, Clone, Copy)]
+pub struct Denoiser {
+    pub iterations: u32,
+    /// Colour difference at which a tap's weight falls to `1/e`
+    pub sigma_color: f32,
+    /// Exponent applied to the cosine between normals
+    pub normal_power: f32,
+    pub sigma_albedo: f32,
+    /// Depth difference tolerance, relative to the centre depth and tap spacing
+    pub sigma_depth: f32,
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Denoiser {
+            iterations: 1,
+            sigma_color: 0.5,
+            normal_power: 64.0,
+            sigma_albedo: 0.2,
+            sigma_depth: 0.01,
+        }
+    }
+}
+
+impl Denoiser {
+    /// Returns a filtered copy of the first three channels of `image`
+    pub fn denoise(&self, image: &Framebuffer, guides: &Guides) -> Framebuffer {
+        let width = image.width as usize;
+        let height = image.height as usize;
+        let mut current = vec3_pixels(image);
+
+        let normal = guides.normal.map(vec3_pixels);
+        let albedo = guides.albedo.map(vec3_pixels);
+        let depth: Option<Vec<f32>> = guides
+            .depth
+            .map(|depth| (0..depth.height).flat_map(|y| depth.row(y).iter().copied()).collect());
+
+        let mut sigma_color = self.sigma_color;
+        for iteration in 0..self.iterations {
+            let step = 1i64 << iteration;
+            let mut next = vec![[0.0f32; 3]; current.len()];
+
+            next.par_chunks_mut(width).enumerate().for_each(|(y, row)| {
+                for (x, out) in row.iter_mut().enumerate() {
+                    let center = y * width + x;
+                    let center_color = current[center];
+                    let mut sum = [0.0f32; 3];
+                    let mut weight_sum = 0.0f32;
+
+                    for (ky, kernel_y) in KERNEL.iter().enumerate() {
+                        let qy = y as i64 + (ky as i64 - 2) * step;
+                        if qy < 0 || qy >= height as i64 {
+                            continue;
+                        }
+                        for (kx, kernel_x) in KERNEL.iter().enumerate() {
+                            let qx = x as i64 + (kx as i64 - 2) * step;
+                            if qx < 0 || qx >= width as i64 {
+                                continue;
+                            }
+                            let tap = qy as usize * width + qx as usize;
+
+                            let mut weight = kernel_x * kernel_y
+                                * (-distance_squared(center_color, current[tap]) / (sigma_color * sigma_color)).exp();
+
+                            if let Some(normal) = &normal {
+                                weight *= self.normal_weight(normal[center], normal[tap]);
+                            }
+                            if let Some(albedo) = &albedo {
+                                weight *= (-distance_squared(albedo[center], albedo[tap])
+                                    / (self.sigma_albedo * self.sigma_albedo))
+                                    .exp();
+                            }
+                            if let Some(depth) = &depth {
+                                let tolerance = self.sigma_depth * step as f32 * depth[center].max(1e-3);
+                                weight *= (-(depth[center] - depth[tap]).abs() / tolerance).exp();
+                            }
+
+                            let color = current[tap];
+                            sum[0] += color[0] * weight;
+                            sum[1] += color[1] * weight;
+                            sum[2] += color[2] * weight;
+                            weight_sum += weight;
+                        }
+                    }
+
+                    *out = if weight_sum > 0.0 {
+                        [sum[0] / weight_sum, sum[1] / weight_sum, sum[2] / weight_sum]
+                    } else {
+                        center_color
+                    };
+                }
+            });
+
+            current = next;
+            sigma_color *= 0.5;
+        }
+
+        let data = current.into_iter().flatten().collect();
+        Framebuffer::from_raw(image.width, image.height, 3, width * 3, data).expect("denoised image has the input's size")
+    }
+
+    /// Misses have a zero normal; they match each other but no surface
+    #[inline]
+    fn normal_weight(&self, a: [f32; 3], b: [f32; 3]) -> f32 {
+        if a == [0.0; 3] && b == [0.0; 3] {
+            return 1.0;
+        }
+        dot(a, b).max(0.0).powf(self.normal_power)
+    }
+}
+
+fn vec3_pixels(image: &Framebuffer) -> Vec<[f32; 3]> {
+    (0..image.height)
+        .flat_map(|y| image.row(y).chunks_exact(image.channels).map(|pixel| [pixel[0], pixel[1], pixel[2]]))
+        .collect()
+}
+
+#[inline]
+fn distance_squared(a: [f32; 3], b: [f32; 3]) -> f32 {
+    let d = [a[0] - b[0], a[1] - b[1], a[2] - b[2]];
+    d[0] * d[0] + d[1] * d[1] + d[2] * d[2]
+}
+
+#[inline]
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 8x8 image split down the middle, with a checkerboard of noise
+    fn split(left: f32, right: f32, noise: f32) -> Framebuffer {
+        let mut image = Framebuffer::new(8, 8, 3);
+        for y in 0..8 {
+            for x in 0..8 {
+                let base = if x < 4 { left } else { right };
+                let value = if (x + y) % 2 == 0 { base + noise } else { base - noise };
+                image.pixel_mut(x, y).fill(value);
+            }
+        }
+        image
+    }
+
+    #[test]
+    fn guides_stop_the_filter_at_edges() {
+        let image = split(0.2, 0.8, 0.1);
+        // Facing the camera on the left, sideways on the right
+        let mut normals = Framebuffer::new(8, 8, 3);
+        for y in 0..8 {
+            for x in 0..8 {
+                normals.pixel_mut(x, y).copy_from_slice(if x < 4 { &[0.0, 0.0, -1.0] } else { &[1.0, 0.0, 0.0] });
+            }
+        }
+        let denoiser = Denoiser {
+            sigma_color: 10.0,
+            ..Denoiser::default()
+        };
+
+        let guided = denoiser.denoise(&image, &Guides { normal: Some(&normals), ..Guides::default() });
+        let unguided = denoiser.denoise(&image, &Guides::default());
+        for y in 1..7 {
+            // The noise is smoothed away on either side
+            assert!((guided.pixel(1, y)[0] - 0.2).abs() < 0.05, "{:?}", guided.pixel(1, y));
+            assert!((guided.pixel(6, y)[0] - 0.8).abs() < 0.05, "{:?}", guided.pixel(6, y));
+            // but only the unguided filter blurs across the edge
+            assert!((guided.pixel(3, y)[0] - 0.2).abs() < 0.1, "{:?}", guided.pixel(3, y));
+            assert!(unguided.pixel(3, y)[0] > 0.35, "{:?}", unguided.pixel(3, y));
+        }
+    }
+
+    #[test]
+    fn flat_images_are_left_alone() {
+        let image = split(0.5, 0.5, 0.0);
+        let denoised = Denoiser { iterations: 3, ..Denoiser::default() }.denoise(&image, &Guides::default());
+        assert!(denoised.as_slice().iter().all(|&value| (value - 0.5).abs() < 1e-6));
+    }
+}
@@ -0,0 +1,56 @@
+use crate::cylinder::hit_cap;
+use crate::hittable::{EmissionSample, HitRecord, Hittable};
+use crate::ray::Ray;
+use crate::rectangle::area_sample;
+use crate::sphere::Material;
+use crate::transform::Frame;
+use crate::vector3d::Vector3D;
+use std::f64::consts::PI;
+
+/// Flat disk facing `frame.w`
+#[derive(Debug, Clone, Copy)]
+pub struct Disk {
+    pub frame: Frame,
+    pub radius: f64,
+    pub material: Material,
+}
+
+impl Disk {
+    pub fn new(center: Vector3D, normal: Vector3D, radius: f64, material: Material) -> Disk {
+        Disk {
+            frame: Frame::from_axis(center, normal),
+            radius,
+            material,
+        }
+    }
+}
+
+impl Hittable for Disk {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let local = self.frame.ray_to_local(ray);
+        let (t, uv) = hit_cap(local.origin, local.direction, 0.0, self.radius, t_min, t_max)?;
+
+        Some(HitRecord {
+            point: ray.at(t),
+            normal: self.frame.w,
+            t,
+            uv,
+            tangent: self.frame.u,
+            bitangent: self.frame.v,
+            material: self.material,
+        })
+    }
+
+    /// Uniform over the area; both faces emit
+    fn sample_emission(&self, origin: Vector3D, u1: f64, u2: f64) -> Option<EmissionSample> {
+        if !self.material.is_emissive() {
+            return None;
+        }
+
+        let r = self.radius * u1.sqrt();
+        let phi = 2.0 * PI * u2;
+        let point = self.frame.origin + self.frame.u * (r * phi.cos()) + self.frame.v * (r * phi.sin());
+        let area = PI * self.radius * self.radius;
+        area_sample(point, self.frame.w, area, origin, self.material.emission)
+    }
+}
@@ -0,0 +1,568 @@
+use crate::aov::{Aov, AovSample};
+use crate::framebuffer::Framebuffer;
+use crate::sphere::Color;
+use crate::tiles::Tile;
+use std::io::{self, Read, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterKind {
+    Box,
+    Tent,
+    Gaussian,
+    MitchellNetravali,
+    Lanczos,
+}
+
+impl FilterKind {
+    pub fn from_name(name: &str) -> Option<FilterKind> {
+        match name {
+            "box" => Some(FilterKind::Box),
+            "tent" | "triangle" => Some(FilterKind::Tent),
+            "gaussian" => Some(FilterKind::Gaussian),
+            "mitchell" => Some(FilterKind::MitchellNetravali),
+            "lanczos" => Some(FilterKind::Lanczos),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            FilterKind::Box => "box",
+            FilterKind::Tent => "tent",
+            FilterKind::Gaussian => "gaussian",
+            FilterKind::MitchellNetravali => "mitchell",
+            FilterKind::Lanczos => "lanczos",
+        }
+    }
+
+    /// Radius in pixels used when none is given
+    pub fn default_radius(self) -> f64 {
+        match self {
+            FilterKind::Box => 0.5,
+            FilterKind::Tent => 1.0,
+            FilterKind::Gaussian => 1.5,
+            FilterKind::MitchellNetravali => 2.0,
+            FilterKind::Lanczos => 3.0,
+        }
+    }
+}
+
+/// Separable pixel reconstruction filter
+#[derive(Debug, Clone, Copy)]
+pub struct Filter {
+    pub kind: FilterKind,
+    pub radius: f64,
+}
+
+impl Filter {
+    pub fn new(kind: FilterKind, radius: f64) -> Filter {
+        Filter {
+            kind,
+            radius: radius.max(0.5),
+        }
+    }
+
+    #[inline]
+    pub fn evaluate(&self, dx: f64, dy: f64) -> f64 {
+        self.evaluate_1d(dx) * self.evaluate_1d(dy)
+    }
+
+    #[inline]
+    fn evaluate_1d(&self, d: f64) -> f64 {
+        // Half-open support so a sample on a pixel edge lands in one pixel only
+        if d < -self.radius || d >= self.radius {
+            return 0.0;
+        }
+        let d = d.abs();
+
+        match self.kind {
+            FilterKind::Box => 1.0,
+            FilterKind::Tent => self.radius - d,
+            FilterKind::Gaussian => {
+                const ALPHA: f64 = 2.0;
+                ((-ALPHA * d * d).exp() - (-ALPHA * self.radius * self.radius).exp()).max(0.0)
+            }
+            FilterKind::MitchellNetravali => {
+                const B: f64 = 1.0 / 3.0;
+                const C: f64 = 1.0 / 3.0;
+                let x = 2.0 * d / self.radius;
+                if x > 1.0 {
+                    ((-B - 6.0 * C) * x * x * x + (6.0 * B + 30.0 * C) * x * x
+                        + (-12.0 * B - 48.0 * C) * x
+                        + (8.0 * B + 24.0 * C))
+                        / 6.0
+                } else {
+                    ((12.0 - 9.0 * B - 6.0 * C) * x * x * x
+                        + (-18.0 + 12.0 * B + 6.0 * C) * x * x
+                        + (6.0 - 2.0 * B))
+                        / 6.0
+                }
+            }
+            FilterKind::Lanczos => sinc(d) * sinc(d / self.radius),
+        }
+    }
+}
+
+#[inline]
+fn sinc(x: f64) -> f64 {
+    if x < 1e-5 {
+        return 1.0;
+    }
+    let px = std::f64::consts::PI * x;
+    px.sin() / px
+}
+
+/// Weighted accumulation for a rectangular region of the film.
+///
+/// Samples splat into every pixel whose centre lies within the filter radius,
+/// so a tile covers its own pixels plus a border of `ceil(radius)` pixels.
+pub struct FilmTile {
+    x0: i64,
+    y0: i64,
+    width: usize,
+    height: usize,
+    filter: Filter,
+    sums: Vec<Color>,
+    weights: Vec<f64>,
+    /// Unweighted sums and counts of the samples inside each pixel, the
+    /// fallback where negative filter lobes cancel out the weights
+    box_sums: Vec<Color>,
+    box_counts: Vec<u32>,
+    aovs: AovBuffer,
+}
+
+impl FilmTile {
+    /// Whether the film this tile belongs to records any AOVs
+    #[inline]
+    pub fn has_aovs(&self) -> bool {
+        !self.aovs.layers.is_empty()
+    }
+
+    /// Records the AOVs of a sample taken inside pixel (`px`, `py`).
+    ///
+    /// AOVs are not filtered: each pixel averages its own samples with equal
+    /// weight, so depth and IDs never blend across neighbouring pixels.
+    /// `None` is a sample the lens can't see, which counts as zero in the
+    /// averages but never becomes the pixel's object ID.
+    #[inline]
+    pub fn add_aov_sample(&mut self, px: u32, py: u32, sample: Option<&AovSample>) {
+        // Samples around a cropped film land outside it
+        let (x, y) = (px as i64 - self.x0, py as i64 - self.y0);
+        if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 {
+            return;
+        }
+        let index = y as usize * self.width + x as usize;
+        self.aovs.add(index, sample);
+    }
+
+    /// `x`, `y` are the continuous film coordinates of the sample
+    #[inline]
+    pub fn add_sample(&mut self, x: f64, y: f64, color: Color) {
+        let (px, py) = (x.floor() as i64 - self.x0, y.floor() as i64 - self.y0);
+        if px >= 0 && py >= 0 && px < self.width as i64 && py < self.height as i64 {
+            let index = py as usize * self.width + px as usize;
+            self.box_sums[index] = self.box_sums[index] + color;
+            self.box_counts[index] += 1;
+        }
+
+        let radius = self.filter.radius;
+        let min_x = ((x - radius - 0.5).ceil() as i64).max(self.x0);
+        let max_x = ((x + radius - 0.5).floor() as i64).min(self.x0 + self.width as i64 - 1);
+        let min_y = ((y - radius - 0.5).ceil() as i64).max(self.y0);
+        let max_y = ((y + radius - 0.5).floor() as i64).min(self.y0 + self.height as i64 - 1);
+
+        for py in min_y..=max_y {
+            for px in min_x..=max_x {
+                let weight = self
+                    .filter
+                    .evaluate(x - (px as f64 + 0.5), y - (py as f64 + 0.5));
+                if weight == 0.0 {
+                    continue;
+                }
+
+                let index = (py - self.y0) as usize * self.width + (px - self.x0) as usize;
+                self.sums[index] = self.sums[index] + color * weight;
+                self.weights[index] += weight;
+            }
+        }
+    }
+}
+
+/// Image-sized reconstruction buffer that tiles are merged into.
+///
+/// A cropped film only records the pixels of `crop`, but `width` and
+/// `height` stay those of the whole image so the camera frames it the same.
+pub struct Film {
+    pub width: u32,
+    pub height: u32,
+    pub filter: Filter,
+    /// Pixels the film records
+    pub crop: Tile,
+    sums: Vec<Color>,
+    weights: Vec<f64>,
+    box_sums: Vec<Color>,
+    box_counts: Vec<u32>,
+    aovs: AovBuffer,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32, filter: Filter) -> Film {
+        Film::with_aovs(width, height, filter, &[])
+    }
+
+    /// Film that also accumulates the given AOVs
+    pub fn with_aovs(width: u32, height: u32, filter: Filter, aovs: &[Aov]) -> Film {
+        let crop = Tile {
+            x0: 0,
+            y0: 0,
+            x1: width,
+            y1: height,
+        };
+        Film::cropped(width, height, filter, aovs, crop)
+    }
+
+    /// Film recording only the `crop` pixels of a `width` x `height` image
+    pub fn cropped(width: u32, height: u32, filter: Filter, aovs: &[Aov], crop: Tile) -> Film {
+        let crop = Tile {
+            x0: crop.x0.min(width),
+            y0: crop.y0.min(height),
+            x1: crop.x1.min(width),
+            y1: crop.y1.min(height),
+        };
+        let count = (crop.width() * crop.height()) as usize;
+        Film {
+            width,
+            height,
+            filter,
+            crop,
+            sums: vec![Color::default(); count],
+            weights: vec![0.0; count],
+            box_sums: vec![Color::default(); count],
+            box_counts: vec![0; count],
+            aovs: AovBuffer::new(aovs, count),
+        }
+    }
+
+    pub fn aovs(&self) -> &[Aov] {
+        &self.aovs.layers
+    }
+
+    /// Pixels to take samples in: the crop plus the filter's reach around
+    /// it, so its edges get the same samples as in a full render
+    pub fn sample_region(&self) -> Tile {
+        let border = self.filter.radius.ceil() as u32;
+        Tile {
+            x0: self.crop.x0.saturating_sub(border),
+            y0: self.crop.y0.saturating_sub(border),
+            x1: (self.crop.x1 + border).min(self.width),
+            y1: (self.crop.y1 + border).min(self.height),
+        }
+    }
+
+    /// Creates an empty tile for samples taken in pixels `[x0, x1) x [y0, y1)`
+    pub fn tile(&self, x0: u32, y0: u32, x1: u32, y1: u32) -> FilmTile {
+        let border = self.filter.radius.ceil() as i64;
+        let tile_x0 = (x0 as i64 - border).max(self.crop.x0 as i64);
+        let tile_y0 = (y0 as i64 - border).max(self.crop.y0 as i64);
+        let tile_x1 = (x1 as i64 + border).min(self.crop.x1 as i64).max(tile_x0);
+        let tile_y1 = (y1 as i64 + border).min(self.crop.y1 as i64).max(tile_y0);
+        let width = (tile_x1 - tile_x0) as usize;
+        let height = (tile_y1 - tile_y0) as usize;
+
+        FilmTile {
+            x0: tile_x0,
+            y0: tile_y0,
+            width,
+            height,
+            filter: self.filter,
+            sums: vec![Color::default(); width * height],
+            weights: vec![0.0; width * height],
+            box_sums: vec![Color::default(); width * height],
+            box_counts: vec![0; width * height],
+            aovs: AovBuffer::new(&self.aovs.layers, width * height),
+        }
+    }
+
+    pub fn merge_tile(&mut self, tile: &FilmTile) {
+        for ty in 0..tile.height {
+            let row = (tile.y0 as usize + ty - self.crop.y0 as usize) * self.crop.width() as usize
+                + (tile.x0 as usize - self.crop.x0 as usize);
+            for tx in 0..tile.width {
+                let index = ty * tile.width + tx;
+                self.sums[row + tx] = self.sums[row + tx] + tile.sums[index];
+                self.weights[row + tx] += tile.weights[index];
+                self.box_sums[row + tx] = self.box_sums[row + tx] + tile.box_sums[index];
+                self.box_counts[row + tx] += tile.box_counts[index];
+                self.aovs.merge(row + tx, &tile.aovs, index);
+            }
+        }
+    }
+
+    /// Writes the raw weighted sums, bit-exact, so accumulation can continue later
+    pub fn write_accumulation(&self, writer: &mut impl Write) -> io::Result<()> {
+        for (sum, weight) in self.sums.iter().zip(&self.weights) {
+            writer.write_all(&sum.r.to_le_bytes())?;
+            writer.write_all(&sum.g.to_le_bytes())?;
+            writer.write_all(&sum.b.to_le_bytes())?;
+            writer.write_all(&weight.to_le_bytes())?;
+        }
+        for (sum, count) in self.box_sums.iter().zip(&self.box_counts) {
+            writer.write_all(&sum.r.to_le_bytes())?;
+            writer.write_all(&sum.g.to_le_bytes())?;
+            writer.write_all(&sum.b.to_le_bytes())?;
+            writer.write_all(&count.to_le_bytes())?;
+        }
+        for value in &self.aovs.values {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        for count in self.aovs.counts.iter().chain(&self.aovs.seen) {
+            writer.write_all(&count.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Counterpart of `write_accumulation` for a film of the same size
+    pub fn read_accumulation(&mut self, reader: &mut impl Read) -> io::Result<()> {
+        fn read_f64(reader: &mut impl Read) -> io::Result<f64> {
+            let mut bytes = [0u8; 8];
+            reader.read_exact(&mut bytes)?;
+            Ok(f64::from_le_bytes(bytes))
+        }
+        fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+            let mut bytes = [0u8; 4];
+            reader.read_exact(&mut bytes)?;
+            Ok(u32::from_le_bytes(bytes))
+        }
+
+        for index in 0..self.sums.len() {
+            self.sums[index] = Color {
+                r: read_f64(reader)?,
+                g: read_f64(reader)?,
+                b: read_f64(reader)?,
+            };
+            self.weights[index] = read_f64(reader)?;
+        }
+        for index in 0..self.box_sums.len() {
+            self.box_sums[index] = Color {
+                r: read_f64(reader)?,
+                g: read_f64(reader)?,
+                b: read_f64(reader)?,
+            };
+            self.box_counts[index] = read_u32(reader)?;
+        }
+        for index in 0..self.aovs.values.len() {
+            self.aovs.values[index] = read_f64(reader)?;
+        }
+        for index in 0..self.aovs.counts.len() {
+            self.aovs.counts[index] = read_u32(reader)?;
+        }
+        for index in 0..self.aovs.seen.len() {
+            self.aovs.seen[index] = read_u32(reader)?;
+        }
+        Ok(())
+    }
+
+    /// Normalises the accumulated samples and clamps to displayable range.
+    /// Pixels whose filter weights sum to zero or less, which Mitchell and
+    /// Lanczos lobes can do, fall back to the plain mean of their own
+    /// samples. The image is the size of the crop.
+    pub fn resolve(&self) -> Framebuffer {
+        let (width, height) = (self.crop.width(), self.crop.height());
+        let mut image = Framebuffer::new(width, height, 3);
+
+        for y in 0..height {
+            for x in 0..width {
+                let index = (y * width + x) as usize;
+                let weight = self.weights[index];
+                let count = self.box_counts[index];
+                let mut color = if weight > 0.0 {
+                    self.sums[index] * (1.0 / weight)
+                } else if count > 0 {
+                    self.box_sums[index] * (1.0 / count as f64)
+                } else {
+                    Color::default()
+                };
+
+                color.r = color.r.clamp(0.0, 1.0);
+                color.g = color.g.clamp(0.0, 1.0);
+                color.b = color.b.clamp(0.0, 1.0);
+                image.set_color(x, y, color);
+            }
+        }
+
+        image
+    }
+
+    /// Averaged AOV images, unclamped, in the order the film was created with
+    pub fn resolve_aovs(&self) -> Vec<(Aov, Framebuffer)> {
+        if self.aovs.layers.is_empty() {
+            return Vec::new();
+        }
+        let mut images: Vec<(Aov, Framebuffer)> = self
+            .aovs
+            .layers
+            .iter()
+            .map(|&aov| (aov, Framebuffer::new(self.crop.width(), self.crop.height(), aov.channels())))
+            .collect();
+
+        for y in 0..self.crop.height() {
+            for x in 0..self.crop.width() {
+                let index = (y * self.crop.width() + x) as usize;
+                let count = self.aovs.counts[index];
+                let pixel = self.aovs.pixel(index);
+                let mut offset = 0;
+
+                for (aov, image) in &mut images {
+                    let scale = if aov.is_averaged() && count > 0 { 1.0 / count as f64 } else { 1.0 };
+                    for (out, value) in image.pixel_mut(x, y).iter_mut().zip(&pixel[offset..]) {
+                        *out = (value * scale) as f32;
+                    }
+                    offset += aov.channels();
+                }
+            }
+        }
+
+        images
+    }
+}
+
+/// Per-pixel AOV sums and sample counts, channels of all layers interleaved
+struct AovBuffer {
+    layers: Vec<Aov>,
+    channels: usize,
+    values: Vec<f64>,
+    counts: Vec<u32>,
+    /// Samples the lens could see, the only ones object IDs are taken from
+    seen: Vec<u32>,
+}
+
+impl AovBuffer {
+    fn new(layers: &[Aov], pixels: usize) -> AovBuffer {
+        let channels = layers.iter().map(|aov| aov.channels()).sum();
+        let pixels = if layers.is_empty() { 0 } else { pixels };
+        AovBuffer {
+            layers: layers.to_vec(),
+            channels,
+            values: vec![0.0; pixels * channels],
+            counts: vec![0; pixels],
+            seen: vec![0; pixels],
+        }
+    }
+
+    #[inline]
+    fn pixel(&self, index: usize) -> &[f64] {
+        &self.values[index * self.channels..(index + 1) * self.channels]
+    }
+
+    #[inline]
+    fn add(&mut self, index: usize, sample: Option<&AovSample>) {
+        self.counts[index] += 1;
+        let Some(sample) = sample else {
+            return;
+        };
+        let first = self.seen[index] == 0;
+        self.seen[index] += 1;
+
+        let mut offset = index * self.channels;
+        let mut channels = [0.0; 3];
+        for &aov in &self.layers {
+            let count = aov.channels();
+            sample.write(aov, &mut channels[..count]);
+            for (value, channel) in self.values[offset..offset + count].iter_mut().zip(&channels) {
+                if aov.is_averaged() {
+                    *value += channel;
+                } else if first {
+                    *value = *channel;
+                }
+            }
+            offset += count;
+        }
+    }
+
+    /// Adds pixel `other_index` of `other` into pixel `index`
+    #[inline]
+    fn merge(&mut self, index: usize, other: &AovBuffer, other_index: usize) {
+        if self.layers.is_empty() || other.counts[other_index] == 0 {
+            return;
+        }
+        let first = self.seen[index] == 0 && other.seen[other_index] > 0;
+        self.counts[index] += other.counts[other_index];
+        self.seen[index] += other.seen[other_index];
+
+        let mut offset = 0;
+        for &aov in &self.layers {
+            for channel in offset..offset + aov.channels() {
+                let value = &mut self.values[index * self.channels + channel];
+                let other_value = other.values[other_index * self.channels + channel];
+                if aov.is_averaged() {
+                    *value += other_value;
+                } else if first {
+                    *value = other_value;
+                }
+            }
+            offset += aov.channels();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn color(r: f64, g: f64, b: f64) -> Color {
+        Color { r, g, b }
+    }
+
+    #[test]
+    fn pixels_with_negative_weight_fall_back_to_their_own_mean() {
+        let mut film = Film::new(4, 1, Filter::new(FilterKind::MitchellNetravali, 2.0));
+        let mut tile = film.tile(0, 0, 4, 1);
+        // Pixel 2's only sample sits in its corner, where the weight is
+        // small, and the samples in pixel 0 reach it through the negative lobe
+        tile.add_sample(2.0, 0.0, color(0.25, 0.5, 0.75));
+        for _ in 0..20 {
+            tile.add_sample(0.9, 0.5, color(1.0, 1.0, 1.0));
+        }
+        film.merge_tile(&tile);
+        assert!(film.weights[2] <= 0.0);
+
+        let image = film.resolve();
+        let fallback = image.color(2, 0);
+        assert!((fallback.r - 0.25).abs() < 1e-6);
+        assert!((fallback.g - 0.5).abs() < 1e-6);
+        assert!((fallback.b - 0.75).abs() < 1e-6);
+        // Pixels with positive weight are still filtered
+        assert!(film.weights[0] > 0.0);
+        assert!(image.color(0, 0).r > 0.9);
+    }
+
+    #[test]
+    fn object_id_comes_from_the_first_sample_the_lens_sees() {
+        let mut film = Film::with_aovs(2, 1, Filter::new(FilterKind::Box, 0.5), &[Aov::Depth, Aov::ObjectId]);
+        let hit = |object_id| AovSample {
+            depth: 2.0,
+            object_id,
+            ..AovSample::default()
+        };
+
+        // Pixel 0 is unseen in the first pass, pixel 1 in the second
+        let mut first_pass = film.tile(0, 0, 2, 1);
+        first_pass.add_aov_sample(0, 0, None);
+        first_pass.add_aov_sample(1, 0, Some(&hit(3)));
+        film.merge_tile(&first_pass);
+        let mut second_pass = film.tile(0, 0, 2, 1);
+        second_pass.add_aov_sample(0, 0, Some(&hit(5)));
+        second_pass.add_aov_sample(0, 0, Some(&hit(7)));
+        second_pass.add_aov_sample(1, 0, None);
+        film.merge_tile(&second_pass);
+
+        let aovs = film.resolve_aovs();
+        let (_, depth) = &aovs[0];
+        let (_, ids) = &aovs[1];
+        assert_eq!(ids.pixel(0, 0), &[5.0]);
+        assert_eq!(ids.pixel(1, 0), &[3.0]);
+        // Unseen samples still count as zero depth in the average
+        assert_eq!(depth.pixel(0, 0), &[4.0 / 3.0]);
+        assert_eq!(depth.pixel(1, 0), &[1.0]);
+    }
+}
@@ -0,0 +1,170 @@
+use crate::sphere::Color;
+
+/// Contiguous image with `channels` values per pixel.
+///
+/// Rows are `stride` values apart, which lets GPU readback buffers with
+/// padded pixels (e.g. RGBA for RGB data) be wrapped without repacking.
+#[derive(Debug, Clone)]
+pub struct Framebuffer<T = f32> {
+    pub width: u32,
+    pub height: u32,
+    pub channels: usize,
+    pub stride: usize,
+    data: Vec<T>,
+}
+
+impl<T: Copy + Default> Framebuffer<T> {
+    pub fn new(width: u32, height: u32, channels: usize) -> Framebuffer<T> {
+        let stride = width as usize * channels;
+        Framebuffer {
+            width,
+            height,
+            channels,
+            stride,
+            data: vec![T::default(); stride * height as usize],
+        }
+    }
+
+    /// Wraps existing pixel data, or returns `None` if it is too short
+    pub fn from_raw(
+        width: u32,
+        height: u32,
+        channels: usize,
+        stride: usize,
+        data: Vec<T>,
+    ) -> Option<Framebuffer<T>> {
+        let row_len = width as usize * channels;
+        if stride < row_len || (height > 0 && data.len() < stride * (height as usize - 1) + row_len) {
+            return None;
+        }
+
+        Some(Framebuffer {
+            width,
+            height,
+            channels,
+            stride,
+            data,
+        })
+    }
+
+    #[inline]
+    fn offset(&self, x: u32, y: u32) -> usize {
+        y as usize * self.stride + x as usize * self.channels
+    }
+
+    #[inline]
+    pub fn pixel(&self, x: u32, y: u32) -> &[T] {
+        let offset = self.offset(x, y);
+        &self.data[offset..offset + self.channels]
+    }
+
+    #[inline]
+    pub fn pixel_mut(&mut self, x: u32, y: u32) -> &mut [T] {
+        let offset = self.offset(x, y);
+        &mut self.data[offset..offset + self.channels]
+    }
+
+    /// Pixel values of row `y`, excluding any stride padding
+    #[inline]
+    pub fn row(&self, y: u32) -> &[T] {
+        let offset = y as usize * self.stride;
+        &self.data[offset..offset + self.width as usize * self.channels]
+    }
+
+    /// Whether rows are packed without padding
+    pub fn is_contiguous(&self) -> bool {
+        self.stride == self.width as usize * self.channels
+    }
+
+    /// Pastes `region` over this image with its top left corner at (`x0`, `y0`).
+    ///
+    /// Only the channels both images have are copied, so an RGBA render can
+    /// go into an RGB image; anything falling outside this image is dropped.
+    pub fn composite(&mut self, region: &Framebuffer<T>, x0: u32, y0: u32) {
+        let channels = self.channels.min(region.channels);
+        let width = region.width.min(self.width.saturating_sub(x0));
+        let height = region.height.min(self.height.saturating_sub(y0));
+
+        for y in 0..height {
+            for x in 0..width {
+                self.pixel_mut(x0 + x, y0 + y)[..channels].copy_from_slice(&region.pixel(x, y)[..channels]);
+            }
+        }
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        &self.data
+    }
+
+    pub fn into_raw(self) -> Vec<T> {
+        self.data
+    }
+}
+
+impl Framebuffer<f32> {
+    /// First three channels as a colour
+    #[inline]
+    pub fn color(&self, x: u32, y: u32) -> Color {
+        let pixel = self.pixel(x, y);
+        Color {
+            r: pixel[0] as f64,
+            g: pixel[1] as f64,
+            b: pixel[2] as f64,
+        }
+    }
+
+    #[inline]
+    pub fn set_color(&mut self, x: u32, y: u32, color: Color) {
+        let pixel = self.pixel_mut(x, y);
+        pixel[0] = color.r as f32;
+        pixel[1] = color.g as f32;
+        pixel[2] = color.b as f32;
+    }
+
+    /// Quantises the first three channels to packed 8-bit RGB
+    pub fn to_rgb8(&self) -> Framebuffer<u8> {
+        let mut data = Vec::with_capacity(self.width as usize * self.height as usize * 3);
+        for y in 0..self.height {
+            for pixel in self.row(y).chunks_exact(self.channels) {
+                data.push((pixel[0].clamp(0.0, 1.0) * 255.0) as u8);
+                data.push((pixel[1].clamp(0.0, 1.0) * 255.0) as u8);
+                data.push((pixel[2].clamp(0.0, 1.0) * 255.0) as u8);
+            }
+        }
+
+        Framebuffer {
+            width: self.width,
+            height: self.height,
+            channels: 3,
+            stride: self.width as usize * 3,
+            data,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn padded_rows_are_skipped() {
+        // 2x2 RGB with two padding values per row, the last row unpadded
+        let data = vec![0.0, 0.1, 0.2, 0.3, 0.4, 0.5, 9.0, 9.0, 0.6, 0.7, 0.8, 0.9, 1.0, 1.0];
+        let image = Framebuffer::from_raw(2, 2, 3, 8, data).unwrap();
+        assert!(!image.is_contiguous());
+        assert_eq!(image.row(1), [0.6, 0.7, 0.8, 0.9, 1.0, 1.0]);
+        assert_eq!(image.pixel(1, 0), [0.3, 0.4, 0.5]);
+
+        let rgb8 = image.to_rgb8();
+        assert!(rgb8.is_contiguous());
+        assert_eq!(rgb8.as_slice().len(), 12);
+        assert_eq!(rgb8.pixel(0, 1), [153, 178, 204]);
+    }
+
+    #[test]
+    fn short_data_and_strides_are_rejected() {
+        assert!(Framebuffer::from_raw(2, 2, 3, 8, vec![0.0f32; 13]).is_none());
+        assert!(Framebuffer::from_raw(2, 2, 3, 5, vec![0.0f32; 12]).is_none());
+        assert!(Framebuffer::<f32>::from_raw(2, 0, 3, 6, Vec::new()).is_some());
+    }
+}
@@ -0,0 +1,524 @@
+use crate::hittable::Hittable;
+use crate::instance::Instance;
+use crate::mesh::Mesh;
+use crate::normal_map::NormalMap;
+use crate::pbr::PbrMaterial;
+use crate::scene::{Camera, Light, Projection, Scene};
+use crate::sphere::{Color, Material};
+use crate::texture::{Texture, TextureId};
+use crate::transform::Transform;
+use crate::vector3d::{Matrix4, Vector3D};
+use base64::Engine;
+use gltf::khr_lights_punctual::Kind;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+/// How far away directional lights are placed. Lights here have no falloff,
+/// so a distant point light is a directional light in all but parallax.
+const DIRECTIONAL_LIGHT_DISTANCE: f64 = 1.0e4;
+
+#[derive(Debug)]
+pub enum GltfError {
+    Io(std::io::Error),
+    Parse(gltf::Error),
+    Image(image::ImageError),
+    /// A buffer or image URI that is neither a relative path nor base64 data
+    UnsupportedUri(String),
+    /// A GLB buffer refers to the binary chunk but the file has none
+    MissingBinaryChunk,
+}
+
+impl fmt::Display for GltfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GltfError::Io(e) => write!(f, "Failed to read glTF data: {}", e),
+            GltfError::Parse(e) => write!(f, "Invalid glTF: {}", e),
+            GltfError::Image(e) => write!(f, "Failed to decode glTF texture: {}", e),
+            GltfError::UnsupportedUri(uri) => write!(f, "Unsupported glTF URI: {}", uri),
+            GltfError::MissingBinaryChunk => write!(f, "GLB file has no binary chunk"),
+        }
+    }
+}
+
+impl std::error::Error for GltfError {}
+
+impl From<std::io::Error> for GltfError {
+    fn from(e: std::io::Error) -> Self {
+        GltfError::Io(e)
+    }
+}
+
+impl From<gltf::Error> for GltfError {
+    fn from(e: gltf::Error) -> Self {
+        GltfError::Parse(e)
+    }
+}
+
+impl From<image::ImageError> for GltfError {
+    fn from(e: image::ImageError) -> Self {
+        GltfError::Image(e)
+    }
+}
+
+/// Objects, lights and cameras imported from a glTF 2.0 (`.gltf` or `.glb`)
+/// file's default scene.
+///
+/// - Each mesh primitive becomes a `Mesh`, shared between the nodes that use
+///   it through `Instance`s carrying the node's world transform.
+/// - Materials use the metallic-roughness factors, the emissive factor, the
+///   base colour texture and the normal texture, on the base colour's UV set
+///   if they differ. The tracer works in display values, so colour factors,
+///   which glTF stores linearly, are sRGB encoded; textures are used as
+///   stored.
+/// - Textures are added to the list passed in, normally the `Scene::textures`
+///   the objects go into, which the materials' `TextureId`s index.
+/// - Perspective cameras keep their vertical FOV and aspect ratio, or an
+///   aspect ratio of 1 when the file leaves it to the viewer.
+/// - `KHR_lights_punctual` point and spot lights become point lights (spot
+///   cones are ignored) and directional lights distant point lights. Light
+///   units don't carry over since lights here have no falloff, so
+///   intensities are rescaled for the brightest light to have intensity 1.
+pub struct GltfScene {
+    pub objects: Vec<Box<dyn Hittable>>,
+    pub lights: Vec<Light>,
+    pub cameras: Vec<Camera>,
+}
+
+impl GltfScene {
+    pub fn load(path: impl AsRef<Path>, textures: &mut Vec<Arc<Texture>>) -> Result<GltfScene, GltfError> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path)?;
+        GltfScene::from_slice(&bytes, path.parent().unwrap_or(Path::new(".")), textures)
+    }
+
+    /// Imports a `.gltf` or `.glb` file already in memory, resolving
+    /// relative URIs against `base_dir`
+    pub fn from_slice(
+        bytes: &[u8],
+        base_dir: &Path,
+        textures: &mut Vec<Arc<Texture>>,
+    ) -> Result<GltfScene, GltfError> {
+        let gltf::Gltf { document, blob } = gltf::Gltf::from_slice(bytes)?;
+
+        let buffers = document
+            .buffers()
+            .map(|buffer| match buffer.source() {
+                gltf::buffer::Source::Bin => blob.clone().ok_or(GltfError::MissingBinaryChunk),
+                gltf::buffer::Source::Uri(uri) => read_uri(uri, base_dir),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut importer = Importer {
+            buffers,
+            base_dir,
+            textures,
+            texture_ids: HashMap::new(),
+            meshes: HashMap::new(),
+            scene: GltfScene {
+                objects: Vec::new(),
+                lights: Vec::new(),
+                cameras: Vec::new(),
+            },
+        };
+
+        if let Some(scene) = document.default_scene().or_else(|| document.scenes().next()) {
+            for node in scene.nodes() {
+                importer.visit(&node, Matrix4::IDENTITY)?;
+            }
+        }
+
+        let mut scene = importer.scene;
+        let brightest = scene.lights.iter().map(|light| light.intensity).fold(0.0, f64::max);
+        if brightest > 0.0 {
+            for light in &mut scene.lights {
+                light.intensity /= brightest;
+            }
+        }
+        Ok(scene)
+    }
+
+    /// Moves the objects and lights into `scene` and hands back the cameras
+    pub fn add_to(self, scene: &mut Scene) -> Vec<Camera> {
+        scene.objects.extend(self.objects);
+        scene.lights.extend(self.lights);
+        self.cameras
+    }
+}
+
+struct Importer<'a> {
+    buffers: Vec<Vec<u8>>,
+    base_dir: &'a Path,
+    textures: &'a mut Vec<Arc<Texture>>,
+    /// Where each image has been added to `textures`, by index
+    texture_ids: HashMap<usize, TextureId>,
+    /// Primitives of each glTF mesh by index, built on first use
+    meshes: HashMap<usize, Vec<Arc<dyn Hittable>>>,
+    scene: GltfScene,
+}
+
+impl Importer<'_> {
+    fn visit(&mut self, node: &gltf::Node, parent: Matrix4) -> Result<(), GltfError> {
+        let local = node.transform().matrix();
+        // glTF matrices are column-major
+        let world = parent * Matrix4::new(std::array::from_fn(|row| std::array::from_fn(|col| local[col][row] as f64)));
+
+        let origin = world.transform_point(Vector3D::ZERO);
+        let forward = world.transform_vector(Vector3D::new(0.0, 0.0, -1.0)).normalize();
+
+        if let Some(mesh) = node.mesh() {
+            // A degenerate transform (zero scale) hides the mesh
+            if let Some(transform) = Transform::from_matrix(world) {
+                for primitive in self.mesh(&mesh)? {
+                    self.scene.objects.push(Box::new(Instance::new(primitive, transform)));
+                }
+            }
+        }
+
+        if let Some(camera) = node.camera() {
+            let mut camera = match camera.projection() {
+                gltf::camera::Projection::Perspective(perspective) => Camera::new(
+                    origin,
+                    origin + forward,
+                    (perspective.yfov() as f64).to_degrees(),
+                    perspective.aspect_ratio().map_or(1.0, |aspect| aspect as f64),
+                ),
+                // xmag and ymag are half the view's width and height
+                gltf::camera::Projection::Orthographic(orthographic) => {
+                    let (xmag, ymag) = (orthographic.xmag() as f64, orthographic.ymag() as f64);
+                    let mut camera = Camera::new(origin, origin + forward, 60.0, (xmag / ymag).abs());
+                    camera.projection = Projection::Orthographic { height: 2.0 * ymag.abs() };
+                    camera
+                }
+            };
+            camera.up = world.transform_vector(Vector3D::new(0.0, 1.0, 0.0)).normalize();
+            self.scene.cameras.push(camera);
+        }
+
+        if let Some(light) = node.light() {
+            let [r, g, b] = light.color();
+            let luminance = 0.2126 * r as f64 + 0.7152 * g as f64 + 0.0722 * b as f64;
+            let position = match light.kind() {
+                Kind::Directional => origin - forward * DIRECTIONAL_LIGHT_DISTANCE,
+                Kind::Point | Kind::Spot { .. } => origin,
+            };
+            self.scene.lights.push(Light {
+                position,
+                intensity: light.intensity() as f64 * luminance,
+            });
+        }
+
+        for child in node.children() {
+            self.visit(&child, world)?;
+        }
+        Ok(())
+    }
+
+    fn mesh(&mut self, mesh: &gltf::Mesh) -> Result<Vec<Arc<dyn Hittable>>, GltfError> {
+        if let Some(primitives) = self.meshes.get(&mesh.index()) {
+            return Ok(primitives.clone());
+        }
+
+        let mut primitives: Vec<Arc<dyn Hittable>> = Vec::new();
+        for primitive in mesh.primitives() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                continue;
+            }
+
+            let reader = primitive.reader(|buffer| self.buffers.get(buffer.index()).map(Vec::as_slice));
+            let Some(positions) = reader.read_positions() else {
+                continue;
+            };
+            let positions: Vec<Vector3D> = positions.map(vector).collect();
+            let normals = reader.read_normals().map(|normals| normals.map(vector).collect()).unwrap_or_default();
+
+            let pbr = primitive.material().pbr_metallic_roughness();
+            let texture_info = pbr.base_color_texture();
+            let normal_info = primitive.material().normal_texture();
+            let uv_set = match (&texture_info, &normal_info) {
+                (Some(info), _) => info.tex_coord(),
+                (None, Some(info)) => info.tex_coord(),
+                (None, None) => 0,
+            };
+            let uvs = reader
+                .read_tex_coords(uv_set)
+                .map(|uvs| uvs.into_f32().map(|[u, v]| (u as f64, v as f64)).collect())
+                .unwrap_or_default();
+
+            let triangles = match reader.read_indices() {
+                Some(indices) => indices.into_u32().collect::<Vec<_>>(),
+                None => (0..positions.len() as u32).collect(),
+            }
+            .chunks_exact(3)
+            .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+            .collect();
+
+            let [r, g, b, _] = pbr.base_color_factor();
+            let [er, eg, eb] = primitive.material().emissive_factor();
+            let mut material = Material::from_pbr(PbrMaterial::new(
+                srgb_color(r, g, b),
+                pbr.metallic_factor() as f64,
+                pbr.roughness_factor() as f64,
+            ));
+            material.emission = srgb_color(er, eg, eb);
+            if let Some(info) = texture_info {
+                material.texture = Some(self.texture(&info.texture().source())?);
+            }
+            if let Some(info) = normal_info {
+                material.normal_map = Some(NormalMap::Tangent {
+                    texture: self.texture(&info.texture().source())?,
+                    strength: info.scale() as f64,
+                });
+            }
+
+            primitives.push(Arc::new(Mesh::new(positions, normals, uvs, triangles, material)));
+        }
+
+        self.meshes.insert(mesh.index(), primitives.clone());
+        Ok(primitives)
+    }
+
+    fn texture(&mut self, image: &gltf::Image) -> Result<TextureId, GltfError> {
+        if let Some(&id) = self.texture_ids.get(&image.index()) {
+            return Ok(id);
+        }
+
+        let decoded = match image.source() {
+            gltf::image::Source::View { view, .. } => {
+                let buffer = &self.buffers[view.buffer().index()];
+                image::load_from_memory(&buffer[view.offset()..view.offset() + view.length()])?
+            }
+            gltf::image::Source::Uri { uri, .. } => image::load_from_memory(&read_uri(uri, self.base_dir)?)?,
+        };
+
+        self.textures.push(Arc::new(Texture::from_image(&decoded)));
+        let id = TextureId(self.textures.len() - 1);
+        self.texture_ids.insert(image.index(), id);
+        Ok(id)
+    }
+}
+
+#[inline]
+fn vector([x, y, z]: [f32; 3]) -> Vector3D {
+    Vector3D::new(x as f64, y as f64, z as f64)
+}
+
+#[inline]
+fn srgb_color(r: f32, g: f32, b: f32) -> Color {
+    Color {
+        r: linear_to_srgb(r as f64),
+        g: linear_to_srgb(g as f64),
+        b: linear_to_srgb(b as f64),
+    }
+}
+
+/// sRGB transfer function, from linear light to display values
+fn linear_to_srgb(value: f64) -> f64 {
+    let value = value.clamp(0.0, 1.0);
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Contents of a base64 data URI or a file relative to `base_dir`
+fn read_uri(uri: &str, base_dir: &Path) -> Result<Vec<u8>, GltfError> {
+    if let Some(data) = uri.strip_prefix("data:") {
+        let Some((_, encoded)) = data.split_once(";base64,") else {
+            return Err(GltfError::UnsupportedUri(uri.to_string()));
+        };
+        return base64::engine::general_purpose::STANDARD
+            .decode(encoded)
+            .map_err(|_| GltfError::UnsupportedUri(uri.to_string()));
+    }
+    if uri.contains("://") {
+        return Err(GltfError::UnsupportedUri(uri.to_string()));
+    }
+    Ok(std::fs::read(base_dir.join(percent_decode(uri)))?)
+}
+
+/// Undoes URI escapes like `%20`, leaving malformed ones as they are
+fn percent_decode(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| uri.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::HitRecord;
+    use crate::ray::Ray;
+
+    /// Base64 of the positions (0, 0, 0), (1, 0, 0) and (0, 1, 0) as
+    /// little-endian f32s
+    const TRIANGLE: &str = "AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAA";
+
+    /// A triangle, a camera and a light under a translated parent. The
+    /// triangle's node turns it a quarter about z with a column-major matrix.
+    fn fixture(buffer_uri: &str) -> String {
+        r#"{
+            "asset": { "version": "2.0" },
+            "extensionsUsed": ["KHR_lights_punctual"],
+            "extensions": { "KHR_lights_punctual": { "lights": [
+                { "type": "point", "color": [1, 1, 1], "intensity": 10 }
+            ] } },
+            "scene": 0,
+            "scenes": [{ "nodes": [0] }],
+            "nodes": [
+                { "translation": [5, 0, 0], "children": [1, 2, 3] },
+                { "mesh": 0, "matrix": [0, 1, 0, 0, -1, 0, 0, 0, 0, 0, 1, 0, 0, 0, -3, 1] },
+                { "camera": 0, "translation": [0, 0, 2] },
+                { "translation": [0, 4, 0], "extensions": { "KHR_lights_punctual": { "light": 0 } } }
+            ],
+            "cameras": [{ "type": "perspective", "perspective": { "yfov": 0.5, "aspectRatio": 1.5, "znear": 0.1 } }],
+            "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 }, "material": 0 }] }],
+            "materials": [{
+                "pbrMetallicRoughness": { "baseColorFactor": [0.2140411, 0, 1, 1], "metallicFactor": 0.25, "roughnessFactor": 0.75 },
+                "emissiveFactor": [1, 0, 0]
+            }],
+            "accessors": [{
+                "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                "min": [0, 0, 0], "max": [1, 1, 0]
+            }],
+            "bufferViews": [{ "buffer": 0, "byteLength": 36 }],
+            "buffers": [{ "byteLength": 36, "uri": "URI" }]
+        }"#
+        .replace("URI", buffer_uri)
+    }
+
+    fn embedded() -> GltfScene {
+        let gltf = fixture(&format!("data:application/octet-stream;base64,{}", TRIANGLE));
+        GltfScene::from_slice(gltf.as_bytes(), Path::new("."), &mut Vec::new()).unwrap()
+    }
+
+    fn hit_from_above(scene: &GltfScene, x: f64, y: f64) -> Option<HitRecord> {
+        let ray = Ray::new(Vector3D::new(x, y, 10.0), Vector3D::new(0.0, 0.0, -1.0));
+        scene.objects.iter().filter_map(|object| object.hit(&ray, 0.0, f64::INFINITY)).next()
+    }
+
+    #[test]
+    fn node_transforms_compose_down_the_hierarchy() {
+        let scene = embedded();
+        assert_eq!(scene.objects.len(), 1);
+
+        // The turned triangle spans (5, 0), (5, 1) and (4, 0) at z = -3
+        let hit = hit_from_above(&scene, 4.75, 0.25).unwrap();
+        assert!((hit.t - 13.0).abs() < 1e-9);
+        assert!((hit.point - Vector3D::new(4.75, 0.25, -3.0)).magnitude() < 1e-9);
+        // Read row-major, the matrix would turn it the other way
+        assert!(hit_from_above(&scene, 5.25, -0.25).is_none());
+        assert!(hit_from_above(&scene, 5.25, 0.25).is_none());
+
+        let camera = &scene.cameras[0];
+        assert!((camera.position - Vector3D::new(5.0, 0.0, 2.0)).magnitude() < 1e-9);
+        assert!((camera.target - Vector3D::new(5.0, 0.0, 1.0)).magnitude() < 1e-9);
+        assert!((camera.up - Vector3D::new(0.0, 1.0, 0.0)).magnitude() < 1e-9);
+        assert!((camera.fov - 0.5f64.to_degrees()).abs() < 1e-5);
+        assert!((camera.aspect_ratio - 1.5).abs() < 1e-9);
+
+        let light = &scene.lights[0];
+        assert!((light.position - Vector3D::new(5.0, 4.0, 0.0)).magnitude() < 1e-9);
+        assert_eq!(light.intensity, 1.0);
+    }
+
+    #[test]
+    fn emissive_meshes_light_the_scene() {
+        let scene = embedded();
+        let sample = scene.objects[0].sample_emission(Vector3D::new(4.75, 0.25, 10.0), 0.5, 0.5).unwrap();
+        assert!((sample.point.z + 3.0).abs() < 1e-9);
+        assert!((sample.normal.z.abs() - 1.0).abs() < 1e-9);
+        assert!(sample.radiance.r > 0.99 && sample.radiance.g == 0.0);
+        assert!(sample.pdf > 0.0);
+    }
+
+    #[test]
+    fn materials_map_to_pbr_in_display_values() {
+        let material = hit_from_above(&embedded(), 4.75, 0.25).unwrap().material;
+        let pbr = material.pbr.unwrap();
+        // Linear 0.214 is sRGB 0.5
+        assert!((pbr.base_color.r - 0.5).abs() < 1e-4);
+        assert_eq!(pbr.base_color.g, 0.0);
+        assert!((pbr.base_color.b - 1.0).abs() < 1e-9);
+        assert_eq!((pbr.metallic, pbr.roughness), (0.25, 0.75));
+        assert!((material.emission.r - 1.0).abs() < 1e-9);
+        assert_eq!((material.emission.g, material.emission.b), (0.0, 0.0));
+    }
+
+    #[test]
+    fn textures_are_added_to_the_scene_list_once_per_image() {
+        // A red texel and a flat normal, used as both the base colour and,
+        // through a second texture, the normal map
+        let png = "iVBORw0KGgoAAAANSUhEUgAAAAIAAAABCAIAAAB7QOjdAAAAD0lEQVR4nGP4z8DQ0PAfAAmAAv+EtP36AAAAAElFTkSuQmCC";
+        let gltf = fixture(&format!("data:application/octet-stream;base64,{}", TRIANGLE))
+            .replace(
+                r#""asset": { "version": "2.0" },"#,
+                &format!(
+                    r#""asset": {{ "version": "2.0" }},
+                    "textures": [{{ "source": 0 }}, {{ "source": 0 }}],
+                    "images": [{{ "uri": "data:image/png;base64,{}" }}],"#,
+                    png
+                ),
+            )
+            .replace(r#""metallicFactor""#, r#""baseColorTexture": { "index": 0 }, "metallicFactor""#)
+            .replace(r#""emissiveFactor": [1, 0, 0]"#, r#""emissiveFactor": [1, 0, 0], "normalTexture": { "index": 1, "scale": 0.5 }"#);
+
+        let mut textures = vec![Arc::new(Texture::new(1, 1, vec![Color::WHITE]))];
+        let scene = GltfScene::from_slice(gltf.as_bytes(), Path::new("."), &mut textures).unwrap();
+        assert_eq!(textures.len(), 2);
+        assert_eq!((textures[1].width, textures[1].height), (2, 1));
+
+        let material = hit_from_above(&scene, 4.75, 0.25).unwrap().material;
+        assert_eq!(material.texture, Some(TextureId(1)));
+        match material.normal_map {
+            Some(NormalMap::Tangent { texture, strength }) => assert_eq!((texture, strength), (TextureId(1), 0.5)),
+            other => panic!("expected a tangent-space normal map, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn uris_decode_data_and_escaped_paths() {
+        let dir = std::env::temp_dir().join(format!("rust-ray-tracing-gltf-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let bytes = base64::engine::general_purpose::STANDARD.decode(TRIANGLE).unwrap();
+        std::fs::write(dir.join("tri angle.bin"), &bytes).unwrap();
+        std::fs::write(dir.join("scene.gltf"), fixture("tri%20angle.bin")).unwrap();
+        let loaded = GltfScene::load(dir.join("scene.gltf"), &mut Vec::new());
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(hit_from_above(&loaded.unwrap(), 4.75, 0.25).is_some());
+
+        assert_eq!(read_uri("data:application/octet-stream;base64,AAEC", Path::new(".")).unwrap(), [0, 1, 2]);
+        for uri in ["data:text/plain,abc", "data:;base64,A!==", "https://example.com/a.bin"] {
+            assert!(matches!(read_uri(uri, Path::new(".")), Err(GltfError::UnsupportedUri(u)) if u == uri));
+        }
+        let gltf = fixture("data:application/octet-stream,raw");
+        assert!(matches!(
+            GltfScene::from_slice(gltf.as_bytes(), Path::new("."), &mut Vec::new()),
+            Err(GltfError::UnsupportedUri(_))
+        ));
+
+        assert_eq!(percent_decode("a%20b%2Fc"), "a b/c");
+        assert_eq!(percent_decode("100%"), "100%");
+        assert_eq!(percent_decode("%zz%4"), "%zz%4");
+        assert_eq!(percent_decode("%E2%9C%93"), "\u{2713}");
+    }
+}
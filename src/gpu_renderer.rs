@@ -1,7 +1,127 @@
+use crate::aov::Aov;
+use crate::cone::Cone;
+use crate::cuboid::{AxisAlignedBox, OrientedBox};
+use crate::cylinder::Cylinder;
+use crate::disk::Disk;
+use crate::framebuffer::Framebuffer;
+use crate::rectangle::Rectangle;
+use crate::sampler::{blue_noise_mask, SamplerKind};
+use crate::scene::Camera;
+use crate::sphere::Material;
+use crate::tiles::{CropWindow, Tile};
+use crate::torus::Torus;
+use crate::transform::Frame;
+use crate::vector3d::Vector3D;
 use bytemuck::{Pod, Zeroable};
 use std::fmt;
 use wgpu::util::DeviceExt;
 
+/// (color, diffuse, specular, shininess, reflectivity)
+pub type MaterialData = ([f32; 3], f32, f32, f32, f32);
+
+/// ((center, radius), material)
+pub type SphereData = (([f32; 3], f32), MaterialData);
+
+/// ((point, normal), material)
+pub type PlaneData = (([f32; 3], [f32; 3]), MaterialData);
+
+/// (kind, (origin, [u, v, w] axes, dims), material). The shape is tested in
+/// the orthonormal frame given by the origin and axes; `ShapeKind` lists what
+/// `dims` holds for each kind.
+pub type ShapeData = (ShapeKind, ([f32; 3], [[f32; 3]; 3], [f32; 3]), MaterialData);
+
+/// Primitives of the generic shapes buffer. The discriminants match the
+/// `SHAPE_*` constants in `raytracer.wgsl`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShapeKind {
+    /// Centred on the origin; dims are the half extents
+    Box = 0,
+    /// Base at the origin along w; dims are (radius, height, 0)
+    Cylinder = 1,
+    /// Base at the origin, apex along w; dims are (radius, height, 0)
+    Cone = 2,
+    /// Facing w; dims are (radius, 0, 0)
+    Disk = 3,
+    /// Facing w; dims are (half width along u, half height along v, 0)
+    Rectangle = 4,
+    /// Around w; dims are (major radius, minor radius, 0)
+    Torus = 5,
+}
+
+/// CPU shapes that have a GPU counterpart in the shapes buffer
+pub trait ToShapeData {
+    fn shape_data(&self) -> ShapeData;
+}
+
+impl ToShapeData for AxisAlignedBox {
+    fn shape_data(&self) -> ShapeData {
+        let frame = Frame {
+            origin: self.center(),
+            u: Vector3D::new(1.0, 0.0, 0.0),
+            v: Vector3D::new(0.0, 1.0, 0.0),
+            w: Vector3D::new(0.0, 0.0, 1.0),
+        };
+        shape_data(ShapeKind::Box, &frame, self.half_extents(), &self.material)
+    }
+}
+
+impl ToShapeData for OrientedBox {
+    fn shape_data(&self) -> ShapeData {
+        shape_data(ShapeKind::Box, &self.frame, self.half_extents, &self.material)
+    }
+}
+
+impl ToShapeData for Cylinder {
+    fn shape_data(&self) -> ShapeData {
+        let dims = Vector3D::new(self.radius, self.height, 0.0);
+        shape_data(ShapeKind::Cylinder, &self.frame, dims, &self.material)
+    }
+}
+
+impl ToShapeData for Cone {
+    fn shape_data(&self) -> ShapeData {
+        let dims = Vector3D::new(self.radius, self.height, 0.0);
+        shape_data(ShapeKind::Cone, &self.frame, dims, &self.material)
+    }
+}
+
+impl ToShapeData for Disk {
+    fn shape_data(&self) -> ShapeData {
+        let dims = Vector3D::new(self.radius, 0.0, 0.0);
+        shape_data(ShapeKind::Disk, &self.frame, dims, &self.material)
+    }
+}
+
+impl ToShapeData for Rectangle {
+    fn shape_data(&self) -> ShapeData {
+        let dims = Vector3D::new(self.half_width, self.half_height, 0.0);
+        shape_data(ShapeKind::Rectangle, &self.frame, dims, &self.material)
+    }
+}
+
+impl ToShapeData for Torus {
+    fn shape_data(&self) -> ShapeData {
+        let dims = Vector3D::new(self.major_radius, self.minor_radius, 0.0);
+        shape_data(ShapeKind::Torus, &self.frame, dims, &self.material)
+    }
+}
+
+fn shape_data(kind: ShapeKind, frame: &Frame, dims: Vector3D, material: &Material) -> ShapeData {
+    let to_f32 = |v: Vector3D| [v.x as f32, v.y as f32, v.z as f32];
+    let color = [material.color.r as f32, material.color.g as f32, material.color.b as f32];
+    (
+        kind,
+        (to_f32(frame.origin), [to_f32(frame.u), to_f32(frame.v), to_f32(frame.w)], to_f32(dims)),
+        (
+            color,
+            material.diffuse as f32,
+            material.specular as f32,
+            material.shininess as f32,
+            material.reflectivity as f32,
+        ),
+    )
+}
+
 #[derive(Debug)]
 pub enum GpuError {
     NoAdapter,
@@ -28,14 +148,16 @@ impl std::error::Error for GpuError {}
 struct GpuCamera {
     position: [f32; 3],
     _padding1: f32,
-    look_at: [f32; 3],
+    forward: [f32; 3],
     _padding2: f32,
-    up: [f32; 3],
+    right: [f32; 3],
     fov: f32,
+    up: [f32; 3],
     aspect_ratio: f32,
-    _padding3: f32,
-    _padding4: f32,
-    _padding5: f32,
+    projection: u32,
+    projection_parameter: f32,
+    shift_x: f32,
+    shift_y: f32,
 }
 
 #[repr(C)]
@@ -67,6 +189,22 @@ struct GpuPlane {
     material: GpuMaterial,
 }
 
+#[repr(C)]
+#[derive(Copy, Clone, Pod, Zeroable)]
+struct GpuShape {
+    origin: [f32; 3],
+    kind: u32,
+    axis_u: [f32; 3],
+    _padding1: f32,
+    axis_v: [f32; 3],
+    _padding2: f32,
+    axis_w: [f32; 3],
+    _padding3: f32,
+    dims: [f32; 3],
+    _padding4: f32,
+    material: GpuMaterial,
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, Pod, Zeroable)]
 struct GpuLight {
@@ -86,13 +224,27 @@ struct RenderParams {
     num_spheres: u32,
     num_planes: u32,
     num_lights: u32,
-    _padding: u32,
+    sampler_kind: u32,
+    aovs: u32,
+    num_shapes: u32,
+    /// Top left pixel of the rendered region; `width` and `height` are
+    /// those of the whole image
+    crop_x: u32,
+    crop_y: u32,
+    crop_width: u32,
+    crop_height: u32,
+    _padding2: u32,
+    _padding3: u32,
 }
 
+/// Bytes of AOV output per pixel: four vec4s, see `raytracer.wgsl`
+const AOV_BYTES_PER_PIXEL: u64 = 4 * 16;
+
 pub struct GpuRenderer {
     device: wgpu::Device,
     queue: wgpu::Queue,
     pipeline: wgpu::ComputePipeline,
+    blue_noise_buffer: wgpu::Buffer,
     memory_info: MemoryInfo,
     adapter_info: wgpu::AdapterInfo,
 }
@@ -201,6 +353,36 @@ impl GpuRenderer {
                     },
                     count: None,
                 },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 6,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 7,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 8,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
             ],
         });
 
@@ -217,10 +399,17 @@ impl GpuRenderer {
             entry_point: "main",
         });
 
+        let blue_noise_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Blue Noise Buffer"),
+            contents: bytemuck::cast_slice(blue_noise_mask()),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+
         Ok(Self {
             device,
             queue,
             pipeline,
+            blue_noise_buffer,
             memory_info: MemoryInfo {
                 total_allocated_mb: 0.0,
                 peak_allocated_mb: 0.0,
@@ -237,46 +426,174 @@ impl GpuRenderer {
         &self.adapter_info.name
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn calculate_memory_usage(
         &self,
         width: u32,
         height: u32,
         num_spheres: usize,
         num_planes: usize,
+        num_shapes: usize,
         num_lights: usize,
+        with_aovs: bool,
     ) -> f64 {
         let output_size = (width * height * 16) as u64;
-        let staging_size = output_size;
+        let aov_size = if with_aovs { width as u64 * height as u64 * AOV_BYTES_PER_PIXEL } else { 0 };
+        let staging_size = output_size + aov_size;
         let params_size = std::mem::size_of::<RenderParams>() as u64;
         let camera_size = std::mem::size_of::<GpuCamera>() as u64;
         let spheres_size = (num_spheres * std::mem::size_of::<GpuSphere>()) as u64;
         let planes_size = (num_planes * std::mem::size_of::<GpuPlane>()) as u64;
+        let shapes_size = (num_shapes * std::mem::size_of::<GpuShape>()) as u64;
         let lights_size = (num_lights * std::mem::size_of::<GpuLight>()) as u64;
 
-        let total_bytes = output_size + staging_size + params_size + camera_size
-                        + spheres_size + planes_size + lights_size;
+        let total_bytes = output_size + aov_size + staging_size + params_size + camera_size
+                        + spheres_size + planes_size + shapes_size + lights_size;
 
         total_bytes as f64 / (1024.0 * 1024.0)
     }
 
+    /// Renders `camera`'s view; the aspect ratio comes from the image size
+    /// rather than the camera
+    #[allow(clippy::too_many_arguments)]
     pub fn render(
         &mut self,
         width: u32,
         height: u32,
         samples: u32,
-        camera_pos: [f32; 3],
-        camera_target: [f32; 3],
-        fov: f32,
-        spheres_data: &[(([f32; 3], f32), ([f32; 3], f32, f32, f32, f32))],
-        planes_data: &[(([f32; 3], [f32; 3]), ([f32; 3], f32, f32, f32, f32))],
+        sampler: SamplerKind,
+        camera: &Camera,
+        spheres_data: &[SphereData],
+        planes_data: &[PlaneData],
+        shapes_data: &[ShapeData],
+        lights_data: &[([f32; 3], f32)],
+        background_color: [f32; 3],
+    ) -> Result<Framebuffer, GpuError> {
+        self.render_with_aovs(
+            width,
+            height,
+            samples,
+            sampler,
+            camera,
+            spheres_data,
+            planes_data,
+            shapes_data,
+            lights_data,
+            background_color,
+            &[],
+        )
+        .map(|(image, _)| image)
+    }
+
+    /// Like `render`, but also reads back the requested AOVs in the order given.
+    ///
+    /// Object IDs number spheres first, then planes, then shapes, starting at 1.
+    #[allow(clippy::too_many_arguments)]
+    pub fn render_with_aovs(
+        &mut self,
+        width: u32,
+        height: u32,
+        samples: u32,
+        sampler: SamplerKind,
+        camera: &Camera,
+        spheres_data: &[SphereData],
+        planes_data: &[PlaneData],
+        shapes_data: &[ShapeData],
         lights_data: &[([f32; 3], f32)],
         background_color: [f32; 3],
-    ) -> Result<Vec<Vec<[f32; 3]>>, GpuError> {
+        aovs: &[Aov],
+    ) -> Result<(Framebuffer, Vec<(Aov, Framebuffer)>), GpuError> {
+        let full = Tile {
+            x0: 0,
+            y0: 0,
+            x1: width,
+            y1: height,
+        };
+        self.render_region(
+            width,
+            height,
+            full,
+            samples,
+            sampler,
+            camera,
+            spheres_data,
+            planes_data,
+            shapes_data,
+            lights_data,
+            background_color,
+            aovs,
+        )
+    }
+
+    /// Renders only `crop` of the `width` x `height` image, framed as in the
+    /// full image, and returns that region with its AOVs. Pixels match the
+    /// same region of a full render with the same settings.
+    #[allow(clippy::too_many_arguments)]
+    pub fn render_crop(
+        &mut self,
+        width: u32,
+        height: u32,
+        crop: CropWindow,
+        samples: u32,
+        sampler: SamplerKind,
+        camera: &Camera,
+        spheres_data: &[SphereData],
+        planes_data: &[PlaneData],
+        shapes_data: &[ShapeData],
+        lights_data: &[([f32; 3], f32)],
+        background_color: [f32; 3],
+        aovs: &[Aov],
+    ) -> Result<(Framebuffer, Vec<(Aov, Framebuffer)>), GpuError> {
+        self.render_region(
+            width,
+            height,
+            crop.to_pixels(width, height),
+            samples,
+            sampler,
+            camera,
+            spheres_data,
+            planes_data,
+            shapes_data,
+            lights_data,
+            background_color,
+            aovs,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn render_region(
+        &mut self,
+        width: u32,
+        height: u32,
+        region: Tile,
+        samples: u32,
+        sampler: SamplerKind,
+        camera: &Camera,
+        spheres_data: &[SphereData],
+        planes_data: &[PlaneData],
+        shapes_data: &[ShapeData],
+        lights_data: &[([f32; 3], f32)],
+        background_color: [f32; 3],
+        aovs: &[Aov],
+    ) -> Result<(Framebuffer, Vec<(Aov, Framebuffer)>), GpuError> {
+        let (region_width, region_height) = (region.width(), region.height());
+        // Buffers can't be empty, and there would be nothing to read back
+        if region.is_empty() {
+            let aov_images = aovs
+                .iter()
+                .map(|&aov| (aov, Framebuffer::new(region_width, region_height, aov.channels())))
+                .collect();
+            return Ok((Framebuffer::new(region_width, region_height, 4), aov_images));
+        }
+
+        let with_aovs = !aovs.is_empty();
         let memory_mb = self.calculate_memory_usage(
-            width, height,
+            region_width, region_height,
             spheres_data.len(),
             planes_data.len(),
-            lights_data.len()
+            shapes_data.len(),
+            lights_data.len(),
+            with_aovs,
         );
 
         // Check against 2GB limit
@@ -290,22 +607,26 @@ impl GpuRenderer {
             self.memory_info.peak_allocated_mb = memory_mb;
         }
 
-        let aspect_ratio = width as f32 / height as f32;
+        let (forward, right, up) = camera.basis();
+        let (projection, projection_parameter) = camera.projection.encode();
+        let to_f32 = |v: Vector3D| [v.x as f32, v.y as f32, v.z as f32];
 
         let gpu_camera = GpuCamera {
-            position: camera_pos,
+            position: to_f32(camera.position),
             _padding1: 0.0,
-            look_at: camera_target,
+            forward: to_f32(forward),
             _padding2: 0.0,
-            up: [0.0, 1.0, 0.0],
-            fov,
-            aspect_ratio,
-            _padding3: 0.0,
-            _padding4: 0.0,
-            _padding5: 0.0,
+            right: to_f32(right),
+            fov: camera.fov as f32,
+            up: to_f32(up),
+            aspect_ratio: width as f32 / height as f32,
+            projection,
+            projection_parameter: projection_parameter as f32,
+            shift_x: camera.shift_x as f32,
+            shift_y: camera.shift_y as f32,
         };
 
-        let gpu_spheres: Vec<GpuSphere> = spheres_data
+        let mut gpu_spheres: Vec<GpuSphere> = spheres_data
             .iter()
             .map(|((center, radius), (color, diffuse, specular, shininess, reflectivity))| {
                 GpuSphere {
@@ -323,7 +644,7 @@ impl GpuRenderer {
             })
             .collect();
 
-        let gpu_planes: Vec<GpuPlane> = planes_data
+        let mut gpu_planes: Vec<GpuPlane> = planes_data
             .iter()
             .map(|((point, normal), (color, diffuse, specular, shininess, reflectivity))| {
                 GpuPlane {
@@ -343,7 +664,34 @@ impl GpuRenderer {
             })
             .collect();
 
-        let gpu_lights: Vec<GpuLight> = lights_data
+        let mut gpu_shapes: Vec<GpuShape> = shapes_data
+            .iter()
+            .map(|(kind, (origin, [axis_u, axis_v, axis_w], dims), (color, diffuse, specular, shininess, reflectivity))| {
+                GpuShape {
+                    origin: *origin,
+                    kind: *kind as u32,
+                    axis_u: *axis_u,
+                    _padding1: 0.0,
+                    axis_v: *axis_v,
+                    _padding2: 0.0,
+                    axis_w: *axis_w,
+                    _padding3: 0.0,
+                    dims: *dims,
+                    _padding4: 0.0,
+                    material: GpuMaterial {
+                        color: *color,
+                        diffuse: *diffuse,
+                        specular: *specular,
+                        shininess: *shininess,
+                        reflectivity: *reflectivity,
+                        _padding: 0.0,
+                    },
+                }
+            })
+            .collect();
+        let num_shapes = gpu_shapes.len() as u32;
+
+        let mut gpu_lights: Vec<GpuLight> = lights_data
             .iter()
             .map(|(position, intensity)| GpuLight {
                 position: *position,
@@ -351,6 +699,22 @@ impl GpuRenderer {
             })
             .collect();
 
+        let (num_spheres, num_planes, num_lights) =
+            (gpu_spheres.len() as u32, gpu_planes.len() as u32, gpu_lights.len() as u32);
+        // Bindings can't be empty; the shader only reads the counted entries
+        if gpu_spheres.is_empty() {
+            gpu_spheres.push(GpuSphere::zeroed());
+        }
+        if gpu_planes.is_empty() {
+            gpu_planes.push(GpuPlane::zeroed());
+        }
+        if gpu_shapes.is_empty() {
+            gpu_shapes.push(GpuShape::zeroed());
+        }
+        if gpu_lights.is_empty() {
+            gpu_lights.push(GpuLight::zeroed());
+        }
+
         let params = RenderParams {
             width,
             height,
@@ -358,10 +722,18 @@ impl GpuRenderer {
             max_depth: 5,
             background_color,
             epsilon: 0.001,
-            num_spheres: gpu_spheres.len() as u32,
-            num_planes: gpu_planes.len() as u32,
-            num_lights: gpu_lights.len() as u32,
-            _padding: 0,
+            num_spheres,
+            num_planes,
+            num_lights,
+            sampler_kind: sampler.gpu_id(),
+            aovs: with_aovs as u32,
+            num_shapes,
+            crop_x: region.x0,
+            crop_y: region.y0,
+            crop_width: region_width,
+            crop_height: region_height,
+            _padding2: 0,
+            _padding3: 0,
         };
 
         let params_buffer = self
@@ -396,6 +768,14 @@ impl GpuRenderer {
                 usage: wgpu::BufferUsages::STORAGE,
             });
 
+        let shapes_buffer = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Shapes Buffer"),
+                contents: bytemuck::cast_slice(&gpu_shapes),
+                usage: wgpu::BufferUsages::STORAGE,
+            });
+
         let lights_buffer = self
             .device
             .create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -406,18 +786,37 @@ impl GpuRenderer {
 
         let output_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Output Buffer"),
-            size: (width * height * 16) as u64,
+            size: (region_width * region_height * 16) as u64,
             usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
             mapped_at_creation: false,
         });
 
         let staging_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Staging Buffer"),
-            size: (width * height * 16) as u64,
+            size: (region_width * region_height * 16) as u64,
             usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
             mapped_at_creation: false,
         });
 
+        // The shader skips AOV writes when they're disabled, so a single
+        // element is enough to satisfy the binding
+        let aov_size = if with_aovs { region_width as u64 * region_height as u64 * AOV_BYTES_PER_PIXEL } else { 16 };
+        let aov_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("AOV Buffer"),
+            size: aov_size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let aov_staging_buffer = with_aovs.then(|| {
+            self.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("AOV Staging Buffer"),
+                size: aov_size,
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            })
+        });
+
         let bind_group_layout = self.pipeline.get_bind_group_layout(0);
         let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("Bind Group"),
@@ -447,6 +846,18 @@ impl GpuRenderer {
                     binding: 5,
                     resource: output_buffer.as_entire_binding(),
                 },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: self.blue_noise_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: aov_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: shapes_buffer.as_entire_binding(),
+                },
             ],
         });
 
@@ -462,52 +873,65 @@ impl GpuRenderer {
             });
             compute_pass.set_pipeline(&self.pipeline);
             compute_pass.set_bind_group(0, &bind_group, &[]);
-            compute_pass.dispatch_workgroups((width + 7) / 8, (height + 7) / 8, 1);
+            compute_pass.dispatch_workgroups(region_width.div_ceil(8), region_height.div_ceil(8), 1);
         }
 
-        encoder.copy_buffer_to_buffer(&output_buffer, 0, &staging_buffer, 0, (width * height * 16) as u64);
+        encoder.copy_buffer_to_buffer(&output_buffer, 0, &staging_buffer, 0, (region_width * region_height * 16) as u64);
+        if let Some(aov_staging_buffer) = &aov_staging_buffer {
+            encoder.copy_buffer_to_buffer(&aov_buffer, 0, aov_staging_buffer, 0, aov_size);
+        }
 
         self.queue.submit(Some(encoder.finish()));
 
-        let buffer_slice = staging_buffer.slice(..);
+        let pixels = self.read_buffer(&staging_buffer);
+
+        // Output is RGBA (vec4 per pixel) with alpha unused
+        let image = Framebuffer::from_raw(region_width, region_height, 4, region_width as usize * 4, pixels)
+            .expect("GPU output buffer is sized for the image");
+
+        let aov_images = match &aov_staging_buffer {
+            Some(aov_staging_buffer) => {
+                let values = self.read_buffer(aov_staging_buffer);
+                aovs.iter()
+                    .map(|&aov| (aov, unpack_aov(aov, region_width, region_height, &values)))
+                    .collect()
+            }
+            None => Vec::new(),
+        };
+
+        Ok((image, aov_images))
+    }
+
+    /// Maps a staging buffer and copies its contents out as floats
+    fn read_buffer(&self, buffer: &wgpu::Buffer) -> Vec<f32> {
+        let buffer_slice = buffer.slice(..);
         buffer_slice.map_async(wgpu::MapMode::Read, |_| {});
         self.device.poll(wgpu::Maintain::Wait);
 
         let data = buffer_slice.get_mapped_range();
-        let result: &[[f32; 4]] = bytemuck::cast_slice(&data);
-
-        let mut image = Vec::new();
-        for y in 0..height {
-            let mut row = Vec::new();
-            for x in 0..width {
-                let pixel = result[(y * width + x) as usize];
-                row.push([pixel[0], pixel[1], pixel[2]]);
-            }
-            image.push(row);
-        }
-
+        let values: Vec<f32> = bytemuck::cast_slice(&data).to_vec();
         drop(data);
-        staging_buffer.unmap();
-
-        Ok(image)
+        buffer.unmap();
+        values
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn render_adaptive(
         &mut self,
         width: u32,
         height: u32,
         target_samples: u32,
-        camera_pos: [f32; 3],
-        camera_target: [f32; 3],
-        fov: f32,
-        spheres_data: &[(([f32; 3], f32), ([f32; 3], f32, f32, f32, f32))],
-        planes_data: &[(([f32; 3], [f32; 3]), ([f32; 3], f32, f32, f32, f32))],
+        sampler: SamplerKind,
+        camera: &Camera,
+        spheres_data: &[SphereData],
+        planes_data: &[PlaneData],
+        shapes_data: &[ShapeData],
         lights_data: &[([f32; 3], f32)],
         background_color: [f32; 3],
         progress_callback: &dyn Fn(u32, u32),
-    ) -> Result<Vec<Vec<[f32; 3]>>, GpuError> {
+    ) -> Result<Framebuffer, GpuError> {
         let sample_steps = [1, 2, 4, 8, target_samples];
-        let mut final_image = vec![vec![[0.0, 0.0, 0.0]; width as usize]; height as usize];
+        let mut final_image = Framebuffer::new(width, height, 4);
 
         for &samples in &sample_steps {
             if samples > target_samples {
@@ -520,11 +944,11 @@ impl GpuRenderer {
                 width,
                 height,
                 samples,
-                camera_pos,
-                camera_target,
-                fov,
+                sampler,
+                camera,
                 spheres_data,
                 planes_data,
+                shapes_data,
                 lights_data,
                 background_color,
             )?;
@@ -533,3 +957,30 @@ impl GpuRenderer {
         Ok(final_image)
     }
 }
+
+/// Extracts one AOV from the packed per-pixel vec4s written by the shader
+fn unpack_aov(aov: Aov, width: u32, height: u32, values: &[f32]) -> Framebuffer {
+    // (vec4 index, first component) of each AOV within a pixel
+    let (vector, component) = match aov {
+        Aov::Normal => (0, 0),
+        Aov::Depth => (0, 3),
+        Aov::Albedo => (1, 0),
+        Aov::ObjectId => (1, 3),
+        Aov::Direct => (2, 0),
+        Aov::Shadow => (2, 3),
+        Aov::Reflected => (3, 0),
+    };
+
+    let mut image = Framebuffer::new(width, height, aov.channels());
+    let floats_per_pixel = AOV_BYTES_PER_PIXEL as usize / 4;
+    for y in 0..height {
+        for x in 0..width {
+            let offset = (y * width + x) as usize * floats_per_pixel + vector * 4 + component;
+            image
+                .pixel_mut(x, y)
+                .copy_from_slice(&values[offset..offset + aov.channels()]);
+        }
+    }
+
+    image
+}
@@ -1,5 +1,5 @@
 use crate::ray::Ray;
-use crate::sphere::Material;
+use crate::sphere::{Color, Material};
 use crate::vector3d::Vector3D;
 
 #[derive(Debug, Clone, Copy)]
@@ -7,9 +7,201 @@ pub struct HitRecord {
     pub point: Vector3D,
     pub normal: Vector3D,
     pub t: f64,
+    /// Surface coordinates, in [0, 1] except on infinite planes where they
+    /// are world units along the plane
+    pub uv: (f64, f64),
+    /// Unit directions of increasing u and v, perpendicular to `normal`:
+    /// the tangent frame normal and bump maps are applied in. Arbitrary
+    /// where the UVs don't vary smoothly.
+    pub tangent: Vector3D,
+    pub bitangent: Vector3D,
     pub material: Material,
 }
 
+/// Tangent frame for a hit with unit `normal` from the rough directions of
+/// increasing u and v. The tangent is made perpendicular to the normal and
+/// the bitangent to both, keeping the side `bitangent` is on, so mirrored
+/// UVs keep their handedness. Falls back to an arbitrary frame when the
+/// directions are degenerate.
+#[inline]
+pub fn tangent_frame(normal: Vector3D, tangent: Vector3D, bitangent: Vector3D) -> (Vector3D, Vector3D) {
+    let tangent = tangent - normal * normal.dot(tangent);
+    let length_sq = tangent.magnitude_squared();
+    if length_sq.is_nan() || length_sq <= 1e-20 {
+        return normal.orthonormal_basis();
+    }
+    let tangent = tangent * (1.0 / length_sq.sqrt());
+    let side = normal.cross(tangent);
+    let bitangent = if side.dot(bitangent) < 0.0 { -side } else { side };
+    (tangent, bitangent)
+}
+
+/// Stretch of a ray's line that lies inside a solid, from the surface where
+/// it enters to the one where it leaves. Unbounded solids use infinite `t`.
+#[derive(Debug, Clone, Copy)]
+pub struct Interval {
+    pub enter: HitRecord,
+    pub exit: HitRecord,
+}
+
+/// Point on an emissive surface picked to light some other point
+#[derive(Debug, Clone, Copy)]
+pub struct EmissionSample {
+    pub point: Vector3D,
+    /// Unit surface normal at `point`, on either side
+    pub normal: Vector3D,
+    /// Emitted radiance arriving from `point`
+    pub radiance: Color,
+    /// Probability density of the sample, per unit solid angle as seen from
+    /// the point being lit
+    pub pdf: f64,
+}
+
+/// Crossings gathered by the default `intervals` before giving up; enough
+/// for any of the built-in shapes
+const MAX_CROSSINGS: usize = 16;
+
 pub trait Hittable: Send + Sync {
     fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
+
+    /// Every interval along the whole line of `ray` (negative `t` included)
+    /// that is inside the object, sorted and disjoint. Used by CSG and to
+    /// bound volumes.
+    ///
+    /// The default walks the surface with repeated `hit` calls and pairs the
+    /// crossings up, which is right for closed, bounded objects. Open
+    /// surfaces like disks and rectangles have no inside, and their odd
+    /// crossing is dropped.
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        let mut crossings = Vec::new();
+        let mut t_min = f64::NEG_INFINITY;
+        while crossings.len() < MAX_CROSSINGS {
+            let Some(hit) = self.hit(ray, t_min, f64::INFINITY) else {
+                break;
+            };
+            t_min = next_up(hit.t);
+            crossings.push(hit);
+        }
+
+        crossings
+            .chunks_exact(2)
+            .map(|pair| Interval {
+                enter: pair[0],
+                exit: pair[1],
+            })
+            .collect()
+    }
+
+    /// Samples a point on the object for light arriving at `origin`, using
+    /// two uniform numbers. `None` for objects that don't emit, and by
+    /// default, so only shapes that implement it light the scene; any
+    /// emissive object still glows where it's seen.
+    fn sample_emission(&self, _origin: Vector3D, _u1: f64, _u2: f64) -> Option<EmissionSample> {
+        None
+    }
+}
+
+/// Smallest float above `t`, so the next `hit` skips the crossing just found
+#[inline]
+fn next_up(t: f64) -> f64 {
+    if t.is_nan() || t == f64::INFINITY {
+        t
+    } else if t == 0.0 {
+        f64::from_bits(1)
+    } else if t > 0.0 {
+        f64::from_bits(t.to_bits() + 1)
+    } else {
+        f64::from_bits(t.to_bits() - 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disk::Disk;
+    use crate::sphere::Sphere;
+    use crate::torus::Torus;
+
+    const MATERIAL: Material = Material {
+        color: Color::WHITE,
+        diffuse: 1.0,
+        specular: 0.0,
+        shininess: 1.0,
+        reflectivity: 0.0,
+        pbr: None,
+        emission: Color { r: 0.0, g: 0.0, b: 0.0 },
+        texture: None,
+        normal_map: None,
+    };
+
+    fn along_x(x: f64) -> Ray {
+        Ray::new(Vector3D::new(x, 0.0, 0.0), Vector3D::new(1.0, 0.0, 0.0))
+    }
+
+    fn spans(object: &dyn Hittable, ray: &Ray) -> Vec<(f64, f64)> {
+        object.intervals(ray).iter().map(|interval| (interval.enter.t, interval.exit.t)).collect()
+    }
+
+    /// Crossings at every whole `t` from 0 on, more than `intervals` gathers
+    struct Slabs;
+
+    impl Hittable for Slabs {
+        fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+            let t = t_min.max(0.0).ceil();
+            (t <= t_max).then(|| HitRecord {
+                point: ray.at(t),
+                normal: Vector3D::new(1.0, 0.0, 0.0),
+                t,
+                uv: (0.0, 0.0),
+                tangent: Vector3D::new(0.0, 1.0, 0.0),
+                bitangent: Vector3D::new(0.0, 0.0, 1.0),
+                material: MATERIAL,
+            })
+        }
+    }
+
+    #[test]
+    fn next_up_steps_to_the_adjacent_float() {
+        assert_eq!(next_up(0.0), f64::from_bits(1));
+        assert_eq!(next_up(-0.0), f64::from_bits(1));
+        assert!(next_up(1.0) > 1.0 && next_up(1.0) - 1.0 == f64::EPSILON);
+        assert!(next_up(-1.0) > -1.0 && next_up(-1.0) + 1.0 == f64::EPSILON / 2.0);
+        assert_eq!(next_up(-f64::from_bits(1)), -0.0);
+        assert_eq!(next_up(f64::INFINITY), f64::INFINITY);
+        assert_eq!(next_up(f64::NEG_INFINITY), f64::MIN);
+        assert!(next_up(f64::NAN).is_nan());
+    }
+
+    #[test]
+    fn default_intervals_pair_up_crossings() {
+        let sphere = Sphere::new(Vector3D::ZERO, 2.0, MATERIAL);
+        assert_eq!(spans(&sphere, &along_x(-10.0)), vec![(8.0, 12.0)]);
+        // The whole line counts, so a ray from inside starts at negative t
+        assert_eq!(spans(&sphere, &along_x(0.0)), vec![(-2.0, 2.0)]);
+        assert_eq!(spans(&sphere, &along_x(10.0)), vec![(-12.0, -8.0)]);
+        let miss = Ray::new(Vector3D::new(-10.0, 3.0, 0.0), Vector3D::new(1.0, 0.0, 0.0));
+        assert!(spans(&sphere, &miss).is_empty());
+
+        let ring = Torus::new(Vector3D::ZERO, Vector3D::new(0.0, 1.0, 0.0), 2.0, 0.5, MATERIAL);
+        let through_hole = spans(&ring, &along_x(-10.0));
+        assert_eq!(through_hole.len(), 2);
+        for ((enter, exit), (expected_enter, expected_exit)) in through_hole.into_iter().zip([(7.5, 8.5), (11.5, 12.5)]) {
+            assert!((enter - expected_enter).abs() < 1e-6 && (exit - expected_exit).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn default_intervals_drop_an_open_surfaces_single_crossing() {
+        let disk = Disk::new(Vector3D::ZERO, Vector3D::new(1.0, 0.0, 0.0), 1.0, MATERIAL);
+        assert_eq!(disk.hit(&along_x(-10.0), 0.0, f64::INFINITY).map(|hit| hit.t), Some(10.0));
+        assert!(spans(&disk, &along_x(-10.0)).is_empty());
+    }
+
+    #[test]
+    fn default_intervals_stop_after_max_crossings() {
+        let spans = spans(&Slabs, &along_x(0.0));
+        assert_eq!(spans.len(), MAX_CROSSINGS / 2);
+        assert_eq!(spans[0], (0.0, 1.0));
+        assert_eq!(spans[spans.len() - 1], (MAX_CROSSINGS as f64 - 2.0, MAX_CROSSINGS as f64 - 1.0));
+    }
 }
@@ -0,0 +1,131 @@
+use crate::hittable::{tangent_frame, EmissionSample, HitRecord, Hittable};
+use crate::ray::Ray;
+use crate::transform::Transform;
+use crate::vector3d::{Normal3D, Point3D, Vector3D};
+use std::sync::Arc;
+
+/// A shared object placed in the scene with its own transform.
+///
+/// Rays are moved into object space instead of transforming the geometry,
+/// so any number of instances can point at the same object.
+#[derive(Clone)]
+pub struct Instance {
+    pub object: Arc<dyn Hittable>,
+    pub transform: Transform,
+}
+
+impl Instance {
+    pub fn new(object: Arc<dyn Hittable>, transform: Transform) -> Instance {
+        Instance { object, transform }
+    }
+}
+
+impl Hittable for Instance {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let local_ray = self.transform.inverse().transform_ray(ray);
+        let hit = self.object.hit(&local_ray, t_min, t_max)?;
+        let normal = (self.transform * Normal3D(hit.normal)).normalize().0;
+        let (tangent, bitangent) = tangent_frame(
+            normal,
+            self.transform.matrix.transform_vector(hit.tangent),
+            self.transform.matrix.transform_vector(hit.bitangent),
+        );
+
+        Some(HitRecord {
+            point: ray.at(hit.t),
+            normal,
+            tangent,
+            bitangent,
+            ..hit
+        })
+    }
+
+    /// Samples the object from the origin moved into its space. Solid angles
+    /// don't survive the transform, so the pdf is carried out per unit area,
+    /// which the transform only stretches.
+    fn sample_emission(&self, origin: Vector3D, u1: f64, u2: f64) -> Option<EmissionSample> {
+        let local_origin = (self.transform.inverse() * Point3D(origin)).0;
+        let local = self.object.sample_emission(local_origin, u1, u2)?;
+        let area_pdf = local.pdf * cos_over_distance_sq(local.point, local.normal, local_origin);
+
+        // Area of the image of a unit square on the surface
+        let (tangent, bitangent) = local.normal.orthonormal_basis();
+        let stretched = (self.transform * tangent).cross(self.transform * bitangent);
+        let stretch = stretched.magnitude();
+
+        let point = (self.transform * Point3D(local.point)).0;
+        let normal = stretched * (1.0 / stretch);
+        let cos_over_distance_sq = cos_over_distance_sq(point, normal, origin);
+        if area_pdf <= 0.0 || stretch <= 0.0 || cos_over_distance_sq <= 0.0 {
+            return None;
+        }
+        Some(EmissionSample {
+            point,
+            normal,
+            radiance: local.radiance,
+            pdf: area_pdf / (stretch * cos_over_distance_sq),
+        })
+    }
+}
+
+/// Converts a density per unit area at `point` on a surface with `normal`
+/// to one per unit solid angle seen from `origin`, when divided by it
+#[inline]
+fn cos_over_distance_sq(point: Vector3D, normal: Vector3D, origin: Vector3D) -> f64 {
+    let offset = point - origin;
+    let distance_sq = offset.magnitude_squared();
+    normal.dot(offset).abs() / (distance_sq * distance_sq.sqrt())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rectangle::Rectangle;
+    use crate::sphere::{Color, Material, Sphere};
+
+    const GLOW: Material = Material {
+        color: Color { r: 1.0, g: 1.0, b: 1.0 },
+        diffuse: 1.0,
+        specular: 0.0,
+        shininess: 1.0,
+        reflectivity: 0.0,
+        pbr: None,
+        emission: Color { r: 2.0, g: 1.0, b: 0.5 },
+        texture: None,
+        normal_map: None,
+    };
+
+    fn assert_same(instanced: EmissionSample, direct: EmissionSample) {
+        assert!((instanced.point - direct.point).magnitude() < 1e-9, "{:?} != {:?}", instanced, direct);
+        assert!((instanced.normal.dot(direct.normal).abs() - 1.0).abs() < 1e-9);
+        assert!((instanced.pdf / direct.pdf - 1.0).abs() < 1e-9, "{} != {}", instanced.pdf, direct.pdf);
+        assert_eq!(instanced.radiance.r, direct.radiance.r);
+    }
+
+    #[test]
+    fn emission_samples_carry_through_the_transform() {
+        let center = Vector3D::new(1.0, -2.0, 5.0);
+        let sphere = Instance::new(
+            Arc::new(Sphere::new(Vector3D::ZERO, 1.0, GLOW)),
+            Transform::translate(center) * Transform::scale(Vector3D::splat(2.0)),
+        );
+        let direct = Sphere::new(center, 2.0, GLOW);
+
+        // Stretched one way only, so the area and normals both change
+        let rectangle = Instance::new(
+            Arc::new(Rectangle::new(Vector3D::ZERO, Vector3D::new(1.0, 0.0, 0.0), Vector3D::new(0.0, 1.0, 1.0), GLOW)),
+            Transform::translate(center) * Transform::scale(Vector3D::new(3.0, 1.0, 0.5)),
+        );
+        let stretched = Rectangle::new(center, Vector3D::new(3.0, 0.0, 0.0), Vector3D::new(0.0, 1.0, 0.5), GLOW);
+
+        for origin in [Vector3D::ZERO, Vector3D::new(4.0, 3.0, -1.0), center + Vector3D::new(0.5, 0.2, 0.0)] {
+            for (u1, u2) in [(0.1, 0.3), (0.5, 0.5), (0.9, 0.75)] {
+                assert_same(sphere.sample_emission(origin, u1, u2).unwrap(), direct.sample_emission(origin, u1, u2).unwrap());
+                assert_same(
+                    rectangle.sample_emission(origin, u1, u2).unwrap(),
+                    stretched.sample_emission(origin, u1, u2).unwrap(),
+                );
+            }
+        }
+    }
+}
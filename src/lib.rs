@@ -0,0 +1,37 @@
+pub mod animation;
+pub mod aov;
+pub mod camera_rig;
+pub mod checkpoint;
+pub mod cone;
+pub mod csg;
+pub mod cuboid;
+pub mod cylinder;
+pub mod denoise;
+pub mod disk;
+pub mod film;
+pub mod framebuffer;
+pub mod gltf_import;
+pub mod gpu_renderer;
+pub mod hittable;
+pub mod instance;
+pub mod mesh;
+pub mod mesh_import;
+pub mod normal_map;
+pub mod pbr;
+pub mod plane;
+pub mod ray;
+pub mod rectangle;
+pub mod sampler;
+pub mod save_image;
+pub mod scene;
+pub mod scene_file;
+pub mod sdf;
+pub mod sphere;
+pub mod stereo;
+pub mod temporal;
+pub mod texture;
+pub mod tiles;
+pub mod torus;
+pub mod transform;
+pub mod vector3d;
+pub mod volume;
@@ -1,236 +1,558 @@
-use gif::{Frame, Repeat};
-use image::RgbaImage;
-use std::fs::File;
+use std::io::Write;
 use std::path::Path;
-
-mod gpu_renderer;
-mod hittable;
-mod plane;
-mod ray;
-mod save_image;
-mod scene;
-mod sphere;
-mod vector3d;
-
-use crate::plane::Plane;
-use crate::save_image::save_image;
-use crate::scene::{Camera, Light, Scene};
-use crate::sphere::{Color, Material, Sphere};
-use crate::vector3d::Vector3D;
+use std::time::{Duration, Instant};
+
+use rust_ray_tracing::aov::Aov;
+use rust_ray_tracing::checkpoint::{
+    camera_hash, content_hash, load_frame, load_history, save_frame, save_history, Checkpoint,
+};
+use rust_ray_tracing::denoise::{Denoiser, Guides};
+use rust_ray_tracing::film::{Film, Filter, FilterKind};
+use rust_ray_tracing::framebuffer::Framebuffer;
+use rust_ray_tracing::sampler::{Sampler, SamplerKind};
+use rust_ray_tracing::save_image::{load_image, save_exr, save_image, GifWriter};
+use rust_ray_tracing::scene::Camera;
+use rust_ray_tracing::scene_file::{SceneDescription, SceneFileError};
+use rust_ray_tracing::stereo::{pack, StereoCamera, StereoLayout};
+use rust_ray_tracing::temporal::TemporalAccumulator;
+use rust_ray_tracing::tiles::{CropWindow, Tile, TileOrder, TileProgress, Tiling};
+
+/// Scene rendered when no `--scene` is given
+const DEMO_SCENE: &str = include_str!("../scenes/demo.scene");
 
 fn main() {
     let args: Vec<String> = std::env::args().collect();
     let use_gpu = args.contains(&"--gpu".to_string());
     let use_adaptive = args.contains(&"--adaptive".to_string());
 
+    let options = parse_options(&args);
+
     if use_gpu {
         println!("Using GPU rendering");
-        pollster::block_on(main_gpu(use_adaptive));
+        pollster::block_on(main_gpu(use_adaptive, &options));
     } else {
         println!("Using CPU rendering (use --gpu for GPU mode)");
-        main_cpu();
+        main_cpu(&options);
     }
 }
 
-fn main_cpu() {
-    main_cpu_with_settings(800, 600, 36, 2, "animation.gif");
+/// Per-render settings taken from the command line
+struct RenderOptions {
+    sampler: SamplerKind,
+    filter: Filter,
+    tile_size: u32,
+    tile_order: TileOrder,
+    checkpoint_dir: String,
+    checkpoint_interval: Option<Duration>,
+    resume: bool,
+    aovs: Vec<Aov>,
+    aov_format: AovFormat,
+    /// Number of à-trous passes, if denoising
+    denoise: Option<u32>,
+    temporal: bool,
+    /// Scene and animation to render
+    scene: SceneDescription,
+    /// `content_hash` of the scene file, recorded in checkpoints
+    scene_hash: u64,
+    /// Packing of the eyes, for stereo renders
+    stereo: Option<StereoLayout>,
+    interpupillary_distance: Option<f64>,
+    convergence: Option<f64>,
+    /// Region to re-render, leaving the rest of each frame alone
+    crop: Option<CropWindow>,
+    /// Paste cropped renders over the existing frames rather than black
+    composite: bool,
 }
 
-fn main_cpu_with_settings(width: u32, height: u32, num_frames: usize, samples: u32, output_file: &str) {
-    println!("Rendering {} frames at {}x{} with {} samples per pixel",
-             num_frames, width, height, samples);
-    println!("Using parallel rendering with rayon...");
+impl RenderOptions {
+    /// Stereo pair around `camera`, with the defaults of `StereoCamera::new`
+    /// unless overridden on the command line
+    fn stereo_camera(&self, camera: Camera) -> StereoCamera {
+        let mut stereo = StereoCamera::new(camera);
+        if let Some(distance) = self.interpupillary_distance {
+            stereo.interpupillary_distance = distance;
+        }
+        if let Some(convergence) = self.convergence {
+            stereo.convergence = convergence;
+        }
+        stereo
+    }
+
+    /// Size of the saved frames for renders of `width` x `height`
+    fn frame_size(&self, width: u32, height: u32) -> (u32, u32) {
+        self.stereo.map_or((width, height), |layout| layout.frame_size(width, height))
+    }
+
+    /// Pixels of the `--crop` window in a `width` x `height` render
+    fn crop_pixels(&self, width: u32, height: u32) -> Option<Tile> {
+        let crop = self.crop?.to_pixels(width, height);
+        if crop.is_empty() {
+            eprintln!("The --crop window lies outside the {}x{} image", width, height);
+            std::process::exit(1);
+        }
+        Some(crop)
+    }
+
+    /// AOVs to render: the requested ones plus the denoiser's guides
+    fn rendered_aovs(&self) -> Vec<Aov> {
+        let mut aovs = self.aovs.clone();
+        if self.denoise.is_some() {
+            for guide in [Aov::Normal, Aov::Albedo, Aov::Depth] {
+                if !aovs.contains(&guide) {
+                    aovs.push(guide);
+                }
+            }
+        }
+        aovs
+    }
+}
+
+/// How AOVs are written next to each frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AovFormat {
+    /// One multi-layer `frame_N.exr` holding the beauty image and all AOVs
+    Exr,
+    /// One `frame_N_<aov>.ppm` preview image per AOV
+    Ppm,
+}
+
+impl AovFormat {
+    fn from_name(name: &str) -> Option<AovFormat> {
+        match name {
+            "exr" => Some(AovFormat::Exr),
+            "ppm" => Some(AovFormat::Ppm),
+            _ => None,
+        }
+    }
+}
 
-    // Create scene with enhanced materials
-    let mut scene = Scene {
-        background_color: Color {
-            r: 0.2,
-            g: 0.3,
-            b: 0.5,
-        },
-        objects: Vec::new(),
-        lights: Vec::new(),
+fn parse_options(args: &[String]) -> RenderOptions {
+    let sampler = parse_arg(args, "--sampler", "random, stratified, halton, sobol or blue-noise", SamplerKind::from_name)
+        .unwrap_or(SamplerKind::Random);
+
+    let filter_kind = parse_arg(args, "--filter", "box, tent, gaussian, mitchell or lanczos", FilterKind::from_name)
+        .unwrap_or(FilterKind::Box);
+    let filter_radius = parse_arg(args, "--filter-radius", "a radius in pixels", |value| value.parse().ok())
+        .unwrap_or(filter_kind.default_radius());
+
+    let tile_size = parse_arg(args, "--tile-size", "a size in pixels", |value| value.parse().ok())
+        .unwrap_or(32);
+    let tile_order = parse_arg(args, "--tile-order", "scanline, hilbert or spiral", TileOrder::from_name)
+        .unwrap_or(TileOrder::Hilbert);
+
+    let resume = args.contains(&"--resume".to_string());
+    let checkpoint_dir = arg_value(args, "--checkpoint-dir").unwrap_or("checkpoint").to_string();
+    let checkpoint_interval = parse_arg(args, "--checkpoint-interval", "a number of seconds", |value| {
+        value.parse::<f64>().ok().filter(|seconds| *seconds >= 0.0)
+    })
+    .or(if resume { Some(60.0) } else { None })
+    .map(Duration::from_secs_f64);
+
+    let aovs = parse_arg(args, "--aov", "a comma separated list of depth, normal, albedo, direct, reflected, shadow, id or all",
+                         Aov::parse_list)
+        .unwrap_or_default();
+    let aov_format = parse_arg(args, "--aov-format", "exr or ppm", AovFormat::from_name)
+        .unwrap_or(AovFormat::Exr);
+
+    let denoise = parse_arg(args, "--denoise-iterations", "a number of passes", |value| value.parse().ok())
+        .or(if args.contains(&"--denoise".to_string()) { Some(Denoiser::default().iterations) } else { None });
+
+    let (scene, scene_hash) = match arg_value(args, "--scene") {
+        Some(path) => std::fs::read_to_string(path)
+            .map_err(SceneFileError::from)
+            .and_then(|source| {
+                let mut scene: SceneDescription = source.parse()?;
+                scene.load_assets(Path::new(path).parent().unwrap_or(Path::new(".")))?;
+                Ok((scene, content_hash(source.as_bytes())))
+            })
+            .unwrap_or_else(|e| {
+                eprintln!("Could not load {}: {}", path, e);
+                std::process::exit(1);
+            }),
+        None => (
+            DEMO_SCENE.parse().expect("the built-in demo scene is valid"),
+            content_hash(DEMO_SCENE.as_bytes()),
+        ),
     };
 
-    // Add ground plane
-    let ground = Plane::new(
-        Vector3D::new(0.0, -1.0, 0.0),
-        Vector3D::new(0.0, 1.0, 0.0),
-        Material {
-            color: Color {
-                r: 0.5,
-                g: 0.5,
-                b: 0.5,
-            },
-            diffuse: 0.8,
-            specular: 0.1,
-            shininess: 10.0,
-            reflectivity: 0.1,
-        },
-    );
-    scene.add_object(Box::new(ground));
-
-    // Add central blue sphere (matte)
-    let sphere1 = Sphere::new(
-        Vector3D::new(0.0, 0.0, 5.0),
-        1.0,
-        Material {
-            color: Color {
-                r: 0.2,
-                g: 0.4,
-                b: 1.0,
-            },
-            diffuse: 0.7,
-            specular: 0.3,
-            shininess: 32.0,
-            reflectivity: 0.1,
-        },
-    );
-    scene.add_object(Box::new(sphere1));
-
-    // Add red reflective sphere
-    let sphere2 = Sphere::new(
-        Vector3D::new(-2.5, 0.5, 4.0),
-        0.8,
-        Material {
-            color: Color {
-                r: 1.0,
-                g: 0.2,
-                b: 0.2,
-            },
-            diffuse: 0.3,
-            specular: 0.9,
-            shininess: 100.0,
-            reflectivity: 0.6,
-        },
-    );
-    scene.add_object(Box::new(sphere2));
-
-    // Add green sphere
-    let sphere3 = Sphere::new(
-        Vector3D::new(2.5, 0.3, 4.5),
-        0.7,
-        Material {
-            color: Color {
-                r: 0.2,
-                g: 1.0,
-                b: 0.3,
-            },
-            diffuse: 0.6,
-            specular: 0.5,
-            shininess: 64.0,
-            reflectivity: 0.2,
-        },
-    );
-    scene.add_object(Box::new(sphere3));
-
-    // Add small yellow sphere
-    let sphere4 = Sphere::new(
-        Vector3D::new(0.0, 1.5, 3.5),
-        0.4,
-        Material {
-            color: Color {
-                r: 1.0,
-                g: 0.9,
-                b: 0.2,
-            },
-            diffuse: 0.5,
-            specular: 0.8,
-            shininess: 128.0,
-            reflectivity: 0.4,
-        },
-    );
-    scene.add_object(Box::new(sphere4));
-
-    // Create camera with proper FOV
-    let aspect_ratio = width as f64 / height as f64;
-    let camera = Camera::new(
-        Vector3D::new(0.0, 1.0, 0.0),
-        Vector3D::new(0.0, 0.5, 5.0),
-        60.0,
-        aspect_ratio,
-    );
+    let stereo = parse_arg(args, "--stereo", "side-by-side or over-under", StereoLayout::from_name);
+    let interpupillary_distance = parse_arg(args, "--ipd", "a distance in scene units", |value| {
+        value.parse::<f64>().ok().filter(|distance| *distance >= 0.0)
+    });
+    let convergence = parse_arg(args, "--convergence", "a distance in scene units or inf", |value| {
+        value.parse::<f64>().ok().filter(|distance| *distance > 0.0)
+    });
+    if stereo.is_some() && (resume || checkpoint_interval.is_some() || args.contains(&"--temporal".to_string())) {
+        eprintln!("--stereo can't be combined with checkpoints or --temporal");
+        std::process::exit(1);
+    }
 
-    let mut frames = Vec::new();
+    let crop = parse_arg(args, "--crop", "x0,y0,x1,y1 in pixels, or fractions of the image like 0.25,0.25,0.75,0.75",
+                         CropWindow::parse);
+    let composite = args.contains(&"--composite".to_string());
+    if crop.is_some() && (stereo.is_some() || resume || checkpoint_interval.is_some()
+                          || args.contains(&"--temporal".to_string())) {
+        eprintln!("--crop can't be combined with --stereo, checkpoints or --temporal");
+        std::process::exit(1);
+    }
+    if composite && crop.is_none() {
+        eprintln!("--composite needs a --crop window");
+        std::process::exit(1);
+    }
 
-    for frame_index in 0..num_frames {
-        println!("Rendering frame {}/{}...", frame_index + 1, num_frames);
+    RenderOptions {
+        sampler,
+        filter: Filter::new(filter_kind, filter_radius),
+        tile_size,
+        tile_order,
+        checkpoint_dir,
+        checkpoint_interval,
+        resume,
+        aovs,
+        aov_format,
+        denoise,
+        temporal: args.contains(&"--temporal".to_string()),
+        scene,
+        scene_hash,
+        stereo,
+        interpupillary_distance,
+        convergence,
+        crop,
+        composite,
+    }
+}
 
-        // Animate rotating light
-        let angle = frame_index as f64 * 2.0 * std::f64::consts::PI / num_frames as f64;
-        let light_x = angle.cos() * 3.0;
-        let light_z = 5.0 + angle.sin() * 2.0;
-        let light_y = 2.0 + angle.sin() * 0.5;
+/// Packs the images and AOVs rendered for each eye of a stereo frame
+fn pack_eyes(
+    [(left, left_aovs), (right, right_aovs)]: [(Framebuffer, Vec<(Aov, Framebuffer)>); 2],
+    layout: StereoLayout,
+) -> (Framebuffer, Vec<(Aov, Framebuffer)>) {
+    let aovs = left_aovs
+        .iter()
+        .zip(&right_aovs)
+        .map(|((aov, left), (_, right))| (*aov, pack(left, right, layout)))
+        .collect();
+    (pack(&left, &right, layout), aovs)
+}
 
-        scene.lights.clear();
-        scene.lights.push(Light {
-            position: Vector3D::new(light_x, light_y, light_z),
-            intensity: 1.0,
-        });
+/// Places the cropped render of frame `frame_index` at `crop` in a full
+/// frame: over the `frame_N.ppm` already on disk with `--composite`,
+/// otherwise over black
+fn uncrop_frame(frame_index: usize, image: &Framebuffer, crop: Tile, width: u32, height: u32,
+                options: &RenderOptions) -> Framebuffer {
+    let filename = format!("frame_{}.ppm", frame_index);
+    let existing = if options.composite {
+        match load_image(&filename) {
+            Ok(frame) if frame.width == width && frame.height == height => Some(frame),
+            Ok(frame) => {
+                eprintln!("{} is {}x{} rather than {}x{}, compositing over black",
+                          filename, frame.width, frame.height, width, height);
+                None
+            }
+            Err(e) => {
+                eprintln!("Could not read {}: {}, compositing over black", filename, e);
+                None
+            }
+        }
+    } else {
+        None
+    };
 
-        // Add a secondary static light
-        scene.lights.push(Light {
-            position: Vector3D::new(-3.0, 4.0, 2.0),
-            intensity: 0.5,
-        });
+    let mut frame = existing.unwrap_or_else(|| Framebuffer::new(width, height, 3));
+    frame.composite(image, crop.x0, crop.y0);
+    frame
+}
 
-        let image = scene.trace(&camera, width, height, samples);
+/// Denoises a finished frame if requested and writes the AOVs the user asked for
+fn finish_frame(
+    frame_index: usize,
+    image: Framebuffer,
+    aovs: &[(Aov, Framebuffer)],
+    options: &RenderOptions,
+) -> Framebuffer {
+    let image = match options.denoise {
+        Some(iterations) => Denoiser {
+            iterations,
+            ..Denoiser::default()
+        }
+        .denoise(&image, &Guides::from_aovs(aovs)),
+        None => image,
+    };
 
-        let mut frame_buffer = RgbaImage::new(width, height);
+    let requested: Vec<(Aov, Framebuffer)> = aovs
+        .iter()
+        .filter(|(aov, _)| options.aovs.contains(aov))
+        .cloned()
+        .collect();
+    save_aovs(frame_index, &image, &requested, options.aov_format);
 
-        for (x, y, pixel) in frame_buffer.enumerate_pixels_mut() {
-            let color = &image[y as usize][x as usize];
-            let rgba_color = image::Rgba([
-                (color.r * 255.0) as u8,
-                (color.g * 255.0) as u8,
-                (color.b * 255.0) as u8,
-                255,
-            ]);
-            *pixel = rgba_color;
+    image
+}
+
+/// Writes the AOVs of frame `frame_index` in the requested format
+fn save_aovs(frame_index: usize, beauty: &Framebuffer, aovs: &[(Aov, Framebuffer)], format: AovFormat) {
+    if aovs.is_empty() {
+        return;
+    }
+
+    match format {
+        AovFormat::Exr => {
+            let filename = format!("frame_{}.exr", frame_index);
+            if let Err(e) = save_exr(beauty, aovs, &filename) {
+                eprintln!("Failed to write {}: {}", filename, e);
+            }
+        }
+        AovFormat::Ppm => {
+            for (aov, image) in aovs {
+                let filename = format!("frame_{}_{}.ppm", frame_index, aov.name());
+                if let Err(e) = save_image(&aov.preview(image), &filename) {
+                    eprintln!("Failed to write {}: {}", filename, e);
+                }
+            }
         }
+    }
+}
 
-        let filename = format!("frame_{}.ppm", frame_index);
-        let _ = save_image(&image, width, height, &filename);
+fn arg_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == flag)
+        .and_then(|index| args.get(index + 1))
+        .map(|value| value.as_str())
+}
+
+/// Parses the value following `flag`, exiting with a message if it is invalid
+fn parse_arg<T>(args: &[String], flag: &str, expected: &str, parse: impl Fn(&str) -> Option<T>) -> Option<T> {
+    let value = arg_value(args, flag)?;
+    match parse(value) {
+        Some(parsed) => Some(parsed),
+        None => {
+            eprintln!("Invalid value '{}' for {} (expected {})", value, flag, expected);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn main_cpu(options: &RenderOptions) {
+    main_cpu_with_settings(800, 600, 2, options, "animation.gif");
+}
+
+fn main_cpu_with_settings(
+    width: u32,
+    height: u32,
+    samples: u32,
+    options: &RenderOptions,
+    output_file: &str,
+) {
+    let num_frames = options.scene.frames;
+    println!("Rendering {} frames at {}x{} with {} samples per pixel ({} sampler, {} filter r={})",
+             num_frames, width, height, samples, options.sampler.name(),
+             options.filter.kind.name(), options.filter.radius);
+    println!("Using parallel rendering with rayon...");
+
+    let aspect_ratio = width as f64 / height as f64;
+    let crop = options.crop_pixels(width, height);
+    if let Some(crop) = crop {
+        println!("Rendering only pixels {},{} to {},{}", crop.x0, crop.y0, crop.x1, crop.y1);
+    }
 
-        frames.push(frame_buffer);
+    let sampler = Sampler::new(options.sampler, samples);
+    let settings = Checkpoint {
+        width,
+        height,
+        samples,
+        num_frames,
+        sampler: options.sampler,
+        filter: options.filter,
+        tile_size: options.tile_size,
+        tile_order: options.tile_order,
+        aovs: options.rendered_aovs(),
+        temporal: options.temporal,
+        scene_hash: options.scene_hash,
+        camera_hash: camera_hash(&options.scene.camera(aspect_ratio)),
+        frame_index: 0,
+        samples_done: 0,
+    };
+    let checkpoint_dir = Path::new(&options.checkpoint_dir);
+
+    let mut resumed = None;
+    if options.resume {
+        match Checkpoint::load(checkpoint_dir) {
+            Ok((checkpoint, film)) if checkpoint.is_compatible(&settings) => {
+                println!("Resuming from frame {} after {}/{} samples",
+                         checkpoint.frame_index + 1, checkpoint.samples_done, samples);
+                resumed = Some((checkpoint, film));
+            }
+            Ok(_) => eprintln!("Checkpoint in {} was made with different settings, starting over",
+                               checkpoint_dir.display()),
+            Err(e) => eprintln!("Could not read checkpoint in {}: {}, starting over",
+                                checkpoint_dir.display(), e),
+        }
     }
 
-    let path = Path::new(output_file);
-    let file = File::create(&path).unwrap();
-    let mut encoder = gif::Encoder::new(file, width as u16, height as u16, &[]).unwrap();
-    encoder.set_repeat(Repeat::Infinite).unwrap();
+    let first_frame = resumed.as_ref().map_or(0, |(checkpoint, _)| checkpoint.frame_index);
+    let mut frames = Vec::new();
 
-    for (i, frame) in frames.iter().enumerate() {
-        println!("Encoding frame {}/{}...", i + 1, num_frames);
+    for frame_index in 0..first_frame {
+        let rgb = load_frame(checkpoint_dir, frame_index).unwrap_or_else(|e| {
+            eprintln!("Checkpointed frame {} in {} is missing: {}", frame_index + 1, checkpoint_dir.display(), e);
+            std::process::exit(1);
+        });
+        let frame = Framebuffer::from_raw(width, height, 3, width as usize * 3, rgb).unwrap_or_else(|| {
+            eprintln!("Checkpointed frame {} in {} has the wrong size", frame_index + 1, checkpoint_dir.display());
+            std::process::exit(1);
+        });
+        frames.push(frame);
+    }
 
-        let rgba_data = frame.as_raw();
-        let mut rgb_data = Vec::with_capacity((width * height * 3) as usize);
+    let mut temporal = options.temporal.then(|| TemporalAccumulator::new(width, height));
+    if let Some(accumulator) = &mut temporal {
+        accumulator.moving_objects = options.scene.moving_objects();
+        if first_frame > 0 {
+            if let Err(e) = load_history(checkpoint_dir, first_frame, accumulator) {
+                eprintln!("Could not restore temporal history: {}, accumulation restarts", e);
+            }
+        }
+    }
+
+    let mut last_checkpoint = Instant::now();
+
+    for frame_index in first_frame..num_frames {
+        println!("Rendering frame {}/{}...", frame_index + 1, num_frames);
+
+        let still = options.scene.evaluate(frame_index as f64);
+        let scene = still.to_scene();
+        let camera = still.camera(aspect_ratio);
+
+        if let Some(layout) = options.stereo {
+            let [left, right] = options.stereo_camera(camera).eyes();
+            let views = [(left, "left"), (right, "right")].map(|(eye, name)| {
+                let mut film = Film::with_aovs(width, height, options.filter, &settings.aovs);
+                let report_progress = |progress: TileProgress| {
+                    print!("\r  {} eye: {:5.1}% ({}/{} tiles)", name, progress.fraction() * 100.0,
+                           progress.tiles_completed, progress.tiles_total);
+                    let _ = std::io::stdout().flush();
+                };
+                let tiling = Tiling {
+                    tile_size: options.tile_size,
+                    order: options.tile_order,
+                    progress: Some(&report_progress),
+                    cancel: None,
+                };
+                scene.render_samples(&mut film, &eye, &sampler, 0..sampler.samples_per_pixel, &tiling);
+                println!();
+                (film.resolve(), film.resolve_aovs())
+            });
+
+            let (image, aovs) = pack_eyes(views, layout);
+            let image = finish_frame(frame_index, image, &aovs, options);
+            let _ = save_image(&image, &format!("frame_{}.ppm", frame_index));
+            frames.push(image.to_rgb8());
+            continue;
+        }
 
-        for chunk in rgba_data.chunks(4) {
-            rgb_data.push(chunk[0]);
-            rgb_data.push(chunk[1]);
-            rgb_data.push(chunk[2]);
+        let (mut film, first_sample) = match resumed.take() {
+            Some((checkpoint, film)) => (film, checkpoint.samples_done),
+            None => match crop {
+                Some(crop) => (Film::cropped(width, height, options.filter, &settings.aovs, crop), 0),
+                None => (Film::with_aovs(width, height, options.filter, &settings.aovs), 0),
+            },
+        };
+
+        // With temporal accumulation every frame continues the sample
+        // sequence, so the history gathers new jitter positions
+        let sample_offset = if options.temporal { frame_index as u32 * samples } else { 0 };
+
+        // One sample index per pass, so a resumed frame accumulates in
+        // exactly the same order as an uninterrupted one
+        for sample_index in first_sample..sampler.samples_per_pixel {
+            let report_progress = |progress: TileProgress| {
+                print!("\r  sample {}/{}: {:5.1}% ({}/{} tiles)", sample_index + 1,
+                       sampler.samples_per_pixel, progress.fraction() * 100.0,
+                       progress.tiles_completed, progress.tiles_total);
+                let _ = std::io::stdout().flush();
+            };
+            let tiling = Tiling {
+                tile_size: options.tile_size,
+                order: options.tile_order,
+                progress: Some(&report_progress),
+                cancel: None,
+            };
+
+            let pass = sample_offset + sample_index;
+            scene.render_samples(&mut film, &camera, &sampler, pass..pass + 1, &tiling);
+
+            if let Some(interval) = options.checkpoint_interval {
+                if last_checkpoint.elapsed() >= interval && sample_index + 1 < sampler.samples_per_pixel {
+                    let checkpoint = Checkpoint {
+                        frame_index,
+                        samples_done: sample_index + 1,
+                        ..settings.clone()
+                    };
+                    if let Err(e) = checkpoint.save(checkpoint_dir, &film) {
+                        eprintln!("\nFailed to write checkpoint: {}", e);
+                    }
+                    last_checkpoint = Instant::now();
+                }
+            }
         }
+        println!();
 
-        let mut gif_frame = Frame::from_rgb(width as u16, height as u16, &rgb_data);
-        gif_frame.delay = 3;
-        encoder.write_frame(&gif_frame).unwrap();
+        let aovs = film.resolve_aovs();
+        let mut image = film.resolve();
+        if let Some(accumulator) = &mut temporal {
+            let surfaces = accumulator.surfaces(&scene, &camera);
+            image = accumulator.accumulate(&image, &surfaces, &camera);
+        }
+        let mut image = finish_frame(frame_index, image, &aovs, options);
+        if let Some(crop) = crop {
+            image = uncrop_frame(frame_index, &image, crop, width, height, options);
+        }
+        let frame = image.to_rgb8();
+
+        let filename = format!("frame_{}.ppm", frame_index);
+        let _ = save_image(&image, &filename);
+
+        if options.checkpoint_interval.is_some() {
+            let checkpoint = Checkpoint {
+                frame_index: frame_index + 1,
+                samples_done: 0,
+                ..settings.clone()
+            };
+            if let Err(e) = save_frame(checkpoint_dir, frame_index, frame.as_slice())
+                .and_then(|_| match &temporal {
+                    Some(accumulator) => save_history(checkpoint_dir, frame_index + 1, accumulator),
+                    None => Ok(()),
+                })
+                .and_then(|_| checkpoint.save(checkpoint_dir, &film))
+            {
+                eprintln!("Failed to write checkpoint: {}", e);
+            }
+            last_checkpoint = Instant::now();
+        }
+
+        frames.push(frame);
     }
 
+    let (frame_width, frame_height) = options.frame_size(width, height);
+    write_animation(output_file, frame_width, frame_height, &frames);
     println!("Animation saved as {}", output_file);
+
+    if options.checkpoint_interval.is_some() {
+        let _ = std::fs::remove_dir_all(checkpoint_dir);
+    }
 }
 
-async fn main_gpu(use_adaptive: bool) {
-    use crate::gpu_renderer::GpuRenderer;
+async fn main_gpu(use_adaptive: bool, options: &RenderOptions) {
+    use rust_ray_tracing::gpu_renderer::GpuRenderer;
 
     let width = 1920;
     let height = 1080;
-    let num_frames = 36;
+    let num_frames = options.scene.frames;
     let samples = 16;
     let output_file = "animation_gpu.gif";
 
-    println!("Rendering {} frames at {}x{} with {} samples per pixel{}",
-             num_frames, width, height, samples,
+    println!("Rendering {} frames at {}x{} with {} samples per pixel ({} sampler){}",
+             num_frames, width, height, samples, options.sampler.name(),
              if use_adaptive { " (adaptive quality)" } else { "" });
 
     let mut renderer = match GpuRenderer::new().await {
@@ -238,11 +560,27 @@ async fn main_gpu(use_adaptive: bool) {
         Err(e) => {
             eprintln!("GPU initialization failed: {}", e);
             eprintln!("Falling back to CPU rendering...");
-            return main_cpu_with_settings(width, height, num_frames, samples, output_file);
+            return main_cpu_with_settings(width, height, samples, options, output_file);
         }
     };
 
     println!("Using GPU: {}", renderer.gpu_name());
+    if options.temporal {
+        eprintln!("--temporal is only supported by the CPU renderer and is ignored");
+    }
+    let scene = &options.scene;
+    if !scene.imports.is_empty() || scene.fog.is_some() || !scene.volumes.is_empty() {
+        eprintln!("Imported models, fog and volumes are only drawn by the CPU renderer and are left out");
+    }
+    let crop = options.crop_pixels(width, height);
+    if crop.is_some() && use_adaptive {
+        eprintln!("--adaptive is ignored with --crop");
+    }
+    let use_adaptive = use_adaptive && crop.is_none();
+    let rendered_aovs = options.rendered_aovs();
+    if use_adaptive && !rendered_aovs.is_empty() {
+        eprintln!("AOVs are not read back in adaptive mode; --aov is ignored and --denoise runs without guides");
+    }
 
     let mut frames = Vec::new();
 
@@ -254,130 +592,118 @@ async fn main_gpu(use_adaptive: bool) {
 
         println!("Rendering frame {}/{}...", frame_index + 1, num_frames);
 
-        let angle = frame_index as f32 * 2.0 * std::f32::consts::PI / num_frames as f32;
-        let light_x = angle.cos() * 3.0;
-        let light_z = 5.0 + angle.sin() * 2.0;
-        let light_y = 2.0 + angle.sin() * 0.5;
-
-        let spheres_data = vec![
-            (
-                ([0.0, 0.0, 5.0], 1.0),
-                ([0.2, 0.4, 1.0], 0.7, 0.3, 32.0, 0.1),
-            ),
-            (
-                ([-2.5, 0.5, 4.0], 0.8),
-                ([1.0, 0.2, 0.2], 0.3, 0.9, 100.0, 0.6),
-            ),
-            (
-                ([2.5, 0.3, 4.5], 0.7),
-                ([0.2, 1.0, 0.3], 0.6, 0.5, 64.0, 0.2),
-            ),
-            (
-                ([0.0, 1.5, 3.5], 0.4),
-                ([1.0, 0.9, 0.2], 0.5, 0.8, 128.0, 0.4),
-            ),
-        ];
-
-        let planes_data = vec![
-            (
-                ([0.0, -1.0, 0.0], [0.0, 1.0, 0.0]),
-                ([0.5, 0.5, 0.5], 0.8, 0.1, 10.0, 0.1),
-            ),
-        ];
-
-        let lights_data = vec![
-            ([light_x, light_y, light_z], 1.0),
-            ([-3.0, 4.0, 2.0], 0.5),
-        ];
-
-        let background_color = [0.2, 0.3, 0.5];
-
-        let image = if use_adaptive {
-            match renderer.render_adaptive(
-                width,
-                height,
-                samples,
-                [0.0, 1.0, 0.0],
-                [0.0, 0.5, 5.0],
-                60.0,
-                &spheres_data,
-                &planes_data,
-                &lights_data,
-                background_color,
-                &|current, target| {
-                    if current < target {
-                        println!("  Progressive quality: {}/{} samples", current, target);
+        let still = options.scene.evaluate(frame_index as f64);
+        let spheres_data = still.gpu_spheres();
+        let planes_data = still.gpu_planes();
+        let shapes_data = still.gpu_shapes();
+        let lights_data = still.gpu_lights();
+        let camera = still.camera(width as f64 / height as f64);
+        let views = match options.stereo {
+            Some(_) => options.stereo_camera(camera).eyes().to_vec(),
+            None => vec![camera],
+        };
+
+        let mut rendered = Vec::new();
+        for camera in views {
+            let view = if use_adaptive {
+                match renderer.render_adaptive(
+                    width,
+                    height,
+                    samples,
+                    options.sampler,
+                    &camera,
+                    &spheres_data,
+                    &planes_data,
+                    &shapes_data,
+                    &lights_data,
+                    still.gpu_background(),
+                    &|current, target| {
+                        if current < target {
+                            println!("  Progressive quality: {}/{} samples", current, target);
+                        }
+                    },
+                ) {
+                    Ok(img) => (img, Vec::new()),
+                    Err(e) => {
+                        eprintln!("GPU rendering failed: {}", e);
+                        eprintln!("Falling back to CPU rendering...");
+                        return main_cpu_with_settings(width, height, samples, options, output_file);
                     }
-                },
-            ) {
-                Ok(img) => img,
-                Err(e) => {
-                    eprintln!("GPU rendering failed: {}", e);
-                    eprintln!("Falling back to CPU rendering...");
-                    return main_cpu_with_settings(width, height, num_frames, samples, output_file);
                 }
-            }
-        } else {
-            match renderer.render(
-                width,
-                height,
-                samples,
-                [0.0, 1.0, 0.0],
-                [0.0, 0.5, 5.0],
-                60.0,
-                &spheres_data,
-                &planes_data,
-                &lights_data,
-                background_color,
-            ) {
-                Ok(img) => img,
-                Err(e) => {
-                    eprintln!("GPU rendering failed: {}", e);
-                    eprintln!("Falling back to CPU rendering...");
-                    return main_cpu_with_settings(width, height, num_frames, samples, output_file);
+            } else {
+                let result = match crop {
+                    Some(crop) => renderer.render_crop(
+                        width,
+                        height,
+                        CropWindow::Pixels(crop),
+                        samples,
+                        options.sampler,
+                        &camera,
+                        &spheres_data,
+                        &planes_data,
+                        &shapes_data,
+                        &lights_data,
+                        still.gpu_background(),
+                        &rendered_aovs,
+                    ),
+                    None => renderer.render_with_aovs(
+                        width,
+                        height,
+                        samples,
+                        options.sampler,
+                        &camera,
+                        &spheres_data,
+                        &planes_data,
+                        &shapes_data,
+                        &lights_data,
+                        still.gpu_background(),
+                        &rendered_aovs,
+                    ),
+                };
+                match result {
+                    Ok(result) => result,
+                    Err(e) => {
+                        eprintln!("GPU rendering failed: {}", e);
+                        eprintln!("Falling back to CPU rendering...");
+                        return main_cpu_with_settings(width, height, samples, options, output_file);
+                    }
                 }
+            };
+            rendered.push(view);
+        }
+
+        let (image, aovs) = match options.stereo {
+            Some(layout) => {
+                let right = rendered.pop().expect("right eye");
+                let left = rendered.pop().expect("left eye");
+                pack_eyes([left, right], layout)
             }
+            None => rendered.pop().expect("one view"),
         };
 
-        let mut frame_buffer = RgbaImage::new(width, height);
-        for (x, y, pixel) in frame_buffer.enumerate_pixels_mut() {
-            let color = &image[y as usize][x as usize];
-            *pixel = image::Rgba([
-                (color[0] * 255.0) as u8,
-                (color[1] * 255.0) as u8,
-                (color[2] * 255.0) as u8,
-                255,
-            ]);
+        let mut image = finish_frame(frame_index, image, &aovs, options);
+        if let Some(crop) = crop {
+            image = uncrop_frame(frame_index, &image, crop, width, height, options);
         }
-
-        frames.push(frame_buffer);
+        // Kept on every path, so a later --crop --composite can patch it
+        let _ = save_image(&image, &format!("frame_{}.ppm", frame_index));
+        frames.push(image.to_rgb8());
     }
 
     println!("Encoding GIF...");
-
-    let path = Path::new(output_file);
-    let file = File::create(&path).unwrap();
-    let mut encoder = gif::Encoder::new(file, width as u16, height as u16, &[]).unwrap();
-    encoder.set_repeat(gif::Repeat::Infinite).unwrap();
-
-    for (i, frame) in frames.iter().enumerate() {
-        println!("Encoding frame {}/{}...", i + 1, num_frames);
-
-        let rgba_data = frame.as_raw();
-        let mut rgb_data = Vec::with_capacity((width * height * 3) as usize);
-
-        for chunk in rgba_data.chunks(4) {
-            rgb_data.push(chunk[0]);
-            rgb_data.push(chunk[1]);
-            rgb_data.push(chunk[2]);
-        }
-
-        let mut gif_frame = gif::Frame::from_rgb(width as u16, height as u16, &rgb_data);
-        gif_frame.delay = 3;
-        encoder.write_frame(&gif_frame).unwrap();
-    }
+    let (frame_width, frame_height) = options.frame_size(width, height);
+    write_animation(output_file, frame_width, frame_height, &frames);
 
     let mem_info = renderer.memory_info();
     println!("Peak GPU memory usage: {:.1}MB", mem_info.peak_allocated_mb);
     println!("Animation saved as {}", output_file);
 }
+
+fn write_animation(output_file: &str, width: u32, height: u32, frames: &[Framebuffer<u8>]) {
+    let mut writer = GifWriter::create(output_file, width, height).unwrap();
+
+    for (i, frame) in frames.iter().enumerate() {
+        println!("Encoding frame {}/{}...", i + 1, frames.len());
+        writer.write_frame(frame, 3).unwrap();
+    }
+}
@@ -0,0 +1,397 @@
+use crate::hittable::{tangent_frame, EmissionSample, HitRecord, Hittable};
+use crate::rectangle::area_sample;
+use crate::ray::Ray;
+use crate::sphere::{Color, Material};
+use crate::vector3d::Vector3D;
+
+/// Triangles per BVH leaf
+const MAX_LEAF_TRIANGLES: usize = 4;
+
+/// Deep enough for any tree built by median splits of `u32` triangle counts
+const MAX_TRAVERSAL_DEPTH: usize = 64;
+
+/// Node of the flattened BVH. Interior nodes keep their left child right
+/// after them and point at the right one with `start`; leaves hold `count`
+/// triangles from `start` in the mesh's reordered triangle list.
+#[derive(Debug, Clone, Copy)]
+struct BvhNode {
+    min: Vector3D,
+    max: Vector3D,
+    start: u32,
+    count: u32,
+}
+
+/// Indexed triangle mesh with its own bounding volume hierarchy, so large
+/// imported models cost roughly log(n) per ray rather than n.
+///
+/// Triangles wind counter-clockwise around their outward normal. Normals,
+/// UVs and colours are per vertex and optional: without normals the faces
+/// are flat shaded, and without UVs hits report the barycentric coordinates.
+pub struct Mesh {
+    positions: Vec<Vector3D>,
+    normals: Vec<Vector3D>,
+    uvs: Vec<(f64, f64)>,
+    colors: Vec<Color>,
+    triangles: Vec<[u32; 3]>,
+    /// Running total of the triangles' areas, for picking emitting
+    /// triangles in proportion to their size
+    area_totals: Vec<f64>,
+    nodes: Vec<BvhNode>,
+    pub material: Material,
+}
+
+impl Mesh {
+    /// Builds the mesh and its BVH. Normals or UVs whose count doesn't
+    /// match `positions` are ignored, as are triangles with indices out of
+    /// range or no area.
+    pub fn new(
+        positions: Vec<Vector3D>,
+        normals: Vec<Vector3D>,
+        uvs: Vec<(f64, f64)>,
+        mut triangles: Vec<[u32; 3]>,
+        material: Material,
+    ) -> Mesh {
+        let vertex_count = positions.len();
+        let normals = if normals.len() == vertex_count { normals } else { Vec::new() };
+        let uvs = if uvs.len() == vertex_count { uvs } else { Vec::new() };
+
+        triangles.retain(|triangle| {
+            if triangle.iter().any(|&index| index as usize >= vertex_count) {
+                return false;
+            }
+            let [a, b, c] = triangle.map(|index| positions[index as usize]);
+            (b - a).cross(c - a).magnitude_squared() > 0.0
+        });
+
+        let mut mesh = Mesh {
+            positions,
+            normals,
+            uvs,
+            colors: Vec::new(),
+            triangles,
+            area_totals: Vec::new(),
+            nodes: Vec::new(),
+            material,
+        };
+        mesh.build_bvh();
+        let mut total = 0.0;
+        mesh.area_totals = mesh
+            .triangles
+            .iter()
+            .map(|&triangle| {
+                let [a, b, c] = mesh.corners(triangle);
+                total += 0.5 * (b - a).cross(c - a).magnitude();
+                total
+            })
+            .collect();
+        mesh
+    }
+
+    /// Multiplies the material colour by `colors`, one per vertex and
+    /// interpolated across each triangle. Ignored if the count is wrong.
+    pub fn with_vertex_colors(mut self, colors: Vec<Color>) -> Mesh {
+        if colors.len() == self.positions.len() {
+            self.colors = colors;
+        }
+        self
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.triangles.len()
+    }
+
+    /// Corners of the axis-aligned box around every triangle, or `None` for
+    /// an empty mesh
+    pub fn bounds(&self) -> Option<(Vector3D, Vector3D)> {
+        self.nodes.first().map(|root| (root.min, root.max))
+    }
+
+    #[inline]
+    fn corners(&self, triangle: [u32; 3]) -> [Vector3D; 3] {
+        triangle.map(|index| self.positions[index as usize])
+    }
+
+    fn build_bvh(&mut self) {
+        if self.triangles.is_empty() {
+            return;
+        }
+        let mut nodes = Vec::with_capacity(2 * self.triangles.len() / MAX_LEAF_TRIANGLES + 1);
+        let mut triangles = std::mem::take(&mut self.triangles);
+        let count = triangles.len();
+        self.build_node(&mut nodes, &mut triangles, 0, count);
+        self.triangles = triangles;
+        self.nodes = nodes;
+    }
+
+    /// Appends the subtree for `triangles[start..end]`, splitting at the
+    /// median centroid along the widest axis
+    fn build_node(&self, nodes: &mut Vec<BvhNode>, triangles: &mut [[u32; 3]], start: usize, end: usize) {
+        let slice = &mut triangles[start..end];
+        let mut min = Vector3D::splat(f64::INFINITY);
+        let mut max = Vector3D::splat(f64::NEG_INFINITY);
+        let mut centroid_min = min;
+        let mut centroid_max = max;
+        for &triangle in slice.iter() {
+            let [a, b, c] = self.corners(triangle);
+            min = min.min(a).min(b).min(c);
+            max = max.max(a).max(b).max(c);
+            let centroid = (a + b + c) / 3.0;
+            centroid_min = centroid_min.min(centroid);
+            centroid_max = centroid_max.max(centroid);
+        }
+
+        let index = nodes.len();
+        nodes.push(BvhNode {
+            min,
+            max,
+            start: start as u32,
+            count: slice.len() as u32,
+        });
+        if slice.len() <= MAX_LEAF_TRIANGLES {
+            return;
+        }
+
+        let axis = (centroid_max - centroid_min).max_dimension();
+        let middle = slice.len() / 2;
+        slice.select_nth_unstable_by(middle, |&a, &b| {
+            let [a0, a1, a2] = self.corners(a);
+            let [b0, b1, b2] = self.corners(b);
+            (a0[axis] + a1[axis] + a2[axis]).total_cmp(&(b0[axis] + b1[axis] + b2[axis]))
+        });
+
+        self.build_node(nodes, triangles, start, start + middle);
+        nodes[index].start = nodes.len() as u32;
+        nodes[index].count = 0;
+        self.build_node(nodes, triangles, start + middle, end);
+    }
+
+    /// Möller-Trumbore test returning the distance and barycentrics of
+    /// the second and third corners
+    #[inline]
+    fn hit_triangle(&self, triangle: [u32; 3], ray: &Ray, t_min: f64, t_max: f64) -> Option<(f64, f64, f64)> {
+        let [a, b, c] = self.corners(triangle);
+        let edge1 = b - a;
+        let edge2 = c - a;
+        let p = ray.direction.cross(edge2);
+        let determinant = edge1.dot(p);
+        if determinant.abs() < 1e-12 {
+            return None;
+        }
+
+        let inv_determinant = 1.0 / determinant;
+        let offset = ray.origin - a;
+        let u = offset.dot(p) * inv_determinant;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = offset.cross(edge1);
+        let v = ray.direction.dot(q) * inv_determinant;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let t = edge2.dot(q) * inv_determinant;
+        (t >= t_min && t <= t_max).then_some((t, u, v))
+    }
+
+    fn record(&self, triangle: [u32; 3], ray: &Ray, t: f64, u: f64, v: f64) -> HitRecord {
+        let w = 1.0 - u - v;
+        let [i0, i1, i2] = triangle.map(|index| index as usize);
+
+        let normal = if self.normals.is_empty() {
+            let [a, b, c] = self.corners(triangle);
+            (b - a).cross(c - a).normalize()
+        } else {
+            (self.normals[i0] * w + self.normals[i1] * u + self.normals[i2] * v).normalize()
+        };
+        let [(u0, v0), (u1, v1), (u2, v2)] = if self.uvs.is_empty() {
+            [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)]
+        } else {
+            [self.uvs[i0], self.uvs[i1], self.uvs[i2]]
+        };
+        let uv = (u0 * w + u1 * u + u2 * v, v0 * w + v1 * u + v2 * v);
+
+        // Solve the edges for the directions in which u and v grow
+        let [a, b, c] = self.corners(triangle);
+        let (edge1, edge2) = (b - a, c - a);
+        let (du1, dv1, du2, dv2) = (u1 - u0, v1 - v0, u2 - u0, v2 - v0);
+        let sign = (du1 * dv2 - du2 * dv1).signum();
+        let (tangent, bitangent) = tangent_frame(
+            normal,
+            (edge1 * dv2 - edge2 * dv1) * sign,
+            (edge2 * du1 - edge1 * du2) * sign,
+        );
+
+        let mut material = self.material;
+        if !self.colors.is_empty() {
+            let tint = self.colors[i0] * w + self.colors[i1] * u + self.colors[i2] * v;
+            material.color = material.color * tint;
+            if let Some(pbr) = &mut material.pbr {
+                pbr.base_color = pbr.base_color * tint;
+            }
+        }
+
+        HitRecord {
+            point: ray.at(t),
+            normal,
+            t,
+            uv,
+            tangent,
+            bitangent,
+            material,
+        }
+    }
+}
+
+/// Distance at which `ray` enters the box, if it does before `t_max`
+#[inline]
+fn hit_bounds(node: &BvhNode, origin: Vector3D, inv_direction: Vector3D, t_min: f64, t_max: f64) -> Option<f64> {
+    let mut near = t_min;
+    let mut far = t_max;
+    for axis in 0..3 {
+        let t0 = (node.min[axis] - origin[axis]) * inv_direction[axis];
+        let t1 = (node.max[axis] - origin[axis]) * inv_direction[axis];
+        near = near.max(t0.min(t1));
+        far = far.min(t0.max(t1));
+    }
+    (near <= far).then_some(near)
+}
+
+impl Hittable for Mesh {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let inv_direction = Vector3D::new(1.0 / ray.direction.x, 1.0 / ray.direction.y, 1.0 / ray.direction.z);
+
+        let mut closest = t_max;
+        let mut found = None;
+        let mut stack = [0u32; MAX_TRAVERSAL_DEPTH];
+        let mut depth = 1;
+
+        while depth > 0 {
+            depth -= 1;
+            let node = &self.nodes[stack[depth] as usize];
+            if hit_bounds(node, ray.origin, inv_direction, t_min, closest).is_none() {
+                continue;
+            }
+
+            if node.count > 0 {
+                let start = node.start as usize;
+                for &triangle in &self.triangles[start..start + node.count as usize] {
+                    if let Some((t, u, v)) = self.hit_triangle(triangle, ray, t_min, closest) {
+                        closest = t;
+                        found = Some((triangle, t, u, v));
+                    }
+                }
+                continue;
+            }
+
+            // Visit the nearer child first so `closest` shrinks sooner
+            let left = stack[depth] + 1;
+            let right = node.start;
+            let near_left = hit_bounds(&self.nodes[left as usize], ray.origin, inv_direction, t_min, closest);
+            let near_right = hit_bounds(&self.nodes[right as usize], ray.origin, inv_direction, t_min, closest);
+            let (first, second) = match (near_left, near_right) {
+                (Some(l), Some(r)) if r < l => (Some(right), Some(left)),
+                (Some(_), Some(_)) => (Some(left), Some(right)),
+                (Some(_), None) => (Some(left), None),
+                (None, Some(_)) => (Some(right), None),
+                (None, None) => (None, None),
+            };
+            for child in [second, first].into_iter().flatten() {
+                stack[depth] = child;
+                depth += 1;
+            }
+        }
+
+        found.map(|(triangle, t, u, v)| self.record(triangle, ray, t, u, v))
+    }
+
+    /// Picks a triangle in proportion to its area with `u1`, then reuses
+    /// where `u1` fell within it and `u2` for a uniform point on it, so the
+    /// whole surface is sampled by area
+    fn sample_emission(&self, origin: Vector3D, u1: f64, u2: f64) -> Option<EmissionSample> {
+        if !self.material.is_emissive() {
+            return None;
+        }
+        let total = *self.area_totals.last()?;
+        let target = u1 * total;
+        let index = self.area_totals.partition_point(|&sum| sum <= target).min(self.triangles.len() - 1);
+        let before = if index > 0 { self.area_totals[index - 1] } else { 0.0 };
+        let u1 = ((target - before) / (self.area_totals[index] - before)).clamp(0.0, 1.0);
+
+        let [a, b, c] = self.corners(self.triangles[index]);
+        let root = u1.sqrt();
+        let point = a * (1.0 - root) + b * (root * (1.0 - u2)) + c * (root * u2);
+        let normal = (b - a).cross(c - a).normalize();
+        area_sample(point, normal, total, origin, self.material.emission)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GLOW: Material = Material {
+        color: Color { r: 1.0, g: 1.0, b: 1.0 },
+        diffuse: 1.0,
+        specular: 0.0,
+        shininess: 1.0,
+        reflectivity: 0.0,
+        pbr: None,
+        emission: Color { r: 2.0, g: 1.0, b: 0.5 },
+        texture: None,
+        normal_map: None,
+    };
+
+    #[test]
+    fn emission_samples_are_uniform_over_the_surface() {
+        // A 4 x 2 panel in the z = 0 plane fanned from an off-centre point,
+        // so the triangles' areas differ and picking them evenly would pull
+        // the samples towards the small ones
+        let positions = vec![
+            Vector3D::new(0.0, 0.0, 0.0),
+            Vector3D::new(4.0, 0.0, 0.0),
+            Vector3D::new(4.0, 2.0, 0.0),
+            Vector3D::new(0.0, 2.0, 0.0),
+            Vector3D::new(0.5, 0.5, 0.0),
+        ];
+        let triangles = vec![[4, 0, 1], [4, 1, 2], [4, 2, 3], [4, 3, 0]];
+        let mesh = Mesh::new(positions, Vec::new(), Vec::new(), triangles, GLOW);
+        let origin = Vector3D::new(1.0, 1.0, 3.0);
+
+        let steps = 64;
+        let mut sum = Vector3D::ZERO;
+        for i in 0..steps {
+            for j in 0..steps {
+                let u1 = (i as f64 + 0.5) / steps as f64;
+                let u2 = (j as f64 + 0.5) / steps as f64;
+                let sample = mesh.sample_emission(origin, u1, u2).unwrap();
+                assert!(sample.point.z.abs() < 1e-12 && (0.0..=4.0).contains(&sample.point.x) && (0.0..=2.0).contains(&sample.point.y));
+                assert!((sample.normal.z.abs() - 1.0).abs() < 1e-12);
+
+                let offset = sample.point - origin;
+                let expected = offset.magnitude_squared() / (8.0 * offset.z.abs() / offset.magnitude());
+                assert!((sample.pdf / expected - 1.0).abs() < 1e-9, "{} != {}", sample.pdf, expected);
+                sum += sample.point;
+            }
+        }
+
+        let mean = sum / (steps * steps) as f64;
+        assert!((mean - Vector3D::new(2.0, 1.0, 0.0)).magnitude() < 0.02, "{:?}", mean);
+    }
+
+    #[test]
+    fn dark_meshes_cast_no_light() {
+        let material = Material { emission: Color::default(), ..GLOW };
+        let mesh = Mesh::new(
+            vec![Vector3D::ZERO, Vector3D::new(1.0, 0.0, 0.0), Vector3D::new(0.0, 1.0, 0.0)],
+            Vec::new(),
+            Vec::new(),
+            vec![[0, 1, 2]],
+            material,
+        );
+        assert!(mesh.sample_emission(Vector3D::new(0.0, 0.0, 1.0), 0.5, 0.5).is_none());
+    }
+}
@@ -0,0 +1,673 @@
+use crate::mesh::Mesh;
+use crate::sphere::{Color, Material};
+use crate::vector3d::Vector3D;
+use std::fmt;
+use std::path::Path;
+
+/// STL binary header, before the triangle count
+const STL_HEADER_LEN: usize = 80;
+
+/// Normal, three corners and the attribute word of a binary STL triangle
+const STL_TRIANGLE_LEN: usize = 50;
+
+#[derive(Debug)]
+pub enum MeshImportError {
+    Io(std::io::Error),
+    /// Malformed or unsupported file contents
+    Invalid(String),
+}
+
+impl fmt::Display for MeshImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MeshImportError::Io(e) => write!(f, "Failed to read mesh: {}", e),
+            MeshImportError::Invalid(message) => write!(f, "Invalid mesh: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for MeshImportError {}
+
+impl From<std::io::Error> for MeshImportError {
+    fn from(e: std::io::Error) -> Self {
+        MeshImportError::Io(e)
+    }
+}
+
+fn invalid(message: impl Into<String>) -> MeshImportError {
+    MeshImportError::Invalid(message.into())
+}
+
+/// Loads a PLY file, ASCII or binary of either byte order. See `parse_ply`.
+pub fn load_ply(path: impl AsRef<Path>, material: Material) -> Result<Mesh, MeshImportError> {
+    parse_ply(&std::fs::read(path)?, material)
+}
+
+/// Builds a mesh from PLY data.
+///
+/// Vertices need `x`, `y` and `z`, and may carry normals (`nx`, `ny`,
+/// `nz`), texture coordinates (`u`/`v`, `s`/`t` or `texture_u`/`texture_v`)
+/// and colours (`red`, `green`, `blue`, or their `diffuse_` forms). Integer
+/// colours are scaled by their type's maximum, so the usual `uchar` 0-255
+/// maps to [0, 1]; vertex colours then multiply `material`'s colour.
+/// Polygonal faces are fanned into triangles, and a face using a vertex
+/// the file doesn't have is an error. Other elements and properties are
+/// skipped.
+pub fn parse_ply(bytes: &[u8], material: Material) -> Result<Mesh, MeshImportError> {
+    let (header, body_start) = PlyHeader::parse(bytes)?;
+    let mut body = match header.format {
+        PlyFormat::Ascii => {
+            let text = std::str::from_utf8(&bytes[body_start..]).map_err(|_| invalid("PLY ASCII body is not UTF-8"))?;
+            PlyBody::Ascii(text.split_ascii_whitespace())
+        }
+        PlyFormat::BinaryLittleEndian | PlyFormat::BinaryBigEndian => PlyBody::Binary {
+            bytes: &bytes[body_start..],
+            position: 0,
+            big_endian: header.format == PlyFormat::BinaryBigEndian,
+        },
+    };
+
+    let mut positions = Vec::new();
+    let mut normals = Vec::new();
+    let mut uvs = Vec::new();
+    let mut colors = Vec::new();
+    let mut triangles = Vec::new();
+    let mut row = Vec::new();
+    let mut list = Vec::new();
+
+    for element in &header.elements {
+        match element.name.as_str() {
+            "vertex" => {
+                let find = |names: &[&str]| element.properties.iter().position(|p| names.contains(&p.name.as_str()));
+                let find_all = |names: [&[&str]; 3]| -> Option<[usize; 3]> {
+                    Some([find(names[0])?, find(names[1])?, find(names[2])?])
+                };
+                let position = find_all([&["x"], &["y"], &["z"]]).ok_or_else(|| invalid("PLY vertices have no position"))?;
+                let normal = find_all([&["nx"], &["ny"], &["nz"]]);
+                let uv = find(&["u", "s", "texture_u", "texture_s"]).zip(find(&["v", "t", "texture_v", "texture_t"]));
+                let color = find_all([&["red", "diffuse_red"], &["green", "diffuse_green"], &["blue", "diffuse_blue"]]);
+
+                for _ in 0..element.count {
+                    row.clear();
+                    for property in &element.properties {
+                        match property.kind {
+                            PlyProperty::Scalar(scalar) => row.push(body.read(scalar)?),
+                            PlyProperty::List { count, item } => {
+                                body.read_list(count, item, &mut list)?;
+                                row.push(0.0);
+                            }
+                        }
+                    }
+
+                    let vector = |[x, y, z]: [usize; 3]| Vector3D::new(row[x], row[y], row[z]);
+                    positions.push(vector(position));
+                    if let Some(normal) = normal {
+                        normals.push(vector(normal));
+                    }
+                    // PLY's t runs up the image, texture lookups' v down it
+                    if let Some((u, v)) = uv {
+                        uvs.push((row[u], 1.0 - row[v]));
+                    }
+                    if let Some(channels) = color {
+                        let [r, g, b] = channels.map(|index| match element.properties[index].kind {
+                            PlyProperty::Scalar(scalar) => row[index] / scalar.full_scale(),
+                            PlyProperty::List { .. } => 0.0,
+                        });
+                        colors.push(Color { r, g, b });
+                    }
+                }
+            }
+            "face" => {
+                let vertex_count = header.elements.iter().find(|e| e.name == "vertex").map_or(0, |e| e.count);
+                let indices = element
+                    .properties
+                    .iter()
+                    .position(|p| matches!(p.name.as_str(), "vertex_indices" | "vertex_index"));
+                for _ in 0..element.count {
+                    for (i, property) in element.properties.iter().enumerate() {
+                        match property.kind {
+                            PlyProperty::Scalar(scalar) => {
+                                body.read(scalar)?;
+                            }
+                            PlyProperty::List { count, item } => {
+                                body.read_list(count, item, &mut list)?;
+                                if Some(i) == indices {
+                                    fan(&list, vertex_count, &mut triangles)?;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            _ => body.skip(element)?,
+        }
+    }
+
+    if triangles.is_empty() {
+        return Err(invalid("PLY file has no faces"));
+    }
+    Ok(Mesh::new(positions, normals, uvs, triangles, material).with_vertex_colors(colors))
+}
+
+/// Splits the polygon with corners `indices` into triangles around its
+/// first, failing if a corner isn't one of the `vertex_count` vertices
+fn fan(indices: &[f64], vertex_count: usize, triangles: &mut Vec<[u32; 3]>) -> Result<(), MeshImportError> {
+    if let Some(index) = indices.iter().find(|&&index| !(index >= 0.0 && (index as usize) < vertex_count)) {
+        return Err(invalid(format!("PLY face uses vertex {} of {}", index, vertex_count)));
+    }
+    for i in 2..indices.len() {
+        triangles.push([indices[0], indices[i - 1], indices[i]].map(|index| index as u32));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PlyFormat {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum PlyScalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl PlyScalar {
+    fn parse(name: &str) -> Result<PlyScalar, MeshImportError> {
+        Ok(match name {
+            "char" | "int8" => PlyScalar::I8,
+            "uchar" | "uint8" => PlyScalar::U8,
+            "short" | "int16" => PlyScalar::I16,
+            "ushort" | "uint16" => PlyScalar::U16,
+            "int" | "int32" => PlyScalar::I32,
+            "uint" | "uint32" => PlyScalar::U32,
+            "float" | "float32" => PlyScalar::F32,
+            "double" | "float64" => PlyScalar::F64,
+            _ => return Err(invalid(format!("unknown PLY type '{}'", name))),
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            PlyScalar::I8 | PlyScalar::U8 => 1,
+            PlyScalar::I16 | PlyScalar::U16 => 2,
+            PlyScalar::I32 | PlyScalar::U32 | PlyScalar::F32 => 4,
+            PlyScalar::F64 => 8,
+        }
+    }
+
+    /// Value that stands for full intensity in a colour channel
+    fn full_scale(self) -> f64 {
+        match self {
+            PlyScalar::I8 => i8::MAX as f64,
+            PlyScalar::U8 => u8::MAX as f64,
+            PlyScalar::I16 => i16::MAX as f64,
+            PlyScalar::U16 => u16::MAX as f64,
+            PlyScalar::I32 => i32::MAX as f64,
+            PlyScalar::U32 => u32::MAX as f64,
+            PlyScalar::F32 | PlyScalar::F64 => 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum PlyProperty {
+    Scalar(PlyScalar),
+    List { count: PlyScalar, item: PlyScalar },
+}
+
+#[derive(Debug)]
+struct PlyNamedProperty {
+    name: String,
+    kind: PlyProperty,
+}
+
+#[derive(Debug)]
+struct PlyElement {
+    name: String,
+    count: usize,
+    properties: Vec<PlyNamedProperty>,
+}
+
+#[derive(Debug)]
+struct PlyHeader {
+    format: PlyFormat,
+    elements: Vec<PlyElement>,
+}
+
+impl PlyHeader {
+    /// Parses the header, returning it with the offset of the body
+    fn parse(bytes: &[u8]) -> Result<(PlyHeader, usize), MeshImportError> {
+        let mut format = None;
+        let mut elements: Vec<PlyElement> = Vec::new();
+        let mut offset = 0;
+        let mut first = true;
+
+        loop {
+            let Some(length) = bytes[offset..].iter().position(|&byte| byte == b'\n') else {
+                return Err(invalid("PLY header has no end_header"));
+            };
+            let line = String::from_utf8_lossy(&bytes[offset..offset + length]);
+            offset += length + 1;
+            let mut words = line.split_ascii_whitespace();
+
+            if first {
+                if words.next() != Some("ply") {
+                    return Err(invalid("not a PLY file"));
+                }
+                first = false;
+                continue;
+            }
+
+            match words.next() {
+                Some("format") => {
+                    format = Some(match words.next() {
+                        Some("ascii") => PlyFormat::Ascii,
+                        Some("binary_little_endian") => PlyFormat::BinaryLittleEndian,
+                        Some("binary_big_endian") => PlyFormat::BinaryBigEndian,
+                        other => return Err(invalid(format!("unknown PLY format '{}'", other.unwrap_or("")))),
+                    });
+                }
+                Some("element") => {
+                    let (Some(name), Some(count)) = (words.next(), words.next().and_then(|w| w.parse().ok())) else {
+                        return Err(invalid(format!("bad PLY element line '{}'", line.trim())));
+                    };
+                    elements.push(PlyElement {
+                        name: name.to_string(),
+                        count,
+                        properties: Vec::new(),
+                    });
+                }
+                Some("property") => {
+                    let element = elements
+                        .last_mut()
+                        .ok_or_else(|| invalid("PLY property before any element"))?;
+                    let words: Vec<&str> = words.collect();
+                    let (kind, name) = match words.as_slice() {
+                        ["list", count, item, name] => (
+                            PlyProperty::List {
+                                count: PlyScalar::parse(count)?,
+                                item: PlyScalar::parse(item)?,
+                            },
+                            name,
+                        ),
+                        [scalar, name] => (PlyProperty::Scalar(PlyScalar::parse(scalar)?), name),
+                        _ => return Err(invalid(format!("bad PLY property line '{}'", line.trim()))),
+                    };
+                    element.properties.push(PlyNamedProperty {
+                        name: name.to_string(),
+                        kind,
+                    });
+                }
+                Some("end_header") => break,
+                // comment, obj_info and blank lines
+                _ => {}
+            }
+        }
+
+        let format = format.ok_or_else(|| invalid("PLY header has no format"))?;
+        Ok((PlyHeader { format, elements }, offset))
+    }
+}
+
+/// Cursor over the element data, which is whitespace-separated text or
+/// packed binary
+enum PlyBody<'a> {
+    Ascii(std::str::SplitAsciiWhitespace<'a>),
+    Binary {
+        bytes: &'a [u8],
+        position: usize,
+        big_endian: bool,
+    },
+}
+
+impl PlyBody<'_> {
+    fn read(&mut self, scalar: PlyScalar) -> Result<f64, MeshImportError> {
+        match self {
+            PlyBody::Ascii(words) => {
+                let word = words.next().ok_or_else(|| invalid("PLY data ends early"))?;
+                word.parse().map_err(|_| invalid(format!("bad PLY value '{}'", word)))
+            }
+            PlyBody::Binary {
+                bytes,
+                position,
+                big_endian,
+            } => {
+                let size = scalar.size();
+                let data = bytes
+                    .get(*position..*position + size)
+                    .ok_or_else(|| invalid("PLY data ends early"))?;
+                *position += size;
+
+                let mut buffer = [0u8; 8];
+                buffer[..size].copy_from_slice(data);
+                if *big_endian {
+                    buffer[..size].reverse();
+                }
+                let [b0, b1, b2, b3, ..] = buffer;
+                Ok(match scalar {
+                    PlyScalar::I8 => b0 as i8 as f64,
+                    PlyScalar::U8 => b0 as f64,
+                    PlyScalar::I16 => i16::from_le_bytes([b0, b1]) as f64,
+                    PlyScalar::U16 => u16::from_le_bytes([b0, b1]) as f64,
+                    PlyScalar::I32 => i32::from_le_bytes([b0, b1, b2, b3]) as f64,
+                    PlyScalar::U32 => u32::from_le_bytes([b0, b1, b2, b3]) as f64,
+                    PlyScalar::F32 => f32::from_le_bytes([b0, b1, b2, b3]) as f64,
+                    PlyScalar::F64 => f64::from_le_bytes(buffer),
+                })
+            }
+        }
+    }
+
+    /// Reads a list property into `values`, replacing their contents
+    fn read_list(&mut self, count: PlyScalar, item: PlyScalar, values: &mut Vec<f64>) -> Result<(), MeshImportError> {
+        let length = self.read(count)?;
+        if length < 0.0 {
+            return Err(invalid("negative PLY list length"));
+        }
+        values.clear();
+        for _ in 0..length as usize {
+            values.push(self.read(item)?);
+        }
+        Ok(())
+    }
+
+    fn skip(&mut self, element: &PlyElement) -> Result<(), MeshImportError> {
+        let mut list = Vec::new();
+        for _ in 0..element.count {
+            for property in &element.properties {
+                match property.kind {
+                    PlyProperty::Scalar(scalar) => {
+                        self.read(scalar)?;
+                    }
+                    PlyProperty::List { count, item } => self.read_list(count, item, &mut list)?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Loads an STL file, ASCII or binary. See `parse_stl`.
+pub fn load_stl(path: impl AsRef<Path>, material: Material) -> Result<Mesh, MeshImportError> {
+    parse_stl(&std::fs::read(path)?, material)
+}
+
+/// Builds a flat-shaded mesh from STL data, ASCII or binary.
+///
+/// STL keeps no shared vertices or colours; the binary attribute word has
+/// rival colour conventions and is ignored. Facets wound against their
+/// stored normal are flipped to agree with it, since CAD exporters are
+/// often careless about one or the other.
+pub fn parse_stl(bytes: &[u8], material: Material) -> Result<Mesh, MeshImportError> {
+    // Binary headers may also start with "solid", so trust an exact size
+    // match first
+    let binary_count = bytes
+        .get(STL_HEADER_LEN..STL_HEADER_LEN + 4)
+        .map(|count| u32::from_le_bytes([count[0], count[1], count[2], count[3]]) as usize);
+    let binary_len = binary_count.map(|count| STL_HEADER_LEN + 4 + count * STL_TRIANGLE_LEN);
+    let text_start = bytes.iter().position(|byte| !byte.is_ascii_whitespace()).unwrap_or(bytes.len());
+    let looks_ascii = bytes[text_start..].starts_with(b"solid");
+
+    let facets = match (binary_count, binary_len) {
+        (Some(count), Some(length)) if length == bytes.len() || (!looks_ascii && length <= bytes.len()) => {
+            parse_stl_binary(&bytes[STL_HEADER_LEN + 4..], count)
+        }
+        _ if looks_ascii => parse_stl_ascii(bytes)?,
+        _ => return Err(invalid("not an STL file")),
+    };
+
+    if facets.is_empty() {
+        return Err(invalid("STL file has no facets"));
+    }
+    let mut positions = Vec::with_capacity(facets.len() * 3);
+    let mut triangles = Vec::with_capacity(facets.len());
+    for (normal, [a, b, c]) in facets {
+        let first = positions.len() as u32;
+        if (b - a).cross(c - a).dot(normal) < 0.0 {
+            positions.extend([a, c, b]);
+        } else {
+            positions.extend([a, b, c]);
+        }
+        triangles.push([first, first + 1, first + 2]);
+    }
+    Ok(Mesh::new(positions, Vec::new(), Vec::new(), triangles, material))
+}
+
+type Facet = (Vector3D, [Vector3D; 3]);
+
+fn parse_stl_binary(records: &[u8], count: usize) -> Vec<Facet> {
+    records
+        .chunks_exact(STL_TRIANGLE_LEN)
+        .take(count)
+        .map(|record| {
+            let vector = |index: usize| {
+                let component = |offset: usize| {
+                    let at = 12 * index + 4 * offset;
+                    f32::from_le_bytes([record[at], record[at + 1], record[at + 2], record[at + 3]]) as f64
+                };
+                Vector3D::new(component(0), component(1), component(2))
+            };
+            (vector(0), [vector(1), vector(2), vector(3)])
+        })
+        .collect()
+}
+
+fn parse_stl_ascii(bytes: &[u8]) -> Result<Vec<Facet>, MeshImportError> {
+    let text = String::from_utf8_lossy(bytes);
+    let mut words = text.split_ascii_whitespace();
+    let mut facets = Vec::new();
+    let mut normal = Vector3D::ZERO;
+    let mut corners = Vec::new();
+    while let Some(word) = words.next() {
+        match word {
+            "facet" => {
+                if words.next() != Some("normal") {
+                    return Err(invalid("STL facet without a normal"));
+                }
+                normal = vector(&mut words)?;
+                corners.clear();
+            }
+            "vertex" => corners.push(vector(&mut words)?),
+            "endfacet" => {
+                // Some exporters write polygons; fan them like PLY faces
+                for i in 2..corners.len() {
+                    facets.push((normal, [corners[0], corners[i - 1], corners[i]]));
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(facets)
+}
+
+fn vector(words: &mut std::str::SplitAsciiWhitespace) -> Result<Vector3D, MeshImportError> {
+    let mut component = || {
+        let word = words.next().ok_or_else(|| invalid("STL data ends early"))?;
+        word.parse::<f64>().map_err(|_| invalid(format!("bad STL number '{}'", word)))
+    };
+    Ok(Vector3D::new(component()?, component()?, component()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hittable::Hittable;
+    use crate::ray::Ray;
+
+    const WHITE: Material = Material {
+        color: Color::WHITE,
+        diffuse: 1.0,
+        specular: 0.0,
+        shininess: 1.0,
+        reflectivity: 0.0,
+        pbr: None,
+        emission: Color { r: 0.0, g: 0.0, b: 0.0 },
+        texture: None,
+        normal_map: None,
+    };
+
+    /// Unit square in the z = 0 plane as a single quad, with every vertex
+    /// coloured (255, 51, 0)
+    const ASCII_PLY: &str = "\
+ply
+format ascii 1.0
+comment unit square
+element vertex 4
+property float x
+property float y
+property float z
+property uchar red
+property uchar green
+property uchar blue
+element face 1
+property list uchar int vertex_indices
+end_header
+0 0 0 255 51 0
+1 0 0 255 51 0
+1 1 0 255 51 0
+0 1 0 255 51 0
+4 0 1 2 3
+";
+
+    const SQUARE: [[f32; 3]; 4] = [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0]];
+
+    /// `ASCII_PLY` in binary of the given byte order
+    fn binary_ply(big_endian: bool) -> Vec<u8> {
+        let format = if big_endian { "binary_big_endian" } else { "binary_little_endian" };
+        let header = ASCII_PLY.replace("ascii", format);
+        let body_start = header.find("end_header\n").unwrap() + "end_header\n".len();
+        let mut bytes = header.as_bytes()[..body_start].to_vec();
+        let order = |value: [u8; 4]| if big_endian { [value[3], value[2], value[1], value[0]] } else { value };
+        for corner in SQUARE {
+            for component in corner {
+                bytes.extend(order(component.to_le_bytes()));
+            }
+            bytes.extend([255, 51, 0]);
+        }
+        bytes.push(4);
+        for index in 0i32..4 {
+            bytes.extend(order(index.to_le_bytes()));
+        }
+        bytes
+    }
+
+    /// One triangle of the square, stored against its normal, in an 80-byte
+    /// header that starts like an ASCII file
+    fn binary_stl() -> Vec<u8> {
+        let mut bytes = b"solid but actually binary".to_vec();
+        bytes.resize(STL_HEADER_LEN, 0);
+        bytes.extend(1u32.to_le_bytes());
+        for vector in [[0.0, 0.0, 1.0], SQUARE[0], SQUARE[2], SQUARE[1]] {
+            for component in vector {
+                bytes.extend(component.to_le_bytes());
+            }
+        }
+        bytes.extend([0, 0]);
+        bytes
+    }
+
+    fn hit_from_below(mesh: &Mesh, x: f64, y: f64) -> Option<crate::hittable::HitRecord> {
+        let ray = Ray::new(Vector3D::new(x, y, -1.0), Vector3D::new(0.0, 0.0, 1.0));
+        mesh.hit(&ray, 0.0, f64::INFINITY)
+    }
+
+    fn assert_unit_square(mesh: &Mesh) {
+        assert_eq!(mesh.triangle_count(), 2);
+        assert_eq!(mesh.bounds(), Some((Vector3D::ZERO, Vector3D::new(1.0, 1.0, 0.0))));
+        let hit = hit_from_below(mesh, 0.25, 0.75).expect("the square covers (0.25, 0.75)");
+        assert!((hit.t - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn ascii_ply_fans_polygons() {
+        assert_unit_square(&parse_ply(ASCII_PLY.as_bytes(), WHITE).unwrap());
+    }
+
+    #[test]
+    fn binary_ply_in_either_byte_order() {
+        for big_endian in [false, true] {
+            assert_unit_square(&parse_ply(&binary_ply(big_endian), WHITE).unwrap());
+        }
+    }
+
+    #[test]
+    fn ply_vertex_colors_multiply_the_material() {
+        let material = Material {
+            color: Color { r: 0.5, g: 1.0, b: 1.0 },
+            ..WHITE
+        };
+        for bytes in [ASCII_PLY.as_bytes().to_vec(), binary_ply(true)] {
+            let mesh = parse_ply(&bytes, material).unwrap();
+            let color = hit_from_below(&mesh, 0.5, 0.25).unwrap().material.color;
+            assert!((color.r - 0.5).abs() < 1e-9);
+            assert!((color.g - 0.2).abs() < 1e-9);
+            assert!(color.b.abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn ply_face_index_out_of_range_is_an_error() {
+        for face in ["4 0 1 2 4", "3 0 1 -1"] {
+            let ply = ASCII_PLY.replace("4 0 1 2 3", face);
+            assert!(matches!(parse_ply(ply.as_bytes(), WHITE), Err(MeshImportError::Invalid(_))), "{}", face);
+        }
+    }
+
+    #[test]
+    fn ascii_stl_flips_facets_to_their_normals() {
+        let stl = "\
+solid square
+  facet normal 0 0 -1
+    outer loop
+      vertex 0 0 0
+      vertex 1 0 0
+      vertex 1 1 0
+    endloop
+  endfacet
+  facet normal 0 0 -1
+    outer loop
+      vertex 0 0 0
+      vertex 1 1 0
+      vertex 0 1 0
+    endloop
+  endfacet
+endsolid square
+";
+        let mesh = parse_stl(stl.as_bytes(), WHITE).unwrap();
+        assert_unit_square(&mesh);
+        let hit = hit_from_below(&mesh, 0.75, 0.25).unwrap();
+        assert!(hit.normal.z < 0.0);
+    }
+
+    #[test]
+    fn binary_stl_with_a_solid_header() {
+        let mesh = parse_stl(&binary_stl(), WHITE).unwrap();
+        assert_eq!(mesh.triangle_count(), 1);
+        assert!(hit_from_below(&mesh, 0.75, 0.25).is_some());
+        assert!(hit_from_below(&mesh, 0.25, 0.75).is_none());
+    }
+
+    #[test]
+    fn truncated_files_are_errors() {
+        for bytes in [binary_ply(false), binary_ply(true), ASCII_PLY.as_bytes().to_vec()] {
+            // The ASCII file is complete without its final newline
+            let complete = bytes.len() - usize::from(bytes.ends_with(b"\n"));
+            for length in 0..complete {
+                assert!(parse_ply(&bytes[..length], WHITE).is_err(), "PLY cut to {} bytes", length);
+            }
+        }
+        let stl = binary_stl();
+        for length in 0..stl.len() {
+            assert!(parse_stl(&stl[..length], WHITE).is_err(), "STL cut to {} bytes", length);
+        }
+    }
+}
@@ -0,0 +1,59 @@
+use crate::hittable::HitRecord;
+use crate::texture::{Texture, TextureId};
+use crate::vector3d::Vector3D;
+
+/// Fine surface detail that tilts the shading normal without changing the
+/// geometry, read from a texture in the hit's tangent frame
+#[derive(Debug, Clone, Copy)]
+pub enum NormalMap {
+    /// Tangent-space normals encoded as colours: red along the tangent,
+    /// green up the image (towards decreasing v, the OpenGL and glTF
+    /// convention) and blue along the normal. `strength` scales the tilt;
+    /// 1 is the map as authored.
+    Tangent { texture: TextureId, strength: f64 },
+    /// Heights from the texture's luminance. `scale` is the height of white
+    /// above black, in the units the UVs span, i.e. one texture repeat.
+    Bump { texture: TextureId, scale: f64 },
+}
+
+impl NormalMap {
+    pub fn texture(&self) -> TextureId {
+        match *self {
+            NormalMap::Tangent { texture, .. } | NormalMap::Bump { texture, .. } => texture,
+        }
+    }
+
+    /// Shading normal at `hit` with `texture` as this map's texture. Keeps
+    /// the geometric normal if the tilted one would face into the surface.
+    pub fn apply(&self, texture: &Texture, hit: &HitRecord) -> Vector3D {
+        let (u, v) = hit.uv;
+        let perturbed = match *self {
+            NormalMap::Tangent { strength, .. } => {
+                let texel = texture.sample(u, v);
+                let x = (2.0 * texel.r - 1.0) * strength;
+                let y = (2.0 * texel.g - 1.0) * strength;
+                let z = 2.0 * texel.b - 1.0;
+                hit.tangent * x - hit.bitangent * y + hit.normal * z
+            }
+            NormalMap::Bump { scale, .. } => {
+                // Central differences one texel apart
+                let du = 1.0 / texture.width.max(1) as f64;
+                let dv = 1.0 / texture.height.max(1) as f64;
+                let height = |u: f64, v: f64| {
+                    let texel = texture.sample(u, v);
+                    0.2126 * texel.r + 0.7152 * texel.g + 0.0722 * texel.b
+                };
+                let slope_u = (height(u + du, v) - height(u - du, v)) / (2.0 * du);
+                let slope_v = (height(u, v + dv) - height(u, v - dv)) / (2.0 * dv);
+                hit.normal - (hit.tangent * slope_u + hit.bitangent * slope_v) * scale
+            }
+        };
+
+        let perturbed = perturbed.normalize();
+        if perturbed.dot(hit.normal) > 0.0 {
+            perturbed
+        } else {
+            hit.normal
+        }
+    }
+}
@@ -0,0 +1,235 @@
+use crate::sphere::{Color, Material};
+use crate::vector3d::Vector3D;
+use std::f64::consts::PI;
+
+/// Smallest GGX alpha, so perfectly smooth surfaces keep a finite highlight
+const MIN_ALPHA: f64 = 1e-3;
+
+/// Reflectance at normal incidence of every dielectric, as in glTF
+const DIELECTRIC_F0: f64 = 0.04;
+
+/// glTF-style metallic-roughness material: a Lambertian base under a GGX
+/// (Trowbridge-Reitz) specular lobe with height-correlated Smith masking.
+///
+/// Metals tint their reflection by `base_color` and have no diffuse part;
+/// dielectrics reflect 4% at normal incidence, rising with Fresnel, and
+/// the light they don't reflect reaches the base. That split is what keeps
+/// the model energy conserving, unlike the free Blinn-Phong weights.
+#[derive(Debug, Clone, Copy)]
+pub struct PbrMaterial {
+    pub base_color: Color,
+    pub metallic: f64,
+    /// Perceptual roughness; GGX alpha is its square
+    pub roughness: f64,
+}
+
+impl PbrMaterial {
+    pub fn new(base_color: Color, metallic: f64, roughness: f64) -> PbrMaterial {
+        PbrMaterial {
+            base_color,
+            metallic: metallic.clamp(0.0, 1.0),
+            roughness: roughness.clamp(0.0, 1.0),
+        }
+    }
+
+    /// Closest match for an old Blinn-Phong material. The exponent maps to
+    /// roughness through the Beckmann equivalence alpha = sqrt(2 / (n + 2)),
+    /// and mirror reflectivity becomes metalness.
+    pub fn from_phong(material: &Material) -> PbrMaterial {
+        let alpha = (2.0 / (material.shininess.max(0.0) + 2.0)).sqrt();
+        PbrMaterial::new(material.color, material.reflectivity, alpha.sqrt())
+    }
+
+    #[inline]
+    pub fn alpha(&self) -> f64 {
+        (self.roughness * self.roughness).max(MIN_ALPHA)
+    }
+
+    /// Specular colour at normal incidence
+    #[inline]
+    fn f0(&self) -> Color {
+        Color::WHITE * (DIELECTRIC_F0 * (1.0 - self.metallic)) + self.base_color * self.metallic
+    }
+
+    /// Reflected light for unit light intensity arriving from unit `light`,
+    /// seen from unit `view`: the BRDF times the cosine term. Scaled by pi
+    /// like the Blinn-Phong terms, so white Lambert gives exactly the cosine.
+    pub fn evaluate(&self, normal: Vector3D, view: Vector3D, light: Vector3D) -> Color {
+        let n_dot_l = normal.dot(light);
+        let n_dot_v = normal.dot(view);
+        if n_dot_l <= 0.0 || n_dot_v <= 0.0 {
+            return Color::default();
+        }
+
+        let half = (view + light).normalize();
+        let alpha = self.alpha();
+        let fresnel = fresnel_schlick(self.f0(), view.dot(half));
+        let masking = 1.0 / (1.0 + smith_lambda(n_dot_v, alpha) + smith_lambda(n_dot_l, alpha));
+        let specular = fresnel * (ggx_distribution(normal.dot(half), alpha) * masking / (4.0 * n_dot_v));
+
+        self.evaluate_diffuse(normal, view, light) + specular * PI
+    }
+
+    /// The base layer's share of `evaluate`, for light whose glossy
+    /// reflection is already found by `sample_reflection`
+    pub fn evaluate_diffuse(&self, normal: Vector3D, view: Vector3D, light: Vector3D) -> Color {
+        let n_dot_l = normal.dot(light);
+        let n_dot_v = normal.dot(view);
+        if n_dot_l <= 0.0 || n_dot_v <= 0.0 {
+            return Color::default();
+        }
+
+        // Only light that the coat lets through on the way in and out reaches
+        // the base; weighting by the half-vector Fresnel alone overshoots at
+        // grazing angles
+        let f0 = self.f0();
+        let transmitted = (Color::WHITE - fresnel_schlick(f0, n_dot_l)) * (Color::WHITE - fresnel_schlick(f0, n_dot_v));
+        transmitted * self.base_color * ((1.0 - self.metallic) * n_dot_l)
+    }
+
+    /// Samples a glossy reflection of unit `view` from the visible GGX
+    /// normals (Heitz 2018), returning the direction and its weight
+    /// `F * G2 / G1`. `None` when the sample points into the surface.
+    pub fn sample_reflection(&self, normal: Vector3D, view: Vector3D, u1: f64, u2: f64) -> Option<(Vector3D, Color)> {
+        let n_dot_v = normal.dot(view);
+        if n_dot_v <= 0.0 {
+            return None;
+        }
+
+        let alpha = self.alpha();
+        let (tangent, bitangent) = normal.orthonormal_basis();
+        let local_view = Vector3D::new(view.dot(tangent), view.dot(bitangent), n_dot_v);
+
+        // Stretch the view into the hemisphere configuration, sample the
+        // projected disk there and unstretch the resulting normal
+        let stretched = Vector3D::new(alpha * local_view.x, alpha * local_view.y, local_view.z).normalize();
+        let length_sq = stretched.x * stretched.x + stretched.y * stretched.y;
+        let t1 = if length_sq > 0.0 {
+            Vector3D::new(-stretched.y, stretched.x, 0.0) * (1.0 / length_sq.sqrt())
+        } else {
+            Vector3D::new(1.0, 0.0, 0.0)
+        };
+        let t2 = stretched.cross(t1);
+
+        let radius = u1.sqrt();
+        let phi = 2.0 * PI * u2;
+        let p1 = radius * phi.cos();
+        let s = 0.5 * (1.0 + stretched.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * radius * phi.sin();
+        let p3 = (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+        let micro = t1 * p1 + t2 * p2 + stretched * p3;
+        let micro = Vector3D::new(alpha * micro.x, alpha * micro.y, micro.z.max(0.0)).normalize();
+
+        let half = tangent * micro.x + bitangent * micro.y + normal * micro.z;
+        let v_dot_h = view.dot(half);
+        let direction = half * (2.0 * v_dot_h) - view;
+        let n_dot_l = normal.dot(direction);
+        if n_dot_l <= 0.0 {
+            return None;
+        }
+
+        let lambda_v = smith_lambda(n_dot_v, alpha);
+        let masking = (1.0 + lambda_v) / (1.0 + lambda_v + smith_lambda(n_dot_l, alpha));
+        Some((direction, fresnel_schlick(self.f0(), v_dot_h) * masking))
+    }
+}
+
+/// GGX normal distribution for a microfacet at `n_dot_h` from the normal
+#[inline]
+fn ggx_distribution(n_dot_h: f64, alpha: f64) -> f64 {
+    let alpha_sq = alpha * alpha;
+    let d = n_dot_h * n_dot_h * (alpha_sq - 1.0) + 1.0;
+    alpha_sq / (PI * d * d)
+}
+
+/// Smith's Lambda for GGX; masking G1 is 1 / (1 + Lambda)
+#[inline]
+fn smith_lambda(cos_theta: f64, alpha: f64) -> f64 {
+    let cos_sq = cos_theta * cos_theta;
+    let tan_sq = (1.0 - cos_sq).max(0.0) / cos_sq;
+    0.5 * ((1.0 + alpha * alpha * tan_sq).sqrt() - 1.0)
+}
+
+#[inline]
+fn fresnel_schlick(f0: Color, cos_theta: f64) -> Color {
+    let weight = (1.0 - cos_theta.clamp(0.0, 1.0)).powi(5);
+    f0 * (1.0 - weight) + Color::WHITE * weight
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NORMAL: Vector3D = Vector3D { x: 0.0, y: 0.0, z: 1.0 };
+
+    fn view(cos_theta: f64) -> Vector3D {
+        Vector3D::new((1.0 - cos_theta * cos_theta).sqrt(), 0.0, cos_theta)
+    }
+
+    /// Mean weight of the sampled glossy reflections: the specular albedo
+    fn sampled_albedo(material: &PbrMaterial, view: Vector3D) -> f64 {
+        let n = 256;
+        let mut sum = 0.0;
+        for i in 0..n {
+            for j in 0..n {
+                let (u1, u2) = ((i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64);
+                if let Some((_, weight)) = material.sample_reflection(NORMAL, view, u1, u2) {
+                    sum += weight.r;
+                }
+            }
+        }
+        sum / (n * n) as f64
+    }
+
+    /// Integral of `f` over the hemisphere, divided by pi like the
+    /// evaluated terms are scaled
+    fn integrated_albedo(f: impl Fn(Vector3D) -> f64) -> f64 {
+        let n = 400;
+        let mut sum = 0.0;
+        for i in 0..n {
+            let cos_theta = (i as f64 + 0.5) / n as f64;
+            let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+            for j in 0..n {
+                let phi = 2.0 * PI * (j as f64 + 0.5) / n as f64;
+                sum += f(Vector3D::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta));
+            }
+        }
+        sum * 2.0 * PI / (n * n) as f64 / PI
+    }
+
+    #[test]
+    fn white_furnace_never_gains_energy() {
+        for roughness in [0.05, 0.2, 0.5, 1.0] {
+            for metallic in [0.0, 1.0] {
+                let material = PbrMaterial::new(Color::WHITE, metallic, roughness);
+                for cos_theta in [1.0, 0.5, 0.1] {
+                    let view = view(cos_theta);
+                    let albedo = sampled_albedo(&material, view)
+                        + integrated_albedo(|light| material.evaluate_diffuse(NORMAL, view, light).r);
+                    assert!(albedo <= 1.0 + 1e-3, "roughness {} metallic {} at {}: {}", roughness, metallic, cos_theta, albedo);
+                    // Moderately rough metals only lose what bounces between microfacets
+                    if metallic == 1.0 && roughness <= 0.5 {
+                        assert!(albedo > 0.85, "roughness {} at {}: {}", roughness, cos_theta, albedo);
+                    }
+                }
+            }
+        }
+
+        // A smooth white mirror loses almost nothing
+        let mirror = PbrMaterial::new(Color::WHITE, 1.0, 0.05);
+        assert!(sampled_albedo(&mirror, view(1.0)) > 0.99);
+    }
+
+    #[test]
+    fn sampled_reflections_match_the_evaluated_lobe() {
+        for roughness in [0.5, 1.0] {
+            let material = PbrMaterial::new(Color::WHITE, 1.0, roughness);
+            for cos_theta in [1.0, 0.5] {
+                let view = view(cos_theta);
+                let sampled = sampled_albedo(&material, view);
+                let integrated = integrated_albedo(|light| material.evaluate(NORMAL, view, light).r);
+                assert!((sampled - integrated).abs() < 0.01, "roughness {} at {}: {} vs {}", roughness, cos_theta, sampled, integrated);
+            }
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use crate::hittable::{HitRecord, Hittable};
+use crate::hittable::{HitRecord, Hittable, Interval};
 use crate::ray::Ray;
 use crate::sphere::Material;
 use crate::vector3d::Vector3D;
@@ -18,6 +18,23 @@ impl Plane {
             material,
         }
     }
+
+    #[inline]
+    fn record(&self, ray: &Ray, t: f64) -> HitRecord {
+        let point = ray.at(t);
+        let (tangent, bitangent) = self.normal.orthonormal_basis();
+        let offset = point - self.point;
+
+        HitRecord {
+            point,
+            normal: self.normal,
+            t,
+            uv: (offset.dot(tangent), offset.dot(bitangent)),
+            tangent,
+            bitangent,
+            material: self.material,
+        }
+    }
 }
 
 impl Hittable for Plane {
@@ -35,13 +52,28 @@ impl Hittable for Plane {
             return None;
         }
 
-        let point = ray.at(t);
+        Some(self.record(ray, t))
+    }
 
-        Some(HitRecord {
-            point,
-            normal: self.normal,
-            t,
-            material: self.material,
-        })
+    /// As a solid, a plane is the half-space behind its normal
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        let denom = self.normal.dot(ray.direction);
+        let depth = (self.point - ray.origin).dot(self.normal);
+
+        let (enter, exit) = if denom.abs() < 1e-8 {
+            if depth <= 0.0 {
+                return Vec::new();
+            }
+            (f64::NEG_INFINITY, f64::INFINITY)
+        } else if denom < 0.0 {
+            (depth / denom, f64::INFINITY)
+        } else {
+            (f64::NEG_INFINITY, depth / denom)
+        };
+
+        vec![Interval {
+            enter: self.record(ray, enter),
+            exit: self.record(ray, exit),
+        }]
     }
 }
@@ -1,14 +1,21 @@
+// The basis is built on the CPU (Camera::basis), roll included
 struct Camera {
     position: vec3<f32>,
     _padding1: f32,
-    look_at: vec3<f32>,
+    forward: vec3<f32>,
     _padding2: f32,
-    up: vec3<f32>,
+    right: vec3<f32>,
     fov: f32,
+    up: vec3<f32>,
     aspect_ratio: f32,
-    _padding3: f32,
-    _padding4: f32,
-    _padding5: f32,
+    // 0 perspective, 1 orthographic, 2 equidistant fisheye,
+    // 3 equisolid fisheye, 4 equirectangular, 5 omni-directional stereo
+    projection: u32,
+    // View height of an orthographic camera, eye offset of a stereo one
+    projection_parameter: f32,
+    // Lens shift in image widths and heights
+    shift_x: f32,
+    shift_y: f32,
 }
 
 struct Material {
@@ -34,6 +41,30 @@ struct Plane {
     material: Material,
 }
 
+// Generic primitive intersected in its local frame (origin, axis_u/v/w);
+// the meaning of dims depends on kind, see ShapeKind in gpu_renderer.rs
+struct Shape {
+    origin: vec3<f32>,
+    kind: u32,
+    axis_u: vec3<f32>,
+    _padding1: f32,
+    axis_v: vec3<f32>,
+    _padding2: f32,
+    axis_w: vec3<f32>,
+    _padding3: f32,
+    dims: vec3<f32>,
+    _padding4: f32,
+    material: Material,
+}
+
+// Result of a shape test; t < 0 on a miss. The normal is in the shape's
+// local frame until hit_shape moves it back out.
+struct ShapeHit {
+    t: f32,
+    normal: vec3<f32>,
+    uv: vec2<f32>,
+}
+
 struct Light {
     position: vec3<f32>,
     intensity: f32,
@@ -49,7 +80,27 @@ struct RenderParams {
     num_spheres: u32,
     num_planes: u32,
     num_lights: u32,
-    _padding: u32,
+    sampler_kind: u32,
+    aovs: u32,
+    num_shapes: u32,
+    // Region of the image this dispatch renders; width and height are the
+    // whole image's
+    crop_x: u32,
+    crop_y: u32,
+    crop_width: u32,
+    crop_height: u32,
+    _padding2: u32,
+    _padding3: u32,
+}
+
+// First-hit values of the current camera ray, filled in by cast_ray
+struct PrimaryHit {
+    normal: vec3<f32>,
+    t: f32,
+    albedo: vec3<f32>,
+    object_id: f32,
+    direct: vec3<f32>,
+    shadow: f32,
 }
 
 @group(0) @binding(0) var<uniform> params: RenderParams;
@@ -58,9 +109,34 @@ struct RenderParams {
 @group(0) @binding(3) var<storage, read> planes: array<Plane>;
 @group(0) @binding(4) var<storage, read> lights: array<Light>;
 @group(0) @binding(5) var<storage, read_write> output: array<vec4<f32>>;
+@group(0) @binding(6) var<storage, read> blue_noise: array<f32>;
+// Four vec4 per pixel: (normal, depth), (albedo, object id), (direct, shadow), (reflected, 0)
+@group(0) @binding(7) var<storage, read_write> aov_output: array<vec4<f32>>;
+@group(0) @binding(8) var<storage, read> shapes: array<Shape>;
+
+var<private> primary: PrimaryHit;
 
 const PI: f32 = 3.14159265359;
 
+const SAMPLER_RANDOM: u32 = 0u;
+const SAMPLER_STRATIFIED: u32 = 1u;
+const SAMPLER_HALTON: u32 = 2u;
+const SAMPLER_SOBOL: u32 = 3u;
+const SAMPLER_BLUE_NOISE: u32 = 4u;
+
+const DIM_PIXEL: u32 = 0u;
+
+const BLUE_NOISE_SIZE: u32 = 64u;
+
+const AOV_VEC4S_PER_PIXEL: u32 = 4u;
+
+const SHAPE_BOX: u32 = 0u;
+const SHAPE_CYLINDER: u32 = 1u;
+const SHAPE_CONE: u32 = 2u;
+const SHAPE_DISK: u32 = 3u;
+const SHAPE_RECTANGLE: u32 = 4u;
+const SHAPE_TORUS: u32 = 5u;
+
 fn pcg_hash(seed: u32) -> u32 {
     var state = seed * 747796405u + 2891336453u;
     var word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
@@ -72,16 +148,212 @@ fn random_f32(seed: ptr<function, u32>) -> f32 {
     return f32(*seed) / 4294967296.0;
 }
 
-fn cast_camera_ray(camera: Camera, ndc_x: f32, ndc_y: f32) -> vec3<f32> {
-    let forward = normalize(camera.look_at - camera.position);
-    let right = normalize(cross(forward, camera.up));
-    let up = cross(right, forward);
+fn to_unit(bits: u32) -> f32 {
+    return f32(bits >> 8u) / 16777216.0;
+}
+
+fn radical_inverse(index: u32, base: u32) -> f32 {
+    let inv_base = 1.0 / f32(base);
+    var inv_base_n = 1.0;
+    var reversed = 0.0;
+    var i = index;
+
+    while (i > 0u) {
+        let next = i / base;
+        let digit = i - next * base;
+        inv_base_n *= inv_base;
+        reversed += f32(digit) * inv_base_n;
+        i = next;
+    }
+
+    return min(reversed, 0.99999994);
+}
+
+// Kensler's hashed permutation of index within [0, length)
+fn permute(index_in: u32, length: u32, seed: u32) -> u32 {
+    if (length <= 1u) {
+        return 0u;
+    }
+
+    var mask = length - 1u;
+    mask |= mask >> 1u;
+    mask |= mask >> 2u;
+    mask |= mask >> 4u;
+    mask |= mask >> 8u;
+    mask |= mask >> 16u;
+
+    var index = index_in;
+    loop {
+        index ^= seed;
+        index *= 0xe170893du;
+        index ^= seed >> 16u;
+        index ^= (index & mask) >> 4u;
+        index ^= seed >> 8u;
+        index *= 0x0929eb3fu;
+        index ^= seed >> 23u;
+        index ^= (index & mask) >> 1u;
+        index *= 1u | (seed >> 27u);
+        index *= 0x6935fa69u;
+        index ^= (index & mask) >> 11u;
+        index *= 0x74dcb303u;
+        index ^= (index & mask) >> 2u;
+        index *= 0x9e501cc3u;
+        index ^= (index & mask) >> 2u;
+        index *= 0xc860a3dfu;
+        index &= mask;
+        index ^= index >> 5u;
+
+        if (index < length) {
+            break;
+        }
+    }
+
+    return (index + seed) % length;
+}
+
+fn laine_karras_permutation(x_in: u32, seed: u32) -> u32 {
+    var x = x_in + seed;
+    x ^= x * 0x6c50b47cu;
+    x ^= x * 0xb82f1e52u;
+    x ^= x * 0xc7afe638u;
+    x ^= x * 0x8d22f6e6u;
+    return x;
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    return reverseBits(laine_karras_permutation(reverseBits(x), seed));
+}
+
+// First two Sobol dimensions with hash-based Owen scrambling (Burley 2020)
+fn sobol_2d(index: u32, seed: u32) -> vec2<u32> {
+    let shuffled = nested_uniform_scramble(index, seed);
+
+    let u = reverseBits(shuffled);
+
+    var v = 0u;
+    var direction = 1u << 31u;
+    var i = shuffled;
+    while (i != 0u) {
+        if ((i & 1u) != 0u) {
+            v ^= direction;
+        }
+        i >>= 1u;
+        direction ^= direction >> 1u;
+    }
+
+    return vec2<u32>(
+        nested_uniform_scramble(u, pcg_hash(seed)),
+        nested_uniform_scramble(v, pcg_hash(seed ^ 0x5bd1e995u)),
+    );
+}
+
+fn blue_noise_at(x: u32, y: u32) -> f32 {
+    return blue_noise[(y % BLUE_NOISE_SIZE) * BLUE_NOISE_SIZE + (x % BLUE_NOISE_SIZE)];
+}
+
+// Mirrors Sampler::get_2d in sampler.rs
+fn sample_2d(x: u32, y: u32, index: u32, dimension: u32) -> vec2<f32> {
+    let pixel_seed = pcg_hash(x ^ pcg_hash(y ^ pcg_hash(dimension)));
+
+    if (params.sampler_kind == SAMPLER_STRATIFIED) {
+        let strata_x = max(u32(floor(sqrt(f32(params.samples)))), 1u);
+        let strata_y = (params.samples + strata_x - 1u) / strata_x;
+        let stratum = permute(index % (strata_x * strata_y), strata_x * strata_y, pixel_seed);
+        let jitter = pcg_hash(index ^ pcg_hash(pixel_seed));
+        return vec2<f32>(
+            (f32(stratum % strata_x) + to_unit(jitter)) / f32(strata_x),
+            (f32(stratum / strata_x) + to_unit(pcg_hash(jitter))) / f32(strata_y),
+        );
+    } else if (params.sampler_kind == SAMPLER_HALTON) {
+        var primes = array<u32, 16>(2u, 3u, 5u, 7u, 11u, 13u, 17u, 19u, 23u, 29u, 31u, 37u, 41u, 43u, 47u, 53u);
+        let base_index = (2u * dimension) % 16u;
+        let u = radical_inverse(index, primes[base_index]);
+        let v = radical_inverse(index, primes[base_index + 1u]);
+        return fract(vec2<f32>(u, v) + vec2<f32>(to_unit(pixel_seed), to_unit(pcg_hash(pixel_seed))));
+    } else if (params.sampler_kind == SAMPLER_SOBOL) {
+        let bits = sobol_2d(index, pixel_seed);
+        return vec2<f32>(to_unit(bits.x), to_unit(bits.y));
+    } else if (params.sampler_kind == SAMPLER_BLUE_NOISE) {
+        let bits = sobol_2d(index, pcg_hash(dimension));
+        let offset_x = (dimension * 17u) % BLUE_NOISE_SIZE;
+        let offset_y = (dimension * 29u) % BLUE_NOISE_SIZE;
+        let shift = vec2<f32>(
+            blue_noise_at(x + offset_x, y + offset_y),
+            blue_noise_at(x + offset_x + BLUE_NOISE_SIZE / 2u, y + offset_y + BLUE_NOISE_SIZE / 2u),
+        );
+        return fract(vec2<f32>(to_unit(bits.x), to_unit(bits.y)) + shift);
+    }
+
+    var seed = pcg_hash(index ^ pixel_seed) ^ pixel_seed;
+    let u = random_f32(&seed);
+    let v = random_f32(&seed);
+    return vec2<f32>(u, v);
+}
+
+struct CameraRay {
+    origin: vec3<f32>,
+    // 0 where a fisheye lens sees nothing
+    valid: f32,
+    direction: vec3<f32>,
+}
+
+fn cast_camera_ray(camera: Camera, image_x: f32, image_y: f32) -> CameraRay {
+    let forward = camera.forward;
+    let right = camera.right;
+    let up = camera.up;
+    let ndc_x = image_x + 2.0 * camera.shift_x;
+    let ndc_y = image_y - 2.0 * camera.shift_y;
+
+    // Image plane coordinates in units of half the image height
+    let x = ndc_x * camera.aspect_ratio;
+    let y = -ndc_y;
+
+    if (camera.projection == 1u) {
+        let origin = camera.position + (right * x + up * y) * (camera.projection_parameter * 0.5);
+        return CameraRay(origin, 1.0, forward);
+    }
+    if (camera.projection == 2u || camera.projection == 3u) {
+        let radius = sqrt(x * x + y * y);
+        let half_fov = camera.fov * PI / 360.0;
+        var theta = radius * half_fov;
+        if (camera.projection == 3u) {
+            let s = radius * sin(half_fov * 0.5);
+            if (s > 1.0) {
+                return CameraRay(camera.position, 0.0, forward);
+            }
+            theta = 2.0 * asin(s);
+        }
+        if (theta > PI) {
+            return CameraRay(camera.position, 0.0, forward);
+        }
+        var sideways = vec3<f32>(0.0);
+        if (radius > 0.0) {
+            sideways = (right * x + up * y) * (sin(theta) / radius);
+        }
+        return CameraRay(camera.position, 1.0, normalize(forward * cos(theta) + sideways));
+    }
+    if (camera.projection >= 4u) {
+        let longitude = ndc_x * PI;
+        let latitude = y * PI * 0.5;
+        let direction = (forward * cos(longitude) + right * sin(longitude)) * cos(latitude) + up * sin(latitude);
+        var origin = camera.position;
+        if (camera.projection == 5u) {
+            origin += (right * cos(longitude) - forward * sin(longitude)) * camera.projection_parameter;
+        }
+        return CameraRay(origin, 1.0, normalize(direction));
+    }
 
     let fov_adjustment = tan(camera.fov * PI / 360.0);
-    let adjusted_x = ndc_x * camera.aspect_ratio * fov_adjustment;
-    let adjusted_y = -ndc_y * fov_adjustment;
+    return CameraRay(camera.position, 1.0, normalize(forward + right * (x * fov_adjustment) + up * (y * fov_adjustment)));
+}
 
-    return normalize(forward + right * adjusted_x + up * adjusted_y);
+// Depth AOV of a hit at distance t: in front of the image plane for
+// perspective and orthographic cameras, from the camera for the others
+fn camera_depth(camera: Camera, ray_dir: vec3<f32>, forward: vec3<f32>, t: f32) -> f32 {
+    if (camera.projection <= 1u) {
+        return t * dot(ray_dir, forward);
+    }
+    return t;
 }
 
 fn reflect_vec(incident: vec3<f32>, normal: vec3<f32>) -> vec3<f32> {
@@ -128,6 +400,308 @@ fn hit_plane(ray_origin: vec3<f32>, ray_dir: vec3<f32>, plane: Plane, t_min: f32
     return t;
 }
 
+// Longitude/latitude coordinates on the unit sphere, as sphere_uv in sphere.rs
+fn sphere_uv(normal: vec3<f32>) -> vec2<f32> {
+    return vec2<f32>(atan2(-normal.z, normal.x) / (2.0 * PI) + 0.5, acos(clamp(-normal.y, -1.0, 1.0)) / PI);
+}
+
+// Orthonormal basis around a unit vector (Duff et al. 2017), as in vector3d.rs
+fn orthonormal_basis(n: vec3<f32>) -> mat2x3<f32> {
+    let sign = select(-1.0, 1.0, n.z >= 0.0);
+    let a = -1.0 / (sign + n.z);
+    let b = n.x * n.y * a;
+    return mat2x3<f32>(
+        vec3<f32>(1.0 + sign * n.x * n.x * a, sign * b, -sign * n.x),
+        vec3<f32>(b, sign + n.y * n.y * a, -n.y),
+    );
+}
+
+// World-unit coordinates along the plane, as in plane.rs
+fn plane_uv(plane: Plane, point: vec3<f32>) -> vec2<f32> {
+    let basis = orthonormal_basis(plane.normal);
+    let offset = point - plane.point;
+    return vec2<f32>(dot(offset, basis[0]), dot(offset, basis[1]));
+}
+
+fn no_hit() -> ShapeHit {
+    return ShapeHit(-1.0, vec3<f32>(0.0), vec2<f32>(0.0));
+}
+
+// Slab test against a box centred on the local origin, as hit_box in cuboid.rs
+fn hit_local_box(o: vec3<f32>, d: vec3<f32>, half_extents: vec3<f32>, t_min: f32, t_max: f32) -> ShapeHit {
+    let inv_d = 1.0 / d;
+    let t0 = (-half_extents - o) * inv_d;
+    let t1 = (half_extents - o) * inv_d;
+    let near_v = min(t0, t1);
+    let far_v = max(t0, t1);
+    let near = max(max(near_v.x, near_v.y), near_v.z);
+    let far = min(min(far_v.x, far_v.y), far_v.z);
+
+    if (near > far) {
+        return no_hit();
+    }
+    var t = near;
+    if (t < t_min || t > t_max) {
+        t = far;
+        if (t < t_min || t > t_max) {
+            return no_hit();
+        }
+    }
+
+    let p = o + d * t;
+    let relative = abs(p / half_extents);
+    let scaled = p / half_extents * 0.5 + 0.5;
+    if (relative.x > relative.y && relative.x > relative.z) {
+        return ShapeHit(t, vec3<f32>(sign(p.x), 0.0, 0.0), scaled.yz);
+    } else if (relative.y > relative.z) {
+        return ShapeHit(t, vec3<f32>(0.0, sign(p.y), 0.0), scaled.zx);
+    }
+    return ShapeHit(t, vec3<f32>(0.0, 0.0, sign(p.z)), scaled.xy);
+}
+
+// Disk of radius in the local plane at height z, with planar uvs
+fn hit_local_cap(o: vec3<f32>, d: vec3<f32>, z: f32, radius: f32, normal_z: f32, t_min: f32, t_max: f32) -> ShapeHit {
+    if (d.z == 0.0) {
+        return no_hit();
+    }
+    let t = (z - o.z) / d.z;
+    if (t < t_min || t > t_max) {
+        return no_hit();
+    }
+    let p = (o + d * t).xy;
+    if (dot(p, p) > radius * radius) {
+        return no_hit();
+    }
+    return ShapeHit(t, vec3<f32>(0.0, 0.0, normal_z), p / radius * 0.5 + 0.5);
+}
+
+// Capped cylinder from z = 0 to z = height
+fn hit_local_cylinder(o: vec3<f32>, d: vec3<f32>, radius: f32, height: f32, t_min: f32, t_max: f32) -> ShapeHit {
+    var best = no_hit();
+    var closest_t = t_max;
+
+    let a = dot(d.xy, d.xy);
+    if (a > 0.0) {
+        let half_b = dot(o.xy, d.xy);
+        let c = dot(o.xy, o.xy) - radius * radius;
+        let discriminant = half_b * half_b - a * c;
+        if (discriminant >= 0.0) {
+            let sqrtd = sqrt(discriminant);
+            for (var i = 0; i < 2; i++) {
+                let t = (-half_b + select(-sqrtd, sqrtd, i == 1)) / a;
+                let z = o.z + d.z * t;
+                if (t >= t_min && t <= closest_t && z >= 0.0 && z <= height) {
+                    let p = o + d * t;
+                    best = ShapeHit(t, vec3<f32>(p.xy / radius, 0.0), vec2<f32>(atan2(p.y, p.x) / (2.0 * PI) + 0.5, z / height));
+                    closest_t = t;
+                    break;
+                }
+            }
+        }
+    }
+
+    let bottom = hit_local_cap(o, d, 0.0, radius, -1.0, t_min, closest_t);
+    if (bottom.t >= 0.0) {
+        best = bottom;
+        closest_t = bottom.t;
+    }
+    let top = hit_local_cap(o, d, height, radius, 1.0, t_min, closest_t);
+    if (top.t >= 0.0) {
+        best = top;
+    }
+    return best;
+}
+
+// Cone with its base at z = 0 and apex at z = height, closed at the base
+fn hit_local_cone(o: vec3<f32>, d: vec3<f32>, radius: f32, height: f32, t_min: f32, t_max: f32) -> ShapeHit {
+    var best = no_hit();
+    var closest_t = t_max;
+
+    let k = radius / height;
+    let k2 = k * k;
+    let height_left = height - o.z;
+    let a = dot(d.xy, d.xy) - k2 * d.z * d.z;
+    let half_b = dot(o.xy, d.xy) + k2 * height_left * d.z;
+    let c = dot(o.xy, o.xy) - k2 * height_left * height_left;
+
+    var roots = vec2<f32>(-1.0);
+    var root_count = 0;
+    if (abs(a) > 1e-8) {
+        let discriminant = half_b * half_b - a * c;
+        if (discriminant >= 0.0) {
+            let sqrtd = sqrt(discriminant);
+            let t0 = (-half_b - sqrtd) / a;
+            let t1 = (-half_b + sqrtd) / a;
+            roots = vec2<f32>(min(t0, t1), max(t0, t1));
+            root_count = 2;
+        }
+    } else if (half_b != 0.0) {
+        roots.x = -c / (2.0 * half_b);
+        root_count = 1;
+    }
+
+    for (var i = 0; i < root_count; i++) {
+        let t = roots[i];
+        let z = o.z + d.z * t;
+        if (t >= t_min && t <= closest_t && z >= 0.0 && z <= height) {
+            let p = o + d * t;
+            let normal = normalize(vec3<f32>(p.xy, k * length(p.xy)));
+            best = ShapeHit(t, normal, vec2<f32>(atan2(p.y, p.x) / (2.0 * PI) + 0.5, z / height));
+            closest_t = t;
+            break;
+        }
+    }
+
+    let base = hit_local_cap(o, d, 0.0, radius, -1.0, t_min, closest_t);
+    if (base.t >= 0.0) {
+        best = base;
+    }
+    return best;
+}
+
+// Torus around the local z axis, solved as in torus.rs (after Inigo Quilez)
+fn hit_local_torus(o_in: vec3<f32>, d_in: vec3<f32>, major_radius: f32, minor_radius: f32, t_min: f32, t_max: f32) -> ShapeHit {
+    let ray_length = length(d_in);
+    let rd = d_in / ray_length;
+
+    // Start just outside the bounding sphere to keep the coefficients small;
+    // the sphere touches the tube along the equator
+    let outer = major_radius + minor_radius;
+    let n0 = dot(o_in, rd);
+    let bound = n0 * n0 - dot(o_in, o_in) + outer * outer;
+    if (bound < 0.0) {
+        return no_hit();
+    }
+    let start = max(-n0 - sqrt(bound) - minor_radius, 0.0);
+    let ro = o_in + rd * start;
+
+    let big_r2 = major_radius * major_radius;
+    let r2 = minor_radius * minor_radius;
+    let m = dot(ro, ro);
+    let n = dot(ro, rd);
+
+    let k = (m - r2 - big_r2) * 0.5;
+    var k3 = n;
+    var k2 = n * n + big_r2 * rd.z * rd.z + k;
+    var k1 = k * n + big_r2 * ro.z * rd.z;
+    var k0 = k * k + big_r2 * ro.z * ro.z - big_r2 * r2;
+
+    let inverted = abs(k3 * (k3 * k3 - k2) + k1) < 0.01;
+    if (inverted) {
+        let swap = k1;
+        k1 = k3;
+        k3 = swap;
+        k0 = 1.0 / k0;
+        k1 *= k0;
+        k2 *= k0;
+        k3 *= k0;
+    }
+
+    let c2 = (2.0 * k2 - 3.0 * k3 * k3) / 3.0;
+    let c1 = (k3 * (k3 * k3 - k2) + k1) * 2.0;
+    let c0 = (k3 * (k3 * (-3.0 * k3 * k3 + 4.0 * k2) - 8.0 * k1) + 4.0 * k0) / 3.0;
+
+    let q = c2 * c2 + c0;
+    let r = 3.0 * c0 * c2 - c2 * c2 * c2 - c1 * c1;
+    let h = r * r - q * q * q;
+    var z = 0.0;
+    if (h < 0.0) {
+        let sq = sqrt(q);
+        z = 2.0 * sq * cos(acos(clamp(r / (sq * q), -1.0, 1.0)) / 3.0);
+    } else {
+        let sq = pow(sqrt(h) + abs(r), 1.0 / 3.0);
+        z = sign(r) * abs(sq + q / sq);
+    }
+    z = c2 - z;
+
+    var d1 = z - 3.0 * c2;
+    var d2 = z * z - 3.0 * c0;
+    if (abs(d1) < 1e-4) {
+        if (d2 < 0.0) {
+            return no_hit();
+        }
+        d2 = sqrt(d2);
+    } else {
+        if (d1 < 0.0) {
+            return no_hit();
+        }
+        d1 = sqrt(d1 * 0.5);
+        d2 = c1 / d1;
+    }
+
+    var roots = vec4<f32>(0.0);
+    var found = vec4<bool>(false);
+    let h1 = d1 * d1 - z + d2;
+    if (h1 > 0.0) {
+        let s = sqrt(h1);
+        roots.x = -d1 - s - k3;
+        roots.y = -d1 + s - k3;
+        found.x = true;
+        found.y = true;
+    }
+    let h2 = d1 * d1 - z - d2;
+    if (h2 > 0.0) {
+        let s = sqrt(h2);
+        roots.z = d1 - s - k3;
+        roots.w = d1 + s - k3;
+        found.z = true;
+        found.w = true;
+    }
+
+    var best_t = t_max + 1.0;
+    for (var i = 0; i < 4; i++) {
+        var s = roots[i];
+        if (inverted) {
+            s = 2.0 / s;
+        }
+        let t = (start + s) / ray_length;
+        if (found[i] && t >= t_min && t <= t_max && t < best_t) {
+            best_t = t;
+        }
+    }
+    if (best_t > t_max) {
+        return no_hit();
+    }
+
+    let p = o_in + d_in * best_t;
+    let normal = normalize(p * (dot(p, p) - r2 - big_r2 * vec3<f32>(1.0, 1.0, -1.0)));
+    let ring = length(p.xy);
+    let uv = vec2<f32>(atan2(p.y, p.x), atan2(p.z, ring - major_radius)) / (2.0 * PI) + 0.5;
+    return ShapeHit(best_t, normal, uv);
+}
+
+// Moves the ray into the shape's frame, tests it there and returns the hit
+// with a world-space normal. Lengths are preserved, so t is the same in both.
+fn hit_shape(ray_origin: vec3<f32>, ray_dir: vec3<f32>, shape: Shape, t_min: f32, t_max: f32) -> ShapeHit {
+    let frame = mat3x3<f32>(shape.axis_u, shape.axis_v, shape.axis_w);
+    let o = (ray_origin - shape.origin) * frame;
+    let d = ray_dir * frame;
+
+    var hit = no_hit();
+    if (shape.kind == SHAPE_BOX) {
+        hit = hit_local_box(o, d, shape.dims, t_min, t_max);
+    } else if (shape.kind == SHAPE_CYLINDER) {
+        hit = hit_local_cylinder(o, d, shape.dims.x, shape.dims.y, t_min, t_max);
+    } else if (shape.kind == SHAPE_CONE) {
+        hit = hit_local_cone(o, d, shape.dims.x, shape.dims.y, t_min, t_max);
+    } else if (shape.kind == SHAPE_DISK) {
+        hit = hit_local_cap(o, d, 0.0, shape.dims.x, 1.0, t_min, t_max);
+    } else if (shape.kind == SHAPE_RECTANGLE) {
+        if (d.z != 0.0) {
+            let t = -o.z / d.z;
+            let p = (o + d * t).xy;
+            if (t >= t_min && t <= t_max && all(abs(p) <= shape.dims.xy)) {
+                hit = ShapeHit(t, vec3<f32>(0.0, 0.0, 1.0), p / shape.dims.xy * 0.5 + 0.5);
+            }
+        }
+    } else if (shape.kind == SHAPE_TORUS) {
+        hit = hit_local_torus(o, d, shape.dims.x, shape.dims.y, t_min, t_max);
+    }
+
+    hit.normal = frame * hit.normal;
+    return hit;
+}
+
 fn is_in_shadow(point: vec3<f32>, light_pos: vec3<f32>) -> bool {
     let direction = light_pos - point;
     let distance = length(direction);
@@ -148,6 +722,13 @@ fn is_in_shadow(point: vec3<f32>, light_pos: vec3<f32>) -> bool {
         }
     }
 
+    for (var i = 0u; i < params.num_shapes; i++) {
+        let hit = hit_shape(shadow_origin, dir_normalized, shapes[i], params.epsilon, distance - params.epsilon);
+        if (hit.t > 0.0) {
+            return true;
+        }
+    }
+
     return false;
 }
 
@@ -162,6 +743,8 @@ fn cast_ray(ray_origin: vec3<f32>, ray_dir: vec3<f32>, max_depth: u32) -> vec3<f
         var hit_material: Material;
         var hit_normal = vec3<f32>(0.0);
         var hit_point = vec3<f32>(0.0);
+        var hit_uv = vec2<f32>(0.0);
+        var hit_id = 0u;
         var did_hit = false;
 
         for (var i = 0u; i < params.num_spheres; i++) {
@@ -170,7 +753,9 @@ fn cast_ray(ray_origin: vec3<f32>, ray_dir: vec3<f32>, max_depth: u32) -> vec3<f
                 closest_t = t;
                 hit_point = current_origin + current_dir * t;
                 hit_normal = normalize((hit_point - spheres[i].center) / spheres[i].radius);
+                hit_uv = sphere_uv(hit_normal);
                 hit_material = spheres[i].material;
+                hit_id = i + 1u;
                 did_hit = true;
             }
         }
@@ -181,20 +766,43 @@ fn cast_ray(ray_origin: vec3<f32>, ray_dir: vec3<f32>, max_depth: u32) -> vec3<f
                 closest_t = t;
                 hit_point = current_origin + current_dir * t;
                 hit_normal = planes[i].normal;
+                hit_uv = plane_uv(planes[i], hit_point);
                 hit_material = planes[i].material;
+                hit_id = params.num_spheres + i + 1u;
+                did_hit = true;
+            }
+        }
+
+        for (var i = 0u; i < params.num_shapes; i++) {
+            let hit = hit_shape(current_origin, current_dir, shapes[i], params.epsilon, closest_t);
+            if (hit.t > 0.0) {
+                closest_t = hit.t;
+                hit_point = current_origin + current_dir * hit.t;
+                hit_normal = hit.normal;
+                hit_uv = hit.uv;
+                hit_material = shapes[i].material;
+                hit_id = params.num_spheres + params.num_planes + i + 1u;
                 did_hit = true;
             }
         }
 
         if (!did_hit) {
             color += attenuation * params.background_color;
+            if (depth == 0u) {
+                primary = PrimaryHit(vec3<f32>(0.0), 0.0, vec3<f32>(0.0), 0.0, params.background_color, 0.0);
+            }
             break;
         }
 
         var local_color = vec3<f32>(0.0);
+        var total_intensity = 0.0;
+        var shadowed_intensity = 0.0;
 
         for (var i = 0u; i < params.num_lights; i++) {
-            if (!is_in_shadow(hit_point, lights[i].position)) {
+            total_intensity += lights[i].intensity;
+            if (is_in_shadow(hit_point, lights[i].position)) {
+                shadowed_intensity += lights[i].intensity;
+            } else {
                 let light_dir = normalize(lights[i].position - hit_point);
                 let view_dir = normalize(current_origin - hit_point);
 
@@ -211,6 +819,14 @@ fn cast_ray(ray_origin: vec3<f32>, ray_dir: vec3<f32>, max_depth: u32) -> vec3<f
 
         color += attenuation * local_color;
 
+        if (depth == 0u) {
+            var shadow = 0.0;
+            if (total_intensity > 0.0) {
+                shadow = shadowed_intensity / total_intensity;
+            }
+            primary = PrimaryHit(hit_normal, closest_t, hit_material.color, f32(hit_id), local_color, shadow);
+        }
+
         if (hit_material.reflectivity > 0.0) {
             attenuation *= hit_material.reflectivity;
             current_origin = hit_point + hit_normal * params.epsilon;
@@ -220,42 +836,69 @@ fn cast_ray(ray_origin: vec3<f32>, ray_dir: vec3<f32>, max_depth: u32) -> vec3<f
         }
     }
 
-    return clamp(color, vec3<f32>(0.0), vec3<f32>(1.0));
+    return color;
 }
 
 @compute @workgroup_size(8, 8)
 fn main(@builtin(global_invocation_id) global_id: vec3<u32>) {
-    let x = global_id.x;
-    let y = global_id.y;
-
-    if (x >= params.width || y >= params.height) {
+    if (global_id.x >= params.crop_width || global_id.y >= params.crop_height) {
         return;
     }
+    let x = params.crop_x + global_id.x;
+    let y = params.crop_y + global_id.y;
 
-    var rng_seed = (y * params.width + x) * 1000u + 1u;
-
+    let forward = camera.forward;
     var color = vec3<f32>(0.0);
-
+    var unclamped = vec3<f32>(0.0);
+    var normal_depth = vec4<f32>(0.0);
+    var albedo = vec3<f32>(0.0);
+    var direct_shadow = vec4<f32>(0.0);
+    var object_id = 0.0;
+    var seen_any = false;
+    var sample_count = 1u;
     if (params.samples > 1u) {
-        for (var s = 0u; s < params.samples; s++) {
-            let offset_x = random_f32(&rng_seed);
-            let offset_y = random_f32(&rng_seed);
-
-            let ndc_x = ((f32(x) + offset_x) / f32(params.width)) * 2.0 - 1.0;
-            let ndc_y = ((f32(y) + offset_y) / f32(params.height)) * 2.0 - 1.0;
+        sample_count = params.samples;
+    }
 
-            let ray_dir = cast_camera_ray(camera, ndc_x, ndc_y);
-            color += cast_ray(camera.position, ray_dir, params.max_depth);
+    for (var s = 0u; s < sample_count; s++) {
+        var offset = vec2<f32>(0.5);
+        if (params.samples > 1u) {
+            offset = sample_2d(x, y, s, DIM_PIXEL);
         }
-        color /= f32(params.samples);
-    } else {
-        let ndc_x = ((f32(x) + 0.5) / f32(params.width)) * 2.0 - 1.0;
-        let ndc_y = ((f32(y) + 0.5) / f32(params.height)) * 2.0 - 1.0;
 
-        let ray_dir = cast_camera_ray(camera, ndc_x, ndc_y);
-        color = cast_ray(camera.position, ray_dir, params.max_depth);
+        let ndc_x = ((f32(x) + offset.x) / f32(params.width)) * 2.0 - 1.0;
+        let ndc_y = ((f32(y) + offset.y) / f32(params.height)) * 2.0 - 1.0;
+
+        let ray = cast_camera_ray(camera, ndc_x, ndc_y);
+        if (ray.valid == 0.0) {
+            continue;
+        }
+        let ray_dir = ray.direction;
+        let sample_color = cast_ray(ray.origin, ray_dir, params.max_depth);
+        color += clamp(sample_color, vec3<f32>(0.0), vec3<f32>(1.0));
+        unclamped += sample_color;
+
+        normal_depth += vec4<f32>(primary.normal, camera_depth(camera, ray_dir, forward, primary.t));
+        albedo += primary.albedo;
+        direct_shadow += vec4<f32>(primary.direct, primary.shadow);
+        // Like the CPU, keep the first sample the lens can see
+        if (!seen_any) {
+            object_id = primary.object_id;
+            seen_any = true;
+        }
     }
+    let inv_count = 1.0 / f32(sample_count);
+    color *= inv_count;
 
-    let pixel_index = y * params.width + x;
+    let pixel_index = global_id.y * params.crop_width + global_id.x;
     output[pixel_index] = vec4<f32>(color, 1.0);
+
+    if (params.aovs != 0u) {
+        let aov_index = pixel_index * AOV_VEC4S_PER_PIXEL;
+        direct_shadow *= inv_count;
+        aov_output[aov_index] = normal_depth * inv_count;
+        aov_output[aov_index + 1u] = vec4<f32>(albedo * inv_count, object_id);
+        aov_output[aov_index + 2u] = direct_shadow;
+        aov_output[aov_index + 3u] = vec4<f32>(unclamped * inv_count - direct_shadow.xyz, 0.0);
+    }
 }
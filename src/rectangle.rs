@@ -0,0 +1,137 @@
+use crate::hittable::{EmissionSample, HitRecord, Hittable};
+use crate::ray::Ray;
+use crate::sphere::{Color, Material};
+use crate::transform::Frame;
+use crate::vector3d::Vector3D;
+
+/// Finite quad centred on `frame.origin`, spanning `half_width` along
+/// `frame.u` and `half_height` along `frame.v`, facing `frame.w`
+#[derive(Debug, Clone, Copy)]
+pub struct Rectangle {
+    pub frame: Frame,
+    pub half_width: f64,
+    pub half_height: f64,
+    pub material: Material,
+}
+
+impl Rectangle {
+    /// Rectangle with one corner at `corner` and sides `edge_u` and
+    /// `edge_v`; `edge_v` is made perpendicular to `edge_u`. It faces
+    /// `edge_u x edge_v`, and UVs run from 0 to 1 along the edges.
+    pub fn new(corner: Vector3D, edge_u: Vector3D, edge_v: Vector3D, material: Material) -> Rectangle {
+        let edge_v = edge_v - edge_u * (edge_v.dot(edge_u) / edge_u.magnitude_squared());
+        Rectangle {
+            frame: Frame::from_axes(corner + (edge_u + edge_v) * 0.5, edge_u, edge_v),
+            half_width: edge_u.magnitude() * 0.5,
+            half_height: edge_v.magnitude() * 0.5,
+            material,
+        }
+    }
+}
+
+impl Hittable for Rectangle {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let local = self.frame.ray_to_local(ray);
+        if local.direction.z == 0.0 {
+            return None;
+        }
+
+        let t = -local.origin.z / local.direction.z;
+        if t < t_min || t > t_max {
+            return None;
+        }
+
+        let x = local.origin.x + local.direction.x * t;
+        let y = local.origin.y + local.direction.y * t;
+        if x.abs() > self.half_width || y.abs() > self.half_height {
+            return None;
+        }
+
+        Some(HitRecord {
+            point: ray.at(t),
+            normal: self.frame.w,
+            t,
+            uv: (0.5 + 0.5 * x / self.half_width, 0.5 + 0.5 * y / self.half_height),
+            tangent: self.frame.u,
+            bitangent: self.frame.v,
+            material: self.material,
+        })
+    }
+
+    /// Uniform over the area; both faces emit
+    fn sample_emission(&self, origin: Vector3D, u1: f64, u2: f64) -> Option<EmissionSample> {
+        if !self.material.is_emissive() {
+            return None;
+        }
+
+        let point = self.frame.origin
+            + self.frame.u * ((2.0 * u1 - 1.0) * self.half_width)
+            + self.frame.v * ((2.0 * u2 - 1.0) * self.half_height);
+        let area = 4.0 * self.half_width * self.half_height;
+        area_sample(point, self.frame.w, area, origin, self.material.emission)
+    }
+}
+
+/// Emission sample at `point` on a flat emitter of the given `area` and
+/// `normal`, picked uniformly by area and converted to solid angle at `origin`
+pub(crate) fn area_sample(
+    point: Vector3D,
+    normal: Vector3D,
+    area: f64,
+    origin: Vector3D,
+    radiance: Color,
+) -> Option<EmissionSample> {
+    let offset = point - origin;
+    let distance_sq = offset.magnitude_squared();
+    let cos_light = normal.dot(offset).abs() / distance_sq.sqrt();
+    if cos_light <= 0.0 || area <= 0.0 {
+        return None;
+    }
+    Some(EmissionSample {
+        point,
+        normal,
+        radiance,
+        pdf: distance_sq / (area * cos_light),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn emission_pdf_matches_the_subtended_solid_angle() {
+        let glow = Material {
+            color: Color::WHITE,
+            diffuse: 1.0,
+            specular: 0.0,
+            shininess: 1.0,
+            reflectivity: 0.0,
+            pbr: None,
+            emission: Color::WHITE,
+            texture: None,
+            normal_map: None,
+        };
+        // 4 x 2 and 3 below the point, seen from its back face
+        let (a, b, d) = (2.0, 1.0, 3.0);
+        let light = Rectangle::new(Vector3D::new(-a, -b, 0.0), Vector3D::new(2.0 * a, 0.0, 0.0),
+                                   Vector3D::new(0.0, 2.0 * b, 0.0), glow);
+        let origin = Vector3D::new(0.0, 0.0, -d);
+
+        let steps = 128;
+        let mut estimate = 0.0;
+        for i in 0..steps {
+            for j in 0..steps {
+                let u1 = (i as f64 + 0.5) / steps as f64;
+                let u2 = (j as f64 + 0.5) / steps as f64;
+                let sample = light.sample_emission(origin, u1, u2).unwrap();
+                assert!(sample.point.z.abs() < 1e-12 && sample.point.x.abs() <= a && sample.point.y.abs() <= b);
+                estimate += 1.0 / sample.pdf;
+            }
+        }
+        estimate /= (steps * steps) as f64;
+
+        let expected = 4.0 * (a * b / ((a * a + d * d) * (b * b + d * d)).sqrt()).asin();
+        assert!((estimate / expected - 1.0).abs() < 1e-3, "{} != {}", estimate, expected);
+    }
+}
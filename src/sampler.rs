@@ -0,0 +1,399 @@
+use std::sync::OnceLock;
+
+/// Sample dimensions. Each dimension hands out a 2D point, so pixel jitter,
+/// the points picked on emitters and the glossy reflections of the first hit
+/// stay decorrelated. `DIM_MEDIUM` seeds the random stream for everything
+/// else.
+pub const DIM_PIXEL: u32 = 0;
+pub const DIM_LIGHT: u32 = 1;
+pub const DIM_MEDIUM: u32 = 2;
+pub const DIM_GLOSSY: u32 = 3;
+
+const BLUE_NOISE_SIZE: usize = 64;
+const PRIMES: [u32; 16] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SamplerKind {
+    Random,
+    Stratified,
+    Halton,
+    Sobol,
+    BlueNoise,
+}
+
+impl SamplerKind {
+    pub fn from_name(name: &str) -> Option<SamplerKind> {
+        match name {
+            "random" => Some(SamplerKind::Random),
+            "stratified" => Some(SamplerKind::Stratified),
+            "halton" => Some(SamplerKind::Halton),
+            "sobol" => Some(SamplerKind::Sobol),
+            "blue-noise" | "bluenoise" => Some(SamplerKind::BlueNoise),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SamplerKind::Random => "random",
+            SamplerKind::Stratified => "stratified",
+            SamplerKind::Halton => "halton",
+            SamplerKind::Sobol => "sobol",
+            SamplerKind::BlueNoise => "blue-noise",
+        }
+    }
+
+    /// Matches the `SAMPLER_*` constants in raytracer.wgsl
+    pub fn gpu_id(self) -> u32 {
+        match self {
+            SamplerKind::Random => 0,
+            SamplerKind::Stratified => 1,
+            SamplerKind::Halton => 2,
+            SamplerKind::Sobol => 3,
+            SamplerKind::BlueNoise => 4,
+        }
+    }
+}
+
+/// Deterministic per-pixel sample generator.
+///
+/// Samples are a pure function of (pixel, sample index, dimension), so any
+/// sample can be regenerated independently of render order or thread count.
+#[derive(Debug, Clone, Copy)]
+pub struct Sampler {
+    pub kind: SamplerKind,
+    pub samples_per_pixel: u32,
+}
+
+impl Sampler {
+    pub fn new(kind: SamplerKind, samples_per_pixel: u32) -> Sampler {
+        Sampler {
+            kind,
+            samples_per_pixel: samples_per_pixel.max(1),
+        }
+    }
+
+    /// Returns a point in [0, 1)^2 for the given pixel, sample and dimension.
+    #[inline]
+    pub fn get_2d(&self, x: u32, y: u32, index: u32, dimension: u32) -> (f64, f64) {
+        let pixel_seed = hash(x ^ hash(y ^ hash(dimension)));
+
+        match self.kind {
+            SamplerKind::Random => {
+                let rng = self.rng(x, y, index, dimension);
+                (rng.f64(), rng.f64())
+            }
+            SamplerKind::Stratified => {
+                let strata_x = (self.samples_per_pixel as f64).sqrt().floor().max(1.0) as u32;
+                let strata_y = self.samples_per_pixel.div_ceil(strata_x);
+                let stratum = permute(index % (strata_x * strata_y), strata_x * strata_y, pixel_seed);
+                let jitter = hash(index ^ hash(pixel_seed));
+                let jitter_x = to_unit(jitter);
+                let jitter_y = to_unit(hash(jitter));
+
+                (
+                    ((stratum % strata_x) as f64 + jitter_x) / strata_x as f64,
+                    ((stratum / strata_x) as f64 + jitter_y) / strata_y as f64,
+                )
+            }
+            SamplerKind::Halton => {
+                let base_index = (2 * dimension as usize) % PRIMES.len();
+                let u = radical_inverse(index, PRIMES[base_index]);
+                let v = radical_inverse(index, PRIMES[base_index + 1]);
+
+                // Cranley-Patterson rotation decorrelates neighbouring pixels
+                (
+                    (u + to_unit(pixel_seed)).fract(),
+                    (v + to_unit(hash(pixel_seed))).fract(),
+                )
+            }
+            SamplerKind::Sobol => {
+                let (u, v) = sobol_2d(index, pixel_seed);
+                (to_unit(u), to_unit(v))
+            }
+            SamplerKind::BlueNoise => {
+                // Sobol points shared by all pixels, offset per pixel by a
+                // blue-noise mask so the error is pushed to high frequencies
+                let (u, v) = sobol_2d(index, hash(dimension));
+                let mask = blue_noise_mask();
+                let offset_x = (dimension as usize * 17) % BLUE_NOISE_SIZE;
+                let offset_y = (dimension as usize * 29) % BLUE_NOISE_SIZE;
+                let shift_u = mask[blue_noise_index(x as usize + offset_x, y as usize + offset_y)];
+                let shift_v = mask[blue_noise_index(
+                    x as usize + offset_x + BLUE_NOISE_SIZE / 2,
+                    y as usize + offset_y + BLUE_NOISE_SIZE / 2,
+                )];
+
+                (
+                    (to_unit(u) + shift_u as f64).fract(),
+                    (to_unit(v) + shift_v as f64).fract(),
+                )
+            }
+        }
+    }
+
+    /// Random stream for the given pixel, sample and dimension, for effects
+    /// that need an open-ended number of values, like walks through media.
+    /// Deterministic in the same way as `get_2d`.
+    pub fn rng(&self, x: u32, y: u32, index: u32, dimension: u32) -> fastrand::Rng {
+        let pixel_seed = hash(x ^ hash(y ^ hash(dimension)));
+        fastrand::Rng::with_seed(((pixel_seed as u64) << 32) | hash(index ^ pixel_seed) as u64)
+    }
+}
+
+/// PCG hash, identical to `pcg_hash` in raytracer.wgsl
+#[inline]
+pub fn hash(seed: u32) -> u32 {
+    let state = seed.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
+
+#[inline]
+fn to_unit(bits: u32) -> f64 {
+    bits as f64 / 4294967296.0
+}
+
+fn radical_inverse(mut index: u32, base: u32) -> f64 {
+    let inv_base = 1.0 / base as f64;
+    let mut inv_base_n = 1.0;
+    let mut reversed = 0u64;
+
+    while index > 0 {
+        let next = index / base;
+        let digit = index - next * base;
+        reversed = reversed * base as u64 + digit as u64;
+        inv_base_n *= inv_base;
+        index = next;
+    }
+
+    (reversed as f64 * inv_base_n).min(1.0 - f64::EPSILON)
+}
+
+/// Kensler's hashed permutation of `index` within [0, length)
+fn permute(mut index: u32, length: u32, seed: u32) -> u32 {
+    if length <= 1 {
+        return 0;
+    }
+
+    let mut mask = length - 1;
+    mask |= mask >> 1;
+    mask |= mask >> 2;
+    mask |= mask >> 4;
+    mask |= mask >> 8;
+    mask |= mask >> 16;
+
+    loop {
+        index ^= seed;
+        index = index.wrapping_mul(0xe170893d);
+        index ^= seed >> 16;
+        index ^= (index & mask) >> 4;
+        index ^= seed >> 8;
+        index = index.wrapping_mul(0x0929eb3f);
+        index ^= seed >> 23;
+        index ^= (index & mask) >> 1;
+        index = index.wrapping_mul(1 | (seed >> 27));
+        index = index.wrapping_mul(0x6935fa69);
+        index ^= (index & mask) >> 11;
+        index = index.wrapping_mul(0x74dcb303);
+        index ^= (index & mask) >> 2;
+        index = index.wrapping_mul(0x9e501cc3);
+        index ^= (index & mask) >> 2;
+        index = index.wrapping_mul(0xc860a3df);
+        index &= mask;
+        index ^= index >> 5;
+
+        if index < length {
+            return index.wrapping_add(seed) % length;
+        }
+    }
+}
+
+#[inline]
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
+
+#[inline]
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+/// First two Sobol dimensions with hash-based Owen scrambling (Burley 2020)
+fn sobol_2d(index: u32, seed: u32) -> (u32, u32) {
+    let shuffled = nested_uniform_scramble(index, seed);
+
+    let u = shuffled.reverse_bits();
+
+    let mut v = 0u32;
+    let mut direction = 1u32 << 31;
+    let mut i = shuffled;
+    while i != 0 {
+        if i & 1 != 0 {
+            v ^= direction;
+        }
+        i >>= 1;
+        direction ^= direction >> 1;
+    }
+
+    (
+        nested_uniform_scramble(u, hash(seed)),
+        nested_uniform_scramble(v, hash(seed ^ 0x5bd1e995)),
+    )
+}
+
+#[inline]
+fn blue_noise_index(x: usize, y: usize) -> usize {
+    (y % BLUE_NOISE_SIZE) * BLUE_NOISE_SIZE + (x % BLUE_NOISE_SIZE)
+}
+
+/// 64x64 tileable blue-noise dither mask with values in [0, 1).
+///
+/// Generated once with Ulichney's void-and-cluster method.
+pub fn blue_noise_mask() -> &'static [f32] {
+    static MASK: OnceLock<Vec<f32>> = OnceLock::new();
+    MASK.get_or_init(generate_blue_noise)
+}
+
+fn generate_blue_noise() -> Vec<f32> {
+    const SIGMA: f32 = 1.5;
+    let size = BLUE_NOISE_SIZE;
+    let count = size * size;
+
+    // Toroidal Gaussian kernel indexed by wrapped offset
+    let mut kernel = vec![0.0f32; count];
+    for dy in 0..size {
+        for dx in 0..size {
+            let wx = dx.min(size - dx) as f32;
+            let wy = dy.min(size - dy) as f32;
+            kernel[dy * size + dx] = (-(wx * wx + wy * wy) / (2.0 * SIGMA * SIGMA)).exp();
+        }
+    }
+
+    let mut pattern = vec![false; count];
+    let mut energy = vec![0.0f32; count];
+
+    let toggle = |pattern: &mut Vec<bool>, energy: &mut Vec<f32>, index: usize| {
+        let sign = if pattern[index] { -1.0 } else { 1.0 };
+        pattern[index] = !pattern[index];
+        let (px, py) = (index % size, index / size);
+        for y in 0..size {
+            for x in 0..size {
+                let dx = (x + size - px) % size;
+                let dy = (y + size - py) % size;
+                energy[y * size + x] += sign * kernel[dy * size + dx];
+            }
+        }
+    };
+
+    let tightest_cluster = |pattern: &[bool], energy: &[f32]| {
+        (0..count)
+            .filter(|&i| pattern[i])
+            .max_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+            .unwrap()
+    };
+    let largest_void = |pattern: &[bool], energy: &[f32]| {
+        (0..count)
+            .filter(|&i| !pattern[i])
+            .min_by(|&a, &b| energy[a].total_cmp(&energy[b]))
+            .unwrap()
+    };
+
+    // Initial random pattern with ~10% of pixels set
+    let rng = fastrand::Rng::with_seed(0x5eed);
+    let initial = count / 10;
+    let mut placed = 0;
+    while placed < initial {
+        let index = rng.usize(..count);
+        if !pattern[index] {
+            toggle(&mut pattern, &mut energy, index);
+            placed += 1;
+        }
+    }
+
+    // Relax by moving the tightest cluster into the largest void
+    loop {
+        let cluster = tightest_cluster(&pattern, &energy);
+        toggle(&mut pattern, &mut energy, cluster);
+        let void = largest_void(&pattern, &energy);
+        if void == cluster {
+            toggle(&mut pattern, &mut energy, cluster);
+            break;
+        }
+        toggle(&mut pattern, &mut energy, void);
+    }
+
+    let mut rank = vec![0usize; count];
+
+    // Rank the initial points by removing clusters first
+    let mut removal_pattern = pattern.clone();
+    let mut removal_energy = energy.clone();
+    for r in (0..initial).rev() {
+        let cluster = tightest_cluster(&removal_pattern, &removal_energy);
+        toggle(&mut removal_pattern, &mut removal_energy, cluster);
+        rank[cluster] = r;
+    }
+
+    // Fill the remaining pixels by repeatedly filling the largest void
+    for r in initial..count {
+        let void = largest_void(&pattern, &energy);
+        toggle(&mut pattern, &mut energy, void);
+        rank[void] = r;
+    }
+
+    rank.iter().map(|&r| (r as f32 + 0.5) / count as f32).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Cell of a `columns` x `rows` grid that each of `count` samples falls in
+    fn cells(sampler: &Sampler, count: u32, columns: u32, rows: u32) -> Vec<u32> {
+        let mut cells: Vec<u32> = (0..count)
+            .map(|index| {
+                let (u, v) = sampler.get_2d(7, 3, index, DIM_PIXEL);
+                assert!((0.0..1.0).contains(&u) && (0.0..1.0).contains(&v), "({}, {})", u, v);
+                (v * rows as f64) as u32 * columns + (u * columns as f64) as u32
+            })
+            .collect();
+        cells.sort_unstable();
+        cells
+    }
+
+    #[test]
+    fn stratified_samples_fill_every_stratum_once() {
+        let sampler = Sampler::new(SamplerKind::Stratified, 16);
+        assert_eq!(cells(&sampler, 16, 4, 4), (0..16).collect::<Vec<_>>());
+
+        // Strata round up to whole rows, leaving one unused
+        let sampler = Sampler::new(SamplerKind::Stratified, 5);
+        let mut used = cells(&sampler, 5, 2, 3);
+        used.dedup();
+        assert_eq!(used.len(), 5);
+    }
+
+    #[test]
+    fn sobol_samples_are_a_net_in_every_pixel() {
+        let sampler = Sampler::new(SamplerKind::Sobol, 16);
+        for (columns, rows) in [(16, 1), (8, 2), (4, 4), (2, 8), (1, 16)] {
+            assert_eq!(cells(&sampler, 16, columns, rows), (0..16).collect::<Vec<_>>(), "{}x{}", columns, rows);
+        }
+    }
+
+    #[test]
+    fn samples_depend_only_on_pixel_index_and_dimension() {
+        for kind in [SamplerKind::Random, SamplerKind::Stratified, SamplerKind::Halton, SamplerKind::Sobol,
+                     SamplerKind::BlueNoise] {
+            let sampler = Sampler::new(kind, 4);
+            assert_eq!(sampler.get_2d(5, 9, 2, DIM_LIGHT), sampler.get_2d(5, 9, 2, DIM_LIGHT));
+            assert_ne!(sampler.get_2d(5, 9, 2, DIM_LIGHT), sampler.get_2d(5, 9, 2, DIM_GLOSSY), "{}", kind.name());
+            assert_eq!(SamplerKind::from_name(kind.name()), Some(kind));
+        }
+    }
+}
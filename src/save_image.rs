@@ -1,26 +1,114 @@
-use crate::sphere::Color;
+use crate::aov::Aov;
+use crate::framebuffer::Framebuffer;
 use std::fs::File;
-use std::io::Write;
+use std::io::{BufWriter, Write};
 use std::path::Path;
 
-pub fn save_image(
-    image: &Vec<Vec<Color>>,
-    width: u32,
-    height: u32,
-    filename: &str,
-) -> std::io::Result<()> {
+pub fn save_image(image: &Framebuffer, filename: &str) -> std::io::Result<()> {
     let path: &Path = Path::new(filename);
-    let mut file: File = File::create(&path)?;
+    let mut file = BufWriter::new(File::create(path)?);
 
-    write!(file, "P3\n{} {}\n255\n", width, height)?;
+    write!(file, "P3\n{} {}\n255\n", image.width, image.height)?;
 
-    for row in image {
-        for color in row {
-            let r: u8 = (color.r.min(1.0).max(0.0) * 255.0) as u8;
-            let g: u8 = (color.g.min(1.0).max(0.0) * 255.0) as u8;
-            let b: u8 = (color.b.min(1.0).max(0.0) * 255.0) as u8;
-            write!(file, "{} {} {}\n", r, g, b)?;
+    let rgb = image.to_rgb8();
+    for y in 0..rgb.height {
+        for pixel in rgb.row(y).chunks_exact(3) {
+            writeln!(file, "{} {} {}", pixel[0], pixel[1], pixel[2])?;
         }
     }
-    Ok(())
+    file.flush()
+}
+
+/// Reads an 8-bit image, such as one written by `save_image`, as RGB.
+///
+/// Values sit in the middle of each 8-bit step so an unchanged image saves
+/// back exactly as it was read.
+pub fn load_image(filename: &str) -> image::ImageResult<Framebuffer> {
+    let rgb = image::open(filename)?.to_rgb8();
+    let mut image = Framebuffer::new(rgb.width(), rgb.height(), 3);
+    for (x, y, pixel) in rgb.enumerate_pixels() {
+        for (value, channel) in image.pixel_mut(x, y).iter_mut().zip(pixel.0) {
+            *value = (channel as f32 + 0.5) / 255.0;
+        }
+    }
+    Ok(image)
+}
+
+/// Writes a multi-layer OpenEXR file: the beauty image as an `RGB` layer
+/// named `beauty`, followed by one layer per AOV named after it.
+///
+/// Values are stored as unclamped 32-bit floats.
+pub fn save_exr(beauty: &Framebuffer, aovs: &[(Aov, Framebuffer)], filename: &str) -> exr::error::UnitResult {
+    use exr::prelude::*;
+
+    fn layer(name: &str, channel_names: &[&str], image: &Framebuffer) -> Layer<AnyChannels<FlatSamples>> {
+        let channels = channel_names
+            .iter()
+            .enumerate()
+            .map(|(channel, channel_name)| {
+                let mut samples = Vec::with_capacity(image.width as usize * image.height as usize);
+                for y in 0..image.height {
+                    samples.extend(image.row(y).chunks_exact(image.channels).map(|pixel| pixel[channel]));
+                }
+                AnyChannel::new(*channel_name, FlatSamples::F32(samples))
+            })
+            .collect();
+
+        Layer::new(
+            (image.width as usize, image.height as usize),
+            LayerAttributes::named(name),
+            Encoding::FAST_LOSSLESS,
+            AnyChannels::sort(channels),
+        )
+    }
+
+    let mut layers = SmallVec::<[Layer<AnyChannels<FlatSamples>>; 2]>::new();
+    layers.push(layer("beauty", &["R", "G", "B"], beauty));
+    for (aov, image) in aovs {
+        layers.push(layer(aov.name(), aov.channel_names(), image));
+    }
+
+    let size = (beauty.width as usize, beauty.height as usize);
+    Image::from_layers(ImageAttributes::new(IntegerBounds::from_dimensions(size)), layers)
+        .write()
+        .to_file(filename)
+}
+
+/// Looping GIF animation written one frame at a time
+pub struct GifWriter {
+    encoder: gif::Encoder<File>,
+    width: u16,
+    height: u16,
+}
+
+impl GifWriter {
+    pub fn create(filename: &str, width: u32, height: u32) -> Result<GifWriter, gif::EncodingError> {
+        let file = File::create(Path::new(filename))?;
+        let mut encoder = gif::Encoder::new(file, width as u16, height as u16, &[])?;
+        encoder.set_repeat(gif::Repeat::Infinite)?;
+
+        Ok(GifWriter {
+            encoder,
+            width: width as u16,
+            height: height as u16,
+        })
+    }
+
+    /// `delay` is in hundredths of a second
+    pub fn write_frame(&mut self, frame: &Framebuffer<u8>, delay: u16) -> Result<(), gif::EncodingError> {
+        let mut gif_frame = if frame.channels == 3 && frame.is_contiguous() {
+            gif::Frame::from_rgb(self.width, self.height, frame.as_slice())
+        } else {
+            let mut rgb = Vec::with_capacity(self.width as usize * self.height as usize * 3);
+            for y in 0..frame.height {
+                for pixel in frame.row(y).chunks_exact(frame.channels) {
+                    rgb.extend_from_slice(&pixel[..3]);
+                }
+            }
+            gif::Frame::from_rgb(self.width, self.height, &rgb)
+        };
+
+        gif_frame.delay = delay;
+        self.encoder.write_frame(&gif_frame)
+    }
 }
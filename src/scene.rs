@@ -1,8 +1,18 @@
+use crate::aov::{Aov, AovSample};
+use crate::film::{Film, FilmTile, Filter};
+use crate::framebuffer::Framebuffer;
 use crate::hittable::{HitRecord, Hittable};
 use crate::ray::Ray;
+use crate::sampler::{Sampler, SamplerKind, DIM_GLOSSY, DIM_LIGHT, DIM_MEDIUM, DIM_PIXEL};
 use crate::sphere::Color;
-use crate::vector3d::Vector3D;
-use rayon::prelude::*;
+use crate::texture::{Texture, TextureId};
+use crate::tiles::{generate_tiles, CropWindow, Tile, TileProgress, TiledRender, Tiling};
+use crate::vector3d::{Matrix4, Quaternion, Vector3D};
+use crate::volume::{Fog, Volume};
+use std::f64::consts::PI;
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 
 const EPSILON: f64 = 0.001;
 const MAX_DEPTH: i32 = 3;
@@ -17,6 +27,14 @@ pub struct Scene {
     pub background_color: Color,
     pub objects: Vec<Box<dyn Hittable>>,
     pub lights: Vec<Light>,
+    /// Distance fog over every ray, camera and reflected alike
+    pub fog: Option<Fog>,
+    /// Participating media, sampled stochastically so they need several
+    /// samples per pixel to converge
+    pub volumes: Vec<Volume>,
+    /// Textures that materials refer to by `TextureId`, their colour and
+    /// normal maps. Shared, so scenes rebuilt per frame don't copy them.
+    pub textures: Vec<Arc<Texture>>,
 }
 
 impl Scene {
@@ -24,8 +42,19 @@ impl Scene {
         self.objects.push(object);
     }
 
+    pub fn add_volume(&mut self, volume: Volume) {
+        self.volumes.push(volume);
+    }
+
+    pub fn add_texture(&mut self, texture: Texture) -> TextureId {
+        self.textures.push(Arc::new(texture));
+        TextureId(self.textures.len() - 1)
+    }
+
+    /// Fraction of the light from `light_position` that reaches `point`:
+    /// zero behind an object, otherwise whatever the volumes let through
     #[inline]
-    fn is_in_shadow(&self, point: Vector3D, light_position: Vector3D) -> bool {
+    fn light_visibility(&self, point: Vector3D, light_position: Vector3D) -> f64 {
         let direction = light_position - point;
         let distance_sq = direction.magnitude_squared();
         let distance = distance_sq.sqrt();
@@ -33,125 +62,505 @@ impl Scene {
         let dir_normalized = direction * inv_distance;
         let shadow_ray = Ray::new(point + dir_normalized * EPSILON, dir_normalized);
 
+        // The ray starts EPSILON along, so this stops EPSILON short of the
+        // target, which may lie on an emitter's surface
+        let t_max = distance - 2.0 * EPSILON;
         for object in &self.objects {
-            if object.hit(&shadow_ray, EPSILON, distance - EPSILON).is_some() {
-                return true;
+            if object.hit(&shadow_ray, EPSILON, t_max).is_some() {
+                return 0.0;
             }
         }
-        false
+        self.volumes
+            .iter()
+            .map(|volume| volume.transmittance(&shadow_ray, 0.0, t_max))
+            .product()
     }
 
+    /// Nearest hit along `ray` and the index of the object it belongs to,
+    /// with its material's colour and normal maps applied
     #[inline]
-    fn cast_ray(&self, ray: &Ray, depth: i32) -> Color {
-        if depth <= 0 {
-            return Color {
-                r: 0.0,
-                g: 0.0,
-                b: 0.0,
-            };
-        }
-
-        let mut closest_hit: Option<HitRecord> = None;
+    pub(crate) fn closest_hit(&self, ray: &Ray) -> Option<(usize, HitRecord)> {
+        let mut closest_hit = None;
         let mut closest_t = f64::INFINITY;
 
-        for object in &self.objects {
+        for (index, object) in self.objects.iter().enumerate() {
             if let Some(hit) = object.hit(ray, EPSILON, closest_t) {
                 closest_t = hit.t;
-                closest_hit = Some(hit);
+                closest_hit = Some((index, hit));
+            }
+        }
+
+        if let Some((_, hit)) = &mut closest_hit {
+            if let Some(texture) = hit.material.texture.and_then(|id| self.textures.get(id.0)) {
+                let tint = texture.sample(hit.uv.0, hit.uv.1);
+                hit.material.color = hit.material.color * tint;
+                if let Some(pbr) = &mut hit.material.pbr {
+                    pbr.base_color = pbr.base_color * tint;
+                }
+            }
+            if let Some(map) = hit.material.normal_map {
+                if let Some(texture) = self.textures.get(map.texture().0) {
+                    hit.normal = map.apply(texture, hit);
+                }
+            }
+        }
+        closest_hit
+    }
+
+    /// Blinn-Phong lighting from all lights at `hit`, on object `index`,
+    /// together with the fraction of the total light intensity that is
+    /// blocked by shadows. Emissive objects other than the one hit add one
+    /// sample each of their light, at `light_sample` in [0, 1)^2.
+    #[inline]
+    fn direct_lighting(&self, ray: &Ray, index: usize, hit: &HitRecord, light_sample: (f64, f64)) -> (Color, f64) {
+        let mut color = Color::default();
+        let mut total_intensity = 0.0;
+        let mut shadowed_intensity = 0.0;
+
+        for light in &self.lights {
+            total_intensity += light.intensity;
+            let visibility = self.light_visibility(hit.point, light.position);
+            shadowed_intensity += light.intensity * (1.0 - visibility);
+            if visibility <= 0.0 {
+                continue;
+            }
+            let intensity = light.intensity * visibility;
+
+            let light_dir = (light.position - hit.point).normalize();
+            let view_dir = (ray.origin - hit.point).normalize();
+
+            if let Some(pbr) = &hit.material.pbr {
+                color = color + pbr.evaluate(hit.normal, view_dir, light_dir) * intensity;
+                continue;
             }
+
+            // Diffuse lighting
+            let diffuse_strength = light_dir.dot(hit.normal).max(0.0);
+            let diffuse = hit.material.color * (hit.material.diffuse * diffuse_strength * intensity);
+
+            // Specular lighting (Blinn-Phong)
+            let halfway_dir = (light_dir + view_dir).normalize();
+            let spec_strength = halfway_dir.dot(hit.normal).max(0.0).powf(hit.material.shininess);
+            let specular = Color::WHITE * (hit.material.specular * spec_strength * intensity);
+
+            color = color + diffuse + specular;
         }
+        color = color + self.emitter_lighting(ray, index, hit, light_sample);
+
+        let shadow = if total_intensity > 0.0 {
+            shadowed_intensity / total_intensity
+        } else {
+            0.0
+        };
+        (color, shadow)
+    }
+
+    /// Diffuse light from a sampled point on each emissive object besides
+    /// object `index`, all picked with the same `(u, v)`. Reflections of
+    /// emitters aren't included, since the reflected rays already see them.
+    ///
+    /// Emitted radiance is on the same scale as material colours: a white
+    /// diffuse surface under a sky glowing with `emission` reflects exactly
+    /// `emission`.
+    fn emitter_lighting(&self, ray: &Ray, index: usize, hit: &HitRecord, (u, v): (f64, f64)) -> Color {
+        let view_dir = (ray.origin - hit.point).normalize();
+        let mut color = Color::default();
 
-        if let Some(hit) = closest_hit {
-            let mut color = Color {
-                r: 0.0,
-                g: 0.0,
-                b: 0.0,
+        for (emitter, object) in self.objects.iter().enumerate() {
+            if emitter == index {
+                continue;
+            }
+            let Some(sample) = object.sample_emission(hit.point, u, v) else {
+                continue;
             };
+            let light_dir = (sample.point - hit.point).normalize();
+            let response = match &hit.material.pbr {
+                Some(pbr) => pbr.evaluate_diffuse(hit.normal, view_dir, light_dir),
+                None => hit.material.color * (hit.material.diffuse * light_dir.dot(hit.normal).max(0.0)),
+            };
+            if response.r <= 0.0 && response.g <= 0.0 && response.b <= 0.0 {
+                continue;
+            }
+
+            let visibility = self.light_visibility(hit.point, sample.point);
+            if visibility > 0.0 {
+                color = color + response * sample.radiance * (visibility / (PI * sample.pdf));
+            }
+        }
+
+        color
+    }
+
+    /// Light arriving at `hit` through its mirror reflection, already scaled by
+    /// reflectivity. PBR materials trace one reflection sampled from their
+    /// glossy lobe at `glossy_sample` instead, which averages out over the
+    /// pixel's samples.
+    #[inline]
+    fn reflected_lighting(
+        &self,
+        ray: &Ray,
+        hit: &HitRecord,
+        depth: i32,
+        glossy_sample: (f64, f64),
+        rng: &fastrand::Rng,
+    ) -> Color {
+        if let Some(pbr) = &hit.material.pbr {
+            let view = -ray.direction.normalize();
+            let (u1, u2) = glossy_sample;
+            let Some((direction, weight)) = pbr.sample_reflection(hit.normal, view, u1, u2) else {
+                return Color::default();
+            };
+            let reflect_ray = Ray::new(hit.point + hit.normal * EPSILON, direction);
+            return self.cast_ray(&reflect_ray, depth - 1, HitSamples::random(rng), rng) * weight;
+        }
+        if hit.material.reflectivity <= 0.0 {
+            return Color::default();
+        }
+
+        let reflect_dir = reflect(ray.direction, hit.normal);
+        let reflect_ray = Ray::new(hit.point + hit.normal * EPSILON, reflect_dir);
+        self.cast_ray(&reflect_ray, depth - 1, HitSamples::random(rng), rng) * hit.material.reflectivity
+    }
 
-            // Calculate lighting
-            for light in &self.lights {
-                if !self.is_in_shadow(hit.point, light.position) {
-                    let light_dir = (light.position - hit.point).normalize();
-                    let view_dir = (ray.origin - hit.point).normalize();
-
-                    // Diffuse lighting
-                    let diffuse_strength = light_dir.dot(hit.normal).max(0.0);
-                    let diffuse = hit.material.color * (hit.material.diffuse * diffuse_strength * light.intensity);
-
-                    // Specular lighting (Blinn-Phong)
-                    let halfway_dir = (light_dir + view_dir).normalize();
-                    let spec_strength = halfway_dir.dot(hit.normal).max(0.0).powf(hit.material.shininess);
-                    let specular = Color {
-                        r: 1.0,
-                        g: 1.0,
-                        b: 1.0,
-                    } * (hit.material.specular * spec_strength * light.intensity);
-
-                    color = color + diffuse + specular;
+    /// Light seen along `ray`. `samples` make the choices at the first hit;
+    /// everything after it draws from `rng`.
+    #[inline]
+    fn cast_ray(&self, ray: &Ray, depth: i32, samples: HitSamples, rng: &fastrand::Rng) -> Color {
+        self.cast_ray_with_aovs(ray, depth, samples, rng, None).0
+    }
+
+    /// Like `cast_ray`, but given a camera also returns the AOVs of the first
+    /// hit. The AOVs describe the surface even when a volume in front of it
+    /// scatters the beauty sample; their extra shading comes after the
+    /// beauty sample is finished, so it never changes what `rng` hands out.
+    fn cast_ray_with_aovs(
+        &self,
+        ray: &Ray,
+        depth: i32,
+        samples: HitSamples,
+        rng: &fastrand::Rng,
+        camera: Option<&Camera>,
+    ) -> (Color, Option<AovSample>) {
+        if depth <= 0 {
+            return (Color::default(), None);
+        }
+
+        let hit = self.closest_hit(ray);
+        let t_max = hit.as_ref().map_or(f64::INFINITY, |(_, hit)| hit.t);
+        let shade = |index: usize, hit: &HitRecord| {
+            let (direct, shadow) = self.direct_lighting(ray, index, hit, samples.light);
+            let reflected = self.reflected_lighting(ray, hit, depth, samples.glossy, rng);
+            (hit.material.emission + direct, shadow, reflected)
+        };
+        let mut shading = None;
+        let color = self.through_media(ray, t_max, depth, rng, || match &hit {
+            Some((index, hit)) => {
+                let (direct, shadow, reflected) = shade(*index, hit);
+                shading = Some((direct, shadow, reflected));
+                direct + reflected
+            }
+            None => self.background_color,
+        });
+
+        let Some(camera) = camera else {
+            return (color, None);
+        };
+        let aovs = match &hit {
+            Some((index, hit)) => {
+                let (direct, shadow, reflected) = shading.unwrap_or_else(|| shade(*index, hit));
+                AovSample {
+                    depth: camera.depth_of(hit.point),
+                    normal: hit.normal,
+                    albedo: hit.material.color,
+                    direct,
+                    reflected,
+                    shadow,
+                    object_id: *index as u32 + 1,
                 }
             }
+            None => AovSample {
+                direct: self.background_color,
+                ..AovSample::default()
+            },
+        };
+        (color, Some(aovs))
+    }
+
+    /// Light reaching the origin of `ray` from `t_max`, where `surface`
+    /// gives what is seen, after the volumes and fog in between.
+    ///
+    /// The nearest volume interaction is sampled by free-flight distance,
+    /// so the ray either scatters in a medium, with weight `albedo`, or
+    /// carries on to the surface. Absorption is the part of the albedo below
+    /// one.
+    #[inline]
+    fn through_media(
+        &self,
+        ray: &Ray,
+        t_max: f64,
+        depth: i32,
+        rng: &fastrand::Rng,
+        surface: impl FnOnce() -> Color,
+    ) -> Color {
+        let interaction = self
+            .volumes
+            .iter()
+            .filter_map(|volume| Some((volume.sample_distance(ray, EPSILON, t_max, rng)?, volume)))
+            .min_by(|a, b| a.0.total_cmp(&b.0));
+
+        let (t, color) = match interaction {
+            Some((t, volume)) => (t, self.scattered_lighting(ray, t, volume, depth, rng)),
+            None => (t_max, surface()),
+        };
 
-            // Reflections
-            if hit.material.reflectivity > 0.0 {
-                let reflect_dir = reflect(ray.direction, hit.normal);
-                let reflect_ray = Ray::new(hit.point + hit.normal * EPSILON, reflect_dir);
-                let reflected_color = self.cast_ray(&reflect_ray, depth - 1);
-                color = color + reflected_color * hit.material.reflectivity;
+        match &self.fog {
+            Some(fog) => fog.apply(color, t * ray.direction.magnitude()),
+            None => color,
+        }
+    }
+
+    /// Light scattered towards the origin of `ray` by `volume` at `t`: each
+    /// light directly, attenuated by whatever lies in between, plus one
+    /// phase-sampled ray for everything else
+    fn scattered_lighting(&self, ray: &Ray, t: f64, volume: &Volume, depth: i32, rng: &fastrand::Rng) -> Color {
+        let point = ray.at(t);
+        let direction = ray.direction.normalize();
+
+        // Scaled by pi to match the Blinn-Phong terms, which leave out the
+        // 1/pi of a Lambertian surface
+        let mut direct = 0.0;
+        for light in &self.lights {
+            let visibility = self.light_visibility(point, light.position);
+            if visibility > 0.0 {
+                let cos_theta = direction.dot((light.position - point).normalize());
+                direct += light.intensity * visibility * PI * volume.phase.evaluate(cos_theta);
             }
+        }
 
-            color
-        } else {
-            self.background_color
+        let scattered = Ray::new(point, volume.phase.sample(direction, rng.f64(), rng.f64()));
+        let indirect = self.cast_ray(&scattered, depth - 1, HitSamples::random(rng), rng);
+
+        (Color::WHITE * direct + indirect) * volume.albedo
+    }
+
+    pub fn trace(
+        &self,
+        camera: &Camera,
+        width: u32,
+        height: u32,
+        samples: u32,
+        sampler: SamplerKind,
+        filter: Filter,
+    ) -> Framebuffer {
+        self.trace_tiled(camera, width, height, samples, sampler, filter, &Tiling::default())
+            .image
+    }
+
+    /// Renders only `crop` of the `width` x `height` image, framed as in the
+    /// full image, and returns just that region. The pixels match the same
+    /// region of a full render, so the result can be composited back with
+    /// `Framebuffer::composite` at the crop's top left corner.
+    #[allow(clippy::too_many_arguments)]
+    pub fn trace_crop(
+        &self,
+        camera: &Camera,
+        width: u32,
+        height: u32,
+        crop: CropWindow,
+        samples: u32,
+        sampler: SamplerKind,
+        filter: Filter,
+    ) -> Framebuffer {
+        let sampler = Sampler::new(sampler, samples);
+        let mut film = Film::cropped(width, height, filter, &[], crop.to_pixels(width, height));
+        self.render_samples(&mut film, camera, &sampler, 0..sampler.samples_per_pixel, &Tiling::default());
+        film.resolve()
+    }
+
+    /// Renders the image together with the requested AOVs, in the order given
+    #[allow(clippy::too_many_arguments)]
+    pub fn trace_with_aovs(
+        &self,
+        camera: &Camera,
+        width: u32,
+        height: u32,
+        samples: u32,
+        sampler: SamplerKind,
+        filter: Filter,
+        aovs: &[Aov],
+    ) -> (Framebuffer, Vec<(Aov, Framebuffer)>) {
+        let sampler = Sampler::new(sampler, samples);
+        let mut film = Film::with_aovs(width, height, filter, aovs);
+        self.render_samples(&mut film, camera, &sampler, 0..sampler.samples_per_pixel, &Tiling::default());
+        (film.resolve(), film.resolve_aovs())
+    }
+
+    /// Renders the image tile by tile on the rayon thread pool.
+    ///
+    /// Tiles are handed out in `tiling.order`; progress is reported after each
+    /// tile and cancellation is checked before starting the next one.
+    #[allow(clippy::too_many_arguments)]
+    pub fn trace_tiled(
+        &self,
+        camera: &Camera,
+        width: u32,
+        height: u32,
+        samples: u32,
+        sampler: SamplerKind,
+        filter: Filter,
+        tiling: &Tiling,
+    ) -> TiledRender {
+        let sampler = Sampler::new(sampler, samples);
+        let mut film = Film::new(width, height, filter);
+        let (tiles_completed, tiles_total) =
+            self.render_samples(&mut film, camera, &sampler, 0..sampler.samples_per_pixel, tiling);
+
+        TiledRender {
+            image: film.resolve(),
+            tiles_completed,
+            tiles_total,
+            cancelled: tiles_completed < tiles_total,
         }
     }
 
-    pub fn trace(&self, camera: &Camera, width: u32, height: u32, samples: u32) -> Vec<Vec<Color>> {
-        let inv_samples = 1.0 / samples as f64;
-        let inv_width = 1.0 / width as f64;
-        let inv_height = 1.0 / height as f64;
+    /// Accumulates the sample indices in `sample_range` for every pixel into `film`,
+    /// or only for those its crop needs.
+    ///
+    /// Finished tiles are merged in tile order rather than completion order, so
+    /// the film contents don't depend on thread scheduling. Returns the number
+    /// of completed and total tiles.
+    pub fn render_samples(
+        &self,
+        film: &mut Film,
+        camera: &Camera,
+        sampler: &Sampler,
+        sample_range: Range<u32>,
+        tiling: &Tiling,
+    ) -> (usize, usize) {
+        let region = film.sample_region();
+        let tiles: Vec<Tile> = generate_tiles(region.width(), region.height(), tiling.tile_size, tiling.order)
+            .into_iter()
+            .map(|tile| Tile {
+                x0: region.x0 + tile.x0,
+                y0: region.y0 + tile.y0,
+                x1: region.x0 + tile.x1,
+                y1: region.y0 + tile.y1,
+            })
+            .collect();
+        let finished = Mutex::new((0..tiles.len()).map(|_| None).collect::<Vec<_>>());
+        let next_tile = AtomicUsize::new(0);
+        let completed = AtomicUsize::new(0);
+        let film_ref = &*film;
 
-        (0..height)
-            .into_par_iter()
-            .map(|y| {
-                let mut row = Vec::with_capacity(width as usize);
-                let rng = fastrand::Rng::new();
+        rayon::scope(|scope| {
+            for _ in 0..rayon::current_num_threads() {
+                scope.spawn(|_| loop {
+                    if tiling.cancel.as_ref().is_some_and(|cancel| cancel.is_cancelled()) {
+                        break;
+                    }
 
-                for x in 0..width {
-                    let mut color = Color {
-                        r: 0.0,
-                        g: 0.0,
-                        b: 0.0,
+                    let index = next_tile.fetch_add(1, Ordering::Relaxed);
+                    let Some(&tile) = tiles.get(index) else {
+                        break;
                     };
 
-                    if samples > 1 {
-                        for _ in 0..samples {
-                            let offset_x = rng.f64();
-                            let offset_y = rng.f64();
+                    let mut film_tile = film_ref.tile(tile.x0, tile.y0, tile.x1, tile.y1);
+                    self.render_tile(film_ref, &mut film_tile, camera, sampler, tile, sample_range.clone());
+                    finished.lock().unwrap()[index] = Some(film_tile);
+
+                    let tiles_completed = completed.fetch_add(1, Ordering::Relaxed) + 1;
+                    if let Some(progress) = tiling.progress {
+                        progress(TileProgress {
+                            tile,
+                            tiles_completed,
+                            tiles_total: tiles.len(),
+                        });
+                    }
+                });
+            }
+        });
 
-                            let ndc_x = ((x as f64 + offset_x) * inv_width) * 2.0 - 1.0;
-                            let ndc_y = ((y as f64 + offset_y) * inv_height) * 2.0 - 1.0;
+        for film_tile in finished.into_inner().unwrap().iter().flatten() {
+            film.merge_tile(film_tile);
+        }
 
-                            let ray = camera.cast_ray(ndc_x, ndc_y);
-                            color = color + self.cast_ray(&ray, MAX_DEPTH);
+        (completed.into_inner(), tiles.len())
+    }
+
+    #[inline]
+    fn render_tile(
+        &self,
+        film: &Film,
+        film_tile: &mut FilmTile,
+        camera: &Camera,
+        sampler: &Sampler,
+        tile: Tile,
+        sample_range: Range<u32>,
+    ) {
+        let inv_width = 1.0 / film.width as f64;
+        let inv_height = 1.0 / film.height as f64;
+
+        for y in tile.y0..tile.y1 {
+            for x in tile.x0..tile.x1 {
+                let mut take_sample = |offset_x: f64, offset_y: f64, sample_index: u32| {
+                    let film_x = x as f64 + offset_x;
+                    let film_y = y as f64 + offset_y;
+
+                    let ndc_x = (film_x * inv_width) * 2.0 - 1.0;
+                    let ndc_y = (film_y * inv_height) * 2.0 - 1.0;
+
+                    // Where the lens sees nothing the sample is black
+                    let Some(ray) = camera.cast_ray(ndc_x, ndc_y) else {
+                        film_tile.add_sample(film_x, film_y, Color::default());
+                        if film_tile.has_aovs() {
+                            film_tile.add_aov_sample(x, y, None);
                         }
-                        color = color * inv_samples;
-                    } else {
-                        let ndc_x = ((x as f64 + 0.5) * inv_width) * 2.0 - 1.0;
-                        let ndc_y = ((y as f64 + 0.5) * inv_height) * 2.0 - 1.0;
-                        let ray = camera.cast_ray(ndc_x, ndc_y);
-                        color = self.cast_ray(&ray, MAX_DEPTH);
+                        return;
+                    };
+                    let samples = HitSamples {
+                        light: sampler.get_2d(x, y, sample_index, DIM_LIGHT),
+                        glossy: sampler.get_2d(x, y, sample_index, DIM_GLOSSY),
+                    };
+                    let rng = sampler.rng(x, y, sample_index, DIM_MEDIUM);
+                    let aov_camera = film_tile.has_aovs().then_some(camera);
+                    let (color, aovs) = self.cast_ray_with_aovs(&ray, MAX_DEPTH, samples, &rng, aov_camera);
+                    film_tile.add_sample(film_x, film_y, color);
+                    if let Some(aovs) = aovs {
+                        film_tile.add_aov_sample(x, y, Some(&aovs));
                     }
+                };
 
-                    color.r = color.r.clamp(0.0, 1.0);
-                    color.g = color.g.clamp(0.0, 1.0);
-                    color.b = color.b.clamp(0.0, 1.0);
-
-                    row.push(color);
+                if sampler.samples_per_pixel > 1 {
+                    for sample_index in sample_range.clone() {
+                        let (offset_x, offset_y) = sampler.get_2d(x, y, sample_index, DIM_PIXEL);
+                        take_sample(offset_x, offset_y, sample_index);
+                    }
+                } else {
+                    for sample_index in sample_range.clone() {
+                        take_sample(0.5, 0.5, sample_index);
+                    }
                 }
+            }
+        }
+    }
+}
 
-                row
-            })
-            .collect()
+/// Points in [0, 1)^2 for the choices made at a ray's first hit: where on
+/// each emitter to sample its light and which way a glossy reflection goes.
+/// Camera rays take them from the `Sampler`; later bounces draw them from
+/// the random stream.
+#[derive(Debug, Clone, Copy)]
+struct HitSamples {
+    light: (f64, f64),
+    glossy: (f64, f64),
+}
+
+impl HitSamples {
+    fn random(rng: &fastrand::Rng) -> HitSamples {
+        HitSamples {
+            light: (rng.f64(), rng.f64()),
+            glossy: (rng.f64(), rng.f64()),
+        }
     }
 }
 
@@ -159,13 +568,94 @@ fn reflect(incident: Vector3D, normal: Vector3D) -> Vector3D {
     incident - normal * (2.0 * incident.dot(normal))
 }
 
+/// How a fisheye lens spreads angles over the image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FisheyeMapping {
+    /// Distance from the centre proportional to the angle off the axis
+    Equidistant,
+    /// Equal solid angles cover equal image areas
+    Equisolid,
+}
+
+/// How a `Camera` maps image positions to rays
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Projection {
+    #[default]
+    Perspective,
+    /// Parallel rays through a view `height` world units tall, with no
+    /// perspective; `fov` is unused
+    Orthographic { height: f64 },
+    /// `fov` degrees across the image height, which may exceed 180. Parts
+    /// of the image beyond what the lens can see stay black.
+    Fisheye(FisheyeMapping),
+    /// Every direction round the camera, longitude across the width and
+    /// latitude down the height, centred on the view direction. Meant for
+    /// 2:1 images; `fov` is unused.
+    Equirectangular,
+    /// One eye of an omni-directional stereo panorama: equirectangular, but
+    /// each ray starts `eye_offset` to the right of the camera as seen
+    /// looking along it (negative for the left eye). See `StereoCamera`.
+    OmniStereo { eye_offset: f64 },
+}
+
+impl Projection {
+    /// Parses `perspective`, `orthographic`, `fisheye` (equidistant),
+    /// `fisheye-equisolid` or `equirectangular`; orthographic views are 2
+    /// units tall
+    pub fn from_name(name: &str) -> Option<Projection> {
+        match name {
+            "perspective" => Some(Projection::Perspective),
+            "orthographic" => Some(Projection::Orthographic { height: 2.0 }),
+            "fisheye" | "fisheye-equidistant" => Some(Projection::Fisheye(FisheyeMapping::Equidistant)),
+            "fisheye-equisolid" => Some(Projection::Fisheye(FisheyeMapping::Equisolid)),
+            "equirectangular" => Some(Projection::Equirectangular),
+            _ => None,
+        }
+    }
+
+    /// Kind number and parameter, as the GPU and checkpoints store them
+    pub(crate) fn encode(self) -> (u32, f64) {
+        match self {
+            Projection::Perspective => (0, 0.0),
+            Projection::Orthographic { height } => (1, height),
+            Projection::Fisheye(FisheyeMapping::Equidistant) => (2, 0.0),
+            Projection::Fisheye(FisheyeMapping::Equisolid) => (3, 0.0),
+            Projection::Equirectangular => (4, 0.0),
+            Projection::OmniStereo { eye_offset } => (5, eye_offset),
+        }
+    }
+
+    pub(crate) fn decode(kind: u32, parameter: f64) -> Option<Projection> {
+        match kind {
+            0 => Some(Projection::Perspective),
+            1 => Some(Projection::Orthographic { height: parameter }),
+            2 => Some(Projection::Fisheye(FisheyeMapping::Equidistant)),
+            3 => Some(Projection::Fisheye(FisheyeMapping::Equisolid)),
+            4 => Some(Projection::Equirectangular),
+            5 => Some(Projection::OmniStereo { eye_offset: parameter }),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct Camera {
     pub position: Vector3D,
     pub target: Vector3D,
+    /// Which way is up in the image. It needn't be perpendicular to the
+    /// view, and looking straight along it falls back to another axis.
     pub up: Vector3D,
     pub fov: f64,
     pub aspect_ratio: f64,
+    pub projection: Projection,
+    /// Degrees the camera turns about its view direction, anticlockwise as
+    /// seen from behind it, so the horizon tilts clockwise in the image
+    pub roll: f64,
+    /// Lens shift in image widths, moving the view right without turning
+    /// the camera, so verticals stay parallel
+    pub shift_x: f64,
+    /// Lens shift in image heights, moving the view up
+    pub shift_y: f64,
 }
 
 impl Camera {
@@ -176,21 +666,364 @@ impl Camera {
             up: Vector3D::new(0.0, 1.0, 0.0),
             fov,
             aspect_ratio,
+            projection: Projection::Perspective,
+            roll: 0.0,
+            shift_x: 0.0,
+            shift_y: 0.0,
+        }
+    }
+
+    /// Unit view direction; +z if the target is the camera's own position
+    #[inline]
+    pub fn forward(&self) -> Vector3D {
+        let offset = self.target - self.position;
+        if offset.magnitude_squared() > 0.0 {
+            offset.normalize()
+        } else {
+            Vector3D::new(0.0, 0.0, 1.0)
+        }
+    }
+
+    /// Maps camera space (x right, y up, z forward) to world space: the
+    /// `Matrix4::look_at` frame, with its fallback for views along `up`,
+    /// turned by the roll
+    pub fn camera_to_world(&self) -> Matrix4 {
+        let look_at = Matrix4::look_at(self.position, self.target, self.up);
+        if self.roll == 0.0 {
+            return look_at;
+        }
+        look_at * Quaternion::from_axis_angle(Vector3D::new(0.0, 0.0, 1.0), self.roll).to_matrix()
+    }
+
+    /// Orthonormal (forward, right, up) camera basis with the roll applied,
+    /// taken from `camera_to_world`
+    #[inline]
+    pub fn basis(&self) -> (Vector3D, Vector3D, Vector3D) {
+        let camera_to_world = self.camera_to_world();
+        (
+            camera_to_world.transform_vector(Vector3D::new(0.0, 0.0, 1.0)),
+            camera_to_world.transform_vector(Vector3D::new(1.0, 0.0, 0.0)),
+            camera_to_world.transform_vector(Vector3D::new(0.0, 1.0, 0.0)),
+        )
+    }
+
+    /// Ray through the image at NDC (`ndc_x`, `ndc_y`), or `None` where a
+    /// fisheye lens sees nothing
+    #[inline]
+    pub fn cast_ray(&self, ndc_x: f64, ndc_y: f64) -> Option<Ray> {
+        let (forward, right, up) = self.basis();
+        let ndc_x = ndc_x + 2.0 * self.shift_x;
+        let ndc_y = ndc_y - 2.0 * self.shift_y;
+
+        // Image plane coordinates in units of half the image height
+        let x = ndc_x * self.aspect_ratio;
+        let y = -ndc_y;
+
+        let ray = match self.projection {
+            Projection::Perspective => {
+                let fov_adjustment = (self.fov.to_radians() / 2.0).tan();
+                let direction = (forward + right * (x * fov_adjustment) + up * (y * fov_adjustment)).normalize();
+                Ray::new(self.position, direction)
+            }
+            Projection::Orthographic { height } => {
+                let origin = self.position + (right * x + up * y) * (height * 0.5);
+                Ray::new(origin, forward)
+            }
+            Projection::Fisheye(mapping) => {
+                let radius = (x * x + y * y).sqrt();
+                let half_fov = self.fov.to_radians() * 0.5;
+                let theta = match mapping {
+                    FisheyeMapping::Equidistant => radius * half_fov,
+                    FisheyeMapping::Equisolid => {
+                        let s = radius * (half_fov * 0.5).sin();
+                        if s > 1.0 {
+                            return None;
+                        }
+                        2.0 * s.asin()
+                    }
+                };
+                if theta > PI {
+                    return None;
+                }
+
+                let sideways = if radius > 0.0 {
+                    (right * x + up * y) * (theta.sin() / radius)
+                } else {
+                    Vector3D::ZERO
+                };
+                Ray::new(self.position, (forward * theta.cos() + sideways).normalize())
+            }
+            Projection::Equirectangular | Projection::OmniStereo { .. } => {
+                let longitude = ndc_x * PI;
+                let latitude = y * PI * 0.5;
+                let direction = (forward * longitude.cos() + right * longitude.sin()) * latitude.cos()
+                    + up * latitude.sin();
+
+                // The eyes sit on a circle round the camera, each offset
+                // square to the horizontal direction it looks in
+                let origin = match self.projection {
+                    Projection::OmniStereo { eye_offset } => {
+                        self.position + (right * longitude.cos() - forward * longitude.sin()) * eye_offset
+                    }
+                    _ => self.position,
+                };
+                Ray::new(origin, direction.normalize())
+            }
+        };
+        Some(ray)
+    }
+
+    /// Depth AOV value of `point`: its distance in front of the image plane
+    /// for perspective and orthographic cameras, and from the camera for
+    /// the panoramic projections, whose views can reach behind it
+    #[inline]
+    pub fn depth_of(&self, point: Vector3D) -> f64 {
+        let offset = point - self.position;
+        match self.projection {
+            Projection::Perspective | Projection::Orthographic { .. } => offset.dot(self.forward()),
+            Projection::Fisheye(_) | Projection::Equirectangular | Projection::OmniStereo { .. } => {
+                offset.magnitude()
+            }
         }
     }
 
+    /// Inverse of `cast_ray`: the NDC coordinates `point` is seen at, or
+    /// `None` if the camera can't see it. Omni-directional stereo eyes are
+    /// treated as sitting at the camera, which is close for distant points.
     #[inline]
-    pub fn cast_ray(&self, ndc_x: f64, ndc_y: f64) -> Ray {
-        let forward = (self.target - self.position).normalize();
-        let right = forward.cross(self.up).normalize();
-        let up = right.cross(forward);
+    pub fn project(&self, point: Vector3D) -> Option<(f64, f64)> {
+        let (forward, right, up) = self.basis();
+        let offset = point - self.position;
+
+        // Image plane coordinates, as in `cast_ray`
+        let (x, y) = match self.projection {
+            Projection::Perspective => {
+                let z = offset.dot(forward);
+                if z <= 0.0 {
+                    return None;
+                }
+                let fov_adjustment = (self.fov.to_radians() / 2.0).tan();
+                (offset.dot(right) / (z * fov_adjustment), offset.dot(up) / (z * fov_adjustment))
+            }
+            Projection::Orthographic { height } => {
+                if offset.dot(forward) <= 0.0 {
+                    return None;
+                }
+                (offset.dot(right) / (height * 0.5), offset.dot(up) / (height * 0.5))
+            }
+            Projection::Fisheye(mapping) => {
+                let distance = offset.magnitude();
+                if distance == 0.0 {
+                    return None;
+                }
+                let theta = (offset.dot(forward) / distance).clamp(-1.0, 1.0).acos();
+                let half_fov = self.fov.to_radians() * 0.5;
+                let radius = match mapping {
+                    FisheyeMapping::Equidistant => theta / half_fov,
+                    FisheyeMapping::Equisolid => (theta * 0.5).sin() / (half_fov * 0.5).sin(),
+                };
+                let (side_x, side_y) = (offset.dot(right), offset.dot(up));
+                let side = (side_x * side_x + side_y * side_y).sqrt();
+                if side == 0.0 {
+                    (0.0, 0.0)
+                } else {
+                    (side_x * radius / side, side_y * radius / side)
+                }
+            }
+            Projection::Equirectangular | Projection::OmniStereo { .. } => {
+                let distance = offset.magnitude();
+                if distance == 0.0 {
+                    return None;
+                }
+                let longitude = offset.dot(right).atan2(offset.dot(forward));
+                let latitude = (offset.dot(up) / distance).clamp(-1.0, 1.0).asin();
+                (longitude / PI * self.aspect_ratio, latitude / (PI * 0.5))
+            }
+        };
+        Some((x / self.aspect_ratio - 2.0 * self.shift_x, -y + 2.0 * self.shift_y))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::film::FilterKind;
+    use crate::scene_file::SceneDescription;
+    use crate::sphere::{Material, Sphere};
+    use crate::volume::PhaseFunction;
+
+    fn assert_close(a: Vector3D, b: Vector3D) {
+        assert!((a - b).magnitude() < 1e-9, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn colour_maps_tint_the_nearest_hit() {
+        let mut scene = SceneDescription::default().to_scene();
+        let texture = scene.add_texture(Texture::new(1, 1, vec![Color { r: 1.0, g: 0.5, b: 0.0 }]));
+        let material = Material {
+            color: Color { r: 0.5, g: 0.5, b: 0.5 },
+            diffuse: 1.0,
+            specular: 0.0,
+            shininess: 1.0,
+            reflectivity: 0.0,
+            pbr: Some(crate::pbr::PbrMaterial::new(Color::WHITE, 0.0, 0.5)),
+            emission: Color::default(),
+            texture: Some(texture),
+            normal_map: None,
+        };
+        scene.add_object(Box::new(Sphere::new(Vector3D::new(0.0, 0.0, 5.0), 1.0, material)));
+
+        let (_, hit) = scene.closest_hit(&Ray::new(Vector3D::ZERO, Vector3D::new(0.0, 0.0, 1.0))).unwrap();
+        let color = hit.material.color;
+        assert_eq!((color.r, color.g, color.b), (0.5, 0.25, 0.0));
+        let base = hit.material.pbr.unwrap().base_color;
+        assert_eq!((base.r, base.g, base.b), (1.0, 0.5, 0.0));
+    }
+
+    #[test]
+    fn aovs_leave_the_beauty_pass_unchanged() {
+        let description: SceneDescription = "\
+camera position 0 1 0 target 0 0.5 5 fov 60
+light key position 3 2 5
+plane ground point 0 -1 0 normal 0 1 0 color 0.5 0.5 0.5 reflectivity 0.3
+sphere blue center 0 0 5 radius 1 color 0.2 0.4 1 reflectivity 0.5
+"
+        .parse()
+        .unwrap();
+        let mut scene = description.to_scene();
+        // Only the boundary's shape matters to a volume
+        let material = Material {
+            color: Color::WHITE,
+            diffuse: 1.0,
+            specular: 0.0,
+            shininess: 1.0,
+            reflectivity: 0.0,
+            pbr: None,
+            emission: Color::default(),
+            texture: None,
+            normal_map: None,
+        };
+        let boundary = Sphere::new(Vector3D::new(0.5, 0.5, 4.0), 1.5, material);
+        scene.volumes.push(Volume::new(Box::new(boundary), 0.6, Color::WHITE, PhaseFunction::HenyeyGreenstein(0.3)));
+
+        let camera = description.camera(1.0);
+        let sampler = Sampler::new(SamplerKind::Sobol, 4);
+        let filter = Filter::new(FilterKind::Box, 0.5);
+        let render = |aovs: &[Aov]| {
+            let mut film = Film::with_aovs(16, 16, filter, aovs);
+            scene.render_samples(&mut film, &camera, &sampler, 0..4, &Tiling::default());
+            film.resolve()
+        };
+
+        let plain = render(&[]);
+        let with_aovs = render(&Aov::ALL);
+        assert!(plain.as_slice() == with_aovs.as_slice());
+    }
+
+    #[test]
+    fn projections_invert_their_rays() {
+        let projections = [
+            Projection::Perspective,
+            Projection::Orthographic { height: 3.0 },
+            Projection::Fisheye(FisheyeMapping::Equidistant),
+            Projection::Fisheye(FisheyeMapping::Equisolid),
+            Projection::Equirectangular,
+        ];
+        for projection in projections {
+            let camera = Camera {
+                projection,
+                roll: 20.0,
+                shift_x: 0.1,
+                shift_y: -0.05,
+                fov: 200.0,
+                ..Camera::new(Vector3D::new(1.0, 2.0, -3.0), Vector3D::new(-1.0, 0.5, 4.0), 200.0, 1.5)
+            };
+            let camera = match projection {
+                Projection::Perspective => Camera { fov: 60.0, ..camera },
+                Projection::Fisheye(FisheyeMapping::Equisolid) => Camera { fov: 180.0, ..camera },
+                // Shifted panoramas wrap round, where any longitude has two names
+                Projection::Equirectangular => Camera { shift_x: 0.0, ..camera },
+                _ => camera,
+            };
+            for i in 0..9 {
+                for j in 0..9 {
+                    let (ndc_x, ndc_y) = (-0.8 + 0.2 * i as f64, -0.8 + 0.2 * j as f64);
+                    let Some(ray) = camera.cast_ray(ndc_x, ndc_y) else {
+                        assert!(matches!(projection, Projection::Fisheye(_)), "{:?} missed", projection);
+                        continue;
+                    };
+                    let (x, y) = camera.project(ray.at(3.0)).unwrap();
+                    assert!((x - ndc_x).abs() < 1e-9 && (y - ndc_y).abs() < 1e-9,
+                            "{:?}: ({}, {}) came back as ({}, {})", projection, ndc_x, ndc_y, x, y);
+
+                    let depth = camera.depth_of(ray.at(3.0));
+                    let expected = match projection {
+                        Projection::Perspective => 3.0 * ray.direction.dot(camera.forward()),
+                        _ => 3.0,
+                    };
+                    assert!((depth - expected).abs() < 1e-9, "{:?}: depth {} != {}", projection, depth, expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn crops_composite_back_into_the_full_render() {
+        let description: SceneDescription = "\
+camera position 0 1 0 target 0 0.5 5 fov 60
+light key position 3 2 5
+plane ground point 0 -1 0 normal 0 1 0 color 0.5 0.5 0.5 reflectivity 0.3
+sphere blue center 0 0 5 radius 1 color 0.2 0.4 1 reflectivity 0.5
+"
+        .parse()
+        .unwrap();
+        let scene = description.to_scene();
+        let camera = description.camera(1.5);
+        // Wide enough that samples outside the window reach its edge pixels
+        let filter = Filter::new(FilterKind::Gaussian, 1.5);
+
+        let full = scene.trace(&camera, 24, 16, 4, SamplerKind::Sobol, filter);
+        let window = CropWindow::parse("0.2,0.2,0.7,0.65").unwrap();
+        let crop = window.to_pixels(24, 16);
+        assert_eq!(crop, Tile { x0: 4, y0: 3, x1: 17, y1: 11 });
+        let region = scene.trace_crop(&camera, 24, 16, window, 4, SamplerKind::Sobol, filter);
+        assert_eq!((region.width, region.height), (13, 8));
+
+        // Patched over a rougher render, only the window changes
+        let rough = scene.trace(&camera, 24, 16, 1, SamplerKind::Random, Filter::new(FilterKind::Box, 0.5));
+        let mut patched = rough.clone();
+        patched.composite(&region, crop.x0, crop.y0);
+        for y in 0..16 {
+            for x in 0..24 {
+                let inside = (crop.x0..crop.x1).contains(&x) && (crop.y0..crop.y1).contains(&y);
+                let expected = if inside { full.pixel(x, y) } else { rough.pixel(x, y) };
+                assert_eq!(patched.pixel(x, y), expected, "({}, {})", x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn straight_down_camera_has_a_valid_basis() {
+        let mut camera = Camera::new(Vector3D::new(0.0, 5.0, 0.0), Vector3D::ZERO, 90.0, 1.0);
+        let (forward, right, up) = camera.basis();
+        assert_close(forward, Vector3D::new(0.0, -1.0, 0.0));
+        assert_close(right, Vector3D::new(-1.0, 0.0, 0.0));
+        assert_close(up, Vector3D::new(0.0, 0.0, 1.0));
 
-        let fov_adjustment = (self.fov.to_radians() / 2.0).tan();
-        let adjusted_x = ndc_x * self.aspect_ratio * fov_adjustment;
-        let adjusted_y = -ndc_y * fov_adjustment;
+        let to_world = camera.camera_to_world();
+        assert_close(to_world.transform_point(Vector3D::ZERO), camera.position);
+        assert_close(to_world.transform_vector(Vector3D::new(0.0, 0.0, 1.0)), forward);
 
-        let direction = (forward + right * adjusted_x + up * adjusted_y).normalize();
+        // The top of the image looks towards +z
+        let top = camera.cast_ray(0.0, -1.0).unwrap();
+        assert_close(top.direction, Vector3D::new(0.0, -1.0, 1.0).normalize());
+        let (x, y) = camera.project(Vector3D::new(0.0, 0.0, 5.0)).unwrap();
+        assert!(x.abs() < 1e-9 && (y + 1.0).abs() < 1e-9);
 
-        Ray::new(self.position, direction)
+        // Roll turns the fallback frame like any other
+        camera.roll = 90.0;
+        let (_, right, up) = camera.basis();
+        assert_close(right, Vector3D::new(0.0, 0.0, 1.0));
+        assert_close(up, Vector3D::new(1.0, 0.0, 0.0));
     }
 }
@@ -0,0 +1,1713 @@
+use crate::animation::{Interpolation, Keyframe, Track};
+use crate::camera_rig::{CameraRig, PathOrientation};
+use crate::cone::Cone;
+use crate::cuboid::OrientedBox;
+use crate::cylinder::Cylinder;
+use crate::disk::Disk;
+use crate::gltf_import::{GltfError, GltfScene};
+use crate::gpu_renderer::{MaterialData, PlaneData, ShapeData, SphereData, ToShapeData};
+use crate::hittable::Hittable;
+use crate::instance::Instance;
+use crate::mesh_import::{load_ply, load_stl, MeshImportError};
+use crate::normal_map::NormalMap;
+use crate::pbr::PbrMaterial;
+use crate::plane::Plane;
+use crate::rectangle::Rectangle;
+use crate::scene::{Camera, Light, Projection, Scene};
+use crate::sphere::{Color, Material, Sphere};
+use crate::texture::{Texture, TextureId};
+use crate::torus::Torus;
+use crate::transform::{Frame, Transform};
+use crate::vector3d::{Quaternion, Vector3D};
+use crate::volume::{Fog, PhaseFunction, Volume};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Material of objects that don't set their own properties
+const DEFAULT_MATERIAL: Material = Material {
+    color: Color { r: 0.8, g: 0.8, b: 0.8 },
+    diffuse: 0.8,
+    specular: 0.2,
+    shininess: 32.0,
+    reflectivity: 0.0,
+    pbr: None,
+    emission: Color { r: 0.0, g: 0.0, b: 0.0 },
+    texture: None,
+    normal_map: None,
+};
+
+#[derive(Debug)]
+pub enum SceneFileError {
+    Io(std::io::Error),
+    /// Problem on a line of the file, counted from 1
+    Parse { line: usize, message: String },
+    /// A model the file imports couldn't be loaded
+    Gltf(PathBuf, GltfError),
+    Mesh(PathBuf, MeshImportError),
+    Texture(PathBuf, image::ImageError),
+}
+
+impl fmt::Display for SceneFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneFileError::Io(e) => write!(f, "Failed to read scene file: {}", e),
+            SceneFileError::Parse { line, message } => write!(f, "Scene file line {}: {}", line, message),
+            SceneFileError::Gltf(path, e) => write!(f, "Failed to import {}: {}", path.display(), e),
+            SceneFileError::Mesh(path, e) => write!(f, "Failed to import {}: {}", path.display(), e),
+            SceneFileError::Texture(path, e) => write!(f, "Failed to load texture {}: {}", path.display(), e),
+        }
+    }
+}
+
+impl std::error::Error for SceneFileError {}
+
+impl From<std::io::Error> for SceneFileError {
+    fn from(e: std::io::Error) -> Self {
+        SceneFileError::Io(e)
+    }
+}
+
+/// Object kinds a scene file can hold, the ones both renderers support
+#[derive(Debug, Clone, Copy)]
+pub enum SceneObject {
+    Sphere(Sphere),
+    Plane(Plane),
+    Box(OrientedBox),
+    Cylinder(Cylinder),
+    Cone(Cone),
+    Disk(Disk),
+    Rectangle(Rectangle),
+    Torus(Torus),
+}
+
+impl SceneObject {
+    /// Keyword of the object in a scene file
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SceneObject::Sphere(_) => "sphere",
+            SceneObject::Plane(_) => "plane",
+            SceneObject::Box(_) => "box",
+            SceneObject::Cylinder(_) => "cylinder",
+            SceneObject::Cone(_) => "cone",
+            SceneObject::Disk(_) => "disk",
+            SceneObject::Rectangle(_) => "rectangle",
+            SceneObject::Torus(_) => "torus",
+        }
+    }
+
+    /// Whether the object has a `rotation` channel; turning a sphere
+    /// changes nothing
+    pub fn rotates(&self) -> bool {
+        !matches!(self, SceneObject::Sphere(_))
+    }
+
+    /// Whether the object has a `scale` channel; planes are unbounded
+    pub fn scales(&self) -> bool {
+        !matches!(self, SceneObject::Plane(_))
+    }
+
+    fn boxed(self) -> Box<dyn Hittable> {
+        match self {
+            SceneObject::Sphere(sphere) => Box::new(sphere),
+            SceneObject::Plane(plane) => Box::new(plane),
+            SceneObject::Box(shape) => Box::new(shape),
+            SceneObject::Cylinder(shape) => Box::new(shape),
+            SceneObject::Cone(shape) => Box::new(shape),
+            SceneObject::Disk(shape) => Box::new(shape),
+            SceneObject::Rectangle(shape) => Box::new(shape),
+            SceneObject::Torus(shape) => Box::new(shape),
+        }
+    }
+
+    fn material_mut(&mut self) -> &mut Material {
+        match self {
+            SceneObject::Sphere(sphere) => &mut sphere.material,
+            SceneObject::Plane(plane) => &mut plane.material,
+            SceneObject::Box(shape) => &mut shape.material,
+            SceneObject::Cylinder(shape) => &mut shape.material,
+            SceneObject::Cone(shape) => &mut shape.material,
+            SceneObject::Disk(shape) => &mut shape.material,
+            SceneObject::Rectangle(shape) => &mut shape.material,
+            SceneObject::Torus(shape) => &mut shape.material,
+        }
+    }
+
+    /// Placement of the shapes that have one
+    fn frame_mut(&mut self) -> Option<&mut Frame> {
+        match self {
+            SceneObject::Sphere(_) | SceneObject::Plane(_) => None,
+            SceneObject::Box(shape) => Some(&mut shape.frame),
+            SceneObject::Cylinder(shape) => Some(&mut shape.frame),
+            SceneObject::Cone(shape) => Some(&mut shape.frame),
+            SceneObject::Disk(shape) => Some(&mut shape.frame),
+            SceneObject::Rectangle(shape) => Some(&mut shape.frame),
+            SceneObject::Torus(shape) => Some(&mut shape.frame),
+        }
+    }
+
+    fn set_position(&mut self, position: Vector3D) {
+        match self {
+            SceneObject::Sphere(sphere) => sphere.center = position,
+            SceneObject::Plane(plane) => plane.point = position,
+            shape => shape.frame_mut().expect("shapes have a frame").origin = position,
+        }
+    }
+
+    /// Turns the object about its position by `rotation` from its
+    /// orientation in `rest`
+    fn rotate(&mut self, rest: &SceneObject, rotation: Quaternion) {
+        match (self, rest) {
+            (SceneObject::Plane(plane), SceneObject::Plane(rest)) => plane.normal = rotation.rotate(rest.normal),
+            (SceneObject::Sphere(_), _) => unreachable!("spheres have no rotation channel"),
+            (shape, rest) => {
+                let mut rest = *rest;
+                let rest = *rest.frame_mut().expect("shapes have a frame");
+                let frame = shape.frame_mut().expect("shapes have a frame");
+                frame.u = rotation.rotate(rest.u);
+                frame.v = rotation.rotate(rest.v);
+                frame.w = rotation.rotate(rest.w);
+            }
+        }
+    }
+
+    /// Scales the object about its position by `factor` from its size in
+    /// `rest`
+    fn scale(&mut self, rest: &SceneObject, factor: f64) {
+        match (self, rest) {
+            (SceneObject::Sphere(sphere), SceneObject::Sphere(rest)) => sphere.radius = rest.radius * factor,
+            (SceneObject::Box(shape), SceneObject::Box(rest)) => shape.half_extents = rest.half_extents * factor,
+            (SceneObject::Cylinder(shape), SceneObject::Cylinder(rest)) => {
+                shape.radius = rest.radius * factor;
+                shape.height = rest.height * factor;
+            }
+            (SceneObject::Cone(shape), SceneObject::Cone(rest)) => {
+                shape.radius = rest.radius * factor;
+                shape.height = rest.height * factor;
+            }
+            (SceneObject::Disk(shape), SceneObject::Disk(rest)) => shape.radius = rest.radius * factor,
+            (SceneObject::Rectangle(shape), SceneObject::Rectangle(rest)) => {
+                shape.half_width = rest.half_width * factor;
+                shape.half_height = rest.half_height * factor;
+            }
+            (SceneObject::Torus(shape), SceneObject::Torus(rest)) => {
+                shape.major_radius = rest.major_radius * factor;
+                shape.minor_radius = rest.minor_radius * factor;
+            }
+            (object, _) => unreachable!("{} has no scale channel", object.kind()),
+        }
+    }
+}
+
+/// Keyframed property of a scene. Indices refer to the description's
+/// `lights` and `objects`; rotations apply to the property's value in the
+/// file and are slerped between keys, which the file gives as Euler angles
+/// in degrees.
+#[derive(Debug, Clone)]
+pub enum Animation {
+    CameraPosition(Track<Vector3D>),
+    CameraTarget(Track<Vector3D>),
+    /// Turns the file's view direction, overriding any target track
+    CameraRotation(Track<Quaternion>),
+    CameraFov(Track<f64>),
+    CameraRoll(Track<f64>),
+    LightPosition(usize, Track<Vector3D>),
+    LightIntensity(usize, Track<f64>),
+    /// Centre of a sphere, reference point of a plane or origin of a
+    /// shape's frame
+    ObjectPosition(usize, Track<Vector3D>),
+    /// Turns a plane's normal or a shape's frame. Only for objects that
+    /// `SceneObject::rotates`.
+    ObjectRotation(usize, Track<Quaternion>),
+    /// Multiplies a sphere's radius or all of a shape's dimensions. Only for
+    /// objects that `SceneObject::scales`.
+    ObjectScale(usize, Track<f64>),
+    ObjectColor(usize, Track<Color>),
+}
+
+/// Participating medium inside a closed shape, which only the CPU renderer
+/// draws. See `Volume`.
+#[derive(Debug, Clone, Copy)]
+pub struct SceneVolume {
+    /// Marks where the medium is and isn't drawn itself
+    pub boundary: SceneObject,
+    pub density: f64,
+    pub albedo: Color,
+    pub phase: PhaseFunction,
+}
+
+/// Model file a scene file pulls in, which only the CPU renderer draws
+#[derive(Clone)]
+pub struct Import {
+    /// As written in the scene file, so relative to its directory
+    pub path: PathBuf,
+    pub kind: ImportKind,
+    /// Places the whole model, on top of any transforms inside it
+    pub transform: Transform,
+    /// Filled in by `SceneDescription::load_assets`
+    objects: Vec<Arc<dyn Hittable>>,
+    lights: Vec<Light>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ImportKind {
+    /// glTF 2.0 file, `.gltf` or `.glb`, with its objects and lights; its
+    /// cameras are ignored
+    Gltf,
+    /// PLY mesh with the scene file's material
+    Ply(Material),
+    /// STL mesh with the scene file's material
+    Stl(Material),
+}
+
+impl fmt::Debug for Import {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Import")
+            .field("path", &self.path)
+            .field("kind", &self.kind)
+            .field("transform", &self.transform)
+            .field("objects", &self.objects.len())
+            .field("lights", &self.lights)
+            .finish()
+    }
+}
+
+/// Scene and animation read from a scene file, renderable by either
+/// backend.
+///
+/// The file is line based, with `#` starting a comment:
+///
+/// ```text
+/// background 0.2 0.3 0.5
+/// frames 36
+/// camera position 0 1 0 target 0 0.5 5 fov 60
+/// light key position 3 2 5 intensity 1
+/// plane ground point 0 -1 0 normal 0 1 0 color 0.5 0.5 0.5 reflectivity 0.1
+/// sphere ball center 0 0 5 radius 1 color 0.2 0.4 1 shininess 32
+///
+/// animate key position catmull-rom
+/// key 0  3 2 5
+/// key 18 -3 2 5
+/// animate camera fov bezier 0.42 0 0.58 1
+/// key 0 60
+/// key 35 40
+/// ```
+///
+/// Besides `position`, `target` and `fov`, the camera takes an `up`
+/// direction, a `roll` in degrees, a lens `shift` in image widths and
+/// heights, and a `projection`: `perspective` (the default),
+/// `orthographic`, `fisheye`, `fisheye-equisolid` or `equirectangular`. A
+/// `height` after `projection orthographic` sets how tall the view is in
+/// world units (2 by default).
+///
+/// Besides spheres and planes there are shapes:
+///
+/// ```text
+/// box      crate  center 1 0 4  size 1 1 1  rotation 0 30 0
+/// cylinder post   base 2 -1 6  top 2 1 6  radius 0.3
+/// cone     spike  base -2 -1 6  apex -2 0.5 6  radius 0.5
+/// disk     plate  center 0 -0.9 3  normal 0 1 0  radius 0.6
+/// rectangle panel corner -1 2 7  edge-u 2 0 0  edge-v 0 0 -1
+/// torus    ring   center 0 1 6  axis 0 1 0  radii 1 0.2
+/// ```
+///
+/// A box's `rotation` is Euler angles in degrees; rectangles face
+/// `edge-u x edge-v`. Shapes turn and scale about, and `position` tracks
+/// move, the base of cylinders and cones and the centre of the rest.
+///
+/// Objects take `color`, `diffuse`, `specular`, `shininess`,
+/// `reflectivity` and `emission` in any order. Either of `metallic` and
+/// `roughness`, both between 0 and 1, makes the material a `PbrMaterial`
+/// on the `color`, with a metalness of 0 and a roughness of 0.5 unless
+/// given; only the CPU renderer uses it, and the GPU keeps the Blinn-Phong
+/// properties. So it is with `normal_map`, an image and an optional
+/// strength, and `bump_map`, an image and the height of white above black
+/// in UV units (see `NormalMap`). Image paths are relative to the scene
+/// file. An `animate` line names the
+/// camera or an earlier light or object, a channel and an interpolation
+/// (`step`, `linear`, `bezier` with optional control points, or
+/// `catmull-rom`), and the `key` lines after it give a frame number and the
+/// value. Channels are `position`, `target`, `rotation`, `fov` and `roll`
+/// for the camera, `position` and `intensity` for lights, and `position` and
+/// `color` for objects, plus `scale` for everything but planes and
+/// `rotation` for everything but spheres.
+///
+/// A `rig` line moves the camera as a whole (see `CameraRig`), targeting
+/// the camera's `target` unless it names its own:
+///
+/// ```text
+/// rig orbit radius 4 height 1 start 0 turns 1
+/// rig dolly target 0 0.5 5 zoom bezier
+/// key 0 6
+/// key 35 2
+/// rig path tangent catmull-rom
+/// key 0 0 1 0
+/// key 35 0 1 8
+/// ```
+///
+/// A dolly's keys give the distance from the target and a path's keys the
+/// camera position; paths default to `catmull-rom` and look either along
+/// the path (`tangent`) or at a point (`look-at x y z`).
+///
+/// A `gltf` line imports a glTF file's objects and lights, which only the
+/// CPU renderer draws, optionally placed with a `position`, a `rotation` in
+/// Euler degrees and a uniform `scale`. The path is relative to the scene
+/// file and can't contain spaces:
+///
+/// ```text
+/// gltf models/lamp.glb position 0 -1 4 rotation 0 90 0 scale 0.5
+/// ```
+///
+/// A `mesh` line does the same for a `.ply` or `.stl` file, which also
+/// takes the material properties of other objects:
+///
+/// ```text
+/// mesh models/bunny.ply position 0 -1 5 scale 10 color 0.9 0.6 0.3
+/// ```
+///
+/// Imported objects have no name, so they can't be animated.
+///
+/// The CPU renderer also draws fog and participating media. `fog` needs a
+/// `color` and a `density`; a `volume` is a sphere, box, cylinder, cone or
+/// torus, given as above but without a name or material, filled with a
+/// medium of some `density` (1 by default) and `albedo` (white) that
+/// scatters evenly unless it has an `anisotropy`, the Henyey-Greenstein `g`
+/// between -1 and 1:
+///
+/// ```text
+/// fog color 0.7 0.7 0.8 density 0.05
+/// volume sphere center 0 0 5 radius 1.5 density 0.8 albedo 0.9 0.9 0.9 anisotropy 0.3
+/// ```
+#[derive(Debug, Clone)]
+pub struct SceneDescription {
+    pub background: Color,
+    /// Length of the animation
+    pub frames: usize,
+    /// The camera's aspect ratio is left to the renderer
+    pub camera: Camera,
+    /// Moves the camera before its own tracks are applied, so a track can
+    /// still change what the rig leaves alone, like the field of view
+    pub rig: Option<CameraRig>,
+    pub lights: Vec<(String, Light)>,
+    pub objects: Vec<(String, SceneObject)>,
+    pub animations: Vec<Animation>,
+    /// Models read from other files, which only the CPU renderer draws
+    pub imports: Vec<Import>,
+    /// CPU only
+    pub fog: Option<Fog>,
+    /// CPU only
+    pub volumes: Vec<SceneVolume>,
+    /// Images for normal and bump maps, as written in the file, in the
+    /// order the materials' `TextureId`s number them
+    pub texture_paths: Vec<PathBuf>,
+    /// Filled in by `load_assets`: the images of `texture_paths`, then
+    /// any the imports bring
+    textures: Vec<Arc<Texture>>,
+}
+
+impl Default for SceneDescription {
+    fn default() -> Self {
+        SceneDescription {
+            background: Color::default(),
+            frames: 1,
+            camera: Camera::new(Vector3D::ZERO, Vector3D::new(0.0, 0.0, 1.0), 60.0, 1.0),
+            rig: None,
+            lights: Vec::new(),
+            objects: Vec::new(),
+            animations: Vec::new(),
+            imports: Vec::new(),
+            fog: None,
+            volumes: Vec::new(),
+            texture_paths: Vec::new(),
+            textures: Vec::new(),
+        }
+    }
+}
+
+impl SceneDescription {
+    /// Reads a scene file and the textures and models it refers to
+    pub fn load(path: impl AsRef<Path>) -> Result<SceneDescription, SceneFileError> {
+        let path = path.as_ref();
+        let mut description: SceneDescription = std::fs::read_to_string(path)?.parse()?;
+        description.load_assets(path.parent().unwrap_or(Path::new(".")))?;
+        Ok(description)
+    }
+
+    /// Reads the textures and imported models, resolving relative paths
+    /// against `base_dir`, the scene file's directory. Parsing a scene file
+    /// only records them.
+    pub fn load_assets(&mut self, base_dir: &Path) -> Result<(), SceneFileError> {
+        self.textures = self
+            .texture_paths
+            .iter()
+            .map(|path| {
+                let path = base_dir.join(path);
+                Texture::load(&path).map(Arc::new).map_err(|e| SceneFileError::Texture(path, e))
+            })
+            .collect::<Result<_, _>>()?;
+
+        for import in &mut self.imports {
+            let path = base_dir.join(&import.path);
+            match import.kind {
+                ImportKind::Gltf => {
+                    let scene =
+                        GltfScene::load(&path, &mut self.textures).map_err(|e| SceneFileError::Gltf(path, e))?;
+                    import.objects = scene.objects.into_iter().map(Arc::from).collect();
+                    import.lights = scene
+                        .lights
+                        .into_iter()
+                        .map(|light| Light {
+                            position: import.transform.matrix.transform_point(light.position),
+                            ..light
+                        })
+                        .collect();
+                }
+                ImportKind::Ply(material) => {
+                    let mesh = load_ply(&path, material).map_err(|e| SceneFileError::Mesh(path, e))?;
+                    import.objects = vec![Arc::new(mesh)];
+                }
+                ImportKind::Stl(material) => {
+                    let mesh = load_stl(&path, material).map_err(|e| SceneFileError::Mesh(path, e))?;
+                    import.objects = vec![Arc::new(mesh)];
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The scene as it stands at `frame`, which may be fractional, with the
+    /// rig and animations applied and removed
+    pub fn evaluate(&self, frame: f64) -> SceneDescription {
+        let mut still = SceneDescription {
+            rig: None,
+            animations: Vec::new(),
+            ..self.clone()
+        };
+        if let Some(rig) = &self.rig {
+            still.camera = rig.pose(&self.camera, frame, self.frames);
+        }
+        let mut camera_rotation = None;
+
+        for animation in &self.animations {
+            match animation {
+                Animation::CameraPosition(track) => still.camera.position = sample(track, frame),
+                Animation::CameraTarget(track) => still.camera.target = sample(track, frame),
+                Animation::CameraRotation(track) => camera_rotation = Some(rotation(track, frame)),
+                Animation::CameraFov(track) => still.camera.fov = sample(track, frame),
+                Animation::CameraRoll(track) => still.camera.roll = sample(track, frame),
+                Animation::LightPosition(index, track) => still.lights[*index].1.position = sample(track, frame),
+                Animation::LightIntensity(index, track) => still.lights[*index].1.intensity = sample(track, frame),
+                Animation::ObjectPosition(index, track) => {
+                    still.objects[*index].1.set_position(sample(track, frame));
+                }
+                Animation::ObjectRotation(index, track) => {
+                    still.objects[*index].1.rotate(&self.objects[*index].1, rotation(track, frame));
+                }
+                Animation::ObjectScale(index, track) => {
+                    still.objects[*index].1.scale(&self.objects[*index].1, sample(track, frame));
+                }
+                Animation::ObjectColor(index, track) => {
+                    still.objects[*index].1.material_mut().color = sample(track, frame);
+                }
+            }
+        }
+
+        // After the position tracks, so the camera turns about where it is
+        if let Some(rotation) = camera_rotation {
+            let direction = self.camera.target - self.camera.position;
+            still.camera.target = still.camera.position + rotation.rotate(direction);
+        }
+        still
+    }
+
+    /// For each object of `to_scene`, whether an animation moves it. Colour
+    /// changes don't count, nor do imported models, which never move.
+    pub fn moving_objects(&self) -> Vec<bool> {
+        let mut moving = vec![false; self.objects.len()];
+        for animation in &self.animations {
+            match animation {
+                Animation::ObjectPosition(index, _) | Animation::ObjectRotation(index, _)
+                | Animation::ObjectScale(index, _) => moving[*index] = true,
+                _ => {}
+            }
+        }
+        moving
+    }
+
+    /// Camera for an image with the given aspect ratio
+    pub fn camera(&self, aspect_ratio: f64) -> Camera {
+        Camera {
+            aspect_ratio,
+            ..self.camera
+        }
+    }
+
+    /// CPU scene with the objects in file order, ignoring the animations
+    pub fn to_scene(&self) -> Scene {
+        Scene {
+            background_color: self.background,
+            objects: self
+                .objects
+                .iter()
+                .map(|(_, object)| object.boxed())
+                .chain(self.imports.iter().flat_map(|import| {
+                    import.objects.iter().map(|object| -> Box<dyn Hittable> {
+                        Box::new(Instance::new(object.clone(), import.transform))
+                    })
+                }))
+                .collect(),
+            lights: self
+                .lights
+                .iter()
+                .map(|(_, light)| *light)
+                .chain(self.imports.iter().flat_map(|import| import.lights.iter().copied()))
+                .collect(),
+            fog: self.fog,
+            volumes: self
+                .volumes
+                .iter()
+                .map(|volume| Volume::new(volume.boundary.boxed(), volume.density, volume.albedo, volume.phase))
+                .collect(),
+            textures: self.textures.clone(),
+        }
+    }
+
+    pub fn gpu_spheres(&self) -> Vec<SphereData> {
+        self.objects
+            .iter()
+            .filter_map(|(_, object)| match object {
+                SceneObject::Sphere(sphere) => {
+                    Some(((to_f32(sphere.center), sphere.radius as f32), material_data(&sphere.material)))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn gpu_planes(&self) -> Vec<PlaneData> {
+        self.objects
+            .iter()
+            .filter_map(|(_, object)| match object {
+                SceneObject::Plane(plane) => {
+                    Some(((to_f32(plane.point), to_f32(plane.normal)), material_data(&plane.material)))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Everything but the spheres and planes, for the GPU's shapes buffer
+    pub fn gpu_shapes(&self) -> Vec<ShapeData> {
+        self.objects
+            .iter()
+            .filter_map(|(_, object)| match object {
+                SceneObject::Sphere(_) | SceneObject::Plane(_) => None,
+                SceneObject::Box(shape) => Some(shape.shape_data()),
+                SceneObject::Cylinder(shape) => Some(shape.shape_data()),
+                SceneObject::Cone(shape) => Some(shape.shape_data()),
+                SceneObject::Disk(shape) => Some(shape.shape_data()),
+                SceneObject::Rectangle(shape) => Some(shape.shape_data()),
+                SceneObject::Torus(shape) => Some(shape.shape_data()),
+            })
+            .collect()
+    }
+
+    pub fn gpu_lights(&self) -> Vec<([f32; 3], f32)> {
+        self.lights
+            .iter()
+            .map(|(_, light)| (to_f32(light.position), light.intensity as f32))
+            .collect()
+    }
+
+    pub fn gpu_background(&self) -> [f32; 3] {
+        [self.background.r as f32, self.background.g as f32, self.background.b as f32]
+    }
+
+    fn find(&self, name: &str) -> Option<Target> {
+        if name == "camera" {
+            return Some(Target::Camera);
+        }
+        if let Some(index) = self.lights.iter().position(|(light, _)| light == name) {
+            return Some(Target::Light(index));
+        }
+        self.objects.iter().position(|(object, _)| object == name).map(Target::Object)
+    }
+}
+
+#[inline]
+fn sample<T: crate::animation::Animatable>(track: &Track<T>, frame: f64) -> T {
+    track.sample(frame).expect("scene file tracks have keys")
+}
+
+#[inline]
+fn rotation(track: &Track<Quaternion>, frame: f64) -> Quaternion {
+    track.sample_rotation(frame).expect("scene file tracks have keys")
+}
+
+#[inline]
+fn euler(angles: Vector3D) -> Quaternion {
+    Quaternion::from_euler(angles.x, angles.y, angles.z)
+}
+
+#[inline]
+fn to_f32(v: Vector3D) -> [f32; 3] {
+    [v.x as f32, v.y as f32, v.z as f32]
+}
+
+fn material_data(material: &Material) -> MaterialData {
+    let color = material.color;
+    (
+        [color.r as f32, color.g as f32, color.b as f32],
+        material.diffuse as f32,
+        material.specular as f32,
+        material.shininess as f32,
+        material.reflectivity as f32,
+    )
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Target {
+    Camera,
+    Light(usize),
+    Object(usize),
+}
+
+/// An animated property while its `key` lines are read
+#[derive(Debug, Clone, Copy)]
+enum Channel {
+    CameraPosition,
+    CameraTarget,
+    CameraRotation,
+    CameraFov,
+    CameraRoll,
+    LightPosition(usize),
+    LightIntensity(usize),
+    ObjectPosition(usize),
+    ObjectRotation(usize),
+    ObjectScale(usize),
+    ObjectColor(usize),
+    /// Distance track of a `CameraRig::Dolly`
+    CameraDolly { target: Vector3D, zoom: bool },
+    /// Points of a `CameraRig::Path`
+    CameraPath(PathOrientation),
+}
+
+impl Channel {
+    fn new(target: Target, name: &str) -> Option<Channel> {
+        Some(match (target, name) {
+            (Target::Camera, "position") => Channel::CameraPosition,
+            (Target::Camera, "target") => Channel::CameraTarget,
+            (Target::Camera, "rotation") => Channel::CameraRotation,
+            (Target::Camera, "fov") => Channel::CameraFov,
+            (Target::Camera, "roll") => Channel::CameraRoll,
+            (Target::Light(index), "position") => Channel::LightPosition(index),
+            (Target::Light(index), "intensity") => Channel::LightIntensity(index),
+            (Target::Object(index), "position") => Channel::ObjectPosition(index),
+            (Target::Object(index), "rotation") => Channel::ObjectRotation(index),
+            (Target::Object(index), "scale") => Channel::ObjectScale(index),
+            (Target::Object(index), "color") => Channel::ObjectColor(index),
+            _ => return None,
+        })
+    }
+
+    /// Numbers in each key's value
+    fn dimensions(self) -> usize {
+        match self {
+            Channel::CameraFov
+            | Channel::CameraRoll
+            | Channel::LightIntensity(_)
+            | Channel::ObjectScale(_)
+            | Channel::CameraDolly { .. } => 1,
+            _ => 3,
+        }
+    }
+
+    /// Adds the finished track to `description`
+    fn add_to(self, keys: &[(f64, [f64; 3])], interpolation: Interpolation, description: &mut SceneDescription) {
+        let scalar = || {
+            let keys = keys.iter().map(|&(time, [value, ..])| Keyframe { time, value }).collect();
+            Track::new(keys, interpolation)
+        };
+        let vector = || {
+            let keys = keys
+                .iter()
+                .map(|&(time, [x, y, z])| Keyframe {
+                    time,
+                    value: Vector3D::new(x, y, z),
+                })
+                .collect();
+            Track::new(keys, interpolation)
+        };
+        let rotation = || {
+            let keys = keys
+                .iter()
+                .map(|&(time, [x, y, z])| Keyframe {
+                    time,
+                    value: Quaternion::from_euler(x, y, z),
+                })
+                .collect();
+            Track::rotation(keys, interpolation)
+        };
+
+        let animation = match self {
+            Channel::CameraDolly { target, zoom } => {
+                description.rig = Some(CameraRig::Dolly {
+                    target,
+                    distance: scalar(),
+                    zoom,
+                });
+                return;
+            }
+            Channel::CameraPath(orientation) => {
+                description.rig = Some(CameraRig::Path {
+                    points: vector(),
+                    orientation,
+                });
+                return;
+            }
+            Channel::CameraPosition => Animation::CameraPosition(vector()),
+            Channel::CameraTarget => Animation::CameraTarget(vector()),
+            Channel::CameraRotation => Animation::CameraRotation(rotation()),
+            Channel::CameraFov => Animation::CameraFov(scalar()),
+            Channel::CameraRoll => Animation::CameraRoll(scalar()),
+            Channel::LightPosition(index) => Animation::LightPosition(index, vector()),
+            Channel::LightIntensity(index) => Animation::LightIntensity(index, scalar()),
+            Channel::ObjectPosition(index) => Animation::ObjectPosition(index, vector()),
+            Channel::ObjectRotation(index) => Animation::ObjectRotation(index, rotation()),
+            Channel::ObjectScale(index) => Animation::ObjectScale(index, scalar()),
+            Channel::ObjectColor(index) => {
+                let keys = keys
+                    .iter()
+                    .map(|&(time, [r, g, b])| Keyframe {
+                        time,
+                        value: Color { r, g, b },
+                    })
+                    .collect();
+                Animation::ObjectColor(index, Track::new(keys, interpolation))
+            }
+        };
+        description.animations.push(animation);
+    }
+}
+
+/// `animate` or keyed `rig` block being read
+struct PendingTrack {
+    line: usize,
+    channel: Channel,
+    interpolation: Interpolation,
+    keys: Vec<(f64, [f64; 3])>,
+}
+
+impl PendingTrack {
+    fn finish(self, description: &mut SceneDescription) -> Result<(), SceneFileError> {
+        if self.keys.is_empty() {
+            return Err(SceneFileError::Parse {
+                line: self.line,
+                message: "animation has no keys".to_string(),
+            });
+        }
+        self.channel.add_to(&self.keys, self.interpolation, description);
+        Ok(())
+    }
+}
+
+/// Words of one line, consumed left to right
+struct Words<'a, 't> {
+    words: Vec<&'a str>,
+    next: usize,
+    line: usize,
+    /// Image paths read so far, numbered by `TextureId`
+    textures: &'t mut Vec<PathBuf>,
+}
+
+impl<'a> Words<'a, '_> {
+    fn error(&self, message: impl Into<String>) -> SceneFileError {
+        SceneFileError::Parse {
+            line: self.line,
+            message: message.into(),
+        }
+    }
+
+    fn next(&mut self) -> Option<&'a str> {
+        let word = self.words.get(self.next).copied();
+        self.next += 1;
+        word
+    }
+
+    fn name(&mut self, what: &str) -> Result<&'a str, SceneFileError> {
+        self.next().ok_or_else(|| self.error(format!("expected {}", what)))
+    }
+
+    fn number(&mut self, what: &str) -> Result<f64, SceneFileError> {
+        let word = self.name(what)?;
+        word.parse::<f64>()
+            .ok()
+            .filter(|value| value.is_finite())
+            .ok_or_else(|| self.error(format!("expected {}, found '{}'", what, word)))
+    }
+
+    fn vector(&mut self, what: &str) -> Result<Vector3D, SceneFileError> {
+        Ok(Vector3D::new(self.number(what)?, self.number(what)?, self.number(what)?))
+    }
+
+    fn color(&mut self, what: &str) -> Result<Color, SceneFileError> {
+        let v = self.vector(what)?;
+        Ok(Color { r: v.x, g: v.y, b: v.z })
+    }
+
+    /// Reads a material property named `property`, returning whether it was one
+    fn material(&mut self, property: &str, material: &mut Material) -> Result<bool, SceneFileError> {
+        match property {
+            "color" => {
+                material.color = self.color("a colour")?;
+                if let Some(pbr) = &mut material.pbr {
+                    pbr.base_color = material.color;
+                }
+            }
+            "diffuse" => material.diffuse = self.number("a diffuse weight")?,
+            "specular" => material.specular = self.number("a specular weight")?,
+            "shininess" => material.shininess = self.number("a shininess exponent")?,
+            "reflectivity" => material.reflectivity = self.number("a reflectivity")?,
+            "emission" => material.emission = self.color("an emission colour")?,
+            "normal_map" => {
+                let texture = self.texture("a normal map image")?;
+                let strength = match self.peek() {
+                    Some(word) if word.parse::<f64>().is_ok() => self.number("a strength")?,
+                    _ => 1.0,
+                };
+                material.normal_map = Some(NormalMap::Tangent { texture, strength });
+            }
+            "bump_map" => {
+                let texture = self.texture("a height map image")?;
+                let scale = self.number("a bump height")?;
+                material.normal_map = Some(NormalMap::Bump { texture, scale });
+            }
+            "metallic" | "roughness" => {
+                let value = self.number(if property == "metallic" { "a metalness" } else { "a roughness" })?;
+                if !(0.0..=1.0).contains(&value) {
+                    return Err(self.error(format!("the {} must be between 0 and 1", property)));
+                }
+                let pbr = material.pbr.get_or_insert(PbrMaterial::new(material.color, 0.0, 0.5));
+                if property == "metallic" {
+                    pbr.metallic = value;
+                } else {
+                    pbr.roughness = value;
+                }
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Reads the path of an image, numbering each file the first time it's
+    /// used
+    fn texture(&mut self, what: &str) -> Result<TextureId, SceneFileError> {
+        let path = Path::new(self.name(what)?);
+        let index = match self.textures.iter().position(|known| known == path) {
+            Some(index) => index,
+            None => {
+                self.textures.push(path.to_path_buf());
+                self.textures.len() - 1
+            }
+        };
+        Ok(TextureId(index))
+    }
+
+    /// Reads the interpolation named `mode`, with a `bezier`'s control
+    /// points if numbers follow it
+    fn interpolation(&mut self, mode: &str) -> Result<Interpolation, SceneFileError> {
+        match Interpolation::from_name(mode) {
+            Some(Interpolation::Bezier { .. }) if self.peek().is_some_and(|word| word.parse::<f64>().is_ok()) => {
+                Ok(Interpolation::Bezier {
+                    x1: self.number("a control point")?,
+                    y1: self.number("a control point")?,
+                    x2: self.number("a control point")?,
+                    y2: self.number("a control point")?,
+                })
+            }
+            Some(interpolation) => Ok(interpolation),
+            None => Err(self.error(format!(
+                "unknown interpolation '{}' (expected step, linear, bezier or catmull-rom)",
+                mode
+            ))),
+        }
+    }
+
+    fn peek(&self) -> Option<&'a str> {
+        self.words.get(self.next).copied()
+    }
+
+    fn unknown(&self, property: &str, of: &str) -> SceneFileError {
+        self.error(format!("unknown {} property '{}'", of, property))
+    }
+}
+
+impl FromStr for SceneDescription {
+    type Err = SceneFileError;
+
+    fn from_str(source: &str) -> Result<SceneDescription, SceneFileError> {
+        let mut description = SceneDescription::default();
+        let mut pending: Option<PendingTrack> = None;
+        let mut texture_paths = Vec::new();
+
+        for (index, text) in source.lines().enumerate() {
+            let text = text.split('#').next().unwrap_or("");
+            let mut words = Words {
+                words: text.split_whitespace().collect(),
+                next: 0,
+                line: index + 1,
+                textures: &mut texture_paths,
+            };
+            let Some(keyword) = words.next() else {
+                continue;
+            };
+
+            if keyword == "key" {
+                let Some(track) = &mut pending else {
+                    return Err(words.error("key outside an animate block"));
+                };
+                let time = words.number("a frame number")?;
+                let mut value = [0.0; 3];
+                for component in value.iter_mut().take(track.channel.dimensions()) {
+                    *component = words.number("a key value")?;
+                }
+                track.keys.push((time, value));
+            } else if let Some(track) = pending.take() {
+                track.finish(&mut description)?;
+            }
+
+            match keyword {
+                "key" => {}
+                "background" => description.background = words.color("a background colour")?,
+                "frames" => {
+                    let frames = words.number("a frame count")?;
+                    if frames < 1.0 || frames.fract() != 0.0 {
+                        return Err(words.error("the frame count must be a positive whole number"));
+                    }
+                    description.frames = frames as usize;
+                }
+                "camera" => {
+                    while let Some(property) = words.next() {
+                        let camera = &mut description.camera;
+                        match property {
+                            "position" => camera.position = words.vector("a camera position")?,
+                            "target" => camera.target = words.vector("a camera target")?,
+                            "fov" => camera.fov = words.number("a field of view in degrees")?,
+                            "up" => camera.up = words.vector("an up direction")?,
+                            "roll" => camera.roll = words.number("a roll angle in degrees")?,
+                            "shift" => {
+                                camera.shift_x = words.number("a horizontal lens shift")?;
+                                camera.shift_y = words.number("a vertical lens shift")?;
+                            }
+                            "projection" => {
+                                let name = words.name("a projection")?;
+                                camera.projection = Projection::from_name(name).ok_or_else(|| {
+                                    words.error(format!(
+                                        "unknown projection '{}' (expected perspective, orthographic, fisheye, \
+                                         fisheye-equisolid or equirectangular)",
+                                        name
+                                    ))
+                                })?;
+                            }
+                            "height" => match &mut camera.projection {
+                                Projection::Orthographic { height } => *height = words.number("a view height")?,
+                                _ => return Err(words.error("only orthographic cameras take a height")),
+                            },
+                            _ => return Err(words.unknown(property, "camera")),
+                        }
+                    }
+                }
+                "light" => {
+                    let name = new_name(&description, &mut words)?;
+                    let mut light = Light {
+                        position: Vector3D::ZERO,
+                        intensity: 1.0,
+                    };
+                    while let Some(property) = words.next() {
+                        match property {
+                            "position" => light.position = words.vector("a light position")?,
+                            "intensity" => light.intensity = words.number("a light intensity")?,
+                            _ => return Err(words.unknown(property, "light")),
+                        }
+                    }
+                    description.lights.push((name, light));
+                }
+                "plane" => {
+                    let name = new_name(&description, &mut words)?;
+                    let mut point = Vector3D::ZERO;
+                    let mut normal = Vector3D::new(0.0, 1.0, 0.0);
+                    let mut material = DEFAULT_MATERIAL;
+                    while let Some(property) = words.next() {
+                        match property {
+                            "point" => point = words.vector("a point on the plane")?,
+                            "normal" => normal = words.vector("a plane normal")?,
+                            _ if words.material(property, &mut material)? => {}
+                            _ => return Err(words.unknown(property, "plane")),
+                        }
+                    }
+                    if normal.magnitude_squared() == 0.0 {
+                        return Err(words.error("the plane normal must not be zero"));
+                    }
+                    description.objects.push((name, SceneObject::Plane(Plane::new(point, normal, material))));
+                }
+                "sphere" | "box" | "cylinder" | "cone" | "disk" | "rectangle" | "torus" => {
+                    let name = new_name(&description, &mut words)?;
+                    let shape = read_shape(keyword, &mut words, true)?;
+                    description.objects.push((name, shape));
+                }
+                "animate" => {
+                    let name = words.name("the camera or a light or object name")?;
+                    let target = description
+                        .find(name)
+                        .ok_or_else(|| words.error(format!("nothing named '{}' has been defined", name)))?;
+                    let channel_name = words.name("a channel")?;
+                    let channel = Channel::new(target, channel_name)
+                        .ok_or_else(|| words.error(format!("'{}' has no channel '{}'", name, channel_name)))?;
+                    match channel {
+                        Channel::ObjectRotation(index) if !description.objects[index].1.rotates() => {
+                            return Err(words.error(format!(
+                                "'{}' is a {}, which can't be rotated; rotation tracks are for planes and shapes",
+                                name,
+                                description.objects[index].1.kind()
+                            )));
+                        }
+                        Channel::ObjectScale(index) if !description.objects[index].1.scales() => {
+                            return Err(words.error(format!(
+                                "'{}' is a {}, which can't be scaled; scale tracks are for spheres and shapes",
+                                name,
+                                description.objects[index].1.kind()
+                            )));
+                        }
+                        _ => {}
+                    }
+
+                    let interpolation = match words.next() {
+                        Some(mode) => words.interpolation(mode)?,
+                        None => Interpolation::Linear,
+                    };
+                    pending = Some(PendingTrack {
+                        line: words.line,
+                        channel,
+                        interpolation,
+                        keys: Vec::new(),
+                    });
+                }
+                "rig" => {
+                    if description.rig.is_some() {
+                        return Err(words.error("the camera already has a rig"));
+                    }
+                    match words.name("orbit, dolly or path")? {
+                        "orbit" => {
+                            let mut target = description.camera.target;
+                            let (mut radius, mut height, mut start_angle, mut turns) = (None, 0.0, 0.0, 1.0);
+                            while let Some(property) = words.next() {
+                                match property {
+                                    "target" => target = words.vector("an orbit target")?,
+                                    "radius" => radius = Some(words.number("an orbit radius")?),
+                                    "height" => height = words.number("a height above the target")?,
+                                    "start" => start_angle = words.number("a start angle in degrees")?,
+                                    "turns" => turns = words.number("a number of turns")?,
+                                    _ => return Err(words.unknown(property, "orbit")),
+                                }
+                            }
+                            let radius = radius.ok_or_else(|| words.error("the orbit needs a radius"))?;
+                            description.rig = Some(CameraRig::Orbit {
+                                target,
+                                radius,
+                                height,
+                                start_angle,
+                                turns,
+                            });
+                        }
+                        "dolly" => {
+                            let mut target = description.camera.target;
+                            let mut zoom = false;
+                            let mut interpolation = Interpolation::Linear;
+                            while let Some(property) = words.next() {
+                                match property {
+                                    "target" => target = words.vector("a dolly target")?,
+                                    "zoom" => zoom = true,
+                                    mode => interpolation = words.interpolation(mode)?,
+                                }
+                            }
+                            pending = Some(PendingTrack {
+                                line: words.line,
+                                channel: Channel::CameraDolly { target, zoom },
+                                interpolation,
+                                keys: Vec::new(),
+                            });
+                        }
+                        "path" => {
+                            let orientation = match words.name("look-at or tangent")? {
+                                "look-at" => PathOrientation::LookAt(words.vector("a point to look at")?),
+                                "tangent" => PathOrientation::Tangent,
+                                other => return Err(words.error(format!("expected look-at or tangent, found '{}'", other))),
+                            };
+                            let interpolation = match words.next() {
+                                Some(mode) => words.interpolation(mode)?,
+                                None => Interpolation::CatmullRom,
+                            };
+                            pending = Some(PendingTrack {
+                                line: words.line,
+                                channel: Channel::CameraPath(orientation),
+                                interpolation,
+                                keys: Vec::new(),
+                            });
+                        }
+                        other => return Err(words.error(format!("unknown camera rig '{}'", other))),
+                    }
+                }
+                "fog" => {
+                    let (mut color, mut density) = (None, None);
+                    while let Some(property) = words.next() {
+                        match property {
+                            "color" => color = Some(words.color("a fog colour")?),
+                            "density" => density = Some(words.number("a fog density")?),
+                            _ => return Err(words.unknown(property, "fog")),
+                        }
+                    }
+                    let (Some(color), Some(density)) = (color, density) else {
+                        return Err(words.error("fog needs a color and a density"));
+                    };
+                    description.fog = Some(Fog::new(color, density));
+                }
+                "volume" => {
+                    let shape = words.name("a sphere, box, cylinder, cone or torus")?;
+                    if !matches!(shape, "sphere" | "box" | "cylinder" | "cone" | "torus") {
+                        return Err(words.error(format!(
+                            "a volume must be a sphere, box, cylinder, cone or torus, not '{}'",
+                            shape
+                        )));
+                    }
+                    let mut volume = SceneVolume {
+                        boundary: SceneObject::Sphere(Sphere::new(Vector3D::ZERO, 1.0, DEFAULT_MATERIAL)),
+                        density: 1.0,
+                        albedo: Color { r: 1.0, g: 1.0, b: 1.0 },
+                        phase: PhaseFunction::Isotropic,
+                    };
+                    // The medium's own properties; the rest describe the shape
+                    let mut rest = Vec::new();
+                    while let Some(property) = words.next() {
+                        match property {
+                            "density" => volume.density = words.number("a density")?,
+                            "albedo" => volume.albedo = words.color("an albedo")?,
+                            "anisotropy" => {
+                                let g = words.number("an anisotropy")?;
+                                if g <= -1.0 || g >= 1.0 {
+                                    return Err(words.error("the anisotropy must be between -1 and 1"));
+                                }
+                                volume.phase = PhaseFunction::HenyeyGreenstein(g);
+                            }
+                            _ => rest.push(property),
+                        }
+                    }
+                    let mut shape_words = Words {
+                        words: rest,
+                        next: 0,
+                        line: words.line,
+                        textures: &mut *words.textures,
+                    };
+                    volume.boundary = read_shape(shape, &mut shape_words, false)?;
+                    description.volumes.push(volume);
+                }
+                "gltf" => {
+                    let path = PathBuf::from(words.name("a file path")?);
+                    let transform = read_placement(keyword, &mut words, None)?;
+                    description.imports.push(Import {
+                        path,
+                        kind: ImportKind::Gltf,
+                        transform,
+                        objects: Vec::new(),
+                        lights: Vec::new(),
+                    });
+                }
+                "mesh" => {
+                    let path = PathBuf::from(words.name("a file path")?);
+                    let extension = path.extension().and_then(|e| e.to_str()).map(str::to_ascii_lowercase);
+                    let mut material = DEFAULT_MATERIAL;
+                    let transform = read_placement(keyword, &mut words, Some(&mut material))?;
+                    let kind = match extension.as_deref() {
+                        Some("ply") => ImportKind::Ply(material),
+                        Some("stl") => ImportKind::Stl(material),
+                        _ => return Err(words.error(format!("'{}' is not a .ply or .stl file", path.display()))),
+                    };
+                    description.imports.push(Import {
+                        path,
+                        kind,
+                        transform,
+                        objects: Vec::new(),
+                        lights: Vec::new(),
+                    });
+                }
+                _ => return Err(words.error(format!("unknown keyword '{}'", keyword))),
+            }
+
+            if let Some(extra) = words.next() {
+                return Err(words.error(format!("unexpected '{}'", extra)));
+            }
+        }
+
+        if let Some(track) = pending {
+            track.finish(&mut description)?;
+        }
+        description.texture_paths = texture_paths;
+        Ok(description)
+    }
+}
+
+/// Reads the name of a new light or object, which must be unique
+fn new_name(description: &SceneDescription, words: &mut Words) -> Result<String, SceneFileError> {
+    let name = words.name("a name")?;
+    if description.find(name).is_some() {
+        return Err(words.error(format!("'{}' is already defined", name)));
+    }
+    if name.parse::<f64>().is_ok() {
+        return Err(words.error(format!("expected a name, found '{}'", name)));
+    }
+    Ok(name.to_string())
+}
+
+/// Reads the `position`, `rotation` and `scale` of an imported model, and
+/// its material properties if it takes a `material`
+fn read_placement(
+    keyword: &str,
+    words: &mut Words,
+    mut material: Option<&mut Material>,
+) -> Result<Transform, SceneFileError> {
+    let mut position = Vector3D::ZERO;
+    let mut rotation = Vector3D::ZERO;
+    let mut scale = 1.0;
+    while let Some(property) = words.next() {
+        match property {
+            "position" => position = words.vector("a position")?,
+            "rotation" => rotation = words.vector("Euler angles in degrees")?,
+            "scale" => scale = words.number("a scale factor")?,
+            _ => {
+                let known = match material.as_deref_mut() {
+                    Some(material) => words.material(property, material)?,
+                    None => false,
+                };
+                if !known {
+                    return Err(words.unknown(property, keyword));
+                }
+            }
+        }
+    }
+    if scale == 0.0 {
+        return Err(words.error(format!("the {} scale must not be zero", keyword)));
+    }
+    Ok(Transform::from_trs(position, euler(rotation), Vector3D::splat(scale)))
+}
+
+/// Reads the properties of a `sphere`, `box`, `cylinder`, `cone`, `disk`,
+/// `rectangle` or `torus` line after its name, including a material unless
+/// the shape only bounds a volume
+fn read_shape(keyword: &str, words: &mut Words, has_material: bool) -> Result<SceneObject, SceneFileError> {
+    let mut material = DEFAULT_MATERIAL;
+    let mut origin = Vector3D::ZERO;
+    let mut end = Vector3D::new(0.0, 1.0, 0.0);
+    let mut axis = Vector3D::new(0.0, 1.0, 0.0);
+    let mut size = Vector3D::new(1.0, 1.0, 1.0);
+    let mut rotation = Vector3D::ZERO;
+    let mut radius = 1.0;
+    let mut minor_radius = 0.25;
+    let (mut edge_u, mut edge_v) = (Vector3D::new(1.0, 0.0, 0.0), Vector3D::new(0.0, 0.0, -1.0));
+
+    while let Some(property) = words.next() {
+        match (keyword, property) {
+            ("sphere" | "box" | "disk" | "torus", "center") => origin = words.vector("a centre")?,
+            ("cylinder" | "cone", "base") => origin = words.vector("the centre of the base")?,
+            ("cylinder", "top") => end = words.vector("the centre of the top")?,
+            ("cone", "apex") => end = words.vector("an apex")?,
+            ("rectangle", "corner") => origin = words.vector("a corner")?,
+            ("rectangle", "edge-u") => edge_u = words.vector("an edge")?,
+            ("rectangle", "edge-v") => edge_v = words.vector("an edge")?,
+            ("box", "size") => size = words.vector("the box's size")?,
+            ("box", "rotation") => rotation = words.vector("Euler angles in degrees")?,
+            ("disk", "normal") => axis = words.vector("a normal")?,
+            ("torus", "axis") => axis = words.vector("an axis")?,
+            ("sphere" | "cylinder" | "cone" | "disk", "radius") => radius = words.number("a radius")?,
+            ("torus", "radii") => {
+                radius = words.number("the ring's radius")?;
+                minor_radius = words.number("the tube's radius")?;
+            }
+            _ if has_material && words.material(property, &mut material)? => {}
+            _ => return Err(words.unknown(property, keyword)),
+        }
+    }
+
+    let degenerate = |v: Vector3D, what: &str| {
+        if v.magnitude_squared() == 0.0 {
+            Err(words.error(format!("the {} {} must not be zero", keyword, what)))
+        } else {
+            Ok(())
+        }
+    };
+    Ok(match keyword {
+        "sphere" => SceneObject::Sphere(Sphere::new(origin, radius, material)),
+        "box" => SceneObject::Box(OrientedBox::new(origin, size * 0.5, euler(rotation), material)),
+        "cylinder" => {
+            degenerate(end - origin, "height")?;
+            SceneObject::Cylinder(Cylinder::new(origin, end, radius, material))
+        }
+        "cone" => {
+            degenerate(end - origin, "height")?;
+            SceneObject::Cone(Cone::new(origin, end, radius, material))
+        }
+        "disk" => {
+            degenerate(axis, "normal")?;
+            SceneObject::Disk(Disk::new(origin, axis, radius, material))
+        }
+        "rectangle" => {
+            degenerate(edge_u.cross(edge_v), "area")?;
+            SceneObject::Rectangle(Rectangle::new(origin, edge_u, edge_v, material))
+        }
+        _ => {
+            degenerate(axis, "axis")?;
+            SceneObject::Torus(Torus::new(origin, axis, radius, minor_radius, material))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OBJECTS: &str = "\
+light key position 3 2 5
+sphere ball center 0 0 5 radius 1
+plane floor point 0 -1 0 normal 0 1 0
+box crate center 1 0 4 size 2 2 2
+";
+
+    fn parse(animation: &str) -> SceneDescription {
+        let source = format!("{}{}", OBJECTS, animation);
+        source.parse().unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Line, counted within `source`, and message of the error it causes
+    fn parse_error(source: &str) -> (usize, String) {
+        match format!("{}{}", OBJECTS, source).parse::<SceneDescription>() {
+            Err(SceneFileError::Parse { line, message }) => (line - OBJECTS.lines().count(), message),
+            other => panic!("expected a parse error from {:?}, got {:?}", source, other.map(|_| ())),
+        }
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-6, "{} != {}", actual, expected);
+    }
+
+    fn intensity(description: &SceneDescription, frame: f64) -> f64 {
+        description.evaluate(frame).lights[0].1.intensity
+    }
+
+    #[test]
+    fn step_tracks_hold_each_key() {
+        let description = parse("animate key intensity step\nkey 0 1\nkey 10 3\nkey 20 2\n");
+        for (frame, expected) in [(-5.0, 1.0), (0.0, 1.0), (9.9, 1.0), (10.0, 3.0), (15.0, 3.0), (20.0, 2.0), (30.0, 2.0)] {
+            assert_close(intensity(&description, frame), expected);
+        }
+    }
+
+    #[test]
+    fn linear_tracks_move_at_constant_speed() {
+        let description = parse("animate ball position linear\nkey 0 0 0 5\nkey 10 10 0 5\nkey 20 10 4 5\n");
+        let center = |frame| match description.evaluate(frame).objects[0].1 {
+            SceneObject::Sphere(sphere) => sphere.center,
+            other => panic!("expected a sphere, got {:?}", other),
+        };
+        assert_eq!(center(0.0), Vector3D::new(0.0, 0.0, 5.0));
+        assert_eq!(center(2.5), Vector3D::new(2.5, 0.0, 5.0));
+        assert_eq!(center(10.0), Vector3D::new(10.0, 0.0, 5.0));
+        assert_eq!(center(15.0), Vector3D::new(10.0, 2.0, 5.0));
+        assert_eq!(center(25.0), Vector3D::new(10.0, 4.0, 5.0));
+    }
+
+    #[test]
+    fn bezier_tracks_ease_between_keys() {
+        let fov = |description: &SceneDescription, frame| description.evaluate(frame).camera.fov;
+
+        let eased = parse("animate camera fov bezier 0.42 0 0.58 1\nkey 0 60\nkey 10 40\n");
+        assert_close(fov(&eased, 0.0), 60.0);
+        assert_close(fov(&eased, 10.0), 40.0);
+        // The curve is symmetric, so halfway in time is halfway in value,
+        // and it starts slower than a straight line
+        assert_close(fov(&eased, 5.0), 50.0);
+        assert!(fov(&eased, 2.5) > 55.0);
+        assert!(fov(&eased, 7.5) < 45.0);
+
+        // Without control points `bezier` is the standard ease-in-out
+        let default = parse("animate camera fov bezier\nkey 0 60\nkey 10 40\n");
+        assert_close(fov(&default, 2.5), fov(&eased, 2.5));
+
+        // Control points on the diagonal make it linear again
+        let straight = parse("animate camera fov bezier 0.25 0.25 0.75 0.75\nkey 0 60\nkey 10 40\n");
+        assert_close(fov(&straight, 2.5), 55.0);
+        assert_close(fov(&straight, 7.0), 46.0);
+    }
+
+    #[test]
+    fn catmull_rom_tracks_pass_through_keys_smoothly() {
+        let roll = |description: &SceneDescription, frame| description.evaluate(frame).camera.roll;
+
+        let peak = parse("animate camera roll catmull-rom\nkey 0 0\nkey 10 10\nkey 20 0\n");
+        assert_close(roll(&peak, 0.0), 0.0);
+        assert_close(roll(&peak, 10.0), 10.0);
+        assert_close(roll(&peak, 20.0), 0.0);
+        // Hermite segment with tangents of 1 per frame at the first key and
+        // 0 at the peak
+        assert_close(roll(&peak, 5.0), 6.25);
+        assert_close(roll(&peak, 15.0), 6.25);
+
+        // Evenly spaced keys on a line reproduce the line
+        let line = parse("animate camera roll catmull-rom\nkey 0 0\nkey 10 10\nkey 20 20\nkey 30 30\n");
+        for frame in [2.0, 5.0, 13.0, 27.5] {
+            assert_close(roll(&line, frame), frame);
+        }
+    }
+
+    #[test]
+    fn rotation_and_scale_tracks_on_shapes() {
+        let description = parse("animate crate rotation linear\nkey 0 0 0 0\nkey 10 0 90 0\nanimate crate scale\nkey 0 1\nkey 10 3\n");
+        let (frame, half_extents) = match description.evaluate(5.0).objects[2].1 {
+            SceneObject::Box(shape) => (shape.frame, shape.half_extents),
+            other => panic!("expected a box, got {:?}", other),
+        };
+        assert_eq!(frame.origin, Vector3D::new(1.0, 0.0, 4.0));
+        assert_close(frame.u.y, 0.0);
+        assert_close(frame.u.x.abs(), 0.5f64.sqrt());
+        assert_close(frame.u.z.abs(), 0.5f64.sqrt());
+        assert_close(frame.v.y, 1.0);
+        assert_eq!(half_extents, Vector3D::splat(2.0));
+    }
+
+    #[test]
+    fn only_placement_tracks_move_objects() {
+        let description = parse("animate crate scale\nkey 0 1\nkey 10 3\nanimate ball color\nkey 0 1 0 0\nkey 10 0 0 1\n");
+        assert_eq!(description.moving_objects(), [false, false, true]);
+    }
+
+    #[test]
+    fn rotation_tracks_slerp_between_keys() {
+        // Euler angles would sweep 340 degrees from 350 to 10; the slerp
+        // crosses 0 halfway through
+        let description = parse("animate crate rotation linear\nkey 0 0 350 0\nkey 10 0 10 0\n");
+        let u = |frame: f64| match description.evaluate(frame).objects[2].1 {
+            SceneObject::Box(shape) => shape.frame.u,
+            other => panic!("expected a box, got {:?}", other),
+        };
+        assert_close((u(5.0) - Vector3D::new(1.0, 0.0, 0.0)).magnitude(), 0.0);
+        assert_close(u(2.5).x, 5f64.to_radians().cos());
+
+        let camera = parse("animate camera rotation\nkey 0 0 0 0\nkey 10 90 90 0\n");
+        let halfway = Quaternion::IDENTITY.slerp(Quaternion::from_euler(90.0, 90.0, 0.0), 0.5);
+        let file = camera.camera.target - camera.camera.position;
+        let still = camera.evaluate(5.0).camera;
+        assert_close((still.target - still.position - halfway.rotate(file)).magnitude(), 0.0);
+    }
+
+    #[test]
+    fn track_parse_errors() {
+        for (source, line, message) in [
+            ("key 0 1\n", 1, "key outside an animate block"),
+            ("animate nobody position\n", 1, "nothing named 'nobody' has been defined"),
+            ("animate ball fov\n", 1, "'ball' has no channel 'fov'"),
+            ("animate key intensity wobbly\n", 1,
+             "unknown interpolation 'wobbly' (expected step, linear, bezier or catmull-rom)"),
+            ("animate camera fov bezier 0.42 0 0.58\nkey 0 60\n", 1, "expected a control point"),
+            ("animate key intensity linear\n\nsphere other\n", 1, "animation has no keys"),
+            ("animate key intensity\nkey 0 1\nkey ten 2\n", 3, "expected a frame number, found 'ten'"),
+            ("animate key position\nkey 0 1 2\n", 2, "expected a key value"),
+            ("animate key intensity\nkey 0 1 2\n", 2, "unexpected '2'"),
+            ("animate ball rotation\nkey 0 0 0 0\n", 1,
+             "'ball' is a sphere, which can't be rotated; rotation tracks are for planes and shapes"),
+            ("animate floor scale\nkey 0 1\n", 1,
+             "'floor' is a plane, which can't be scaled; scale tracks are for spheres and shapes"),
+        ] {
+            assert_eq!(parse_error(source), (line, message.to_string()), "{}", source);
+        }
+    }
+
+    #[test]
+    fn gltf_imports_are_placed_and_light_the_scene() {
+        // An emissive triangle spanning (0, 0, 0), (1, 0, 0) and (0, 1, 0)
+        let model = r#"{
+            "asset": { "version": "2.0" },
+            "scenes": [{ "nodes": [0] }],
+            "nodes": [{ "mesh": 0 }],
+            "meshes": [{ "primitives": [{ "attributes": { "POSITION": 0 }, "material": 0 }] }],
+            "materials": [{ "emissiveFactor": [1, 1, 1] }],
+            "accessors": [{
+                "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                "min": [0, 0, 0], "max": [1, 1, 0]
+            }],
+            "bufferViews": [{ "buffer": 0, "byteLength": 36 }],
+            "buffers": [{
+                "byteLength": 36,
+                "uri": "data:application/octet-stream;base64,AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAA"
+            }]
+        }"#;
+        let dir = std::env::temp_dir().join(format!("rust-ray-tracing-scene-import-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("glow.gltf"), model).unwrap();
+        std::fs::write(dir.join("test.scene"), "gltf glow.gltf position 0 0 5 rotation 0 0 90 scale 2\n").unwrap();
+        let description = SceneDescription::load(dir.join("test.scene"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let scene = description.unwrap().to_scene();
+        assert_eq!(scene.objects.len(), 1);
+        let object = &scene.objects[0];
+
+        // Turned a quarter about z, then doubled, it spans (0, 0), (0, 2)
+        // and (-2, 0)
+        let ray = |x, y| crate::ray::Ray::new(Vector3D::new(x, y, 0.0), Vector3D::new(0.0, 0.0, 1.0));
+        let hit = object.hit(&ray(-0.5, 0.5), 0.0, f64::INFINITY).unwrap();
+        assert_close(hit.t, 5.0);
+        assert!(object.hit(&ray(0.5, 0.5), 0.0, f64::INFINITY).is_none());
+
+        let sample = object.sample_emission(Vector3D::ZERO, 0.3, 0.6).unwrap();
+        assert_close(sample.point.z, 5.0);
+        assert!(sample.point.x <= 0.0 && sample.point.y >= 0.0 && sample.point.y - sample.point.x <= 2.0 + 1e-9);
+        assert!(sample.radiance.r > 0.0);
+    }
+
+    #[test]
+    fn mesh_imports_take_a_material() {
+        let ply = "ply\nformat ascii 1.0\nelement vertex 3\nproperty float x\nproperty float y\nproperty float z\n\
+                   element face 1\nproperty list uchar int vertex_indices\nend_header\n0 0 0\n1 0 0\n0 1 0\n3 0 1 2\n";
+        let stl = "solid t\nfacet normal 0 0 1\nouter loop\nvertex 0 0 0\nvertex 1 0 0\nvertex 0 1 0\n\
+                   endloop\nendfacet\nendsolid t\n";
+        let dir = std::env::temp_dir().join(format!("rust-ray-tracing-scene-mesh-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.ply"), ply).unwrap();
+        std::fs::write(dir.join("b.STL"), stl).unwrap();
+        std::fs::write(
+            dir.join("test.scene"),
+            "mesh a.ply position 0 0 5 color 1 0 0\nmesh b.STL position 0 0 7 scale 2 emission 0 1 0\n",
+        )
+        .unwrap();
+        let description = SceneDescription::load(dir.join("test.scene"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let scene = description.unwrap().to_scene();
+        assert_eq!(scene.objects.len(), 2);
+        let ray = |x, y| crate::ray::Ray::new(Vector3D::new(x, y, 0.0), Vector3D::new(0.0, 0.0, 1.0));
+
+        let ply = scene.objects[0].hit(&ray(0.25, 0.25), 0.0, f64::INFINITY).unwrap();
+        assert_close(ply.t, 5.0);
+        assert_eq!((ply.material.color.r, ply.material.color.g), (1.0, 0.0));
+
+        // Only the doubled STL triangle reaches (0.75, 0.75)
+        assert!(scene.objects[0].hit(&ray(0.75, 0.75), 0.0, f64::INFINITY).is_none());
+        let stl = scene.objects[1].hit(&ray(0.75, 0.75), 0.0, f64::INFINITY).unwrap();
+        assert_close(stl.t, 7.0);
+        assert_eq!(stl.material.emission.g, 1.0);
+        assert!(scene.objects[1].sample_emission(Vector3D::ZERO, 0.5, 0.5).is_some());
+    }
+
+    #[test]
+    fn fog_and_volumes_reach_the_cpu_scene() {
+        let description = parse(
+            "fog color 0.7 0.7 0.8 density 0.05\n\
+             volume sphere center 0 0 10 density 0.5 radius 2 albedo 0.9 0.8 0.7 anisotropy 0.3\n\
+             volume box center 0 0 20 size 2 2 2 rotation 0 45 0\n",
+        );
+        let fog = description.fog.unwrap();
+        assert_eq!((fog.color.b, fog.density), (0.8, 0.05));
+
+        let smoke = &description.volumes[0];
+        assert_eq!(smoke.boundary.kind(), "sphere");
+        assert_eq!((smoke.density, smoke.albedo.g), (0.5, 0.8));
+        assert_eq!(smoke.phase, PhaseFunction::HenyeyGreenstein(0.3));
+        let haze = &description.volumes[1];
+        assert_eq!(haze.boundary.kind(), "box");
+        assert_eq!((haze.density, haze.albedo.r), (1.0, 1.0));
+        assert_eq!(haze.phase, PhaseFunction::Isotropic);
+
+        let scene = description.to_scene();
+        assert!(scene.fog.is_some());
+        assert_eq!(scene.volumes.len(), 2);
+        // Straight through the middle of the sphere, 4 units of medium
+        let ray = crate::ray::Ray::new(Vector3D::ZERO, Vector3D::new(0.0, 0.0, 1.0));
+        assert_close(scene.volumes[0].transmittance(&ray, 0.0, 15.0), (-0.5f64 * 4.0).exp());
+    }
+
+    #[test]
+    fn metallic_and_roughness_make_pbr_materials() {
+        let description = parse(
+            "sphere gold metallic 1 roughness 0.2 color 1 0.8 0.3 shininess 64\n\
+             rectangle panel roughness 0.7\n\
+             sphere plain color 0.5 0.5 0.5\n",
+        );
+        let material = |index: usize| *description.objects[index].1.clone().material_mut();
+
+        let gold = material(3);
+        let pbr = gold.pbr.unwrap();
+        assert_eq!((pbr.metallic, pbr.roughness), (1.0, 0.2));
+        // The colour follows later `color`s, while Blinn-Phong properties
+        // stay for the GPU
+        assert_eq!((pbr.base_color.r, pbr.base_color.g, pbr.base_color.b), (1.0, 0.8, 0.3));
+        assert_eq!(gold.shininess, 64.0);
+
+        let panel = material(4).pbr.unwrap();
+        assert_eq!((panel.metallic, panel.roughness), (0.0, 0.7));
+        assert_eq!(panel.base_color.r, DEFAULT_MATERIAL.color.r);
+        assert!(material(5).pbr.is_none());
+    }
+
+    #[test]
+    fn normal_and_bump_maps_number_their_images() {
+        let mut description = parse(
+            "sphere bumpy bump_map heights.png 0.02\n\
+             box tiled normal_map tiles.png color 1 1 1\n\
+             disk faint center 0 2 0 normal_map tiles.png 0.5\n",
+        );
+        let map = |index: usize| description.objects[index].1.clone().material_mut().normal_map;
+        assert!(matches!(map(3), Some(NormalMap::Bump { texture: TextureId(0), scale }) if scale == 0.02));
+        assert!(matches!(map(4), Some(NormalMap::Tangent { texture: TextureId(1), strength }) if strength == 1.0));
+        assert!(matches!(map(5), Some(NormalMap::Tangent { texture: TextureId(1), strength }) if strength == 0.5));
+        assert_eq!(description.texture_paths, [PathBuf::from("heights.png"), PathBuf::from("tiles.png")]);
+
+        // A red texel and a flat normal
+        let png = "iVBORw0KGgoAAAANSUhEUgAAAAIAAAABCAIAAAB7QOjdAAAAD0lEQVR4nGP4z8DQ0PAfAAmAAv+EtP36AAAAAElFTkSuQmCC";
+        let dir = std::env::temp_dir().join(format!("rust-ray-tracing-scene-textures-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let bytes = base64::Engine::decode(&base64::engine::general_purpose::STANDARD, png).unwrap();
+        std::fs::write(dir.join("heights.png"), &bytes).unwrap();
+        std::fs::write(dir.join("tiles.png"), &bytes).unwrap();
+        let loaded = description.load_assets(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        loaded.unwrap();
+        let scene = description.to_scene();
+        assert_eq!(scene.textures.len(), 2);
+        assert_eq!((scene.textures[1].width, scene.textures[1].height), (2, 1));
+        assert!(matches!(description.load_assets(Path::new("/nonexistent")), Err(SceneFileError::Texture(..))));
+    }
+
+    #[test]
+    fn import_medium_and_material_parse_errors() {
+        for (source, message) in [
+            ("gltf\n", "expected a file path"),
+            ("gltf model.glb scale 0\n", "the gltf scale must not be zero"),
+            ("gltf model.glb color 1 1 1\n", "unknown gltf property 'color'"),
+            ("mesh model.obj\n", "'model.obj' is not a .ply or .stl file"),
+            ("mesh model.ply shininess\n", "expected a shininess exponent"),
+            ("mesh model.stl radius 2\n", "unknown mesh property 'radius'"),
+            ("fog density 0.1\n", "fog needs a color and a density"),
+            ("fog color 1 1 1 density 0.1 height 2\n", "unknown fog property 'height'"),
+            ("volume plane\n", "a volume must be a sphere, box, cylinder, cone or torus, not 'plane'"),
+            ("volume sphere anisotropy 1\n", "the anisotropy must be between -1 and 1"),
+            ("volume sphere radius 2 color 1 0 0\n", "unknown sphere property 'color'"),
+            ("volume sphere density\n", "expected a density"),
+            ("sphere shiny metallic 1.5\n", "the metallic must be between 0 and 1"),
+            ("box rough roughness\n", "expected a roughness"),
+            ("sphere flat normal_map\n", "expected a normal map image"),
+            ("sphere flat bump_map heights.png\n", "expected a bump height"),
+        ] {
+            assert_eq!(parse_error(source), (1, message.to_string()), "{}", source);
+        }
+        assert!("gltf missing.glb\n".parse::<SceneDescription>().unwrap().load_assets(Path::new("/nonexistent")).is_err());
+    }
+}
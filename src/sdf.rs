@@ -0,0 +1,626 @@
+use crate::hittable::{HitRecord, Hittable, Interval};
+use crate::ray::Ray;
+use crate::sphere::Material;
+use crate::vector3d::{Quaternion, Vector3D};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+/// Signed distance function, built from primitives centred on the origin
+/// and operators applied to them.
+///
+/// Trees can be written in Rust or parsed from a small expression format of
+/// nested calls, numbers first, then child shapes:
+///
+/// ```text
+/// smooth_union(0.3, sphere(1), translate(1.2, 0, 0, round_box(0.5, 0.5, 0.5, 0.1)))
+/// repeat(3, 0, 3, twist(0.8, torus(1, 0.25)))
+/// ```
+///
+/// Primitives: `sphere(r)`, `box(hx, hy, hz)`, `round_box(hx, hy, hz, r)`,
+/// `torus(major, minor)` and `cylinder(r, half_height)` around y, and
+/// `plane(nx, ny, nz, offset)`. Operators: `union(a, b, ...)`,
+/// `intersection(a, b, ...)`, `difference(a, b)`, `smooth_union(k, a, b, ...)`,
+/// `translate(x, y, z, a)`, `rotate(x, y, z, a)` (Euler degrees),
+/// `scale(s, a)`, `twist(radians_per_unit, a)` about y and
+/// `repeat(px, py, pz, a)`, where a period of 0 leaves that axis alone.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Sdf {
+    Sphere { radius: f64 },
+    Box { half_extents: Vector3D },
+    RoundBox { half_extents: Vector3D, radius: f64 },
+    Torus { major_radius: f64, minor_radius: f64 },
+    Cylinder { radius: f64, half_height: f64 },
+    Plane { normal: Vector3D, offset: f64 },
+    Union(Vec<Sdf>),
+    Intersection(Vec<Sdf>),
+    Difference(Box<Sdf>, Box<Sdf>),
+    /// Polynomial smooth minimum blending over a distance of `k`
+    SmoothUnion { k: f64, children: Vec<Sdf> },
+    Translate { offset: Vector3D, child: Box<Sdf> },
+    Rotate { rotation: Quaternion, child: Box<Sdf> },
+    Scale { factor: f64, child: Box<Sdf> },
+    /// Rotates about y by `rate` radians per unit of height
+    Twist { rate: f64, child: Box<Sdf> },
+    /// Infinite copies every `period` units; zero components don't repeat
+    Repeat { period: Vector3D, child: Box<Sdf> },
+}
+
+impl Sdf {
+    /// Distance from `p` to the surface, negative inside
+    pub fn distance(&self, p: Vector3D) -> f64 {
+        match self {
+            Sdf::Sphere { radius } => p.magnitude() - radius,
+            Sdf::Box { half_extents } => box_distance(p, *half_extents),
+            Sdf::RoundBox { half_extents, radius } => {
+                box_distance(p, *half_extents - Vector3D::splat(*radius)) - radius
+            }
+            Sdf::Torus {
+                major_radius,
+                minor_radius,
+            } => {
+                let ring = (p.x * p.x + p.z * p.z).sqrt() - major_radius;
+                (ring * ring + p.y * p.y).sqrt() - minor_radius
+            }
+            Sdf::Cylinder { radius, half_height } => {
+                let dx = (p.x * p.x + p.z * p.z).sqrt() - radius;
+                let dy = p.y.abs() - half_height;
+                dx.max(dy).min(0.0) + (dx.max(0.0).powi(2) + dy.max(0.0).powi(2)).sqrt()
+            }
+            Sdf::Plane { normal, offset } => p.dot(*normal) - offset,
+            Sdf::Union(children) => children.iter().map(|c| c.distance(p)).fold(f64::INFINITY, f64::min),
+            Sdf::Intersection(children) => {
+                children.iter().map(|c| c.distance(p)).fold(f64::NEG_INFINITY, f64::max)
+            }
+            Sdf::Difference(a, b) => a.distance(p).max(-b.distance(p)),
+            Sdf::SmoothUnion { k, children } => children
+                .iter()
+                .map(|c| c.distance(p))
+                .reduce(|a, b| smooth_min(a, b, *k))
+                .unwrap_or(f64::INFINITY),
+            Sdf::Translate { offset, child } => child.distance(p - *offset),
+            Sdf::Rotate { rotation, child } => child.distance(rotation.conjugate().rotate(p)),
+            Sdf::Scale { factor, child } => child.distance(p / *factor) * factor,
+            Sdf::Twist { rate, child } => {
+                let (sin, cos) = (rate * p.y).sin_cos();
+                child.distance(Vector3D::new(cos * p.x - sin * p.z, p.y, sin * p.x + cos * p.z))
+            }
+            Sdf::Repeat { period, child } => {
+                let mut q = p;
+                for axis in 0..3 {
+                    if period[axis] > 0.0 {
+                        q[axis] -= period[axis] * (p[axis] / period[axis]).round();
+                    }
+                }
+                child.distance(q)
+            }
+        }
+    }
+
+    /// Outward surface normal at `p` from the distance gradient, using the
+    /// four-tap tetrahedron stencil
+    pub fn normal(&self, p: Vector3D, h: f64) -> Vector3D {
+        let taps = [
+            Vector3D::new(1.0, -1.0, -1.0),
+            Vector3D::new(-1.0, -1.0, 1.0),
+            Vector3D::new(-1.0, 1.0, -1.0),
+            Vector3D::new(1.0, 1.0, 1.0),
+        ];
+        taps.iter()
+            .map(|&k| k * self.distance(p + k * h))
+            .sum::<Vector3D>()
+            .normalize()
+    }
+
+    fn contains_twist(&self) -> bool {
+        match self {
+            Sdf::Twist { .. } => true,
+            Sdf::Union(children) | Sdf::Intersection(children) | Sdf::SmoothUnion { children, .. } => {
+                children.iter().any(Sdf::contains_twist)
+            }
+            Sdf::Difference(a, b) => a.contains_twist() || b.contains_twist(),
+            Sdf::Translate { child, .. }
+            | Sdf::Rotate { child, .. }
+            | Sdf::Scale { child, .. }
+            | Sdf::Repeat { child, .. } => child.contains_twist(),
+            _ => false,
+        }
+    }
+}
+
+#[inline]
+fn box_distance(p: Vector3D, half_extents: Vector3D) -> f64 {
+    let q = p.abs() - half_extents;
+    q.max(Vector3D::ZERO).magnitude() + q.max_component().min(0.0)
+}
+
+#[inline]
+fn smooth_min(a: f64, b: f64, k: f64) -> f64 {
+    if k <= 0.0 {
+        return a.min(b);
+    }
+    let h = (0.5 + 0.5 * (b - a) / k).clamp(0.0, 1.0);
+    b + (a - b) * h - k * h * (1.0 - h)
+}
+
+/// A distance function placed in the scene, rendered by sphere tracing.
+///
+/// SDFs have no surface parameterisation, so hits report a uv of (0, 0).
+#[derive(Debug, Clone)]
+pub struct SdfObject {
+    pub sdf: Sdf,
+    pub material: Material,
+    pub max_steps: u32,
+    /// Distance below which the march counts as on the surface
+    pub epsilon: f64,
+    /// How far to march along a ray, on either side of its origin for
+    /// `intervals`
+    pub max_distance: f64,
+    /// Fraction of the distance bound taken per step. Operators like twist
+    /// stretch space so the field overestimates the true distance; `new`
+    /// halves the step for those.
+    pub step_scale: f64,
+}
+
+impl SdfObject {
+    pub fn new(sdf: Sdf, material: Material) -> SdfObject {
+        let step_scale = if sdf.contains_twist() { 0.5 } else { 1.0 };
+        SdfObject {
+            sdf,
+            material,
+            max_steps: 256,
+            epsilon: 1e-4,
+            max_distance: 100.0,
+            step_scale,
+        }
+    }
+
+    /// First surface point at or after distance `start` along the unit
+    /// `direction`, stopping at `end`. Marches by the absolute distance, so
+    /// it finds the way out of the solid as well as the way in.
+    fn march(&self, origin: Vector3D, direction: Vector3D, start: f64, end: f64) -> Option<f64> {
+        let mut s = start;
+        for _ in 0..self.max_steps {
+            if s > end {
+                return None;
+            }
+            let distance = self.sdf.distance(origin + direction * s).abs();
+            if distance < self.epsilon {
+                return Some(s);
+            }
+            s += distance * self.step_scale;
+        }
+        None
+    }
+
+    fn record(&self, ray: &Ray, t: f64) -> HitRecord {
+        let point = ray.at(t);
+        let normal = if t.is_finite() {
+            self.sdf.normal(point, self.epsilon)
+        } else {
+            Vector3D::ZERO
+        };
+
+        let (tangent, bitangent) = normal.orthonormal_basis();
+        HitRecord {
+            point,
+            normal,
+            t,
+            uv: (0.0, 0.0),
+            tangent,
+            bitangent,
+            material: self.material,
+        }
+    }
+}
+
+impl Hittable for SdfObject {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let length = ray.direction.magnitude();
+        let direction = ray.direction * (1.0 / length);
+        let start = (t_min * length).max(-self.max_distance);
+        let end = (t_max * length).min(self.max_distance);
+
+        let s = self.march(ray.origin, direction, start, end)?;
+        Some(self.record(ray, s / length))
+    }
+
+    /// Marches the whole range and classifies each crossing by the sign of
+    /// the field just past it, so grazing touches don't count
+    fn intervals(&self, ray: &Ray) -> Vec<Interval> {
+        let length = ray.direction.magnitude();
+        let direction = ray.direction * (1.0 / length);
+        let distance_at = |s: f64| self.sdf.distance(ray.origin + direction * s);
+
+        let mut intervals = Vec::new();
+        let mut s = -self.max_distance;
+        let mut inside = distance_at(s) < 0.0;
+        let mut enter = inside.then(|| self.record(ray, f64::NEG_INFINITY));
+
+        // Repetition can put many crossings on one line, so bound them by the
+        // step budget rather than a small fixed count
+        for _ in 0..self.max_steps {
+            let Some(crossing) = self.march(ray.origin, direction, s, self.max_distance) else {
+                break;
+            };
+
+            // Step off the surface before looking for the next crossing
+            s = crossing + self.epsilon;
+            for _ in 0..self.max_steps {
+                if distance_at(s).abs() >= self.epsilon {
+                    break;
+                }
+                s += self.epsilon;
+            }
+
+            let now_inside = distance_at(s) < 0.0;
+            if now_inside != inside {
+                let record = self.record(ray, crossing / length);
+                match enter.take() {
+                    Some(enter) => intervals.push(Interval { enter, exit: record }),
+                    None => enter = Some(record),
+                }
+                inside = now_inside;
+            }
+        }
+
+        if let Some(enter) = enter {
+            intervals.push(Interval {
+                enter,
+                exit: self.record(ray, f64::INFINITY),
+            });
+        }
+        intervals
+    }
+}
+
+/// Error from parsing an `Sdf` expression
+#[derive(Debug, Clone, PartialEq)]
+pub struct SdfParseError {
+    /// Byte offset into the expression
+    pub position: usize,
+    pub message: String,
+}
+
+impl fmt::Display for SdfParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+impl Error for SdfParseError {}
+
+impl FromStr for Sdf {
+    type Err = SdfParseError;
+
+    fn from_str(source: &str) -> Result<Sdf, SdfParseError> {
+        let mut parser = Parser { source, position: 0 };
+        let sdf = parser.node()?;
+        parser.skip_whitespace();
+        if parser.position < source.len() {
+            return Err(parser.error("unexpected trailing input"));
+        }
+        Ok(sdf)
+    }
+}
+
+enum Argument {
+    Number(f64),
+    Node(Sdf),
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: impl Into<String>) -> SdfParseError {
+        SdfParseError {
+            position: self.position,
+            message: message.into(),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.source[self.position..];
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.source[self.position..].chars().next()
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), SdfParseError> {
+        if self.peek() == Some(expected) {
+            self.position += expected.len_utf8();
+            Ok(())
+        } else {
+            Err(self.error(format!("expected '{}'", expected)))
+        }
+    }
+
+    /// Consumes the longest prefix whose characters satisfy `accept`
+    fn take_while(&mut self, accept: impl Fn(char) -> bool) -> &str {
+        let start = self.position;
+        let rest = &self.source[start..];
+        let len = rest.find(|c: char| !accept(c)).unwrap_or(rest.len());
+        self.position += len;
+        &self.source[start..start + len]
+    }
+
+    fn node(&mut self) -> Result<Sdf, SdfParseError> {
+        self.skip_whitespace();
+        let start = self.position;
+        let name = self.take_while(|c| c.is_ascii_alphanumeric() || c == '_').to_string();
+        if name.is_empty() {
+            return Err(self.error("expected a shape or operator name"));
+        }
+
+        self.expect('(')?;
+        let mut arguments = Vec::new();
+        if self.peek() != Some(')') {
+            loop {
+                arguments.push(self.argument()?);
+                if self.peek() == Some(',') {
+                    self.position += 1;
+                } else {
+                    break;
+                }
+            }
+        }
+        self.expect(')')?;
+
+        build(&name, arguments).map_err(|message| SdfParseError { position: start, message })
+    }
+
+    fn argument(&mut self) -> Result<Argument, SdfParseError> {
+        match self.peek() {
+            Some(c) if c.is_ascii_digit() || c == '-' || c == '+' || c == '.' => {
+                let text = self.take_while(|c| c.is_ascii_digit() || matches!(c, '-' | '+' | '.' | 'e' | 'E'));
+                let text = text.to_string();
+                text.parse()
+                    .map(Argument::Number)
+                    .map_err(|_| self.error(format!("invalid number '{}'", text)))
+            }
+            _ => self.node().map(Argument::Node),
+        }
+    }
+}
+
+/// Checks a call's arguments against the expected counts and builds the node
+fn build(name: &str, arguments: Vec<Argument>) -> Result<Sdf, String> {
+    let mut numbers = Vec::new();
+    let mut nodes = Vec::new();
+    for argument in arguments {
+        match argument {
+            Argument::Number(value) if nodes.is_empty() => numbers.push(value),
+            Argument::Number(_) => return Err(format!("{}: numbers must come before shapes", name)),
+            Argument::Node(node) => nodes.push(node),
+        }
+    }
+
+    let (want_numbers, min_nodes, max_nodes) = match name {
+        "sphere" => (1, 0, 0),
+        "box" => (3, 0, 0),
+        "round_box" => (4, 0, 0),
+        "torus" | "cylinder" => (2, 0, 0),
+        "plane" => (4, 0, 0),
+        "union" | "intersection" => (0, 2, usize::MAX),
+        "difference" => (0, 2, 2),
+        "smooth_union" => (1, 2, usize::MAX),
+        "translate" | "rotate" | "repeat" => (3, 1, 1),
+        "scale" | "twist" => (1, 1, 1),
+        _ => return Err(format!("unknown shape or operator '{}'", name)),
+    };
+    if numbers.len() != want_numbers || nodes.len() < min_nodes || nodes.len() > max_nodes {
+        let shapes = match (min_nodes, max_nodes) {
+            (0, 0) => String::new(),
+            (min, usize::MAX) => format!(" and at least {} shapes", min),
+            (min, max) if min == max => format!(" and {} shape{}", min, if min == 1 { "" } else { "s" }),
+            (min, max) => format!(" and {} to {} shapes", min, max),
+        };
+        return Err(format!("{} takes {} numbers{}", name, want_numbers, shapes));
+    }
+
+    let vector = |i: usize| Vector3D::new(numbers[i], numbers[i + 1], numbers[i + 2]);
+    let mut nodes = nodes.into_iter();
+    let mut child = || Box::new(nodes.next().expect("node count was checked"));
+
+    Ok(match name {
+        "sphere" => Sdf::Sphere { radius: numbers[0] },
+        "box" => Sdf::Box { half_extents: vector(0) },
+        "round_box" => Sdf::RoundBox {
+            half_extents: vector(0),
+            radius: numbers[3],
+        },
+        "torus" => Sdf::Torus {
+            major_radius: numbers[0],
+            minor_radius: numbers[1],
+        },
+        "cylinder" => Sdf::Cylinder {
+            radius: numbers[0],
+            half_height: numbers[1],
+        },
+        "plane" => Sdf::Plane {
+            normal: vector(0).normalize(),
+            offset: numbers[3],
+        },
+        "difference" => Sdf::Difference(child(), child()),
+        "translate" => Sdf::Translate {
+            offset: vector(0),
+            child: child(),
+        },
+        "rotate" => Sdf::Rotate {
+            rotation: Quaternion::from_euler(numbers[0], numbers[1], numbers[2]),
+            child: child(),
+        },
+        "repeat" => Sdf::Repeat {
+            period: vector(0),
+            child: child(),
+        },
+        "scale" => Sdf::Scale {
+            factor: numbers[0],
+            child: child(),
+        },
+        "twist" => Sdf::Twist {
+            rate: numbers[0],
+            child: child(),
+        },
+        "union" => Sdf::Union(nodes.collect()),
+        "intersection" => Sdf::Intersection(nodes.collect()),
+        "smooth_union" => Sdf::SmoothUnion {
+            k: numbers[0],
+            children: nodes.collect(),
+        },
+        _ => unreachable!("names were matched above"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(source: &str) -> Sdf {
+        source.parse().unwrap_or_else(|e| panic!("{}: {}", source, e))
+    }
+
+    fn parse_error(source: &str) -> SdfParseError {
+        source.parse::<Sdf>().expect_err(source)
+    }
+
+    fn assert_distance(source: &str, p: Vector3D, expected: f64) {
+        let distance = parse(source).distance(p);
+        assert!((distance - expected).abs() < 1e-12, "{} at {:?}: {} != {}", source, p, distance, expected);
+    }
+
+    #[test]
+    fn parses_nested_calls() {
+        let expected = Sdf::SmoothUnion {
+            k: 0.3,
+            children: vec![
+                Sdf::Sphere { radius: 1.0 },
+                Sdf::Translate {
+                    offset: Vector3D::new(1.2, 0.0, 0.0),
+                    child: Box::new(Sdf::RoundBox {
+                        half_extents: Vector3D::splat(0.5),
+                        radius: 0.1,
+                    }),
+                },
+            ],
+        };
+        assert_eq!(parse("smooth_union(0.3, sphere(1), translate(1.2, 0, 0, round_box(0.5, 0.5, 0.5, 0.1)))"), expected);
+
+        let expected = Sdf::Repeat {
+            period: Vector3D::new(3.0, 0.0, 3.0),
+            child: Box::new(Sdf::Twist {
+                rate: 0.8,
+                child: Box::new(Sdf::Torus {
+                    major_radius: 1.0,
+                    minor_radius: 0.25,
+                }),
+            }),
+        };
+        assert_eq!(parse("repeat(3, 0, 3, twist(0.8, torus(1, 0.25)))"), expected);
+    }
+
+    #[test]
+    fn layout_and_number_forms_do_not_matter() {
+        assert_eq!(
+            parse("  difference (\n\tbox(1, 2, 3) ,cylinder( .5,+2 ) )  "),
+            parse("difference(box(1,2,3),cylinder(0.5,2))"),
+        );
+        assert_eq!(parse("scale(-1.5e-1, sphere(1E1))"), parse("scale(-0.15, sphere(10))"));
+        assert_eq!(
+            parse("union(sphere(1), sphere(2), sphere(3))"),
+            Sdf::Union(vec![
+                Sdf::Sphere { radius: 1.0 },
+                Sdf::Sphere { radius: 2.0 },
+                Sdf::Sphere { radius: 3.0 },
+            ]),
+        );
+        assert_eq!(
+            parse("plane(0, 2, 0, 1)"),
+            Sdf::Plane {
+                normal: Vector3D::new(0.0, 1.0, 0.0),
+                offset: 1.0,
+            },
+        );
+    }
+
+    #[test]
+    fn mismatched_parentheses() {
+        assert_eq!(parse_error("sphere(1").message, "expected ')'");
+        assert_eq!(parse_error("union(sphere(1), box(1, 1, 1)").position, 29);
+        assert_eq!(parse_error("sphere 1)").message, "expected '('");
+        let extra = parse_error("sphere(1))");
+        assert_eq!((extra.position, extra.message.as_str()), (9, "unexpected trailing input"));
+        assert_eq!(parse_error("").message, "expected a shape or operator name");
+    }
+
+    #[test]
+    fn unknown_names() {
+        let error = parse_error("union(sphere(1), blob(2))");
+        assert_eq!(error.position, 17);
+        assert_eq!(error.message, "unknown shape or operator 'blob'");
+    }
+
+    #[test]
+    fn wrong_argument_counts() {
+        for (source, message) in [
+            ("sphere()", "sphere takes 1 numbers"),
+            ("box(1, 2)", "box takes 3 numbers"),
+            ("torus(1, 2, sphere(1))", "torus takes 2 numbers"),
+            ("union(sphere(1))", "union takes 0 numbers and at least 2 shapes"),
+            ("difference(sphere(1), sphere(2), sphere(3))", "difference takes 0 numbers and 2 shapes"),
+            ("translate(1, 2, sphere(1))", "translate takes 3 numbers and 1 shape"),
+            ("union(sphere(1), 2, sphere(3))", "union: numbers must come before shapes"),
+        ] {
+            assert_eq!(parse_error(source).message, message, "{}", source);
+        }
+        assert_eq!(parse_error("sphere(1.2.3)").message, "invalid number '1.2.3'");
+    }
+
+    #[test]
+    fn primitive_distances() {
+        let origin = Vector3D::ZERO;
+        assert_distance("sphere(1)", Vector3D::new(0.0, 3.0, 0.0), 2.0);
+        assert_distance("sphere(1)", origin, -1.0);
+
+        assert_distance("box(1, 2, 3)", Vector3D::new(2.0, 0.0, 0.0), 1.0);
+        assert_distance("box(1, 2, 3)", Vector3D::new(2.0, 3.0, 0.0), 2f64.sqrt());
+        assert_distance("box(1, 2, 3)", origin, -1.0);
+
+        assert_distance("round_box(1, 1, 1, 0.25)", Vector3D::new(0.0, 0.0, 2.0), 1.0);
+        assert_distance("round_box(1, 1, 1, 0.25)", Vector3D::new(2.0, 2.0, 0.0), 1.25 * 2f64.sqrt() - 0.25);
+
+        assert_distance("torus(2, 0.5)", Vector3D::new(0.0, 0.0, 2.0), -0.5);
+        assert_distance("torus(2, 0.5)", Vector3D::new(3.0, 0.0, 0.0), 0.5);
+        assert_distance("torus(2, 0.5)", Vector3D::new(2.0, 1.0, 0.0), 0.5);
+        assert_distance("torus(2, 0.5)", origin, 1.5);
+
+        assert_distance("cylinder(1, 2)", Vector3D::new(0.0, 0.0, 3.0), 2.0);
+        assert_distance("cylinder(1, 2)", Vector3D::new(0.0, 5.0, 0.0), 3.0);
+        assert_distance("cylinder(1, 2)", Vector3D::new(2.0, 3.0, 0.0), 2f64.sqrt());
+        assert_distance("cylinder(1, 2)", origin, -1.0);
+
+        assert_distance("plane(0, 2, 0, 1)", Vector3D::new(5.0, 3.0, -7.0), 2.0);
+        assert_distance("plane(0, 2, 0, 1)", origin, -1.0);
+    }
+
+    #[test]
+    fn operator_distances() {
+        let p = Vector3D::new(3.0, 0.0, 0.0);
+        assert_distance("union(sphere(1), sphere(2))", p, 1.0);
+        assert_distance("intersection(sphere(1), sphere(2))", p, 2.0);
+        assert_distance("difference(sphere(2), sphere(1))", Vector3D::ZERO, 1.0);
+        assert_distance("translate(1, 0, 0, sphere(1))", p, 1.0);
+        assert_distance("rotate(0, 0, 90, box(2, 0.5, 0.5))", Vector3D::new(0.0, 3.0, 0.0), 1.0);
+        assert_distance("scale(2, sphere(1))", p, 1.0);
+        assert_distance("repeat(4, 0, 0, sphere(1))", Vector3D::new(8.5, 0.0, 0.0), -0.5);
+        // Blending pulls the surface out where the two shapes meet
+        let blended = parse("smooth_union(0.5, sphere(1), translate(2, 0, 0, sphere(1)))");
+        assert!(blended.distance(Vector3D::new(1.0, 0.0, 0.0)) < 0.0);
+    }
+}
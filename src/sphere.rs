@@ -1,15 +1,23 @@
-use crate::hittable::{HitRecord, Hittable};
+use crate::hittable::{tangent_frame, EmissionSample, HitRecord, Hittable};
+use crate::normal_map::NormalMap;
+use crate::pbr::PbrMaterial;
 use crate::ray::Ray;
+use crate::texture::TextureId;
 use crate::vector3d::Vector3D;
-use std::ops::{Add, Mul};
+use std::f64::consts::PI;
+use std::ops::{Add, Mul, Sub};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Default)]
 pub struct Color {
     pub r: f64,
     pub g: f64,
     pub b: f64,
 }
 
+impl Color {
+    pub const WHITE: Color = Color { r: 1.0, g: 1.0, b: 1.0 };
+}
+
 impl Mul<f64> for Color {
     type Output = Self;
 
@@ -23,6 +31,20 @@ impl Mul<f64> for Color {
     }
 }
 
+impl Mul for Color {
+    type Output = Self;
+
+    /// Component-wise, e.g. for filtering light by a surface colour
+    #[inline]
+    fn mul(self, other: Self) -> Self {
+        Color {
+            r: self.r * other.r,
+            g: self.g * other.g,
+            b: self.b * other.b,
+        }
+    }
+}
+
 impl Add for Color {
     type Output = Self;
 
@@ -36,6 +58,19 @@ impl Add for Color {
     }
 }
 
+impl Sub for Color {
+    type Output = Self;
+
+    #[inline]
+    fn sub(self, other: Self) -> Self {
+        Color {
+            r: self.r - other.r,
+            g: self.g - other.g,
+            b: self.b - other.b,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct Material {
     pub color: Color,
@@ -43,6 +78,57 @@ pub struct Material {
     pub specular: f64,
     pub shininess: f64,
     pub reflectivity: f64,
+    /// Physically based parameters. When set they replace the Blinn-Phong
+    /// terms on the CPU; the GPU renderer keeps using the fields above.
+    pub pbr: Option<PbrMaterial>,
+    /// Radiance given off by the surface, added to whatever it reflects.
+    /// Black for anything that isn't a light source.
+    pub emission: Color,
+    /// Colour map multiplying `color` and the PBR base colour at the hit's
+    /// UV (CPU only)
+    pub texture: Option<TextureId>,
+    /// Normal or bump map tilting the shading normal (CPU only)
+    pub normal_map: Option<NormalMap>,
+}
+
+impl Material {
+    /// Material shaded with the GGX metallic-roughness model, with its
+    /// Blinn-Phong fields filled in as an approximation for the GPU
+    pub fn from_pbr(pbr: PbrMaterial) -> Material {
+        let alpha = pbr.alpha();
+        Material {
+            color: pbr.base_color,
+            diffuse: 1.0 - pbr.metallic,
+            specular: 0.04 + 0.96 * pbr.metallic,
+            shininess: 2.0 / (alpha * alpha) - 2.0,
+            reflectivity: pbr.metallic * (1.0 - pbr.roughness),
+            pbr: Some(pbr),
+            emission: Color::default(),
+            texture: None,
+            normal_map: None,
+        }
+    }
+
+    /// The same material glowing with `color` scaled by `strength`
+    pub fn with_emission(self, color: Color, strength: f64) -> Material {
+        Material {
+            emission: color * strength,
+            ..self
+        }
+    }
+
+    #[inline]
+    pub fn is_emissive(&self) -> bool {
+        self.emission.r > 0.0 || self.emission.g > 0.0 || self.emission.b > 0.0
+    }
+
+    /// The same material shaded with its `PbrMaterial::from_phong` conversion
+    pub fn to_pbr(self) -> Material {
+        Material {
+            pbr: Some(PbrMaterial::from_phong(&self)),
+            ..self
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -86,12 +172,146 @@ impl Hittable for Sphere {
 
         let point = ray.at(root);
         let normal = (point - self.center) * (1.0 / self.radius);
+        // u runs around the y axis and v from the bottom pole to the top
+        let (tangent, bitangent) = tangent_frame(
+            normal,
+            Vector3D::new(normal.z, 0.0, -normal.x),
+            Vector3D::new(0.0, 1.0, 0.0),
+        );
 
         Some(HitRecord {
             point,
             normal,
             t: root,
+            uv: sphere_uv(normal),
+            tangent,
+            bitangent,
             material: self.material,
         })
     }
+
+    /// Uniform over the cone of directions the sphere covers as seen from
+    /// outside, which wastes no samples on the far side; uniform over the
+    /// surface from inside
+    fn sample_emission(&self, origin: Vector3D, u1: f64, u2: f64) -> Option<EmissionSample> {
+        if !self.material.is_emissive() {
+            return None;
+        }
+
+        let to_center = self.center - origin;
+        let distance_sq = to_center.magnitude_squared();
+        let radius_sq = self.radius * self.radius;
+        let phi = 2.0 * PI * u2;
+
+        if distance_sq <= radius_sq {
+            let z = 1.0 - 2.0 * u1;
+            let r = (1.0 - z * z).max(0.0).sqrt();
+            let normal = Vector3D::new(r * phi.cos(), r * phi.sin(), z);
+            let point = self.center + normal * self.radius;
+            let offset = point - origin;
+            let cos_light = normal.dot(offset).abs() / offset.magnitude();
+            if cos_light <= 0.0 {
+                return None;
+            }
+            return Some(EmissionSample {
+                point,
+                normal,
+                radiance: self.material.emission,
+                pdf: offset.magnitude_squared() / (4.0 * PI * radius_sq * cos_light),
+            });
+        }
+
+        let distance = distance_sq.sqrt();
+        let axis = to_center * (1.0 / distance);
+        let sin_max_sq = radius_sq / distance_sq;
+        let cos_max = (1.0 - sin_max_sq).sqrt();
+        // 1 - cos_max without the cancellation for small, distant spheres
+        let solid_fraction = sin_max_sq / (1.0 + cos_max);
+
+        let cos_theta = 1.0 - u1 * solid_fraction;
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let (tangent, bitangent) = axis.orthonormal_basis();
+        let direction = axis * cos_theta + (tangent * phi.cos() + bitangent * phi.sin()) * sin_theta;
+
+        // Nearer intersection with the sphere, clamped for grazing samples
+        let along = distance * cos_theta - (radius_sq - distance_sq * sin_theta * sin_theta).max(0.0).sqrt();
+        let point = origin + direction * along;
+        Some(EmissionSample {
+            point,
+            normal: (point - self.center) * (1.0 / self.radius),
+            radiance: self.material.emission,
+            pdf: 1.0 / (2.0 * PI * solid_fraction),
+        })
+    }
+}
+
+/// Longitude/latitude coordinates of a point on the unit sphere, with v = 0
+/// at the bottom pole
+#[inline]
+fn sphere_uv(normal: Vector3D) -> (f64, f64) {
+    let u = (-normal.z).atan2(normal.x) / (2.0 * PI) + 0.5;
+    let v = (-normal.y).clamp(-1.0, 1.0).acos() / PI;
+    (u, v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GLOW: Material = Material {
+        color: Color::WHITE,
+        diffuse: 1.0,
+        specular: 0.0,
+        shininess: 1.0,
+        reflectivity: 0.0,
+        pbr: None,
+        emission: Color::WHITE,
+        texture: None,
+        normal_map: None,
+    };
+
+    /// Sum of `1 / pdf` over stratified samples, which estimates the solid
+    /// angle the samples are spread over
+    fn solid_angle(sphere: &Sphere, origin: Vector3D, check: impl Fn(&EmissionSample)) -> f64 {
+        let steps = 64;
+        let mut sum = 0.0;
+        for i in 0..steps {
+            for j in 0..steps {
+                let u1 = (i as f64 + 0.5) / steps as f64;
+                let u2 = (j as f64 + 0.5) / steps as f64;
+                let sample = sphere.sample_emission(origin, u1, u2).unwrap();
+                assert!(((sample.point - sphere.center).magnitude() - sphere.radius).abs() < 1e-9);
+                check(&sample);
+                sum += 1.0 / sample.pdf;
+            }
+        }
+        sum / (steps * steps) as f64
+    }
+
+    #[test]
+    fn emission_pdf_covers_the_visible_cone_from_outside() {
+        let sphere = Sphere::new(Vector3D::new(0.0, 0.0, 5.0), 2.0, GLOW);
+        let origin = Vector3D::new(0.5, -1.0, 0.0);
+        let distance_sq = (sphere.center - origin).magnitude_squared();
+        let cone = 2.0 * PI * (1.0 - (1.0 - 4.0 / distance_sq).sqrt());
+
+        let estimate = solid_angle(&sphere, origin, |sample| {
+            // The point is the first one the sampled direction reaches
+            let ray = Ray::new(origin, (sample.point - origin).normalize());
+            let hit = sphere.hit(&ray, 1e-9, f64::INFINITY).unwrap();
+            assert!((hit.point - sample.point).magnitude() < 1e-6);
+            assert!((sample.pdf - 1.0 / cone).abs() < 1e-9);
+        });
+        assert!((estimate - cone).abs() < 1e-9);
+    }
+
+    #[test]
+    fn emission_pdf_covers_every_direction_from_inside() {
+        let sphere = Sphere::new(Vector3D::ZERO, 3.0, GLOW);
+        let estimate = solid_angle(&sphere, Vector3D::new(1.0, 0.5, -0.5), |_| {});
+        assert!((estimate / (4.0 * PI) - 1.0).abs() < 0.01, "{}", estimate);
+
+        let dark = Sphere::new(Vector3D::ZERO, 3.0, Material { emission: Color::default(), ..GLOW });
+        assert!(dark.sample_emission(Vector3D::new(0.0, 0.0, 10.0), 0.5, 0.5).is_none());
+    }
 }
@@ -0,0 +1,207 @@
+use crate::film::Filter;
+use crate::framebuffer::Framebuffer;
+use crate::sampler::SamplerKind;
+use crate::scene::{Camera, Projection, Scene};
+
+/// How the two eye images share one frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StereoLayout {
+    /// Left eye on the left, right eye on the right
+    SideBySide,
+    /// Left eye on top, right eye below
+    OverUnder,
+}
+
+impl StereoLayout {
+    pub fn from_name(name: &str) -> Option<StereoLayout> {
+        match name {
+            "side-by-side" => Some(StereoLayout::SideBySide),
+            "over-under" => Some(StereoLayout::OverUnder),
+            _ => None,
+        }
+    }
+
+    /// Size of the packed frame for eye images of `width` x `height`
+    pub fn frame_size(self, width: u32, height: u32) -> (u32, u32) {
+        match self {
+            StereoLayout::SideBySide => (width * 2, height),
+            StereoLayout::OverUnder => (width, height * 2),
+        }
+    }
+}
+
+/// Pair of eyes either side of `camera`.
+///
+/// Flat projections give each eye its own copy of the camera, moved half
+/// the interpupillary distance sideways, with views that cross
+/// `convergence` units ahead; things at that distance appear at the screen.
+/// Perspective eyes stay parallel and converge by lens shift, which avoids
+/// the vertical parallax of turning them in; other projections turn in.
+/// Equirectangular cameras become omni-directional stereo eyes instead,
+/// which keep the parallax right in every direction of the panorama but
+/// always converge at infinity.
+#[derive(Debug, Clone, Copy)]
+pub struct StereoCamera {
+    pub camera: Camera,
+    /// Distance between the eyes in world units
+    pub interpupillary_distance: f64,
+    /// Distance from the camera to where the eyes' views meet, or infinity
+    /// for parallel eyes
+    pub convergence: f64,
+}
+
+impl StereoCamera {
+    /// Average human eyes, 64mm apart in a scene measured in metres,
+    /// converging on the camera's target
+    pub fn new(camera: Camera) -> StereoCamera {
+        StereoCamera {
+            camera,
+            interpupillary_distance: 0.064,
+            convergence: (camera.target - camera.position).magnitude(),
+        }
+    }
+
+    /// The left and right eye cameras
+    pub fn eyes(&self) -> [Camera; 2] {
+        let half = self.interpupillary_distance * 0.5;
+        let converges = self.convergence.is_finite() && self.convergence > 0.0;
+        let (forward, right, _) = self.camera.basis();
+
+        [-half, half].map(|offset| {
+            let mut eye = self.camera;
+            match self.camera.projection {
+                Projection::Equirectangular | Projection::OmniStereo { .. } => {
+                    eye.projection = Projection::OmniStereo { eye_offset: offset };
+                }
+                Projection::Perspective => {
+                    // Parallel eyes with their frustums shifted towards the
+                    // middle until they meet at the convergence distance
+                    eye.position = self.camera.position + right * offset;
+                    eye.target = eye.position + forward;
+                    if converges {
+                        let half_height = self.convergence * (self.camera.fov.to_radians() * 0.5).tan();
+                        eye.shift_x -= offset / (2.0 * half_height * self.camera.aspect_ratio);
+                    }
+                }
+                _ => {
+                    eye.position = self.camera.position + right * offset;
+                    eye.target = if converges {
+                        self.camera.position + forward * self.convergence
+                    } else {
+                        eye.position + forward
+                    };
+                }
+            }
+            eye
+        })
+    }
+
+    /// Renders both eyes at `width` x `height` with `Scene::trace` and packs
+    /// them into one frame
+    #[allow(clippy::too_many_arguments)]
+    pub fn trace(
+        &self,
+        scene: &Scene,
+        layout: StereoLayout,
+        width: u32,
+        height: u32,
+        samples: u32,
+        sampler: SamplerKind,
+        filter: Filter,
+    ) -> Framebuffer {
+        let [left, right] = self.eyes().map(|eye| scene.trace(&eye, width, height, samples, sampler, filter));
+        pack(&left, &right, layout)
+    }
+}
+
+/// Packs two equally sized eye images into one frame
+pub fn pack<T: Copy + Default>(left: &Framebuffer<T>, right: &Framebuffer<T>, layout: StereoLayout) -> Framebuffer<T> {
+    assert!(
+        left.width == right.width && left.height == right.height && left.channels == right.channels,
+        "both eyes must have the same size"
+    );
+
+    let (width, height) = layout.frame_size(left.width, left.height);
+    let mut frame = Framebuffer::new(width, height, left.channels);
+    let (dx, dy) = match layout {
+        StereoLayout::SideBySide => (left.width, 0),
+        StereoLayout::OverUnder => (0, left.height),
+    };
+    for y in 0..left.height {
+        for x in 0..left.width {
+            frame.pixel_mut(x, y).copy_from_slice(left.pixel(x, y));
+            frame.pixel_mut(x + dx, y + dy).copy_from_slice(right.pixel(x, y));
+        }
+    }
+    frame
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vector3d::Vector3D;
+
+    fn assert_close(a: Vector3D, b: Vector3D) {
+        assert!((a - b).magnitude() < 1e-9, "{:?} != {:?}", a, b);
+    }
+
+    fn camera(projection: Projection) -> Camera {
+        Camera {
+            projection,
+            ..Camera::new(Vector3D::new(0.0, 1.0, 0.0), Vector3D::new(0.0, 1.0, 5.0), 60.0, 1.5)
+        }
+    }
+
+    #[test]
+    fn flat_eyes_sit_apart_and_meet_at_the_convergence_distance() {
+        for projection in [Projection::Perspective, Projection::Orthographic { height: 2.0 }] {
+            let stereo = StereoCamera {
+                interpupillary_distance: 0.5,
+                ..StereoCamera::new(camera(projection))
+            };
+            let [left, right] = stereo.eyes();
+            // Looking along +z with y up, the camera's right is -x
+            assert_close(left.position, Vector3D::new(0.25, 1.0, 0.0));
+            assert_close(right.position, Vector3D::new(-0.25, 1.0, 0.0));
+
+            let meeting = Vector3D::new(0.0, 1.0, 5.0);
+            for eye in [left, right] {
+                let (x, y) = eye.project(meeting).unwrap();
+                assert!(x.abs() < 1e-9 && y.abs() < 1e-9, "{:?} sees it at ({}, {})", projection, x, y);
+            }
+            if projection == Projection::Perspective {
+                // Parallel, converging by lens shift alone
+                assert_close(left.forward(), Vector3D::new(0.0, 0.0, 1.0));
+                assert!(left.shift_x > 0.0 && right.shift_x < 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn panoramic_eyes_circle_the_camera() {
+        let stereo = StereoCamera {
+            interpupillary_distance: 0.5,
+            ..StereoCamera::new(camera(Projection::Equirectangular))
+        };
+        let [left, right] = stereo.eyes();
+        assert_eq!(left.projection, Projection::OmniStereo { eye_offset: -0.25 });
+        assert_eq!(right.projection, Projection::OmniStereo { eye_offset: 0.25 });
+
+        // Whichever way the eyes look, the left one is on the left: ahead,
+        // behind, and turned to the right, where the right eye is at the back
+        assert_close(left.cast_ray(0.0, 0.0).unwrap().origin, Vector3D::new(0.25, 1.0, 0.0));
+        assert_close(left.cast_ray(1.0, 0.0).unwrap().origin, Vector3D::new(-0.25, 1.0, 0.0));
+        assert_close(right.cast_ray(0.5, 0.0).unwrap().origin, Vector3D::new(0.0, 1.0, -0.25));
+    }
+
+    #[test]
+    fn eyes_are_packed_by_layout() {
+        let mut left = Framebuffer::<u8>::new(2, 1, 1);
+        let mut right = Framebuffer::<u8>::new(2, 1, 1);
+        left.pixel_mut(1, 0)[0] = 1;
+        right.pixel_mut(0, 0)[0] = 2;
+        assert_eq!(pack(&left, &right, StereoLayout::SideBySide).as_slice(), [0, 1, 2, 0]);
+        assert_eq!(pack(&left, &right, StereoLayout::OverUnder).as_slice(), [0, 1, 2, 0]);
+        assert_eq!(StereoLayout::OverUnder.frame_size(2, 1), (2, 2));
+    }
+}
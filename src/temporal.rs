@@ -0,0 +1,475 @@
+use crate::framebuffer::Framebuffer;
+use crate::ray::Ray;
+use crate::scene::{Camera, Projection, Scene};
+use crate::vector3d::Vector3D;
+use rayon::prelude::*;
+use std::io::{self, Read, Write};
+
+/// Accumulates successive animation frames into a running history.
+///
+/// Each pixel is reprojected into the previous frame with the depth of the
+/// surface through its centre and the camera motion, giving a screen-space
+/// motion vector. History taps that saw another object, or whose depth
+/// doesn't match, are treated as disocclusions and dropped, and the
+/// remaining history is clipped to the current frame's 3x3 colour
+/// distribution so lighting changes (the moving light's shadows) don't
+/// leave trails. Motion vectors only follow the camera, so pixels on
+/// `moving_objects` start afresh every frame rather than smear.
+///
+/// Shading here is noise-free, so any lag is pure error when the light
+/// moves; the defaults keep a short history and a tight clip. Lower
+/// `min_blend` for scenes whose lighting changes slowly.
+pub struct TemporalAccumulator {
+    pub width: u32,
+    pub height: u32,
+    /// Smallest weight given to the new frame, which caps the effective
+    /// history at about `1 / min_blend` frames
+    pub min_blend: f32,
+    /// Depth difference, relative to the expected depth, above which a
+    /// history tap counts as a different surface
+    pub depth_tolerance: f32,
+    /// Width of the colour box history is clipped to, in standard deviations
+    /// of the current frame's 3x3 neighbourhood
+    pub clip_gamma: f32,
+    /// Objects that move by themselves, by index in the scene, e.g. from
+    /// `SceneDescription::moving_objects`
+    pub moving_objects: Vec<bool>,
+    history: Option<History>,
+}
+
+/// Nearest surface along the ray through a pixel's centre. Unlike the
+/// depth AOV it isn't filtered, so edges don't blend two surfaces' depths.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Surface {
+    /// Depth as `Camera::depth_of` measures it, infinite for misses
+    pub depth: f32,
+    /// Index of the object in the scene, `None` for misses
+    pub object: Option<u32>,
+}
+
+impl Surface {
+    pub const MISS: Surface = Surface {
+        depth: f32::INFINITY,
+        object: None,
+    };
+}
+
+struct History {
+    camera: Camera,
+    color: Vec<[f32; 3]>,
+    surfaces: Vec<Surface>,
+    /// Number of frames accumulated per pixel
+    length: Vec<f32>,
+}
+
+/// Where a pixel's surface was in the previous frame
+struct Reprojection {
+    /// Continuous pixel coordinates in the previous frame
+    x: f64,
+    y: f64,
+    /// Expected depth there, infinite for the background
+    depth: f32,
+}
+
+impl TemporalAccumulator {
+    pub fn new(width: u32, height: u32) -> TemporalAccumulator {
+        TemporalAccumulator {
+            width,
+            height,
+            min_blend: 0.5,
+            depth_tolerance: 0.02,
+            clip_gamma: 0.5,
+            moving_objects: Vec::new(),
+            history: None,
+        }
+    }
+
+    /// Drops the history, e.g. on a camera cut
+    pub fn reset(&mut self) {
+        self.history = None;
+    }
+
+    /// Traces the ray through the centre of every pixel of a frame taken
+    /// with `camera`, for `accumulate`
+    pub fn surfaces(&self, scene: &Scene, camera: &Camera) -> Vec<Surface> {
+        let width = self.width as usize;
+        let mut surfaces = vec![Surface::MISS; width * self.height as usize];
+        surfaces.par_chunks_mut(width).enumerate().for_each(|(y, row)| {
+            for (x, surface) in row.iter_mut().enumerate() {
+                let hit = self.pixel_ray(x, y, camera).and_then(|ray| scene.closest_hit(&ray));
+                if let Some((index, hit)) = hit {
+                    *surface = Surface {
+                        depth: camera.depth_of(hit.point) as f32,
+                        object: Some(index as u32),
+                    };
+                }
+            }
+        });
+        surfaces
+    }
+
+    /// Blends `image` into the history and returns the accumulated frame.
+    ///
+    /// `surfaces` are those of the same frame, taken with `camera`.
+    pub fn accumulate(&mut self, image: &Framebuffer, surfaces: &[Surface], camera: &Camera) -> Framebuffer {
+        let width = self.width as usize;
+        let height = self.height as usize;
+        let current: Vec<[f32; 3]> = (0..image.height)
+            .flat_map(|y| image.row(y).chunks_exact(image.channels).map(|pixel| [pixel[0], pixel[1], pixel[2]]))
+            .collect();
+
+        let mut color = vec![[0.0f32; 3]; width * height];
+        let mut length = vec![1.0f32; width * height];
+
+        match &self.history {
+            None => color.copy_from_slice(&current),
+            Some(history) => {
+                color
+                    .par_chunks_mut(width)
+                    .zip(length.par_chunks_mut(width))
+                    .enumerate()
+                    .for_each(|(y, (color_row, length_row))| {
+                        for x in 0..width {
+                            let index = y * width + x;
+                            let surface = surfaces[index];
+                            let reprojected = if self.is_moving(surface) {
+                                None
+                            } else {
+                                self.reproject(x, y, surface.depth, camera, &history.camera)
+                                    .and_then(|reprojection| self.sample_history(history, surface, &reprojection))
+                            };
+
+                            let Some((history_color, history_length)) = reprojected else {
+                                color_row[x] = current[index];
+                                continue;
+                            };
+
+                            let (low, high) = neighbourhood_bounds(&current, width, height, x, y, self.clip_gamma);
+                            let mut blended = [0.0; 3];
+                            let frames = history_length + 1.0;
+                            let alpha = (1.0 / frames).max(self.min_blend);
+                            for channel in 0..3 {
+                                let clamped = history_color[channel].clamp(low[channel], high[channel]);
+                                blended[channel] = clamped + (current[index][channel] - clamped) * alpha;
+                            }
+
+                            color_row[x] = blended;
+                            length_row[x] = frames;
+                        }
+                    });
+            }
+        }
+
+        let data = color.iter().flatten().copied().collect();
+        self.history = Some(History {
+            camera: *camera,
+            color,
+            surfaces: surfaces.to_vec(),
+            length,
+        });
+
+        Framebuffer::from_raw(self.width, self.height, 3, width * 3, data).expect("history has the image's size")
+    }
+
+    fn is_moving(&self, surface: Surface) -> bool {
+        surface
+            .object
+            .is_some_and(|object| self.moving_objects.get(object as usize).copied().unwrap_or(false))
+    }
+
+    /// Camera ray through the centre of pixel (`x`, `y`)
+    fn pixel_ray(&self, x: usize, y: usize, camera: &Camera) -> Option<Ray> {
+        let ndc_x = ((x as f64 + 0.5) / self.width as f64) * 2.0 - 1.0;
+        let ndc_y = ((y as f64 + 0.5) / self.height as f64) * 2.0 - 1.0;
+        camera.cast_ray(ndc_x, ndc_y)
+    }
+
+    /// Follows the camera ray through pixel (`x`, `y`) to its surface and
+    /// projects that into `previous`. Background pixels are reprojected by
+    /// direction alone, as if infinitely far away.
+    fn reproject(&self, x: usize, y: usize, depth: f32, camera: &Camera, previous: &Camera) -> Option<Reprojection> {
+        let ray = self.pixel_ray(x, y, camera)?;
+
+        let (point, expected_depth) = if depth.is_finite() {
+            let point = ray.at(depth as f64 / camera.depth_of(ray.at(1.0)));
+            (point, previous.depth_of(point) as f32)
+        } else {
+            // Moved along with the camera, so orthographic rays keep their place
+            (ray.origin + (previous.position - camera.position) + ray.direction, f32::INFINITY)
+        };
+
+        let (previous_x, previous_y) = previous.project(point)?;
+        Some(Reprojection {
+            x: (previous_x + 1.0) * 0.5 * self.width as f64,
+            y: (previous_y + 1.0) * 0.5 * self.height as f64,
+            depth: expected_depth,
+        })
+    }
+
+    /// Bilinearly filtered history colour and length at a reprojected
+    /// position, skipping taps that belong to a different surface than
+    /// `surface`
+    fn sample_history(
+        &self,
+        history: &History,
+        surface: Surface,
+        reprojection: &Reprojection,
+    ) -> Option<([f32; 3], f32)> {
+        let fx = reprojection.x - 0.5;
+        let fy = reprojection.y - 0.5;
+        let x0 = fx.floor();
+        let y0 = fy.floor();
+        let tx = (fx - x0) as f32;
+        let ty = (fy - y0) as f32;
+
+        let mut color = [0.0f32; 3];
+        let mut length = 0.0f32;
+        let mut total_weight = 0.0f32;
+
+        for (dx, dy, weight) in [
+            (0, 0, (1.0 - tx) * (1.0 - ty)),
+            (1, 0, tx * (1.0 - ty)),
+            (0, 1, (1.0 - tx) * ty),
+            (1, 1, tx * ty),
+        ] {
+            let px = x0 as i64 + dx;
+            let py = y0 as i64 + dy;
+            if weight <= 0.0 || px < 0 || py < 0 || px >= self.width as i64 || py >= self.height as i64 {
+                continue;
+            }
+
+            let index = py as usize * self.width as usize + px as usize;
+            // Matching objects are both hits or both misses
+            let tap = history.surfaces[index];
+            let same_surface = tap.object == surface.object
+                && (tap.object.is_none()
+                    || (tap.depth - reprojection.depth).abs() <= self.depth_tolerance * reprojection.depth);
+            if !same_surface {
+                continue;
+            }
+
+            for (sum, value) in color.iter_mut().zip(history.color[index]) {
+                *sum += value * weight;
+            }
+            length += history.length[index] * weight;
+            total_weight += weight;
+        }
+
+        // Mostly disoccluded: better to restart than to trust one corner tap
+        if total_weight < 0.25 {
+            return None;
+        }
+
+        let inv_weight = 1.0 / total_weight;
+        Some((
+            [color[0] * inv_weight, color[1] * inv_weight, color[2] * inv_weight],
+            length * inv_weight,
+        ))
+    }
+
+    /// Writes the history so accumulation can continue after a restart
+    pub fn write_history(&self, writer: &mut impl Write) -> io::Result<()> {
+        let Some(history) = &self.history else {
+            return writer.write_all(&[0]);
+        };
+        writer.write_all(&[1])?;
+
+        let camera = &history.camera;
+        let (projection, projection_parameter) = camera.projection.encode();
+        for value in [
+            camera.position.x,
+            camera.position.y,
+            camera.position.z,
+            camera.target.x,
+            camera.target.y,
+            camera.target.z,
+            camera.up.x,
+            camera.up.y,
+            camera.up.z,
+            camera.fov,
+            camera.aspect_ratio,
+            projection as f64,
+            projection_parameter,
+            camera.roll,
+            camera.shift_x,
+            camera.shift_y,
+        ] {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+
+        for ((color, surface), length) in history.color.iter().zip(&history.surfaces).zip(&history.length) {
+            for value in [color[0], color[1], color[2], surface.depth, *length] {
+                writer.write_all(&value.to_le_bytes())?;
+            }
+            writer.write_all(&surface.object.unwrap_or(u32::MAX).to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Counterpart of `write_history` for an accumulator of the same size
+    pub fn read_history(&mut self, reader: &mut impl Read) -> io::Result<()> {
+        let mut present = [0u8; 1];
+        reader.read_exact(&mut present)?;
+        if present[0] == 0 {
+            self.history = None;
+            return Ok(());
+        }
+
+        let mut camera_values = [0.0f64; 16];
+        for value in &mut camera_values {
+            let mut bytes = [0u8; 8];
+            reader.read_exact(&mut bytes)?;
+            *value = f64::from_le_bytes(bytes);
+        }
+        let [px, py, pz, tx, ty, tz, ux, uy, uz, fov, aspect_ratio, projection, projection_parameter, roll, shift_x, shift_y] =
+            camera_values;
+        let projection = Projection::decode(projection as u32, projection_parameter)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "unknown camera projection"))?;
+        let camera = Camera {
+            position: Vector3D::new(px, py, pz),
+            target: Vector3D::new(tx, ty, tz),
+            up: Vector3D::new(ux, uy, uz),
+            fov,
+            aspect_ratio,
+            projection,
+            roll,
+            shift_x,
+            shift_y,
+        };
+
+        let count = self.width as usize * self.height as usize;
+        let mut history = History {
+            camera,
+            color: Vec::with_capacity(count),
+            surfaces: Vec::with_capacity(count),
+            length: Vec::with_capacity(count),
+        };
+        let mut read_bytes = || -> io::Result<[u8; 4]> {
+            let mut bytes = [0u8; 4];
+            reader.read_exact(&mut bytes)?;
+            Ok(bytes)
+        };
+        for _ in 0..count {
+            let [r, g, b, depth, length] = [(); 5].map(|_| read_bytes().map(f32::from_le_bytes));
+            let object = u32::from_le_bytes(read_bytes()?);
+            history.color.push([r?, g?, b?]);
+            history.surfaces.push(Surface {
+                depth: depth?,
+                object: (object != u32::MAX).then_some(object),
+            });
+            history.length.push(length?);
+        }
+
+        self.history = Some(history);
+        Ok(())
+    }
+}
+
+/// Per-channel colour box of the 3x3 neighbourhood of (`x`, `y`): the mean
+/// plus or minus `gamma` standard deviations (variance clipping, Salvi 2016)
+#[inline]
+fn neighbourhood_bounds(
+    image: &[[f32; 3]],
+    width: usize,
+    height: usize,
+    x: usize,
+    y: usize,
+    gamma: f32,
+) -> ([f32; 3], [f32; 3]) {
+    let mut sum = [0.0f32; 3];
+    let mut sum_squared = [0.0f32; 3];
+    let mut count = 0.0f32;
+
+    for ny in y.saturating_sub(1)..(y + 2).min(height) {
+        for nx in x.saturating_sub(1)..(x + 2).min(width) {
+            let color = image[ny * width + nx];
+            for channel in 0..3 {
+                sum[channel] += color[channel];
+                sum_squared[channel] += color[channel] * color[channel];
+            }
+            count += 1.0;
+        }
+    }
+
+    let mut low = [0.0f32; 3];
+    let mut high = [0.0f32; 3];
+    for channel in 0..3 {
+        let mean = sum[channel] / count;
+        let deviation = (sum_squared[channel] / count - mean * mean).max(0.0).sqrt();
+        low[channel] = mean - gamma * deviation;
+        high[channel] = mean + gamma * deviation;
+    }
+
+    (low, high)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scene_file::SceneDescription;
+    use crate::sphere::{Color, Material, Sphere};
+
+    fn frame(values: &[f32]) -> Framebuffer {
+        let data = values.iter().flat_map(|&value| [value; 3]).collect();
+        Framebuffer::from_raw(values.len() as u32, 1, 3, values.len() * 3, data).unwrap()
+    }
+
+    fn hit(object: u32, depth: f32) -> Surface {
+        Surface {
+            depth,
+            object: Some(object),
+        }
+    }
+
+    #[test]
+    fn history_is_kept_only_for_the_same_still_surface() {
+        let camera = Camera::new(Vector3D::ZERO, Vector3D::new(0.0, 0.0, 1.0), 60.0, 5.0);
+        let mut accumulator = TemporalAccumulator::new(5, 1);
+        accumulator.clip_gamma = 100.0;
+        accumulator.moving_objects = vec![false, false, true];
+        let before = [hit(0, 4.0), hit(0, 4.0), hit(2, 4.0), Surface::MISS, hit(0, 4.0)];
+        accumulator.accumulate(&frame(&[0.5; 5]), &before, &camera);
+
+        // The history survives a checkpoint
+        let mut bytes = Vec::new();
+        accumulator.write_history(&mut bytes).unwrap();
+        let mut accumulator = TemporalAccumulator {
+            clip_gamma: 100.0,
+            moving_objects: vec![false, false, true],
+            ..TemporalAccumulator::new(5, 1)
+        };
+        accumulator.read_history(&mut bytes.as_slice()).unwrap();
+
+        // Same surface, another object, a moving object, background and a
+        // surface that has come forward
+        let after = [hit(0, 4.0), hit(1, 4.0), hit(2, 4.0), Surface::MISS, hit(0, 5.0)];
+        let image = accumulator.accumulate(&frame(&[1.0, 0.0, 1.0, 0.0, 1.0]), &after, &camera);
+        let values: Vec<f64> = (0..5).map(|x| image.color(x, 0).r).collect();
+        for (value, expected) in values.iter().zip([0.75, 0.0, 1.0, 0.25, 1.0]) {
+            assert!((value - expected).abs() < 1e-4, "{:?}", values);
+        }
+    }
+
+    #[test]
+    fn surfaces_are_the_nearest_hits_through_pixel_centres() {
+        let mut scene = SceneDescription::default().to_scene();
+        let material = Material {
+            color: Color::WHITE,
+            diffuse: 1.0,
+            specular: 0.0,
+            shininess: 1.0,
+            reflectivity: 0.0,
+            pbr: None,
+            emission: Color::default(),
+            texture: None,
+            normal_map: None,
+        };
+        scene.add_object(Box::new(Sphere::new(Vector3D::new(0.0, 0.0, 5.0), 1.0, material)));
+        let camera = Camera::new(Vector3D::ZERO, Vector3D::new(0.0, 0.0, 1.0), 60.0, 1.0);
+
+        let surfaces = TemporalAccumulator::new(3, 3).surfaces(&scene, &camera);
+        assert_eq!(surfaces[4].object, Some(0));
+        assert!((surfaces[4].depth - 4.0).abs() < 1e-5);
+        assert_eq!(surfaces[0], Surface::MISS);
+    }
+}
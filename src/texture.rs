@@ -0,0 +1,68 @@
+use crate::sphere::Color;
+use std::path::Path;
+
+/// Handle to a texture owned by a scene, from `Scene::add_texture`. Lets
+/// materials refer to textures while staying plain `Copy` data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextureId(pub usize);
+
+/// RGB image looked up by UV, e.g. a mesh's base colour map.
+///
+/// Texels are kept as stored in the file; like the rest of the tracer they
+/// are display values, with no colour space conversion.
+#[derive(Debug, Clone)]
+pub struct Texture {
+    pub width: u32,
+    pub height: u32,
+    texels: Vec<Color>,
+}
+
+impl Texture {
+    /// Texture from `width * height` texels in rows from the top
+    pub fn new(width: u32, height: u32, texels: Vec<Color>) -> Texture {
+        assert_eq!(texels.len(), width as usize * height as usize, "texel count must match the size");
+        Texture { width, height, texels }
+    }
+
+    pub fn from_image(image: &image::DynamicImage) -> Texture {
+        let rgb = image.to_rgb8();
+        let texels = rgb
+            .pixels()
+            .map(|pixel| Color {
+                r: pixel[0] as f64 / 255.0,
+                g: pixel[1] as f64 / 255.0,
+                b: pixel[2] as f64 / 255.0,
+            })
+            .collect();
+        Texture::new(rgb.width(), rgb.height(), texels)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> image::ImageResult<Texture> {
+        Ok(Texture::from_image(&image::open(path)?))
+    }
+
+    #[inline]
+    fn texel(&self, x: i64, y: i64) -> Color {
+        let x = x.rem_euclid(self.width as i64) as usize;
+        let y = y.rem_euclid(self.height as i64) as usize;
+        self.texels[y * self.width as usize + x]
+    }
+
+    /// Bilinearly filtered colour at `(u, v)`, repeating outside [0, 1].
+    /// `v` runs down the image, as in glTF.
+    pub fn sample(&self, u: f64, v: f64) -> Color {
+        if self.texels.is_empty() {
+            return Color::default();
+        }
+
+        let x = u * self.width as f64 - 0.5;
+        let y = v * self.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let top = self.texel(x0, y0) * (1.0 - fx) + self.texel(x0 + 1, y0) * fx;
+        let bottom = self.texel(x0, y0 + 1) * (1.0 - fx) + self.texel(x0 + 1, y0 + 1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+}
@@ -0,0 +1,279 @@
+use crate::framebuffer::Framebuffer;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TileOrder {
+    Scanline,
+    Hilbert,
+    Spiral,
+}
+
+impl TileOrder {
+    pub fn from_name(name: &str) -> Option<TileOrder> {
+        match name {
+            "scanline" => Some(TileOrder::Scanline),
+            "hilbert" => Some(TileOrder::Hilbert),
+            "spiral" => Some(TileOrder::Spiral),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            TileOrder::Scanline => "scanline",
+            TileOrder::Hilbert => "hilbert",
+            TileOrder::Spiral => "spiral",
+        }
+    }
+}
+
+/// Pixel rectangle `[x0, x1) x [y0, y1)`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tile {
+    pub x0: u32,
+    pub y0: u32,
+    pub x1: u32,
+    pub y1: u32,
+}
+
+impl Tile {
+    pub fn width(&self) -> u32 {
+        self.x1.saturating_sub(self.x0)
+    }
+
+    pub fn height(&self) -> u32 {
+        self.y1.saturating_sub(self.y0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width() == 0 || self.height() == 0
+    }
+}
+
+/// Part of an image to render on its own, framed exactly as in the full image
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CropWindow {
+    /// Pixels `[x0, x1) x [y0, y1)`
+    Pixels(Tile),
+    /// Fractions of the image size, 0 to 1 in the same directions as pixels
+    Normalized { x0: f64, y0: f64, x1: f64, y1: f64 },
+}
+
+impl CropWindow {
+    /// Parses `x0,y0,x1,y1`, in pixels, or as fractions of the image when
+    /// any value has a decimal point
+    pub fn parse(text: &str) -> Option<CropWindow> {
+        let values: Vec<&str> = text.split(',').map(str::trim).collect();
+        if values.len() != 4 {
+            return None;
+        }
+
+        if values.iter().any(|value| value.contains('.')) {
+            let mut fractions = [0.0; 4];
+            for (fraction, value) in fractions.iter_mut().zip(&values) {
+                *fraction = value.parse::<f64>().ok().filter(|f| (0.0..=1.0).contains(f))?;
+            }
+            let [x0, y0, x1, y1] = fractions;
+            (x0 < x1 && y0 < y1).then_some(CropWindow::Normalized { x0, y0, x1, y1 })
+        } else {
+            let mut pixels = [0u32; 4];
+            for (pixel, value) in pixels.iter_mut().zip(&values) {
+                *pixel = value.parse().ok()?;
+            }
+            let [x0, y0, x1, y1] = pixels;
+            (x0 < x1 && y0 < y1).then_some(CropWindow::Pixels(Tile { x0, y0, x1, y1 }))
+        }
+    }
+
+    /// The pixels of a `width` x `height` image inside the window, which may
+    /// be empty. Normalised windows grow outwards to whole pixels.
+    pub fn to_pixels(self, width: u32, height: u32) -> Tile {
+        let tile = match self {
+            CropWindow::Pixels(tile) => tile,
+            CropWindow::Normalized { x0, y0, x1, y1 } => Tile {
+                x0: (x0 * width as f64).floor().max(0.0) as u32,
+                y0: (y0 * height as f64).floor().max(0.0) as u32,
+                x1: (x1 * width as f64).ceil().max(0.0) as u32,
+                y1: (y1 * height as f64).ceil().max(0.0) as u32,
+            },
+        };
+
+        Tile {
+            x0: tile.x0.min(width),
+            y0: tile.y0.min(height),
+            x1: tile.x1.min(width),
+            y1: tile.y1.min(height),
+        }
+    }
+}
+
+/// Splits the image into tiles of `tile_size` pixels, sorted in render order
+pub fn generate_tiles(width: u32, height: u32, tile_size: u32, order: TileOrder) -> Vec<Tile> {
+    let tile_size = tile_size.max(1);
+    let tiles_x = width.div_ceil(tile_size);
+    let tiles_y = height.div_ceil(tile_size);
+
+    let mut grid: Vec<(u32, u32)> = (0..tiles_y)
+        .flat_map(|ty| (0..tiles_x).map(move |tx| (tx, ty)))
+        .collect();
+
+    match order {
+        TileOrder::Scanline => {}
+        TileOrder::Hilbert => {
+            let side = tiles_x.max(tiles_y).next_power_of_two();
+            grid.sort_by_key(|&(tx, ty)| hilbert_index(side, tx, ty));
+        }
+        TileOrder::Spiral => {
+            // Outward from the centre, ring by ring, each ring walked by angle
+            let center_x = (tiles_x as f64 - 1.0) * 0.5;
+            let center_y = (tiles_y as f64 - 1.0) * 0.5;
+            grid.sort_by(|&(ax, ay), &(bx, by)| {
+                let key = |tx: u32, ty: u32| {
+                    let dx = tx as f64 - center_x;
+                    let dy = ty as f64 - center_y;
+                    (dx.abs().max(dy.abs()), dy.atan2(dx))
+                };
+                let (ring_a, angle_a) = key(ax, ay);
+                let (ring_b, angle_b) = key(bx, by);
+                ring_a
+                    .total_cmp(&ring_b)
+                    .then(angle_a.total_cmp(&angle_b))
+            });
+        }
+    }
+
+    grid.into_iter()
+        .map(|(tx, ty)| Tile {
+            x0: tx * tile_size,
+            y0: ty * tile_size,
+            x1: ((tx + 1) * tile_size).min(width),
+            y1: ((ty + 1) * tile_size).min(height),
+        })
+        .collect()
+}
+
+/// Distance along a Hilbert curve filling a `side` x `side` grid
+fn hilbert_index(side: u32, mut x: u32, mut y: u32) -> u64 {
+    let mut index = 0u64;
+    let mut s = side / 2;
+
+    while s > 0 {
+        let rx = u32::from(x & s > 0);
+        let ry = u32::from(y & s > 0);
+        index += s as u64 * s as u64 * ((3 * rx) ^ ry) as u64;
+
+        if ry == 0 {
+            if rx == 1 {
+                x = side - 1 - x;
+                y = side - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+
+    index
+}
+
+/// Shared flag for cooperative cancellation.
+///
+/// Workers check it between tiles, so a cancelled render stops after the
+/// tiles currently in flight finish.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TileProgress {
+    pub tile: Tile,
+    pub tiles_completed: usize,
+    pub tiles_total: usize,
+}
+
+impl TileProgress {
+    pub fn fraction(&self) -> f64 {
+        self.tiles_completed as f64 / self.tiles_total.max(1) as f64
+    }
+}
+
+/// How a tiled render is scheduled and observed
+pub struct Tiling<'a> {
+    pub tile_size: u32,
+    pub order: TileOrder,
+    pub progress: Option<&'a (dyn Fn(TileProgress) + Sync)>,
+    pub cancel: Option<CancelToken>,
+}
+
+impl Default for Tiling<'_> {
+    fn default() -> Self {
+        Tiling {
+            tile_size: 32,
+            order: TileOrder::Scanline,
+            progress: None,
+            cancel: None,
+        }
+    }
+}
+
+/// Result of a tiled render; tiles that never ran are left black
+pub struct TiledRender {
+    pub image: Framebuffer,
+    pub tiles_completed: usize,
+    pub tiles_total: usize,
+    pub cancelled: bool,
+}
+
+impl TiledRender {
+    pub fn is_complete(&self) -> bool {
+        self.tiles_completed == self.tiles_total
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_order_covers_each_pixel_once() {
+        let (width, height) = (70, 45);
+        for order in [TileOrder::Scanline, TileOrder::Hilbert, TileOrder::Spiral] {
+            let mut coverage = vec![0; (width * height) as usize];
+            for tile in generate_tiles(width, height, 16, order) {
+                assert!(!tile.is_empty());
+                for y in tile.y0..tile.y1 {
+                    for x in tile.x0..tile.x1 {
+                        coverage[(y * width + x) as usize] += 1;
+                    }
+                }
+            }
+            assert!(coverage.iter().all(|&count| count == 1), "{}", order.name());
+        }
+    }
+
+    #[test]
+    fn hilbert_tiles_follow_on_from_their_neighbours() {
+        let tiles = generate_tiles(64, 64, 8, TileOrder::Hilbert);
+        assert_eq!(tiles.len(), 64);
+        assert_eq!((tiles[0].x0, tiles[0].y0), (0, 0));
+        for pair in tiles.windows(2) {
+            let step = pair[0].x0.abs_diff(pair[1].x0) + pair[0].y0.abs_diff(pair[1].y0);
+            assert_eq!(step, 8, "{:?} to {:?}", pair[0], pair[1]);
+        }
+    }
+}
@@ -0,0 +1,202 @@
+use crate::hittable::{tangent_frame, HitRecord, Hittable};
+use crate::ray::Ray;
+use crate::sphere::Material;
+use crate::transform::Frame;
+use crate::vector3d::Vector3D;
+use std::f64::consts::PI;
+
+/// Ring around the `frame.w` axis: a tube of `minor_radius` swept along a
+/// circle of `major_radius` in the frame's uv plane
+#[derive(Debug, Clone, Copy)]
+pub struct Torus {
+    pub frame: Frame,
+    pub major_radius: f64,
+    pub minor_radius: f64,
+    pub material: Material,
+}
+
+impl Torus {
+    pub fn new(center: Vector3D, axis: Vector3D, major_radius: f64, minor_radius: f64, material: Material) -> Torus {
+        Torus {
+            frame: Frame::from_axis(center, axis),
+            major_radius,
+            minor_radius,
+            material,
+        }
+    }
+}
+
+impl Hittable for Torus {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let local = self.frame.ray_to_local(ray);
+        let length = local.direction.magnitude();
+        let direction = local.direction * (1.0 / length);
+
+        // Start from the bounding sphere so the quartic's coefficients stay
+        // small; distant rays otherwise lose most of their precision. The
+        // sphere touches the tube along the equator, so back off by the minor
+        // radius to keep in-plane rays from starting on a root
+        let outer = self.major_radius + self.minor_radius;
+        let n = local.origin.dot(direction);
+        let h = n * n - local.origin.magnitude_squared() + outer * outer;
+        if h < 0.0 {
+            return None;
+        }
+        let start = (-n - h.sqrt() - self.minor_radius).max(0.0);
+        let origin = local.origin + direction * start;
+
+        let t = torus_roots(origin, direction, self.major_radius, self.minor_radius)
+            .into_iter()
+            .map(|s| (start + s) / length)
+            .filter(|t| *t >= t_min && *t <= t_max)
+            .min_by(f64::total_cmp)?;
+
+        let point = local.origin + local.direction * t;
+        let r2 = self.minor_radius * self.minor_radius;
+        let big_r2 = self.major_radius * self.major_radius;
+        let normal = point * Vector3D::splat(point.magnitude_squared() - r2) - Vector3D::new(big_r2, big_r2, -big_r2) * point;
+
+        let ring = (point.x * point.x + point.y * point.y).sqrt();
+        let uv = (
+            point.y.atan2(point.x) / (2.0 * PI) + 0.5,
+            point.z.atan2(ring - self.major_radius) / (2.0 * PI) + 0.5,
+        );
+
+        // u runs around the main ring and v around the tube
+        let normal = normal.normalize();
+        let around_tube = Vector3D::new(-point.x * point.z, -point.y * point.z, ring * (ring - self.major_radius));
+        let (tangent, bitangent) = tangent_frame(normal, Vector3D::new(-point.y, point.x, 0.0), around_tube);
+
+        Some(HitRecord {
+            point: ray.at(t),
+            normal: self.frame.to_world(normal),
+            t,
+            uv,
+            tangent: self.frame.to_world(tangent),
+            bitangent: self.frame.to_world(bitangent),
+            material: self.material,
+        })
+    }
+}
+
+/// Distances along the unit direction `rd` at which the ray meets a torus
+/// around the z axis; missing roots are NaN. Solves the quartic through its
+/// resolvent cubic (after Inigo Quilez), the same way as `raytracer.wgsl`.
+fn torus_roots(ro: Vector3D, rd: Vector3D, major_radius: f64, minor_radius: f64) -> [f64; 4] {
+    let mut roots = [f64::NAN; 4];
+    let big_r2 = major_radius * major_radius;
+    let r2 = minor_radius * minor_radius;
+    let m = ro.magnitude_squared();
+    let n = ro.dot(rd);
+
+    let k = (m - r2 - big_r2) * 0.5;
+    let mut k3 = n;
+    let mut k2 = n * n + big_r2 * rd.z * rd.z + k;
+    let mut k1 = k * n + big_r2 * ro.z * rd.z;
+    let mut k0 = k * k + big_r2 * ro.z * ro.z - big_r2 * r2;
+
+    // Solve for the reciprocal roots instead when the cubic term nearly vanishes
+    let inverted = (k3 * (k3 * k3 - k2) + k1).abs() < 0.01;
+    if inverted {
+        std::mem::swap(&mut k1, &mut k3);
+        k0 = 1.0 / k0;
+        k1 *= k0;
+        k2 *= k0;
+        k3 *= k0;
+    }
+
+    let c2 = (2.0 * k2 - 3.0 * k3 * k3) / 3.0;
+    let c1 = (k3 * (k3 * k3 - k2) + k1) * 2.0;
+    let c0 = (k3 * (k3 * (-3.0 * k3 * k3 + 4.0 * k2) - 8.0 * k1) + 4.0 * k0) / 3.0;
+
+    let q = c2 * c2 + c0;
+    let r = 3.0 * c0 * c2 - c2 * c2 * c2 - c1 * c1;
+    let h = r * r - q * q * q;
+    let z = if h < 0.0 {
+        let sq = q.sqrt();
+        2.0 * sq * ((r / (sq * q)).clamp(-1.0, 1.0).acos() / 3.0).cos()
+    } else {
+        let sq = (h.sqrt() + r.abs()).cbrt();
+        r.signum() * (sq + q / sq).abs()
+    };
+    let z = c2 - z;
+
+    let mut d1 = z - 3.0 * c2;
+    let mut d2 = z * z - 3.0 * c0;
+    if d1.abs() < 1e-4 {
+        if d2 < 0.0 {
+            return roots;
+        }
+        d2 = d2.sqrt();
+    } else {
+        if d1 < 0.0 {
+            return roots;
+        }
+        d1 = (d1 * 0.5).sqrt();
+        d2 = c1 / d1;
+    }
+
+    let fix = |t: f64| if inverted { 2.0 / t } else { t };
+    let h = d1 * d1 - z + d2;
+    if h > 0.0 {
+        let h = h.sqrt();
+        roots[0] = fix(-d1 - h - k3);
+        roots[1] = fix(-d1 + h - k3);
+    }
+    let h = d1 * d1 - z - d2;
+    if h > 0.0 {
+        let h = h.sqrt();
+        roots[2] = fix(d1 - h - k3);
+        roots[3] = fix(d1 + h - k3);
+    }
+
+    roots
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sphere::Color;
+
+    const MATERIAL: Material = Material {
+        color: Color::WHITE,
+        diffuse: 1.0,
+        specular: 0.0,
+        shininess: 1.0,
+        reflectivity: 0.0,
+        pbr: None,
+        emission: Color { r: 0.0, g: 0.0, b: 0.0 },
+        texture: None,
+        normal_map: None,
+    };
+
+    #[test]
+    fn rays_in_the_rings_plane_hit_the_outer_equator() {
+        let ring = Torus::new(Vector3D::ZERO, Vector3D::new(0.0, 0.0, 1.0), 2.0, 0.5, MATERIAL);
+        for step in 0..16 {
+            let angle = step as f64 * PI / 8.0;
+            let outward = Vector3D::new(angle.cos(), angle.sin(), 0.0);
+            for distance in [3.0, 10.0, 1000.0] {
+                let ray = Ray::new(outward * distance, outward * -1.0);
+                let hit = ring.hit(&ray, 1e-6, f64::INFINITY).unwrap_or_else(|| panic!("missed at {} from {}", angle, distance));
+                assert!((hit.t - (distance - 2.5)).abs() < 1e-6 * distance, "t = {} from {}", hit.t, distance);
+                assert!((hit.normal - outward).magnitude() < 1e-6, "{:?}", hit.normal);
+            }
+        }
+
+        // From the centre, out through the inner equator
+        let hit = ring.hit(&Ray::new(Vector3D::ZERO, Vector3D::new(0.0, 1.0, 0.0)), 1e-6, f64::INFINITY).unwrap();
+        assert!((hit.t - 1.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn rays_along_the_axis_pass_through_the_hole() {
+        let ring = Torus::new(Vector3D::ZERO, Vector3D::new(0.0, 0.0, 1.0), 2.0, 0.5, MATERIAL);
+        let down = Vector3D::new(0.0, 0.0, -1.0);
+        assert!(ring.hit(&Ray::new(Vector3D::new(0.0, 0.0, 5.0), down), 1e-6, f64::INFINITY).is_none());
+
+        let hit = ring.hit(&Ray::new(Vector3D::new(2.0, 0.0, 5.0), down), 1e-6, f64::INFINITY).unwrap();
+        assert!((hit.t - 4.5).abs() < 1e-9);
+        assert!((hit.normal - Vector3D::new(0.0, 0.0, 1.0)).magnitude() < 1e-9);
+    }
+}
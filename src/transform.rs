@@ -0,0 +1,214 @@
+use crate::ray::Ray;
+use crate::vector3d::{Matrix4, Normal3D, Point3D, Quaternion, Vector3D};
+use std::ops::Mul;
+
+/// Object-to-world transform stored together with its inverse, so neither
+/// direction ever needs a matrix inversion at render time.
+///
+/// `a * b` applies `b` first, then `a`; `a.then(b)` reads in application order.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub matrix: Matrix4,
+    pub inverse: Matrix4,
+}
+
+impl Transform {
+    pub const IDENTITY: Transform = Transform {
+        matrix: Matrix4::IDENTITY,
+        inverse: Matrix4::IDENTITY,
+    };
+
+    /// Wraps an arbitrary matrix, or returns `None` if it can't be inverted
+    pub fn from_matrix(matrix: Matrix4) -> Option<Transform> {
+        Some(Transform {
+            matrix,
+            inverse: matrix.inverse()?,
+        })
+    }
+
+    pub fn translate(offset: Vector3D) -> Transform {
+        let mut matrix = Matrix4::IDENTITY;
+        let mut inverse = Matrix4::IDENTITY;
+        matrix.m[0][3] = offset.x;
+        matrix.m[1][3] = offset.y;
+        matrix.m[2][3] = offset.z;
+        inverse.m[0][3] = -offset.x;
+        inverse.m[1][3] = -offset.y;
+        inverse.m[2][3] = -offset.z;
+        Transform { matrix, inverse }
+    }
+
+    /// Non-uniform scale; components must be non-zero
+    pub fn scale(factors: Vector3D) -> Transform {
+        let mut matrix = Matrix4::IDENTITY;
+        let mut inverse = Matrix4::IDENTITY;
+        matrix.m[0][0] = factors.x;
+        matrix.m[1][1] = factors.y;
+        matrix.m[2][2] = factors.z;
+        inverse.m[0][0] = 1.0 / factors.x;
+        inverse.m[1][1] = 1.0 / factors.y;
+        inverse.m[2][2] = 1.0 / factors.z;
+        Transform { matrix, inverse }
+    }
+
+    pub fn rotate_x(degrees: f64) -> Transform {
+        Transform::rotate(Vector3D::new(1.0, 0.0, 0.0), degrees)
+    }
+
+    pub fn rotate_y(degrees: f64) -> Transform {
+        Transform::rotate(Vector3D::new(0.0, 1.0, 0.0), degrees)
+    }
+
+    pub fn rotate_z(degrees: f64) -> Transform {
+        Transform::rotate(Vector3D::new(0.0, 0.0, 1.0), degrees)
+    }
+
+    /// Counter-clockwise rotation about `axis` when looking down the axis
+    pub fn rotate(axis: Vector3D, degrees: f64) -> Transform {
+        Transform::from_quaternion(Quaternion::from_axis_angle(axis, degrees))
+    }
+
+    pub fn from_quaternion(rotation: Quaternion) -> Transform {
+        // Rotations are orthogonal, so the inverse is the transpose
+        let matrix = rotation.normalize().to_matrix();
+        Transform {
+            matrix,
+            inverse: matrix.transpose(),
+        }
+    }
+
+    /// Scale, then rotate, then translate, the usual decomposition for
+    /// scene graph nodes and animated objects
+    pub fn from_trs(translation: Vector3D, rotation: Quaternion, scale: Vector3D) -> Transform {
+        Transform::translate(translation) * Transform::from_quaternion(rotation) * Transform::scale(scale)
+    }
+
+    /// Applies `self`, then `next`
+    pub fn then(self, next: Transform) -> Transform {
+        next * self
+    }
+
+    pub fn inverse(&self) -> Transform {
+        Transform {
+            matrix: self.inverse,
+            inverse: self.matrix,
+        }
+    }
+
+    /// Keeps the direction unnormalized so ray parameters `t` mean the same
+    /// point on both sides of the transform
+    #[inline]
+    pub fn transform_ray(&self, ray: &Ray) -> Ray {
+        Ray::new((*self * Point3D(ray.origin)).0, *self * ray.direction)
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform::IDENTITY
+    }
+}
+
+impl Mul for Transform {
+    type Output = Transform;
+
+    fn mul(self, other: Transform) -> Transform {
+        Transform {
+            matrix: self.matrix * other.matrix,
+            inverse: other.inverse * self.inverse,
+        }
+    }
+}
+
+impl Mul<Point3D> for Transform {
+    type Output = Point3D;
+
+    #[inline]
+    fn mul(self, point: Point3D) -> Point3D {
+        self.matrix * point
+    }
+}
+
+impl Mul<Vector3D> for Transform {
+    type Output = Vector3D;
+
+    #[inline]
+    fn mul(self, vector: Vector3D) -> Vector3D {
+        self.matrix * vector
+    }
+}
+
+impl Mul<Normal3D> for Transform {
+    type Output = Normal3D;
+
+    /// Uses the inverse transpose; the result is not normalized
+    #[inline]
+    fn mul(self, normal: Normal3D) -> Normal3D {
+        let m = &self.inverse.m;
+        let n = normal.0;
+        Normal3D::new(
+            m[0][0] * n.x + m[1][0] * n.y + m[2][0] * n.z,
+            m[0][1] * n.x + m[1][1] * n.y + m[2][1] * n.z,
+            m[0][2] * n.x + m[1][2] * n.y + m[2][2] * n.z,
+        )
+    }
+}
+
+/// Rigid local coordinate system: an origin and three orthonormal axes.
+///
+/// Cheaper than a `Transform` for shapes that are only placed and rotated,
+/// since moving into the frame is three dot products.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frame {
+    pub origin: Vector3D,
+    pub u: Vector3D,
+    pub v: Vector3D,
+    pub w: Vector3D,
+}
+
+impl Frame {
+    /// Frame whose local z axis is `axis`; the other two are arbitrary
+    pub fn from_axis(origin: Vector3D, axis: Vector3D) -> Frame {
+        let w = axis.normalize();
+        let (u, v) = w.orthonormal_basis();
+        Frame { origin, u, v, w }
+    }
+
+    /// Frame with local x along `u` and local y along `v`, made
+    /// perpendicular to `u` if it isn't already
+    pub fn from_axes(origin: Vector3D, u: Vector3D, v: Vector3D) -> Frame {
+        let u = u.normalize();
+        let v = (v - u * v.dot(u)).normalize();
+        Frame {
+            origin,
+            u,
+            v,
+            w: u.cross(v),
+        }
+    }
+
+    pub fn from_rotation(origin: Vector3D, rotation: Quaternion) -> Frame {
+        Frame {
+            origin,
+            u: rotation.rotate(Vector3D::new(1.0, 0.0, 0.0)),
+            v: rotation.rotate(Vector3D::new(0.0, 1.0, 0.0)),
+            w: rotation.rotate(Vector3D::new(0.0, 0.0, 1.0)),
+        }
+    }
+
+    #[inline]
+    pub fn to_local(&self, vector: Vector3D) -> Vector3D {
+        Vector3D::new(vector.dot(self.u), vector.dot(self.v), vector.dot(self.w))
+    }
+
+    #[inline]
+    pub fn to_world(&self, vector: Vector3D) -> Vector3D {
+        self.u * vector.x + self.v * vector.y + self.w * vector.z
+    }
+
+    /// The ray in local coordinates; lengths and so `t` are unchanged
+    #[inline]
+    pub fn ray_to_local(&self, ray: &Ray) -> Ray {
+        Ray::new(self.to_local(ray.origin - self.origin), self.to_local(ray.direction))
+    }
+}
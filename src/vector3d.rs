@@ -1,11 +1,19 @@
-use std::ops::{Add, Mul, Sub};
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Div, DivAssign, Index, IndexMut, Mul, MulAssign, Neg, Sub, SubAssign};
 
 impl Vector3D {
+    pub const ZERO: Vector3D = Vector3D { x: 0.0, y: 0.0, z: 0.0 };
+
     #[inline]
     pub fn new(x: f64, y: f64, z: f64) -> Vector3D {
         Vector3D { x, y, z }
     }
 
+    #[inline]
+    pub fn splat(value: f64) -> Vector3D {
+        Vector3D::new(value, value, value)
+    }
+
     #[inline]
     pub fn dot(self, other: Vector3D) -> f64 {
         self.x * other.x + self.y * other.y + self.z * other.z
@@ -39,6 +47,67 @@ impl Vector3D {
             z: self.z * inv_mag,
         }
     }
+
+    /// Component-wise minimum
+    #[inline]
+    pub fn min(self, other: Vector3D) -> Vector3D {
+        Vector3D::new(self.x.min(other.x), self.y.min(other.y), self.z.min(other.z))
+    }
+
+    /// Component-wise maximum
+    #[inline]
+    pub fn max(self, other: Vector3D) -> Vector3D {
+        Vector3D::new(self.x.max(other.x), self.y.max(other.y), self.z.max(other.z))
+    }
+
+    #[inline]
+    pub fn abs(self) -> Vector3D {
+        Vector3D::new(self.x.abs(), self.y.abs(), self.z.abs())
+    }
+
+    #[inline]
+    pub fn min_component(self) -> f64 {
+        self.x.min(self.y).min(self.z)
+    }
+
+    #[inline]
+    pub fn max_component(self) -> f64 {
+        self.x.max(self.y).max(self.z)
+    }
+
+    /// Index of the largest component, e.g. the dominant axis of a normal
+    #[inline]
+    pub fn max_dimension(self) -> usize {
+        if self.x > self.y {
+            if self.x > self.z {
+                0
+            } else {
+                2
+            }
+        } else if self.y > self.z {
+            1
+        } else {
+            2
+        }
+    }
+
+    #[inline]
+    pub fn lerp(self, other: Vector3D, t: f64) -> Vector3D {
+        self + (other - self) * t
+    }
+
+    /// Two unit vectors completing an orthonormal basis with this unit
+    /// vector (Duff et al. 2017), stable for every direction
+    #[inline]
+    pub fn orthonormal_basis(self) -> (Vector3D, Vector3D) {
+        let sign = 1.0f64.copysign(self.z);
+        let a = -1.0 / (sign + self.z);
+        let b = self.x * self.y * a;
+        (
+            Vector3D::new(1.0 + sign * self.x * self.x * a, sign * b, -sign * self.x),
+            Vector3D::new(b, sign + self.y * self.y * a, -self.y),
+        )
+    }
 }
 
 impl Add for Vector3D {
@@ -93,9 +162,626 @@ impl Mul<Vector3D> for f64 {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+impl Mul<Vector3D> for Vector3D {
+    type Output = Self;
+
+    /// Component-wise product
+    #[inline]
+    fn mul(self, other: Vector3D) -> Self {
+        Vector3D {
+            x: self.x * other.x,
+            y: self.y * other.y,
+            z: self.z * other.z,
+        }
+    }
+}
+
+impl Div<f64> for Vector3D {
+    type Output = Self;
+
+    #[inline]
+    fn div(self, scalar: f64) -> Self {
+        self * (1.0 / scalar)
+    }
+}
+
+impl Div<Vector3D> for Vector3D {
+    type Output = Self;
+
+    /// Component-wise quotient
+    #[inline]
+    fn div(self, other: Vector3D) -> Self {
+        Vector3D {
+            x: self.x / other.x,
+            y: self.y / other.y,
+            z: self.z / other.z,
+        }
+    }
+}
+
+impl Neg for Vector3D {
+    type Output = Self;
+
+    #[inline]
+    fn neg(self) -> Self {
+        Vector3D {
+            x: -self.x,
+            y: -self.y,
+            z: -self.z,
+        }
+    }
+}
+
+impl AddAssign for Vector3D {
+    #[inline]
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other;
+    }
+}
+
+impl SubAssign for Vector3D {
+    #[inline]
+    fn sub_assign(&mut self, other: Self) {
+        *self = *self - other;
+    }
+}
+
+impl MulAssign<f64> for Vector3D {
+    #[inline]
+    fn mul_assign(&mut self, scalar: f64) {
+        *self = *self * scalar;
+    }
+}
+
+impl DivAssign<f64> for Vector3D {
+    #[inline]
+    fn div_assign(&mut self, scalar: f64) {
+        *self = *self / scalar;
+    }
+}
+
+impl Index<usize> for Vector3D {
+    type Output = f64;
+
+    #[inline]
+    fn index(&self, index: usize) -> &f64 {
+        match index {
+            0 => &self.x,
+            1 => &self.y,
+            2 => &self.z,
+            _ => panic!("Vector3D index {} out of range", index),
+        }
+    }
+}
+
+impl IndexMut<usize> for Vector3D {
+    #[inline]
+    fn index_mut(&mut self, index: usize) -> &mut f64 {
+        match index {
+            0 => &mut self.x,
+            1 => &mut self.y,
+            2 => &mut self.z,
+            _ => panic!("Vector3D index {} out of range", index),
+        }
+    }
+}
+
+impl Sum for Vector3D {
+    fn sum<I: Iterator<Item = Vector3D>>(iter: I) -> Vector3D {
+        iter.fold(Vector3D::ZERO, Add::add)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
 pub struct Vector3D {
     pub x: f64,
     pub y: f64,
     pub z: f64,
 }
+
+/// A position. Transforms apply translation to points but not to vectors.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Point3D(pub Vector3D);
+
+/// A surface normal. Transforms map normals through the inverse transpose
+/// so they stay perpendicular to the surface under non-uniform scale.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Normal3D(pub Vector3D);
+
+impl Point3D {
+    #[inline]
+    pub fn new(x: f64, y: f64, z: f64) -> Point3D {
+        Point3D(Vector3D::new(x, y, z))
+    }
+}
+
+impl Normal3D {
+    #[inline]
+    pub fn new(x: f64, y: f64, z: f64) -> Normal3D {
+        Normal3D(Vector3D::new(x, y, z))
+    }
+
+    #[inline]
+    pub fn normalize(self) -> Normal3D {
+        Normal3D(self.0.normalize())
+    }
+
+    /// Flips the normal into the hemisphere of `v`
+    #[inline]
+    pub fn face_forward(self, v: Vector3D) -> Normal3D {
+        if self.0.dot(v) < 0.0 {
+            -self
+        } else {
+            self
+        }
+    }
+}
+
+impl From<Point3D> for Vector3D {
+    #[inline]
+    fn from(point: Point3D) -> Vector3D {
+        point.0
+    }
+}
+
+impl From<Normal3D> for Vector3D {
+    #[inline]
+    fn from(normal: Normal3D) -> Vector3D {
+        normal.0
+    }
+}
+
+impl Sub for Point3D {
+    type Output = Vector3D;
+
+    #[inline]
+    fn sub(self, other: Point3D) -> Vector3D {
+        self.0 - other.0
+    }
+}
+
+impl Add<Vector3D> for Point3D {
+    type Output = Point3D;
+
+    #[inline]
+    fn add(self, offset: Vector3D) -> Point3D {
+        Point3D(self.0 + offset)
+    }
+}
+
+impl Sub<Vector3D> for Point3D {
+    type Output = Point3D;
+
+    #[inline]
+    fn sub(self, offset: Vector3D) -> Point3D {
+        Point3D(self.0 - offset)
+    }
+}
+
+impl Neg for Normal3D {
+    type Output = Normal3D;
+
+    #[inline]
+    fn neg(self) -> Normal3D {
+        Normal3D(-self.0)
+    }
+}
+
+/// Row-major 4x4 matrix acting on column vectors
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Matrix4 {
+    pub m: [[f64; 4]; 4],
+}
+
+impl Matrix4 {
+    pub const IDENTITY: Matrix4 = Matrix4 {
+        m: [
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ],
+    };
+
+    pub fn new(m: [[f64; 4]; 4]) -> Matrix4 {
+        Matrix4 { m }
+    }
+
+    /// Camera-to-world matrix for an eye at `eye` looking at `target`. The
+    /// columns are the camera's right, up and forward axes (right is
+    /// `forward x up`, as in `Camera`), so +z maps onto the view direction.
+    /// A target at the eye looks along +z, and when the view runs along `up`
+    /// (or `up` is zero), world +z and then +x stand in for it, so looking
+    /// straight down a y-up scene puts +z at the top of the image.
+    pub fn look_at(eye: Vector3D, target: Vector3D, up: Vector3D) -> Matrix4 {
+        let offset = target - eye;
+        let forward = if offset.magnitude_squared() > 0.0 {
+            offset.normalize()
+        } else {
+            Vector3D::new(0.0, 0.0, 1.0)
+        };
+        let right = [up, Vector3D::new(0.0, 0.0, 1.0), Vector3D::new(1.0, 0.0, 0.0)]
+            .into_iter()
+            .map(|up| forward.cross(up))
+            .find(|right| right.magnitude_squared() > 1e-12)
+            .unwrap_or(Vector3D::new(1.0, 0.0, 0.0))
+            .normalize();
+        let up = right.cross(forward);
+        Matrix4::new([
+            [right.x, up.x, forward.x, eye.x],
+            [right.y, up.y, forward.y, eye.y],
+            [right.z, up.z, forward.z, eye.z],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn transpose(&self) -> Matrix4 {
+        let mut m = [[0.0; 4]; 4];
+        for (row, values) in m.iter_mut().enumerate() {
+            for (column, value) in values.iter_mut().enumerate() {
+                *value = self.m[column][row];
+            }
+        }
+        Matrix4 { m }
+    }
+
+    /// Inverse by Gauss-Jordan elimination with partial pivoting, or `None`
+    /// if the matrix is singular
+    pub fn inverse(&self) -> Option<Matrix4> {
+        let mut a = self.m;
+        let mut inverse = Matrix4::IDENTITY.m;
+
+        for column in 0..4 {
+            let pivot = (column..4)
+                .max_by(|&i, &j| a[i][column].abs().total_cmp(&a[j][column].abs()))
+                .unwrap_or(column);
+            if a[pivot][column].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(column, pivot);
+            inverse.swap(column, pivot);
+
+            let scale = 1.0 / a[column][column];
+            for k in 0..4 {
+                a[column][k] *= scale;
+                inverse[column][k] *= scale;
+            }
+
+            for row in 0..4 {
+                if row == column {
+                    continue;
+                }
+                let factor = a[row][column];
+                if factor == 0.0 {
+                    continue;
+                }
+                for k in 0..4 {
+                    a[row][k] -= factor * a[column][k];
+                    inverse[row][k] -= factor * inverse[column][k];
+                }
+            }
+        }
+
+        Some(Matrix4 { m: inverse })
+    }
+
+    /// Applies the matrix to a position (w = 1)
+    #[inline]
+    pub fn transform_point(&self, p: Vector3D) -> Vector3D {
+        let m = &self.m;
+        let x = m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3];
+        let y = m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3];
+        let z = m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3];
+        let w = m[3][0] * p.x + m[3][1] * p.y + m[3][2] * p.z + m[3][3];
+        if w == 1.0 {
+            Vector3D::new(x, y, z)
+        } else {
+            Vector3D::new(x / w, y / w, z / w)
+        }
+    }
+
+    /// Applies the matrix to a direction (w = 0), ignoring translation
+    #[inline]
+    pub fn transform_vector(&self, v: Vector3D) -> Vector3D {
+        let m = &self.m;
+        Vector3D::new(
+            m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        )
+    }
+}
+
+impl Mul for Matrix4 {
+    type Output = Matrix4;
+
+    fn mul(self, other: Matrix4) -> Matrix4 {
+        let mut m = [[0.0; 4]; 4];
+        for (row, values) in m.iter_mut().enumerate() {
+            for (column, value) in values.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.m[row][k] * other.m[k][column]).sum();
+            }
+        }
+        Matrix4 { m }
+    }
+}
+
+impl Mul<Point3D> for Matrix4 {
+    type Output = Point3D;
+
+    #[inline]
+    fn mul(self, point: Point3D) -> Point3D {
+        Point3D(self.transform_point(point.0))
+    }
+}
+
+impl Mul<Vector3D> for Matrix4 {
+    type Output = Vector3D;
+
+    #[inline]
+    fn mul(self, vector: Vector3D) -> Vector3D {
+        self.transform_vector(vector)
+    }
+}
+
+/// Rotation as a unit quaternion `w + xi + yj + zk`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quaternion {
+    pub w: f64,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl Quaternion {
+    pub const IDENTITY: Quaternion = Quaternion {
+        w: 1.0,
+        x: 0.0,
+        y: 0.0,
+        z: 0.0,
+    };
+
+    pub fn new(w: f64, x: f64, y: f64, z: f64) -> Quaternion {
+        Quaternion { w, x, y, z }
+    }
+
+    /// Counter-clockwise rotation about `axis`, matching `Transform::rotate`
+    pub fn from_axis_angle(axis: Vector3D, degrees: f64) -> Quaternion {
+        let axis = axis.normalize();
+        let (sin, cos) = (degrees.to_radians() * 0.5).sin_cos();
+        Quaternion::new(cos, axis.x * sin, axis.y * sin, axis.z * sin)
+    }
+
+    /// Rotation about x, then y, then z, in degrees
+    pub fn from_euler(x: f64, y: f64, z: f64) -> Quaternion {
+        Quaternion::from_axis_angle(Vector3D::new(0.0, 0.0, 1.0), z)
+            * Quaternion::from_axis_angle(Vector3D::new(0.0, 1.0, 0.0), y)
+            * Quaternion::from_axis_angle(Vector3D::new(1.0, 0.0, 0.0), x)
+    }
+
+    #[inline]
+    pub fn dot(self, other: Quaternion) -> f64 {
+        self.w * other.w + self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    #[inline]
+    pub fn normalize(self) -> Quaternion {
+        let inv_len = 1.0 / self.dot(self).sqrt();
+        Quaternion::new(self.w * inv_len, self.x * inv_len, self.y * inv_len, self.z * inv_len)
+    }
+
+    /// Inverse rotation, for unit quaternions
+    #[inline]
+    pub fn conjugate(self) -> Quaternion {
+        Quaternion::new(self.w, -self.x, -self.y, -self.z)
+    }
+
+    #[inline]
+    pub fn rotate(self, v: Vector3D) -> Vector3D {
+        let u = Vector3D::new(self.x, self.y, self.z);
+        let t = 2.0 * u.cross(v);
+        v + self.w * t + u.cross(t)
+    }
+
+    pub fn to_matrix(self) -> Matrix4 {
+        let Quaternion { w, x, y, z } = self;
+        Matrix4::new([
+            [1.0 - 2.0 * (y * y + z * z), 2.0 * (x * y - w * z), 2.0 * (x * z + w * y), 0.0],
+            [2.0 * (x * y + w * z), 1.0 - 2.0 * (x * x + z * z), 2.0 * (y * z - w * x), 0.0],
+            [2.0 * (x * z - w * y), 2.0 * (y * z + w * x), 1.0 - 2.0 * (x * x + y * y), 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    /// Constant-speed interpolation along the shorter arc between two
+    /// rotations, falling back to normalized lerp when they nearly coincide
+    pub fn slerp(self, other: Quaternion, t: f64) -> Quaternion {
+        let mut cos = self.dot(other);
+        let other = if cos < 0.0 {
+            cos = -cos;
+            -other
+        } else {
+            other
+        };
+
+        if cos > 0.9995 {
+            return Quaternion::new(
+                self.w + (other.w - self.w) * t,
+                self.x + (other.x - self.x) * t,
+                self.y + (other.y - self.y) * t,
+                self.z + (other.z - self.z) * t,
+            )
+            .normalize();
+        }
+
+        let theta = cos.acos();
+        let inv_sin = 1.0 / theta.sin();
+        let a = ((1.0 - t) * theta).sin() * inv_sin;
+        let b = (t * theta).sin() * inv_sin;
+        Quaternion::new(
+            self.w * a + other.w * b,
+            self.x * a + other.x * b,
+            self.y * a + other.y * b,
+            self.z * a + other.z * b,
+        )
+    }
+}
+
+impl Default for Quaternion {
+    fn default() -> Self {
+        Quaternion::IDENTITY
+    }
+}
+
+impl Mul for Quaternion {
+    type Output = Quaternion;
+
+    /// Hamilton product: `a * b` rotates by `b`, then by `a`
+    #[inline]
+    fn mul(self, o: Quaternion) -> Quaternion {
+        Quaternion::new(
+            self.w * o.w - self.x * o.x - self.y * o.y - self.z * o.z,
+            self.w * o.x + self.x * o.w + self.y * o.z - self.z * o.y,
+            self.w * o.y - self.x * o.z + self.y * o.w + self.z * o.x,
+            self.w * o.z + self.x * o.y - self.y * o.x + self.z * o.w,
+        )
+    }
+}
+
+impl Neg for Quaternion {
+    type Output = Quaternion;
+
+    #[inline]
+    fn neg(self) -> Quaternion {
+        Quaternion::new(-self.w, -self.x, -self.y, -self.z)
+    }
+}
+
+/// Component-wise, so rotation tracks can run Catmull-Rom curves through
+/// quaternions; the results need normalizing
+impl Add for Quaternion {
+    type Output = Quaternion;
+
+    #[inline]
+    fn add(self, o: Quaternion) -> Quaternion {
+        Quaternion::new(self.w + o.w, self.x + o.x, self.y + o.y, self.z + o.z)
+    }
+}
+
+impl Sub for Quaternion {
+    type Output = Quaternion;
+
+    #[inline]
+    fn sub(self, o: Quaternion) -> Quaternion {
+        Quaternion::new(self.w - o.w, self.x - o.x, self.y - o.y, self.z - o.z)
+    }
+}
+
+impl Mul<f64> for Quaternion {
+    type Output = Quaternion;
+
+    #[inline]
+    fn mul(self, scalar: f64) -> Quaternion {
+        Quaternion::new(self.w * scalar, self.x * scalar, self.y * scalar, self.z * scalar)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: Vector3D, b: Vector3D) {
+        assert!((a - b).magnitude() < 1e-9, "{:?} != {:?}", a, b);
+    }
+
+    fn assert_matrix_close(a: &Matrix4, b: &Matrix4) {
+        for row in 0..4 {
+            for column in 0..4 {
+                assert!((a.m[row][column] - b.m[row][column]).abs() < 1e-9, "{:?} != {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn inverse_undoes_the_matrix() {
+        let matrix = Matrix4::new([
+            [2.0, 0.5, 0.0, 1.0],
+            [0.0, 1.0, -3.0, 2.0],
+            [1.0, 0.0, 0.25, -4.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        let inverse = matrix.inverse().unwrap();
+        assert_matrix_close(&(matrix * inverse), &Matrix4::IDENTITY);
+        assert_matrix_close(&(inverse * matrix), &Matrix4::IDENTITY);
+
+        let point = Vector3D::new(0.3, -7.0, 2.5);
+        assert_close(inverse.transform_point(matrix.transform_point(point)), point);
+        assert_close(inverse.transform_vector(matrix.transform_vector(point)), point);
+
+        let mut flat = matrix;
+        flat.m[2] = [0.0, 0.0, 0.0, 0.0];
+        assert!(flat.inverse().is_none());
+    }
+
+    #[test]
+    fn look_at_maps_camera_axes_onto_the_view() {
+        let eye = Vector3D::new(1.0, 2.0, 3.0);
+        let matrix = Matrix4::look_at(eye, Vector3D::new(1.0, 2.0, 8.0), Vector3D::new(0.0, 1.0, 0.0));
+        assert_close(matrix.transform_point(Vector3D::ZERO), eye);
+        assert_close(matrix.transform_vector(Vector3D::new(0.0, 0.0, 1.0)), Vector3D::new(0.0, 0.0, 1.0));
+        assert_close(matrix.transform_vector(Vector3D::new(1.0, 0.0, 0.0)), Vector3D::new(-1.0, 0.0, 0.0));
+        // Up stays on the side of the up vector
+        assert_close(matrix.transform_vector(Vector3D::new(0.0, 1.0, 0.0)), Vector3D::new(0.0, 1.0, 0.0));
+
+        // A slanted up vector still gives an orthonormal frame
+        let matrix = Matrix4::look_at(eye, Vector3D::ZERO, Vector3D::new(0.3, 1.0, -0.2));
+        let axes = [0, 1, 2].map(|axis| {
+            let mut unit = Vector3D::ZERO;
+            unit[axis] = 1.0;
+            matrix.transform_vector(unit)
+        });
+        for (i, a) in axes.iter().enumerate() {
+            assert!((a.magnitude() - 1.0).abs() < 1e-9);
+            for b in &axes[i + 1..] {
+                assert!(a.dot(*b).abs() < 1e-9);
+            }
+        }
+        assert!(axes[1].dot(Vector3D::new(0.3, 1.0, -0.2)) > 0.0);
+        assert_close(matrix.inverse().unwrap().transform_point(Vector3D::ZERO), Vector3D::new(0.0, 0.0, eye.magnitude()));
+    }
+
+    #[test]
+    fn quaternions_round_trip_through_matrices() {
+        let rotation = Quaternion::from_axis_angle(Vector3D::new(1.0, 2.0, -0.5), 73.0);
+        let v = Vector3D::new(0.4, -1.5, 2.0);
+        assert_close(rotation.to_matrix().transform_vector(v), rotation.rotate(v));
+        assert_close(rotation.conjugate().rotate(rotation.rotate(v)), v);
+        assert_matrix_close(&(rotation.to_matrix() * rotation.conjugate().to_matrix()), &Matrix4::IDENTITY);
+
+        // Anticlockwise about z takes x to y
+        let quarter = Quaternion::from_axis_angle(Vector3D::new(0.0, 0.0, 1.0), 90.0);
+        assert_close(quarter.rotate(Vector3D::new(1.0, 0.0, 0.0)), Vector3D::new(0.0, 1.0, 0.0));
+
+        let euler = Quaternion::from_euler(30.0, 45.0, 60.0);
+        let (x, y, z) = (
+            Quaternion::from_axis_angle(Vector3D::new(1.0, 0.0, 0.0), 30.0),
+            Quaternion::from_axis_angle(Vector3D::new(0.0, 1.0, 0.0), 45.0),
+            Quaternion::from_axis_angle(Vector3D::new(0.0, 0.0, 1.0), 60.0),
+        );
+        assert_close(euler.rotate(v), z.rotate(y.rotate(x.rotate(v))));
+    }
+
+    #[test]
+    fn slerp_turns_steadily_along_the_short_arc() {
+        let axis = Vector3D::new(0.0, 1.0, 0.0);
+        let (a, b) = (Quaternion::from_axis_angle(axis, 10.0), Quaternion::from_axis_angle(axis, 130.0));
+        for t in [0.0, 0.25, 0.5, 1.0] {
+            let expected = Quaternion::from_axis_angle(axis, 10.0 + 120.0 * t);
+            assert!((a.slerp(b, t).dot(expected).abs() - 1.0).abs() < 1e-9);
+        }
+        // The same rotation with the opposite sign still takes the short way
+        let mid = a.slerp(-b, 0.5);
+        assert!((mid.dot(Quaternion::from_axis_angle(axis, 70.0)).abs() - 1.0).abs() < 1e-9);
+        assert!((a.slerp(a, 0.5).dot(a) - 1.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,191 @@
+use crate::hittable::Hittable;
+use crate::ray::Ray;
+use crate::sphere::Color;
+use crate::vector3d::Vector3D;
+use std::f64::consts::PI;
+
+/// Exponential distance fog, blended over everything a ray sees. A cheap
+/// stand-in for a volume filling the whole scene: no lighting, no shafts.
+#[derive(Debug, Clone, Copy)]
+pub struct Fog {
+    pub color: Color,
+    /// Fraction of the remaining view faded out per unit distance
+    pub density: f64,
+}
+
+impl Fog {
+    pub fn new(color: Color, density: f64) -> Fog {
+        Fog { color, density }
+    }
+
+    /// `color` as seen from `distance` away, which may be infinite for rays
+    /// that miss everything
+    #[inline]
+    pub fn apply(&self, color: Color, distance: f64) -> Color {
+        if self.density <= 0.0 {
+            return color;
+        }
+        let transmittance = (-self.density * distance).exp();
+        color * transmittance + self.color * (1.0 - transmittance)
+    }
+}
+
+/// How a medium redistributes the light it scatters
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PhaseFunction {
+    Isotropic,
+    /// Asymmetry `g` in (-1, 1): positive scatters forward, negative back
+    HenyeyGreenstein(f64),
+}
+
+impl PhaseFunction {
+    /// Probability density per steradian of turning by the angle whose
+    /// cosine is `cos_theta`, measured between the old and new directions of
+    /// travel
+    #[inline]
+    pub fn evaluate(self, cos_theta: f64) -> f64 {
+        match self {
+            PhaseFunction::Isotropic => 1.0 / (4.0 * PI),
+            PhaseFunction::HenyeyGreenstein(g) => {
+                let denominator = 1.0 + g * g - 2.0 * g * cos_theta;
+                (1.0 - g * g) / (4.0 * PI * denominator * denominator.sqrt())
+            }
+        }
+    }
+
+    /// New direction of travel for a ray going along unit `direction`,
+    /// distributed exactly as `evaluate`
+    pub fn sample(self, direction: Vector3D, u1: f64, u2: f64) -> Vector3D {
+        let cos_theta = match self {
+            PhaseFunction::HenyeyGreenstein(g) if g.abs() > 1e-3 => {
+                let s = (1.0 - g * g) / (1.0 + g - 2.0 * g * u1);
+                (1.0 + g * g - s * s) / (2.0 * g)
+            }
+            _ => 1.0 - 2.0 * u1,
+        }
+        .clamp(-1.0, 1.0);
+
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        let phi = 2.0 * PI * u2;
+        let (u, v) = direction.orthonormal_basis();
+        direction * cos_theta + (u * phi.cos() + v * phi.sin()) * sin_theta
+    }
+}
+
+/// Medium of constant density filling a closed `Hittable`, e.g. smoke in a
+/// box or haze in a huge sphere around the scene.
+///
+/// The boundary only marks where the medium is; it isn't drawn. Light is
+/// absorbed and out-scattered at `density` per unit length, and `albedo`
+/// is the part of that which scatters rather than absorbs.
+pub struct Volume {
+    pub boundary: Box<dyn Hittable>,
+    pub density: f64,
+    pub albedo: Color,
+    pub phase: PhaseFunction,
+}
+
+impl Volume {
+    pub fn new(boundary: Box<dyn Hittable>, density: f64, albedo: Color, phase: PhaseFunction) -> Volume {
+        Volume {
+            boundary,
+            density,
+            albedo,
+            phase,
+        }
+    }
+
+    /// Stretches of `ray` between `t_min` and `t_max` inside the medium
+    fn segments(&self, ray: &Ray, t_min: f64, t_max: f64) -> impl Iterator<Item = (f64, f64)> {
+        self.boundary
+            .intervals(ray)
+            .into_iter()
+            .map(move |interval| (interval.enter.t.max(t_min), interval.exit.t.min(t_max)))
+            .filter(|(enter, exit)| exit > enter)
+    }
+
+    /// Fraction of light that makes it through the medium along `ray`
+    /// between `t_min` and `t_max`
+    pub fn transmittance(&self, ray: &Ray, t_min: f64, t_max: f64) -> f64 {
+        let inside: f64 = self.segments(ray, t_min, t_max).map(|(enter, exit)| exit - enter).sum();
+        (-self.density * inside * ray.direction.magnitude()).exp()
+    }
+
+    /// Distance along `ray` at which it next interacts with the medium
+    /// before `t_max`, drawn in proportion to the transmittance up to it.
+    /// `None` means the ray passes through.
+    pub fn sample_distance(&self, ray: &Ray, t_min: f64, t_max: f64, rng: &fastrand::Rng) -> Option<f64> {
+        if self.density <= 0.0 {
+            return None;
+        }
+        let length = ray.direction.magnitude();
+
+        // Free flights are memoryless, so each segment takes a fresh draw
+        self.segments(ray, t_min, t_max).find_map(|(enter, exit)| {
+            let flight = -(1.0 - rng.f64()).ln() / (self.density * length);
+            (enter + flight < exit).then_some(enter + flight)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PHASES: [PhaseFunction; 4] = [
+        PhaseFunction::Isotropic,
+        PhaseFunction::HenyeyGreenstein(0.0),
+        PhaseFunction::HenyeyGreenstein(0.7),
+        PhaseFunction::HenyeyGreenstein(-0.4),
+    ];
+
+    #[test]
+    fn phase_functions_integrate_to_one() {
+        let steps = 100_000;
+        for phase in PHASES {
+            let total: f64 = (0..steps)
+                .map(|i| {
+                    let cos_theta = -1.0 + 2.0 * (i as f64 + 0.5) / steps as f64;
+                    phase.evaluate(cos_theta) * 2.0 * PI * 2.0 / steps as f64
+                })
+                .sum();
+            assert!((total - 1.0).abs() < 1e-4, "{:?} integrates to {}", phase, total);
+        }
+    }
+
+    #[test]
+    fn sampled_directions_follow_the_phase_function() {
+        let direction = Vector3D::new(1.0, 2.0, -2.0).normalize();
+        let (samples, bins) = (200_000, 20);
+        for phase in PHASES {
+            let mut histogram = vec![0usize; bins];
+            let mut mean_cosine = 0.0;
+            for i in 0..samples {
+                let u1 = (i as f64 + 0.5) / samples as f64;
+                let scattered = phase.sample(direction, u1, (i as f64 * 0.618034).fract());
+                assert!((scattered.magnitude() - 1.0).abs() < 1e-9);
+                let cos_theta = direction.dot(scattered);
+                histogram[(((cos_theta + 1.0) * 0.5 * bins as f64) as usize).min(bins - 1)] += 1;
+                mean_cosine += cos_theta / samples as f64;
+            }
+
+            // Each bin holds the pdf's share of the sphere, by the midpoint rule
+            for (bin, &count) in histogram.iter().enumerate() {
+                let expected: f64 = (0..100)
+                    .map(|step| {
+                        let cos_theta = -1.0 + 2.0 * (bin as f64 + (step as f64 + 0.5) / 100.0) / bins as f64;
+                        phase.evaluate(cos_theta) * 2.0 * PI * 2.0 / (bins * 100) as f64
+                    })
+                    .sum();
+                let actual = count as f64 / samples as f64;
+                assert!((actual - expected).abs() < 1e-3, "{:?} bin {}: {} vs {}", phase, bin, actual, expected);
+            }
+
+            let g = match phase {
+                PhaseFunction::Isotropic => 0.0,
+                PhaseFunction::HenyeyGreenstein(g) => g,
+            };
+            assert!((mean_cosine - g).abs() < 1e-3, "{:?} has mean cosine {}", phase, mean_cosine);
+        }
+    }
+}